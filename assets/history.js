@@ -0,0 +1,74 @@
+// Draws GET /api/history into the dashboard's <svg id="history">, one line
+// per channel in the color of its key. The format is described in
+// src/history.rs.
+"use strict";
+
+const DIGITS = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const RANGE_CM = 300;
+
+// {intervalMs, ageMs, channels: {L: [cm or null, ...], ...}}, oldest first
+function decodeHistory(text) {
+    const lines = text.trim().split("\n");
+    const [format, intervalMs, count, ageMs] = lines[0].split(" ");
+    if (format !== "H1") {
+        throw new Error("unknown history format " + format);
+    }
+    const channels = {};
+    for (const line of lines.slice(1)) {
+        const [tag, digits] = line.split(":");
+        const samples = [];
+        let value = 0, group = 0, shift = 0;
+        for (const char of digits) {
+            const digit = DIGITS.indexOf(char);
+            group += (digit & 31) << shift;
+            shift += 5;
+            if (digit & 32) {
+                continue;
+            }
+            // the lowest bit is the sign of the difference
+            value += group & 1 ? -(group >> 1) : group >> 1;
+            samples.push(value === 0 ? null : value - 1);
+            group = 0;
+            shift = 0;
+        }
+        if (samples.length !== Number(count)) {
+            throw new Error("channel " + tag + " has " + samples.length + " samples, expected " + count);
+        }
+        channels[tag] = samples;
+    }
+    return { intervalMs: Number(intervalMs), ageMs: Number(ageMs), channels };
+}
+
+function drawHistory(svg, history) {
+    const [width, height] = [300, 100];
+    for (const [tag, samples] of Object.entries(history.channels)) {
+        // a gap in the line wherever there was no reading
+        let path = "", pen = "M";
+        samples.forEach((cm, index) => {
+            if (cm === null) {
+                pen = "M";
+                return;
+            }
+            const x = width * (index + 1) / samples.length;
+            const y = height * (1 - Math.min(cm, RANGE_CM) / RANGE_CM);
+            path += pen + x.toFixed(1) + " " + y.toFixed(1) + " ";
+            pen = "L";
+        });
+        if (path) {
+            const line = document.createElementNS("http://www.w3.org/2000/svg", "path");
+            line.setAttribute("d", path);
+            line.setAttribute("fill", "none");
+            const key = document.querySelector(".history-key-" + tag);
+            line.setAttribute("stroke", key ? getComputedStyle(key).color : "gray");
+            svg.appendChild(line);
+        }
+    }
+}
+
+const svg = document.getElementById("history");
+if (svg) {
+    fetch("/api/history")
+        .then(response => response.text())
+        .then(text => drawHistory(svg, decodeHistory(text)))
+        .catch(error => console.warn("history:", error));
+}
@@ -7,6 +7,35 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! The FLASH length in `memory.x` is also written out as `PROGRAM_SIZE`, for
+//! the flash layout checks in `src/partitions.rs`.
+//!
+//! It also generates the build defaults (see `src/defaults.rs`) from these
+//! environment variables, so nobody has to edit tracked files to configure
+//! their build:
+//!
+//! - `VISIONASSIST_SSID`: access point name, "VisionAssist" by default
+//! - `VISIONASSIST_PASSWORD`: WPA2 passphrase used until one is provisioned,
+//!   the access point is open by default
+//! - `VISIONASSIST_DEFAULT_THRESHOLDS`: critical, warning and notice zone
+//!   distances in cm as "enter/exit" pairs like "30/35,60/66,100/110"; a
+//!   plain "enter" gets an exit 10% further out
+//! - `VISIONASSIST_QA_TOKEN`: token for the SIMULATE test command, which is
+//!   disabled without one
+//! - `VISIONASSIST_ADMIN_TOKEN`: token a command session (AUTH) or an HTTP
+//!   POST needs to change settings, without one every client may
+//! - `VISIONASSIST_AP_CHANNEL`: access point channel from 1 to 11, or "auto"
+//!   (the default) to pick the least busy one at startup
+//! - `VISIONASSIST_SENSORS`: the board's distance sensors, per channel, like
+//!   "left=maxbotix-serial:in,right=maxbotix-analog:cm". Kinds are `hcsr04`
+//!   (every channel left out), `maxbotix-serial`, `maxbotix-analog` and
+//!   `tfluna` (also `tfmini`); a MaxSonar's unit is `in` (LV), `cm` (XL, the
+//!   default) or `mm` (HRLV). Channels are `left`, `right`, `overhead` and
+//!   `rear`, the rear one can't be analog.
+//! - `VISIONASSIST_SPARE_SOCKETS`: network stack sockets on top of the ones
+//!   the firmware uses, for a service being added (mDNS, a DHCP server), 0
+//!   to 8 and 0 by default
 
 use std::env;
 use std::fs::File;
@@ -28,8 +57,163 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+    File::create(out.join("memory.rs"))
+        .unwrap()
+        .write_all(format!("const PROGRAM_SIZE: usize = {};\n", program_size(include_str!("./memory.x"))).as_bytes())
+        .unwrap();
+
+    File::create(out.join("defaults.rs"))
+        .unwrap()
+        .write_all(generate_defaults().as_bytes())
+        .unwrap();
+    for var in ["VISIONASSIST_SSID", "VISIONASSIST_PASSWORD", "VISIONASSIST_DEFAULT_THRESHOLDS", "VISIONASSIST_QA_TOKEN", "VISIONASSIST_ADMIN_TOKEN", "VISIONASSIST_AP_CHANNEL", "VISIONASSIST_SENSORS", "VISIONASSIST_SPARE_SOCKETS"] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+fn generate_defaults() -> String {
+    let ssid = env::var("VISIONASSIST_SSID").unwrap_or_else(|_| "VisionAssist".into());
+    if ssid.is_empty() || ssid.len() > 32 {
+        panic!("VISIONASSIST_SSID must be 1 to 32 bytes long");
+    }
+
+    let passphrase = env::var("VISIONASSIST_PASSWORD").ok().filter(|passphrase| !passphrase.is_empty());
+    if let Some(passphrase) = &passphrase {
+        if !(8..=63).contains(&passphrase.len()) || !passphrase.bytes().all(|byte| (0x20..0x7F).contains(&byte)) {
+            panic!("VISIONASSIST_PASSWORD must be 8 to 63 printable ASCII characters");
+        }
+    }
+
+    let qa_token = env::var("VISIONASSIST_QA_TOKEN").ok().filter(|token| !token.is_empty());
+    if let Some(token) = &qa_token {
+        if token.len() < 8 || !token.bytes().all(|byte| byte.is_ascii_graphic()) {
+            panic!("VISIONASSIST_QA_TOKEN must be at least 8 printable characters without spaces");
+        }
+    }
+
+    let admin_token = env::var("VISIONASSIST_ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    if let Some(token) = &admin_token {
+        if token.len() < 8 || !token.bytes().all(|byte| byte.is_ascii_graphic()) {
+            panic!("VISIONASSIST_ADMIN_TOKEN must be at least 8 printable characters without spaces");
+        }
+    }
+
+    let channel = match env::var("VISIONASSIST_AP_CHANNEL").ok().filter(|channel| channel != "auto" && !channel.is_empty()) {
+        Some(channel) => match channel.parse::<u8>() {
+            Ok(channel) if (1..=11).contains(&channel) => Some(channel),
+            _ => panic!("VISIONASSIST_AP_CHANNEL must be \"auto\" or a channel from 1 to 11"),
+        },
+        None => None,
+    };
+
+    let thresholds = env::var("VISIONASSIST_DEFAULT_THRESHOLDS").unwrap_or_else(|_| "30/35,60/66,100/110".into());
+    let thresholds = parse_thresholds(&thresholds)
+        .unwrap_or_else(|e| panic!("VISIONASSIST_DEFAULT_THRESHOLDS: {}", e));
+
+    let sensors = env::var("VISIONASSIST_SENSORS").unwrap_or_default();
+    let sensors = parse_sensors(&sensors).unwrap_or_else(|e| panic!("VISIONASSIST_SENSORS: {}", e));
+
+    let spare_sockets = match env::var("VISIONASSIST_SPARE_SOCKETS").ok().filter(|spare| !spare.is_empty()) {
+        Some(spare) => match spare.parse::<usize>() {
+            Ok(spare @ 0..=8) => spare,
+            _ => panic!("VISIONASSIST_SPARE_SOCKETS must be a number from 0 to 8"),
+        },
+        None => 0,
+    };
+
+    format!(
+        "pub const AP_SSID: &str = {:?};\n\
+         pub const AP_PASSPHRASE: Option<&str> = {:?};\n\
+         pub const ZONE_THRESHOLDS_CM: [(f32, f32); 3] = {:?};\n\
+         pub const QA_TOKEN: Option<&str> = {:?};\n\
+         pub const ADMIN_TOKEN: Option<&str> = {:?};\n\
+         pub const AP_CHANNEL: Option<u8> = {:?};\n\
+         pub const SENSOR_KINDS: [crate::rangefinder::SensorKind; 4] = [{}];\n\
+         pub const SPARE_SOCKETS: usize = {};\n",
+        ssid, passphrase, thresholds, qa_token, admin_token, channel, sensors.join(", "), spare_sockets
+    )
+}
+
+// LENGTH of the FLASH region in memory.x, in bytes
+fn program_size(memory_x: &str) -> usize {
+    let line = memory_x
+        .lines()
+        .find(|line| line.trim_start().starts_with("FLASH "))
+        .expect("memory.x has no FLASH region");
+    let length = line.split("LENGTH").nth(1).and_then(|rest| rest.trim_start().strip_prefix('='));
+    let length = length.expect("memory.x: FLASH needs a LENGTH").trim();
+    let (number, scale) = match length.strip_suffix('K') {
+        Some(number) => (number, 1024),
+        None => match length.strip_suffix('M') {
+            Some(number) => (number, 1024 * 1024),
+            None => (length, 1),
+        },
+    };
+    number.trim().parse::<usize>().expect("memory.x: FLASH LENGTH must be a number with K or M") * scale
+}
+
+fn parse_thresholds(text: &str) -> Result<[(f32, f32); 3], String> {
+    let mut thresholds = Vec::new();
+    for item in text.split(',') {
+        let (enter, exit) = match item.split_once('/') {
+            Some((enter, exit)) => (enter, Some(exit)),
+            None => (item, None),
+        };
+        let number = |text: &str| text.trim().parse::<f32>().map_err(|_| format!("{:?} is not a number", text));
+        let enter = number(enter)?;
+        let exit = match exit {
+            Some(exit) => number(exit)?,
+            None => enter * 1.1,
+        };
+        if enter <= 0.0 || exit < enter {
+            return Err("distances must be positive with exit >= enter".into());
+        }
+        thresholds.push((enter, exit));
+    }
+
+    let thresholds: [(f32, f32); 3] = thresholds
+        .try_into()
+        .map_err(|_| String::from("expected three zones: critical, warning, notice"))?;
+    if thresholds[0].0 >= thresholds[1].0 || thresholds[1].0 >= thresholds[2].0 {
+        return Err("zones must be ordered critical < warning < notice".into());
+    }
+    Ok(thresholds)
+}
+
+// The left, right, overhead and rear sensor kinds as Rust expressions
+fn parse_sensors(text: &str) -> Result<[String; 4], String> {
+    let mut kinds: [String; 4] = std::array::from_fn(|_| "crate::rangefinder::SensorKind::HcSr04".to_string());
+
+    for item in text.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (channel, kind) = item.split_once('=').ok_or_else(|| format!("{:?} is not <channel>=<kind>", item))?;
+        let index = ["left", "right", "overhead", "rear"]
+            .iter()
+            .position(|name| *name == channel.trim())
+            .ok_or_else(|| format!("{:?} is not left, right, overhead or rear", channel))?;
+        let (kind, unit) = match kind.trim().split_once(':') {
+            Some((kind, unit)) => (kind, Some(unit)),
+            None => (kind.trim(), None),
+        };
+        let unit = match unit {
+            Some("in") => "Inch",
+            Some("cm") | None => "Cm",
+            Some("mm") => "Mm",
+            Some(unit) => return Err(format!("{:?} is not in, cm or mm", unit)),
+        };
+        kinds[index] = match kind {
+            "hcsr04" => "crate::rangefinder::SensorKind::HcSr04".to_string(),
+            "maxbotix-serial" => format!("crate::rangefinder::SensorKind::MaxbotixSerial(crate::rangefinder::RangeUnit::{})", unit),
+            // every ADC pin is taken by the other channels
+            "maxbotix-analog" if index == 3 => return Err("the rear channel can't be maxbotix-analog".into()),
+            "maxbotix-analog" => format!("crate::rangefinder::SensorKind::MaxbotixAnalog(crate::rangefinder::RangeUnit::{})", unit),
+            // the lidars always report cm
+            "tfluna" | "tfmini" => "crate::rangefinder::SensorKind::TfLuna".to_string(),
+            kind => return Err(format!("unknown sensor kind {:?}", kind)),
+        };
+    }
+    Ok(kinds)
+}
@@ -0,0 +1,82 @@
+//! Stored actuator mapping.
+//!
+//! `actuators` in the configuration describes how the unit is wired rather
+//! than how it is tuned: which motor output is worn on which side, the
+//! polarity of each driver, the start-up kick and whether a headset is
+//! plugged in. It isn't part of a profile, so it lives in its own flash
+//! sector: [`load`] restores it before the feedback task sets up the motors,
+//! so active-low drivers are off from boot, and [`storage_task`] writes it
+//! back a few seconds after it changes, from the web page, `SET` or an import.
+
+use embassy_time::Timer;
+
+use crate::config::{self, ActuatorConfig, Config};
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Feedback;
+
+// Change when the stored layout changes, old data is then ignored
+const ACTUATORS_MAGIC: u32 = 0x4143_5431;
+const HEADER_LEN: usize = 6;
+
+// How often the mapping is checked for changes to store
+const SAVE_CHECK_MS: u64 = 5000;
+
+fn read() -> Option<ActuatorConfig> {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::ACTUATORS, &mut buffer) {
+        warn!("Failed to read actuator mapping: {}", e);
+        return None;
+    }
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = u16::from_le_bytes(buffer[4..HEADER_LEN].try_into().ok()?) as usize;
+    if magic != ACTUATORS_MAGIC || HEADER_LEN + len > buffer.len() {
+        return None;
+    }
+    let actuators = postcard::from_bytes(&buffer[HEADER_LEN..HEADER_LEN + len]).ok()?;
+    Config { actuators, ..Config::DEFAULT }.validate().ok().map(|()| actuators)
+}
+
+// Apply the stored mapping, call once at startup before the feedback task starts
+pub fn load() {
+    if let Some(actuators) = read() {
+        info!(
+            "Actuators: sides {}, inverted {}/{}",
+            if actuators.swap_sides { "swapped" } else { "as wired" },
+            actuators.invert[0],
+            actuators.invert[1]
+        );
+        config::update(|config| config.actuators = actuators);
+    }
+}
+
+// Stores the mapping once it has changed, checking every few seconds
+#[embassy_executor::task]
+pub async fn storage_task() {
+    let mut stored = read().unwrap_or(Config::DEFAULT.actuators);
+
+    loop {
+        Timer::after_millis(SAVE_CHECK_MS).await;
+        let actuators = config::get().actuators;
+        if actuators == stored {
+            continue;
+        }
+
+        let mut buffer = [0xFF; ERASE_SIZE];
+        let len = match postcard::to_slice(&actuators, &mut buffer[HEADER_LEN..]) {
+            Ok(encoded) => encoded.len(),
+            Err(_) => {
+                warn!("Actuator mapping too large to store");
+                continue;
+            }
+        };
+        buffer[..4].copy_from_slice(&ACTUATORS_MAGIC.to_le_bytes());
+        buffer[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+        match storage::write_sector(partitions::ACTUATORS, &buffer) {
+            Ok(()) => stored = actuators,
+            Err(e) => warn!("Failed to store actuator mapping: {}", e),
+        }
+    }
+}
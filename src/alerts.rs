@@ -0,0 +1,85 @@
+//! Alert forwarding rules.
+//!
+//! Each kind of event worth telling someone about ([`AlertKind`]) has a rule
+//! in the configuration: which channels it goes to (the device log, an
+//! `ALERT` line broadcast on the network, a double beep) and optionally a
+//! throttle, so a flapping sensor doesn't flood anyone. A trainer changes
+//! the rules through `/api/alerts` or a config import, no firmware needed.
+//! Assist requests don't go through the rules, they always go out.
+
+use core::fmt::Write as FmtWrite;
+use embassy_time::Instant;
+use heapless::String;
+use vision_protocol::AlertEvent;
+use vision_protocol::zoning::Zone;
+
+use crate::broadcast;
+use crate::config::{self, AlertChannels, AlertKind, HapticPattern};
+use crate::events::{self, Event, EventSubscriber};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::logging::{Scope, debug, warn};
+use crate::shutdown::Reason;
+
+const SCOPE: Scope = Scope::System;
+
+const ALERT_BEEP: [u16; 3] = [150, 100, 150];
+
+// The rule an event falls under, if any
+fn kind(event: &Event) -> Option<AlertKind> {
+    match event {
+        Event::ZoneChanged { zone: Zone::Critical, .. } => Some(AlertKind::Critical),
+        Event::ZoneChanged { zone: Zone::Warning, .. } => Some(AlertKind::Warning),
+        Event::SensorDegraded { .. } => Some(AlertKind::SensorFault),
+        Event::InterferenceSuspected { .. } => Some(AlertKind::Interference),
+        Event::ShuttingDown { reason: Reason::LowBattery } => Some(AlertKind::LowBattery),
+        Event::WifiLink { .. } => Some(AlertKind::WifiLink),
+        _ => None,
+    }
+}
+
+// Applies the rules to every event. Gets its subscriber when spawned, like
+// the event log task.
+#[embassy_executor::task]
+pub async fn alerts_task(mut subscriber: EventSubscriber) {
+    // when each kind was last forwarded
+    let mut last: [Option<Instant>; AlertKind::ALL.len()] = [None; AlertKind::ALL.len()];
+
+    loop {
+        let event = events::next(&mut subscriber).await;
+        let Some(kind) = kind(&event) else {
+            continue;
+        };
+        let rule = config::get().alerts.rule(kind);
+        if rule.channels == AlertChannels::NONE {
+            continue;
+        }
+
+        let now = Instant::now();
+        let throttled = last[kind as usize].is_some_and(|at| (now - at).as_millis() < rule.throttle_ms as u64);
+        if throttled {
+            debug!("Alert {} throttled", kind.name());
+            continue;
+        }
+        last[kind as usize] = Some(now);
+
+        let mut text: String<96> = String::new();
+        events::describe(&mut text, &event);
+
+        if rule.channels.contains(AlertChannels::LOG) {
+            warn!("Alert {}: {}", kind.name(), text.as_str());
+        }
+        if rule.channels.contains(AlertChannels::UDP) {
+            let mut line = String::new();
+            let _ = FmtWrite::write_fmt(&mut line, format_args!("{}\n", AlertEvent { kind: kind.name(), text: &text }));
+            broadcast::send(line, 1);
+        }
+        if rule.channels.contains(AlertChannels::BEEP) {
+            feedback::submit(FeedbackCommand {
+                pattern: Pattern::Preview { steps: HapticPattern::new(&ALERT_BEEP), target: PreviewTarget::Buzzer },
+                side: None,
+                priority: Priority::Normal,
+                ttl_ms: 1000,
+            });
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Files the web server sends as they are, embedded in the image.
+//!
+//! They live in `assets/` and are served under the same path, so
+//! `assets/theme/dark.css` is `GET /theme/dark.css` and `assets/history.js`
+//! (the dashboard's chart) `GET /history.js`. Browsers may cache them
+//! for [`MAX_AGE_S`]; they only change with the firmware.
+
+pub const MAX_AGE_S: u32 = 24 * 3600;
+
+pub struct Asset {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub body: &'static str,
+}
+
+const ASSETS: &[Asset] = &[
+    Asset { path: "/theme/light.css", content_type: "text/css", body: include_str!("../assets/theme/light.css") },
+    Asset { path: "/theme/dark.css", content_type: "text/css", body: include_str!("../assets/theme/dark.css") },
+    Asset {
+        path: "/theme/high-contrast.css",
+        content_type: "text/css",
+        body: include_str!("../assets/theme/high-contrast.css"),
+    },
+    Asset { path: "/history.js", content_type: "text/javascript", body: include_str!("../assets/history.js") },
+];
+
+pub fn find(path: &str) -> Option<&'static Asset> {
+    ASSETS.iter().find(|asset| asset.path == path)
+}
@@ -0,0 +1,52 @@
+//! Assist requests: a triple press of the button asks for help.
+//!
+//! The request goes out everywhere a caregiver might be listening: as an
+//! `ASSIST` line on every open `STREAM`, as a UDP broadcast on the access
+//! point's subnet (see [`broadcast`](crate::broadcast), sent three times
+//! since UDP can drop it) and in the dashboard's event list. It carries the
+//! device's name, its owner and emergency contact as far as they are shared
+//! (see [`owner`](crate::owner)) and, once a GPS receiver is wired up, its
+//! position. A
+//! short, short, long pattern on the motors and buzzer tells the user it
+//! went out.
+
+use core::fmt::Write as FmtWrite;
+use heapless::String;
+
+use crate::broadcast::{self, LINE_LEN};
+use crate::config::HapticPattern;
+use crate::events::{self, Event};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::logging::{Scope, warn};
+use crate::owner;
+
+const SCOPE: Scope = Scope::System;
+
+// Short, short, long: nothing else sounds like it
+const CONFIRMATION: [u16; 5] = [100, 100, 100, 100, 500];
+// Broadcasts per request
+const BROADCASTS: u8 = 3;
+
+// The request as it goes out, with its newline
+pub fn line() -> String<LINE_LEN> {
+    let mut line = String::new();
+    let _ = FmtWrite::write_fmt(&mut line, format_args!("{}\n", owner::shared().assist_event()));
+    line
+}
+
+// Ask for help, from the button
+pub fn request() {
+    warn!("Assist requested");
+    events::publish(Event::AssistRequested);
+
+    broadcast::send(line(), BROADCASTS);
+
+    for target in [PreviewTarget::Both, PreviewTarget::Buzzer] {
+        feedback::submit(FeedbackCommand {
+            pattern: Pattern::Preview { steps: HapticPattern::new(&CONFIRMATION), target },
+            side: None,
+            priority: Priority::High,
+            ttl_ms: 1000,
+        });
+    }
+}
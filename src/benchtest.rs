@@ -0,0 +1,126 @@
+//! Bench test for checking a newly assembled unit.
+//!
+//! `BENCHTEST` on a command link (admin only) plays a fixed script on the
+//! actuators while the link gets the supply voltage as `BENCH` lines, so
+//! whoever assembled the unit hears and feels every output and sees whether
+//! the battery and the wiring hold up under load:
+//!
+//! 1. a second with everything off, for the resting voltage
+//! 2. the buzzer toggled at 500 Hz to 4 kHz in 500 Hz steps; a passive buzzer
+//!    rises in pitch, an active one rattles over its own tone
+//! 3. each motor output in turn, GPIO19 then GPIO20, ramped in eighths from a
+//!    duty of 32 to full
+//!
+//! The script goes through the feedback task like any other pattern, at
+//! critical priority so obstacle feedback waits for it, but drives the
+//! buzzer and the motors directly: no headset, side mapping, levels or kicks.
+//! The duty cap from supply sags (see [`supply`](crate::supply)) still holds.
+
+use core::cell::Cell;
+use embassy_rp::gpio::Output;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Timer};
+use vision_protocol::{BenchSample, BenchStep};
+
+use crate::feedback::{self, FeedbackCommand, Pattern, Priority};
+use crate::logging::{Scope, info};
+use crate::motor::PwmMotor;
+use crate::supply;
+
+const SCOPE: Scope = Scope::Feedback;
+
+const IDLE_MS: u64 = 1000;
+const SWEEP_FROM_HZ: u32 = 500;
+const SWEEP_STEP_HZ: u32 = 500;
+const SWEEP_STEPS: u32 = 8;
+const SWEEP_STEP_MS: u64 = 300;
+const RAMP_STEP: u8 = 32;
+const RAMP_STEPS: u8 = 8;
+const RAMP_STEP_MS: u64 = 400;
+// How long the feedback task may take to get to it
+const START_TTL_MS: u32 = 1000;
+
+// What the script is doing, None while it isn't running
+static STEP: Mutex<CriticalSectionRawMutex, Cell<Option<BenchStep>>> = Mutex::new(Cell::new(None));
+
+pub fn step() -> Option<BenchStep> {
+    STEP.lock(|step| step.get())
+}
+
+pub fn running() -> bool {
+    step().is_some()
+}
+
+fn set(step: BenchStep) {
+    STEP.lock(|current| current.set(Some(step)));
+}
+
+// Queue the script for the feedback task
+pub fn start() -> Result<(), &'static str> {
+    if running() {
+        return Err("bench test already running");
+    }
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::BenchTest,
+        side: None,
+        priority: Priority::Critical,
+        ttl_ms: START_TTL_MS,
+    });
+    Ok(())
+}
+
+// The current step with the latest VSYS sample
+pub fn sample() -> Option<BenchSample> {
+    let status = supply::status();
+    let millivolts = status.latest_v.filter(|_| status.measured).map(|volts| (volts * 1000.0) as u32);
+    Some(BenchSample { step: step()?, millivolts })
+}
+
+// Clears the step however the script ends, preempted included
+struct Running;
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        STEP.lock(|step| step.set(None));
+    }
+}
+
+// Played by the feedback task, which owns the outputs and switches them all
+// off again afterwards
+pub async fn run(buzzer: &mut Output<'static>, motors: &mut [PwmMotor; 2]) {
+    let _running = Running;
+    info!("Bench test started");
+
+    set(BenchStep::Idle);
+    Timer::after_millis(IDLE_MS).await;
+
+    for index in 0..SWEEP_STEPS {
+        let hz = SWEEP_FROM_HZ + index * SWEEP_STEP_HZ;
+        set(BenchStep::Buzzer { hz });
+        tone(buzzer, hz, SWEEP_STEP_MS).await;
+    }
+    buzzer.set_low();
+
+    for (output, motor) in motors.iter_mut().enumerate() {
+        for index in 1..=RAMP_STEPS {
+            let duty = (index as u16 * RAMP_STEP as u16).min(u8::MAX as u16) as u8;
+            set(BenchStep::Motor { output: output as u8, duty });
+            motor.set_duty(duty);
+            Timer::after_millis(RAMP_STEP_MS).await;
+        }
+        motor.set_duty(0);
+    }
+
+    info!("Bench test done");
+}
+
+// Square wave on the buzzer pin, timed by the executor so it is only roughly
+// at `hz` while other tasks run
+async fn tone(buzzer: &mut Output<'static>, hz: u32, duration_ms: u64) {
+    let half_period = Duration::from_micros(500_000 / hz as u64);
+    let end = Instant::now() + Duration::from_millis(duration_ms);
+    while Instant::now() < end {
+        buzzer.toggle();
+        Timer::after(half_period).await;
+    }
+}
@@ -0,0 +1,70 @@
+//! One-line UDP broadcasts on the access point's subnet.
+//!
+//! Assist requests and forwarded alerts go out this way, so an app on any
+//! phone or laptop joined to the access point hears them without keeping a
+//! connection open. Lines are queued and sent by [`broadcast_task`] from a
+//! single socket; UDP can drop a packet, so important lines are repeated.
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+use heapless::String;
+use vision_protocol::BROADCAST_UDP_PORT;
+
+use crate::logging::{Scope, debug, warn};
+
+const SCOPE: Scope = Scope::Web;
+
+pub const LINE_LEN: usize = 192;
+const QUEUE_DEPTH: usize = 4;
+// Between the repeats of one line
+const REPEAT_MS: u64 = 1000;
+
+struct Queued {
+    line: String<LINE_LEN>,
+    repeats: u8,
+}
+
+static QUEUE: Channel<CriticalSectionRawMutex, Queued, QUEUE_DEPTH> = Channel::new();
+
+// Queue a line (with its newline) to be sent `repeats` times, a second
+// apart. Dropped when the queue is full.
+pub fn send(line: String<LINE_LEN>, repeats: u8) {
+    // nobody would ever take it off the queue
+    if !cfg!(feature = "wifi") {
+        return;
+    }
+    if QUEUE.try_send(Queued { line, repeats }).is_err() {
+        warn!("Broadcast queue full, line dropped");
+    }
+}
+
+#[cfg_attr(not(feature = "wifi"), allow(dead_code))]
+#[embassy_executor::task]
+pub async fn broadcast_task(stack: &'static Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * LINE_LEN];
+    let mut socket = UdpSocket::new(*stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(BROADCAST_UDP_PORT) {
+        warn!("Failed to bind the broadcast socket: {:?}", e);
+        return;
+    }
+
+    let destination = (Ipv4Address::new(192, 168, 4, 255), BROADCAST_UDP_PORT);
+    loop {
+        let queued = QUEUE.receive().await;
+        for repeat in 0..queued.repeats {
+            if repeat > 0 {
+                Timer::after_millis(REPEAT_MS).await;
+            }
+            if let Err(e) = socket.send_to(queued.line.as_bytes(), destination).await {
+                warn!("Failed to broadcast: {:?}", e);
+            }
+        }
+        debug!("Broadcast on UDP port {}: {}", BROADCAST_UDP_PORT, queued.line.trim_end());
+    }
+}
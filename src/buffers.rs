@@ -0,0 +1,158 @@
+//! Shared buffers for network connections.
+//!
+//! Connection handlers used to keep their socket and request buffers in their
+//! own futures, a few KB per listener that sat there whether a client was
+//! connected or not. Now the web and TCP servers borrow a set from this static
+//! pool for each connection and hand it back when it closes. There is one set
+//! per listener, so running out means a handler leaked one; that is counted
+//! and logged, and the handler waits for a set to come back.
+
+use core::cell::RefCell;
+use core::ops::{Deref, DerefMut};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Timer;
+use heapless::Vec;
+use serde::Serialize;
+use static_cell::ConstStaticCell;
+
+use crate::config;
+use crate::logging::{Scope, warn};
+#[cfg(feature = "web")]
+use crate::web_server::WEB_SERVER_TASKS;
+
+const SCOPE: Scope = Scope::System;
+
+pub const SOCKET_RX_SIZE: usize = 1024;
+pub const SOCKET_TX_SIZE: usize = 4096;
+// Room for the request line and headers of a POST, or the command in front
+// of an `IMPORT` over TCP
+const HEADER_ROOM: usize = 1024;
+// Big enough for a config import of the largest export plus its headers
+pub const REQUEST_SIZE: usize = config::JSON_MAX_LEN + HEADER_ROOM;
+const _: () = assert!(
+    REQUEST_SIZE >= config::JSON_MAX_LEN + "POST /api/config/import HTTP/1.1\r\n".len(),
+    "a config export must fit in a request"
+);
+
+// Built without the web server, there are none of its listeners
+#[cfg(not(feature = "web"))]
+const WEB_SERVER_TASKS: usize = 0;
+
+// The web server listeners plus the TCP server
+pub const POOL_SIZE: usize = WEB_SERVER_TASKS + cfg!(feature = "tcp") as usize;
+
+// How often a handler looks again when the pool is empty
+const RETRY_MS: u64 = 100;
+
+pub struct ConnectionBuffers {
+    pub socket_rx: [u8; SOCKET_RX_SIZE],
+    pub socket_tx: [u8; SOCKET_TX_SIZE],
+    pub request: [u8; REQUEST_SIZE],
+}
+
+impl ConnectionBuffers {
+    const fn new() -> Self {
+        Self { socket_rx: [0; SOCKET_RX_SIZE], socket_tx: [0; SOCKET_TX_SIZE], request: [0; REQUEST_SIZE] }
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub struct PoolStats {
+    pub capacity: usize,
+    pub in_use: usize,
+    // Most sets in use at the same time since boot
+    pub peak: usize,
+    pub acquired: u32,
+    // Times a handler found the pool empty
+    pub exhausted: u32,
+}
+
+struct Pool {
+    free: Vec<&'static mut ConnectionBuffers, POOL_SIZE>,
+    stats: PoolStats,
+}
+
+static BUFFERS: ConstStaticCell<[ConnectionBuffers; POOL_SIZE]> =
+    ConstStaticCell::new([const { ConnectionBuffers::new() }; POOL_SIZE]);
+
+static POOL: Mutex<CriticalSectionRawMutex, RefCell<Pool>> = Mutex::new(RefCell::new(Pool {
+    free: Vec::new(),
+    stats: PoolStats { capacity: POOL_SIZE, in_use: 0, peak: 0, acquired: 0, exhausted: 0 },
+}));
+
+// Fill the pool, call once at startup before the servers are spawned
+#[cfg_attr(not(any(feature = "web", feature = "tcp")), allow(dead_code))]
+pub fn init() {
+    let buffers = BUFFERS.take();
+    POOL.lock(|pool| {
+        let mut pool = pool.borrow_mut();
+        for set in buffers {
+            let _ = pool.free.push(set);
+        }
+    });
+}
+
+// A set of buffers, back in the pool when dropped
+pub struct PooledBuffers {
+    buffers: Option<&'static mut ConnectionBuffers>,
+}
+
+impl Deref for PooledBuffers {
+    type Target = ConnectionBuffers;
+
+    fn deref(&self) -> &ConnectionBuffers {
+        self.buffers.as_deref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffers {
+    fn deref_mut(&mut self) -> &mut ConnectionBuffers {
+        self.buffers.as_deref_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffers {
+    fn drop(&mut self) {
+        if let Some(buffers) = self.buffers.take() {
+            POOL.lock(|pool| {
+                let mut pool = pool.borrow_mut();
+                let _ = pool.free.push(buffers);
+                pool.stats.in_use -= 1;
+            });
+        }
+    }
+}
+
+// Take a set of buffers, waiting for one if they are all in use
+#[cfg_attr(not(any(feature = "web", feature = "tcp")), allow(dead_code))]
+pub async fn acquire() -> PooledBuffers {
+    let mut waiting = false;
+    loop {
+        let buffers = POOL.lock(|pool| {
+            let mut pool = pool.borrow_mut();
+            let buffers = pool.free.pop();
+            let stats = &mut pool.stats;
+            if buffers.is_some() {
+                stats.acquired += 1;
+                stats.in_use += 1;
+                stats.peak = stats.peak.max(stats.in_use);
+            } else if !waiting {
+                stats.exhausted += 1;
+            }
+            buffers
+        });
+
+        if let Some(buffers) = buffers {
+            return PooledBuffers { buffers: Some(buffers) };
+        }
+        if !waiting {
+            warn!("Connection buffer pool exhausted, waiting for a free set");
+            waiting = true;
+        }
+        Timer::after_millis(RETRY_MS).await;
+    }
+}
+
+pub fn stats() -> PoolStats {
+    POOL.lock(|pool| pool.borrow().stats)
+}
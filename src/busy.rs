@@ -0,0 +1,188 @@
+//! Turning clients away when every listener is taken.
+//!
+//! The web and TCP servers have a fixed number of listeners. With all of
+//! them in a connection nothing listens on the port, and a new client's SYN
+//! gets a bare reset, which browsers and apps report as the device being
+//! gone. So each server counts its connections in a [`Listeners`], and while
+//! they are all taken [`busy_task`] listens on the port with a small socket
+//! of its own, answers with a refusal (a 503 with Retry-After from the web
+//! server, a BUSY line from the TCP server) and closes. Refusals and the most
+//! connections at once are in `GET /api/metrics`.
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use embassy_futures::select::{Either, select};
+use embassy_net::{Stack, tcp::TcpSocket};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::Write;
+use heapless::String;
+use serde::Serialize;
+#[cfg(feature = "tcp")]
+use vision_protocol::{Busy, TCP_PORT};
+
+#[cfg(feature = "web")]
+use crate::http;
+use crate::logging::{Scope, warn};
+use crate::transport;
+#[cfg(feature = "web")]
+use crate::web_server::WEB_SERVER_TASKS;
+
+const SCOPE: Scope = Scope::System;
+
+// What refused clients are told to wait before trying again
+const RETRY_AFTER_S: u8 = 2;
+// The refusal fits the socket's buffer, this is only for a client that vanished
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+const SOCKET_RX_SIZE: usize = 64;
+const SOCKET_TX_SIZE: usize = 256;
+
+// One per server
+pub const TASKS: usize = cfg!(feature = "web") as usize + cfg!(feature = "tcp") as usize;
+
+#[derive(Clone, Copy, Serialize)]
+pub struct Stats {
+    pub active: usize,
+    // Most connections at the same time since boot
+    pub peak: usize,
+    // Clients turned away because every listener was taken
+    pub refused: u32,
+}
+
+pub struct Listeners {
+    capacity: usize,
+    active: AtomicUsize,
+    peak: AtomicUsize,
+    refused: AtomicU32,
+    // a listener was taken or freed, for the busy task
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+#[cfg(feature = "web")]
+pub static WEB: Listeners = Listeners::new(WEB_SERVER_TASKS);
+#[cfg(feature = "tcp")]
+pub static TCP: Listeners = Listeners::new(1);
+
+impl Listeners {
+    const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            active: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            refused: AtomicU32::new(0),
+            changed: Signal::new(),
+        }
+    }
+
+    // A listener accepted a connection, it is free again when the guard drops
+    pub fn take(&'static self) -> Taken {
+        let active = self.active.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(active, Ordering::Relaxed);
+        self.changed.signal(());
+        Taken { listeners: self }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            active: self.active.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            refused: self.refused.load(Ordering::Relaxed),
+        }
+    }
+
+    fn all_taken(&self) -> bool {
+        self.active.load(Ordering::Relaxed) >= self.capacity
+    }
+
+    async fn wait_until(&self, all_taken: bool) {
+        while self.all_taken() != all_taken {
+            self.changed.wait().await;
+        }
+    }
+}
+
+pub struct Taken {
+    listeners: &'static Listeners,
+}
+
+impl Drop for Taken {
+    fn drop(&mut self) {
+        self.listeners.active.fetch_sub(1, Ordering::Relaxed);
+        self.listeners.changed.signal(());
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Server {
+    #[cfg(feature = "web")]
+    Web,
+    #[cfg(feature = "tcp")]
+    Tcp,
+}
+
+impl Server {
+    fn listeners(self) -> &'static Listeners {
+        match self {
+            #[cfg(feature = "web")]
+            Server::Web => &WEB,
+            #[cfg(feature = "tcp")]
+            Server::Tcp => &TCP,
+        }
+    }
+
+    fn port(self) -> u16 {
+        match self {
+            #[cfg(feature = "web")]
+            Server::Web => 80,
+            #[cfg(feature = "tcp")]
+            Server::Tcp => TCP_PORT,
+        }
+    }
+
+    fn refusal(self) -> String<SOCKET_TX_SIZE> {
+        let mut refusal = String::new();
+        match self {
+            #[cfg(feature = "web")]
+            Server::Web => {
+                http::write_headers_with(
+                    &mut refusal,
+                    "503 Service Unavailable",
+                    "text/plain",
+                    format_args!("Retry-After: {}\r\n", RETRY_AFTER_S),
+                );
+                let _ = refusal.push_str("Too many connections, try again shortly");
+            }
+            #[cfg(feature = "tcp")]
+            Server::Tcp => {
+                let _ = core::fmt::Write::write_fmt(&mut refusal, format_args!("{}\n", Busy { retry_after_s: RETRY_AFTER_S }));
+            }
+        }
+        refusal
+    }
+}
+
+// Answers the clients `server` has no listener left for
+#[embassy_executor::task(pool_size = TASKS)]
+pub async fn busy_task(stack: &'static Stack<'static>, server: Server) {
+    let listeners = server.listeners();
+    let mut socket_rx = [0; SOCKET_RX_SIZE];
+    let mut socket_tx = [0; SOCKET_TX_SIZE];
+    loop {
+        // listening any earlier would take clients a listener could serve
+        listeners.wait_until(true).await;
+
+        let mut socket = TcpSocket::new(*stack, &mut socket_rx, &mut socket_tx);
+        match select(socket.accept(server.port()), listeners.wait_until(false)).await {
+            Either::First(Ok(())) => {
+                let refused = listeners.refused.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("No listener free on port {}, turned a client away ({} so far)", server.port(), refused);
+                let _ = with_timeout(WRITE_TIMEOUT, socket.write_all(server.refusal().as_bytes())).await;
+                transport::close(&mut socket).await;
+            }
+            Either::First(Err(e)) => warn!("Failed to accept connection: {:?}", e),
+            // a listener is free again, it takes the next client
+            Either::Second(()) => socket.abort(),
+        }
+    }
+}
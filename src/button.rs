@@ -0,0 +1,124 @@
+//! User button (GPIO21 to ground). A double press cycles to the next profile,
+//! a triple press asks for help (see [`assist`](crate::assist)), holding it
+//! for three seconds switches WiFi off or back on. Chords ending in a press
+//! held for about a second change the master levels (see
+//! [`levels`](crate::levels)): press then hold for up, press twice then hold
+//! for down. While the locator tone plays, any press just stops it.
+
+use embassy_rp::gpio::Input;
+use embassy_time::{Duration, Timer, with_timeout};
+
+use crate::assist;
+use crate::levels;
+use crate::locate;
+use crate::logging::{Scope, debug, warn};
+use crate::profiles;
+use crate::radio;
+
+const SCOPE: Scope = Scope::Sensing;
+
+// Contacts settle well within this
+const DEBOUNCE_MS: u64 = 30;
+// The next press has to start this soon after the last one was released
+const MULTI_PRESS_MS: u64 = 400;
+// Held this long, and let go, ends a level chord
+const HOLD_MS: u64 = 800;
+// Held this long counts as a long press, long enough not to happen by accident
+const LONG_PRESS_MS: u64 = 3000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Press {
+    Short,
+    // Held for a while, then released
+    Held,
+    // Still down after LONG_PRESS_MS
+    Long,
+}
+
+// Wait for a full press and release, ignoring contact bounce. A long press
+// returns as soon as it is one, with the button still down.
+async fn press(button: &mut Input<'static>) -> Press {
+    loop {
+        button.wait_for_low().await;
+        Timer::after_millis(DEBOUNCE_MS).await;
+        if button.is_low() {
+            break;
+        }
+    }
+    let press = if with_timeout(Duration::from_millis(HOLD_MS), button.wait_for_high()).await.is_ok() {
+        Press::Short
+    } else if with_timeout(Duration::from_millis(LONG_PRESS_MS - HOLD_MS), button.wait_for_high()).await.is_ok() {
+        Press::Held
+    } else {
+        return Press::Long;
+    };
+    Timer::after_millis(DEBOUNCE_MS).await;
+    press
+}
+
+// Switch WiFi while the button is still held, so the cue comes right away
+async fn long_press(button: &mut Input<'static>) {
+    debug!("Button long press");
+    if let Err(e) = radio::toggle() {
+        warn!("Failed to switch WiFi: {}", e);
+    }
+    button.wait_for_high().await;
+    Timer::after_millis(DEBOUNCE_MS).await;
+}
+
+#[embassy_executor::task]
+pub async fn button_task(mut button: Input<'static>) {
+    loop {
+        let long = press(&mut button).await == Press::Long;
+        if locate::active() {
+            debug!("Button press, locator tone stopped");
+            locate::stop();
+            if long {
+                button.wait_for_high().await;
+                Timer::after_millis(DEBOUNCE_MS).await;
+            }
+            continue;
+        }
+        if long {
+            long_press(&mut button).await;
+            continue;
+        }
+
+        // count the presses that follow quickly, a double press waits to see
+        // whether a third one comes. A held one ends a chord.
+        let mut count = 1;
+        let mut last = Press::Short;
+        while count < 3 && with_timeout(Duration::from_millis(MULTI_PRESS_MS), button.wait_for_low()).await.is_ok() {
+            last = press(&mut button).await;
+            if last == Press::Long {
+                break;
+            }
+            count += 1;
+            if last == Press::Held {
+                break;
+            }
+        }
+        if last == Press::Long {
+            long_press(&mut button).await;
+            continue;
+        }
+        if last == Press::Held {
+            debug!("Button chord of {} presses", count);
+            levels::step(count == 2);
+            continue;
+        }
+
+        match count {
+            // single presses aren't used yet
+            1 => debug!("Button single press"),
+            2 => {
+                debug!("Button double press");
+                profiles::cycle();
+            }
+            _ => {
+                debug!("Button triple press");
+                assist::request();
+            }
+        }
+    }
+}
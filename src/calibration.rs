@@ -0,0 +1,362 @@
+//! Multi-point distance calibration against a measuring stick.
+//!
+//! The wizard walks through four reference distances (30, 60, 100 and
+//! 200 cm). For each, the user puts a flat target at that distance in front
+//! of the sensors and asks for a capture; the sensing loop then hands over
+//! its readings until every sensor has [`SAMPLES_PER_POINT`] of them. After
+//! the last point each sensor gets a gain and offset fitted by least
+//! squares, `true = gain * measured + offset`. A fit is only stored (in its
+//! own flash sector) and used when every sensor's RMS residual stays within
+//! [`MAX_RESIDUAL_CM`]; otherwise the wizard reports the residuals and the
+//! old calibration stays. Every enabled channel takes part except the rear
+//! one.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::channels;
+use crate::config;
+use crate::events::Side;
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Sensing;
+
+// Change when the stored layout changes, old data is then ignored
+const CALIBRATION_MAGIC: u32 = 0x4341_4c32;
+const HEADER_LEN: usize = 6;
+
+// Reference distances the target goes to, in order
+pub const POINTS_CM: [f32; 4] = [30.0, 60.0, 100.0, 200.0];
+pub const SAMPLES_PER_POINT: u16 = 40;
+// A capture that can't collect its samples in this long fails
+const CAPTURE_TIMEOUT_MS: u64 = 20_000;
+// Worst RMS residual of a fit that still gets stored
+pub const MAX_RESIDUAL_CM: f32 = 2.0;
+// Gains outside this range mean a wrong target rather than a sensor to correct
+const GAIN_RANGE: core::ops::RangeInclusive<f32> = 0.8..=1.25;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Fit {
+    pub gain: f32,
+    pub offset_cm: f32,
+    // RMS distance of the points from the fitted line
+    pub residual_cm: f32,
+}
+
+impl Fit {
+    const IDENTITY: Fit = Fit { gain: 1.0, offset_cm: 0.0, residual_cm: 0.0 };
+
+    fn correct(&self, distance_cm: f32) -> f32 {
+        self.gain * distance_cm + self.offset_cm
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Idle,
+    // Waiting for the target to be placed at this point
+    Waiting { point: usize },
+    Capturing { point: usize, started_ms: u64 },
+    // The new fits were stored
+    Stored,
+    // The last run didn't produce a usable fit
+    Failed { reason: &'static str },
+}
+
+impl Step {
+    pub fn name(self) -> &'static str {
+        match self {
+            Step::Idle => "idle",
+            Step::Waiting { .. } => "waiting",
+            Step::Capturing { .. } => "capturing",
+            Step::Stored => "stored",
+            Step::Failed { .. } => "failed",
+        }
+    }
+}
+
+// Readings collected for one point on one sensor
+#[derive(Clone, Copy)]
+struct Samples {
+    sum_cm: f32,
+    count: u16,
+}
+
+impl Samples {
+    const EMPTY: Samples = Samples { sum_cm: 0.0, count: 0 };
+}
+
+struct Wizard {
+    step: Step,
+    // Channels taking part in this run, the enabled ones looking forward
+    channels: [bool; channels::COUNT],
+    samples: [[Samples; channels::COUNT]; POINTS_CM.len()],
+    candidate: Option<[Fit; channels::COUNT]>,
+}
+
+struct Calibration {
+    fits: [Fit; channels::COUNT],
+    wizard: Wizard,
+}
+
+static CALIBRATION: Mutex<CriticalSectionRawMutex, RefCell<Calibration>> = Mutex::new(RefCell::new(Calibration {
+    fits: [Fit::IDENTITY; channels::COUNT],
+    wizard: Wizard {
+        step: Step::Idle,
+        channels: [false; channels::COUNT],
+        samples: [[Samples::EMPTY; channels::COUNT]; POINTS_CM.len()],
+        candidate: None,
+    },
+}));
+
+// Read the stored fits, call once at startup
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::CALIBRATION, &mut buffer) {
+        warn!("Failed to read calibration: {}", e);
+        return;
+    }
+    if let Some(fits) = decode(&buffer) {
+        info!(
+            "Calibration: left x{} + {} cm, right x{} + {} cm",
+            fits[0].gain, fits[0].offset_cm, fits[1].gain, fits[1].offset_cm
+        );
+        CALIBRATION.lock(|calibration| calibration.borrow_mut().fits = fits);
+    }
+}
+
+fn decode(buffer: &[u8]) -> Option<[Fit; channels::COUNT]> {
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = u16::from_le_bytes(buffer[4..HEADER_LEN].try_into().ok()?) as usize;
+    if magic != CALIBRATION_MAGIC || HEADER_LEN + len > buffer.len() {
+        return None;
+    }
+    postcard::from_bytes(&buffer[HEADER_LEN..HEADER_LEN + len]).ok()
+}
+
+fn save(fits: &[Fit; channels::COUNT]) -> Result<(), &'static str> {
+    let mut buffer = [0xFF; ERASE_SIZE];
+    let len = postcard::to_slice(fits, &mut buffer[HEADER_LEN..])
+        .map_err(|_| "Calibration too large")?
+        .len();
+    buffer[..4].copy_from_slice(&CALIBRATION_MAGIC.to_le_bytes());
+    buffer[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    storage::write_sector(partitions::CALIBRATION, &buffer)
+}
+
+// Fits in use, per sensor
+pub fn fits() -> [(Side, Fit); channels::COUNT] {
+    let fits = CALIBRATION.lock(|calibration| calibration.borrow().fits);
+    Side::ALL.map(|side| (side, fits[side as usize]))
+}
+
+pub struct Status {
+    pub step: Step,
+    // Samples so far at the point being captured, of the sensor furthest behind
+    pub samples: u16,
+    // Fits of the last run, also when they weren't stored
+    pub candidate: Option<[(Side, Fit); channels::COUNT]>,
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn status() -> Status {
+    CALIBRATION.lock(|calibration| {
+        let wizard = &calibration.borrow().wizard;
+        let samples = match wizard.step {
+            Step::Capturing { point, .. } => Side::ALL
+                .iter()
+                .filter(|side| wizard.takes_part(**side))
+                .map(|side| wizard.samples[point][*side as usize].count)
+                .min()
+                .unwrap_or(0),
+            _ => 0,
+        };
+        let candidate = wizard.candidate.map(|fits| Side::ALL.map(|side| (side, fits[side as usize])));
+        Status { step: wizard.step, samples, candidate }
+    })
+}
+
+// Start over at the first point
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn start() {
+    let config = config::get();
+    // the rear sensor can't see a target in front, and only pings when reversing
+    let taking_part = Side::ALL.map(|side| channels::placement(side).enabled(&config) && !channels::placement(side).rear());
+    CALIBRATION.lock(|calibration| {
+        calibration.borrow_mut().wizard = Wizard {
+            step: Step::Waiting { point: 0 },
+            channels: taking_part,
+            samples: [[Samples::EMPTY; channels::COUNT]; POINTS_CM.len()],
+            candidate: None,
+        };
+    });
+    info!("Calibration started, place the target at {} cm", POINTS_CM[0]);
+}
+
+// The target is in place, collect the readings for the current point
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn capture() -> Result<(), &'static str> {
+    let started_ms = Instant::now().as_millis();
+    CALIBRATION.lock(|calibration| {
+        let wizard = &mut calibration.borrow_mut().wizard;
+        let Step::Waiting { point } = wizard.step else {
+            return Err("Calibration isn't waiting for a target");
+        };
+        wizard.samples[point] = [Samples::EMPTY; channels::COUNT];
+        wizard.step = Step::Capturing { point, started_ms };
+        Ok(())
+    })
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn cancel() {
+    CALIBRATION.lock(|calibration| calibration.borrow_mut().wizard.step = Step::Idle);
+    info!("Calibration cancelled");
+}
+
+// Back to uncorrected readings
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn reset() -> Result<(), &'static str> {
+    let fits = [Fit::IDENTITY; channels::COUNT];
+    save(&fits)?;
+    CALIBRATION.lock(|calibration| calibration.borrow_mut().fits = fits);
+    info!("Calibration reset");
+    Ok(())
+}
+
+// For the sensing loop: count a raw reading towards a running capture and
+// return it corrected
+pub fn process(side: Side, reading: Result<f32, &'static str>) -> Result<f32, &'static str> {
+    let (fit, finished) = CALIBRATION.lock(|calibration| {
+        let mut calibration = calibration.borrow_mut();
+        let fit = calibration.fits[side as usize];
+        let finished = calibration.wizard.record(side, reading);
+        (fit, finished)
+    });
+    if finished {
+        finish();
+    }
+
+    // blind zone contact stays what it is
+    reading.map(|distance_cm| if distance_cm < crate::MIN_DETECTABLE_DISTANCE { distance_cm } else { fit.correct(distance_cm) })
+}
+
+impl Wizard {
+    fn takes_part(&self, side: Side) -> bool {
+        self.channels[side as usize]
+    }
+
+    // Returns true once the last point has been captured
+    fn record(&mut self, side: Side, reading: Result<f32, &'static str>) -> bool {
+        let Step::Capturing { point, started_ms } = self.step else {
+            return false;
+        };
+        if !self.takes_part(side) {
+            return false;
+        }
+
+        let samples = &mut self.samples[point][side as usize];
+        match reading {
+            Ok(distance_cm) if distance_cm >= crate::MIN_DETECTABLE_DISTANCE && samples.count < SAMPLES_PER_POINT => {
+                samples.sum_cm += distance_cm;
+                samples.count += 1;
+            }
+            _ => {}
+        }
+
+        let complete = Side::ALL
+            .iter()
+            .filter(|side| self.takes_part(**side))
+            .all(|side| self.samples[point][*side as usize].count >= SAMPLES_PER_POINT);
+        if !complete {
+            if Instant::now().as_millis() > started_ms + CAPTURE_TIMEOUT_MS {
+                warn!("Calibration point {} cm timed out", POINTS_CM[point]);
+                self.step = Step::Failed { reason: "not enough valid readings, check the target" };
+            }
+            return false;
+        }
+
+        info!("Calibration point {} cm captured", POINTS_CM[point]);
+        if point + 1 < POINTS_CM.len() {
+            self.step = Step::Waiting { point: point + 1 };
+            info!("Place the target at {} cm", POINTS_CM[point + 1]);
+            return false;
+        }
+        true
+    }
+
+    // Least squares line through the mean readings of one sensor
+    fn fit(&self, side: Side) -> Fit {
+        let n = POINTS_CM.len() as f32;
+        let (mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0);
+        for (point, true_cm) in POINTS_CM.iter().enumerate() {
+            let samples = self.samples[point][side as usize];
+            let x = samples.sum_cm / samples.count as f32;
+            sx += x;
+            sy += true_cm;
+            sxx += x * x;
+            sxy += x * true_cm;
+        }
+        let gain = (n * sxy - sx * sy) / (n * sxx - sx * sx);
+        let offset_cm = (sy - gain * sx) / n;
+
+        let mut squares = 0.0;
+        for (point, true_cm) in POINTS_CM.iter().enumerate() {
+            let samples = self.samples[point][side as usize];
+            let error = gain * (samples.sum_cm / samples.count as f32) + offset_cm - true_cm;
+            squares += error * error;
+        }
+        Fit { gain, offset_cm, residual_cm: libm::sqrtf(squares / n) }
+    }
+}
+
+// Fit every sensor and store the result if it's good enough
+fn finish() {
+    let (fits, taking_part) = CALIBRATION.lock(|calibration| {
+        let calibration = calibration.borrow();
+        let wizard = &calibration.wizard;
+        let mut fits = calibration.fits;
+        for side in Side::ALL.into_iter().filter(|side| wizard.takes_part(*side)) {
+            fits[side as usize] = wizard.fit(side);
+        }
+        (fits, wizard.channels)
+    });
+
+    let mut result = Ok(());
+    for side in Side::ALL.into_iter().filter(|side| taking_part[*side as usize]) {
+        let fit = fits[side as usize];
+        info!(
+            "Calibration {}: x{} + {} cm, residual {} cm",
+            side.name(),
+            fit.gain,
+            fit.offset_cm,
+            fit.residual_cm
+        );
+        if !GAIN_RANGE.contains(&fit.gain) {
+            result = Err("gain out of range, check the target and the measurements");
+        } else if fit.residual_cm > MAX_RESIDUAL_CM {
+            result = Err("residual too large, check the target and the measurements");
+        }
+    }
+    let result = result.and_then(|()| save(&fits));
+
+    CALIBRATION.lock(|calibration| {
+        let mut calibration = calibration.borrow_mut();
+        calibration.wizard.candidate = Some(fits);
+        match result {
+            Ok(()) => {
+                calibration.fits = fits;
+                calibration.wizard.step = Step::Stored;
+            }
+            Err(reason) => calibration.wizard.step = Step::Failed { reason },
+        }
+    });
+    match result {
+        Ok(()) => info!("Calibration stored"),
+        Err(e) => warn!("Calibration not stored: {}", e),
+    }
+}
@@ -0,0 +1,109 @@
+//! The device's distance channels and where they point.
+//!
+//! Everything after the sensors (pipeline, zone state, feedback, the
+//! servers and the protocol) keeps its per channel state in arrays indexed
+//! by [`Side`], in [`Side::ALL`] order, and asks [`LAYOUT`] what it needs to
+//! know about a channel instead of having a field per sensor: which way it
+//! looks, whether it watches for head height obstacles or behind the user
+//! and which motors it is felt on (see [`Motors::towards`]). A build with another sensor arrangement changes the table
+//! here and the pins in rangefinder.rs.
+
+use crate::config::{Config, Mounting};
+use crate::defaults::SENSOR_KINDS;
+use crate::events::Side;
+
+pub const COUNT: usize = Side::ALL.len();
+
+// Channels aimed more than this far up watch for head height obstacles,
+// with zones and a feedback pattern of their own
+const HEAD_HEIGHT_DEG: f32 = 20.0;
+// Obstacles within this of straight ahead are felt on both motors
+const AHEAD_DEG: f32 = 5.0;
+// Channels aimed further round than this look behind the user
+const REAR_DEG: f32 = 90.0;
+
+// Where a sensor is aimed, seen from the user
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub side: Side,
+    pub bearing_deg: f32,    // 0 is straight ahead, negative to the left
+    pub elevation_deg: f32,  // 0 is level, positive up
+}
+
+// The wearable's sensors: a level pair turned outwards, one angled up and,
+// on a wheelchair, one looking back
+pub const LAYOUT: [Placement; COUNT] = [
+    Placement { side: Side::Left, bearing_deg: -15.0, elevation_deg: 0.0 },
+    Placement { side: Side::Right, bearing_deg: 15.0, elevation_deg: 0.0 },
+    Placement { side: Side::Overhead, bearing_deg: 0.0, elevation_deg: 30.0 },
+    Placement { side: Side::Rear, bearing_deg: 180.0, elevation_deg: 0.0 },
+];
+
+// Which vibration motors obstacles in some direction are felt on
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Motors {
+    Left,
+    Right,
+    Both,
+}
+
+impl Motors {
+    pub fn towards(bearing_deg: f32) -> Self {
+        if bearing_deg < -AHEAD_DEG {
+            Motors::Left
+        } else if bearing_deg > AHEAD_DEG {
+            Motors::Right
+        } else {
+            Motors::Both
+        }
+    }
+}
+
+impl Placement {
+    pub fn head_height(&self) -> bool {
+        self.elevation_deg > HEAD_HEIGHT_DEG
+    }
+
+    pub fn rear(&self) -> bool {
+        libm::fabsf(self.bearing_deg) > REAR_DEG
+    }
+
+    // Level and looking forward, what the occupancy map and the side
+    // feedback are made of
+    pub fn ahead(&self) -> bool {
+        !self.head_height() && !self.rear()
+    }
+
+    // Depends on the kind of sensor the board has on the channel
+    pub fn beam_half_angle_deg(&self) -> f32 {
+        SENSOR_KINDS[self.side as usize].beam_half_angle_deg()
+    }
+
+    // Head height channels are optional, without the sensor every cycle
+    // would wait for its echo timeout. Rear channels only exist on a wheelchair.
+    pub fn enabled(&self, config: &Config) -> bool {
+        if self.rear() {
+            return config.mounting.mode == Mounting::Wheelchair;
+        }
+        !self.head_height() || config.overhead.enabled
+    }
+
+    // Whether the sensing loop reads the channel this cycle: rear channels
+    // only while the chair is reversing
+    pub fn sensing(&self, config: &Config, reversing: bool) -> bool {
+        self.enabled(config) && (reversing || !self.rear())
+    }
+}
+
+pub fn placement(side: Side) -> &'static Placement {
+    &LAYOUT[side as usize]
+}
+
+// The level channels furthest to the left and right, which the wall geometry
+// correction works on. None with fewer than two level channels.
+pub fn level_pair() -> Option<(Side, Side)> {
+    let level = || LAYOUT.iter().filter(|placement| placement.ahead());
+    let left = level().min_by(|a, b| a.bearing_deg.total_cmp(&b.bearing_deg))?;
+    let right = level().max_by(|a, b| a.bearing_deg.total_cmp(&b.bearing_deg))?;
+    (left.side != right.side).then_some((left.side, right.side))
+}
@@ -0,0 +1,70 @@
+//! Who has been talking to the device.
+//!
+//! Every connection to the web or TCP server is recorded by client address,
+//! so the dashboard and `/api/clients` can show who is attached. The table is
+//! bounded; when it is full the client seen longest ago makes room.
+
+use core::cell::RefCell;
+use embassy_net::IpAddress;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::Vec;
+
+pub const MAX_CLIENTS: usize = 8;
+// Clients seen within this long count as connected
+pub const ACTIVE_MS: u64 = 60_000;
+
+#[derive(Clone, Copy)]
+pub struct Client {
+    pub address: IpAddress,
+    // Port of the server it last connected to
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub port: u16,
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    pub connections: u32,
+}
+
+impl Client {
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn is_active(&self, now_ms: u64) -> bool {
+        now_ms.saturating_sub(self.last_seen_ms) < ACTIVE_MS
+    }
+}
+
+static CLIENTS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Client, MAX_CLIENTS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+// Note a connection from `address` to the server on `port`
+pub fn record(address: IpAddress, port: u16, now_ms: u64) {
+    CLIENTS.lock(|clients| {
+        let mut clients = clients.borrow_mut();
+
+        if let Some(client) = clients.iter_mut().find(|client| client.address == address) {
+            client.port = port;
+            client.last_seen_ms = now_ms;
+            client.connections = client.connections.saturating_add(1);
+            return;
+        }
+
+        if clients.is_full() {
+            let oldest = clients
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, client)| client.last_seen_ms)
+                .map(|(i, _)| i);
+            if let Some(i) = oldest {
+                clients.swap_remove(i);
+            }
+        }
+        let _ = clients.push(Client { address, port, first_seen_ms: now_ms, last_seen_ms: now_ms, connections: 1 });
+    });
+}
+
+// Copy of the table, most recently seen first
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn list() -> Vec<Client, MAX_CLIENTS> {
+    let mut clients = CLIENTS.lock(|clients| clients.borrow().clone());
+    clients.sort_unstable_by(|a, b| b.last_seen_ms.cmp(&a.last_seen_ms));
+    clients
+}
@@ -0,0 +1,38 @@
+//! The device's time, for clients to put what it tells them in order.
+//!
+//! Every status line, HTTP response and pairing frame carries the uptime in
+//! ms; a client that sees it go backwards knows the device restarted. The
+//! wall clock is only known once someone tells it: the access point has no
+//! internet to reach an NTP server through, so a companion app sends its
+//! phone's time with `POST /api/clock`. Until then, and after every boot,
+//! [`unix_s`] is None.
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+
+// Anything before 2024 is a phone that doesn't know the time either
+const EARLIEST_UNIX_S: u64 = 1_704_067_200;
+
+// Wall clock time of the boot in ms since 1970, None until set
+static BOOT_UNIX_MS: Mutex<CriticalSectionRawMutex, Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+pub fn uptime_ms() -> u64 {
+    Instant::now().as_millis()
+}
+
+// Seconds since 1970, None until a client set the clock
+pub fn unix_s() -> Option<u64> {
+    let boot_ms = BOOT_UNIX_MS.lock(Cell::get)?;
+    Some((boot_ms + uptime_ms()) / 1000)
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn set(unix_s: u64) -> Result<(), &'static str> {
+    if unix_s < EARLIEST_UNIX_S {
+        return Err("Time must be in seconds since 1970, 2024 or later");
+    }
+    let boot_ms = (unix_s * 1000).saturating_sub(uptime_ms());
+    BOOT_UNIX_MS.lock(|boot| boot.set(Some(boot_ms)));
+    Ok(())
+}
@@ -0,0 +1,535 @@
+//! The command dispatcher shared by every link.
+//!
+//! TCP, USB serial and the BLE UART bridge all hand their requests to
+//! [`handle`] through the [`Transport`] trait, so every command (status,
+//! thresholds, stream, config transfer, ...) works the same on all of them.
+//! Command handling still logs under the `tcp` scope it started out in.
+//!
+//! Each link keeps a [`Session`] with the role it authenticated to. Viewers
+//! can read telemetry and the configuration; changing anything needs the
+//! admin role, so a caregiver's monitoring app can't retune the device by
+//! accident.
+
+use core::fmt::Write as FmtWrite;
+use heapless::String;
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use vision_protocol::{
+    Capabilities, Command, ParseError, Role, TrendName, Version, Welcome, ZoneEvent, ZoneName,
+    BENCH_SAMPLE_MS, MAX_STREAM_BATCH, MAX_STREAM_HZ,
+};
+use vision_protocol::zoning::Zone;
+
+use crate::assist;
+use crate::benchtest;
+use crate::broadcast;
+use crate::buffers::REQUEST_SIZE;
+use crate::config;
+use crate::defaults::{ADMIN_TOKEN, QA_TOKEN};
+use crate::echoes;
+use crate::events::{self, Event, Side};
+use crate::faults;
+use crate::locate;
+use crate::logging::{Scope, info, warn};
+use crate::radio;
+use crate::secrets;
+use crate::shutdown;
+use crate::simulation;
+use crate::telemetry;
+use crate::transport::Transport;
+use crate::zone_state::{self, Trend};
+
+const SCOPE: Scope = Scope::Tcp;
+
+// One line answering a command
+type Reply = String<96>;
+
+// The BLE module doesn't say when a phone goes away, so an admin session
+// falls back to viewer after this long without a command
+const ADMIN_IDLE_MS: u64 = 10 * 60 * 1000;
+
+// What a link is allowed to do, from connect (or the last fall back) on
+pub struct Session {
+    role: Role,
+    last_command: Instant,
+}
+
+impl Session {
+    // Builds without an admin token have nothing to authenticate against,
+    // every session may do everything like before roles existed
+    pub fn new() -> Self {
+        let role = match ADMIN_TOKEN {
+            Some(_) => Role::Viewer,
+            None => Role::Admin,
+        };
+        Self { role, last_command: Instant::now() }
+    }
+
+    // The role for the next command, dropping an idle admin back to viewer
+    fn role(&mut self) -> Role {
+        if self.role == Role::Admin && ADMIN_TOKEN.is_some() && self.last_command.elapsed().as_millis() > ADMIN_IDLE_MS {
+            info!("Admin session idle, back to viewer");
+            self.role = Role::Viewer;
+        }
+        self.last_command = Instant::now();
+        self.role
+    }
+}
+
+// Simple line protocol (see vision_protocol::Command), one command per
+// connection on TCP and one per line on the serial links:
+//   STATUS                         -> "L:<cm> R:<cm> NL:<noise> NR:<noise> ZS:<zone scale> AGE:<ms>"
+//   SET <zone> <enter_cm> [exit_cm] -> "OK" or "ERR <reason>", zone is critical/warning/notice
+//   STREAM <hz> [BATCH=<n>] [FLUSH=<ms>]
+//                                  -> status lines at up to 20 Hz until the client disconnects,
+//                                     with "ZONE <side> <zone> <cm> <trend>" lines as zones change
+//                                     and "ASSIST <device>" when the user asks for help
+//   EXPORT                         -> the whole configuration as one line of JSON
+//   IMPORT <json>                  -> "OK" or "ERR <reason>", replaces the whole configuration
+//   SIMULATE L=<cm> R=<cm> OH=<cm> DURATION=<time> TOKEN=<token>
+//                                  -> "OK" or "ERR <reason>", fakes sensor readings for testing
+//   RADIO ON|OFF                   -> "OK" or "ERR <reason>", OFF also ends this connection
+//   HELLO <major>.<minor> [CAPS=..] -> "WELCOME <major>.<minor> CAPS=<name>,..." or "ERR unsupported protocol ..."
+//   AUTH <token>                   -> "OK admin" or "ERR bad token", on TCP the next command follows on
+//                                     the same connection
+//   SHUTDOWN                       -> "OK", then the device saves the session and powers down
+//   REBOOT [SAFE]                  -> "OK", then the device saves the session and restarts, with SAFE
+//                                     on default settings without optional subsystems
+//   LOCATE [ON|OFF]                -> "OK", the locator tone plays until the button is pressed or OFF
+//   ECHOES ON|OFF                  -> "OK", starts or stops recording the echo pin of every ping
+//   ECHOES                         -> "ECHO <seq> <side> <at_ms> <us>,..." per recorded ping, then "OK <count>"
+//   FAULT SENSOR [<side>] <count> | SOCKET <count> | FLASH <count> | HANG | CLEAR
+//                                  -> "OK" or "ERR <reason>", makes things fail on purpose (see faults.rs)
+//   BENCHTEST                      -> "BENCH <step> <mV>" every 100 ms while the actuator script plays
+//                                     (see benchtest.rs), then "OK"
+// Anything else gets the status line, which is what older clients expect.
+// SET, IMPORT, SIMULATE, RADIO, SHUTDOWN, REBOOT, LOCATE, ECHOES ON|OFF, FAULT and BENCHTEST need the admin role (see `Session`).
+// Returns whether to keep serving the link: false once it is gone, and on
+// TCP after the one command it carries.
+pub async fn handle<T: Transport>(link: &mut T, rx_buffer: &mut [u8; REQUEST_SIZE], session: &mut Session) -> bool {
+    let mut len = 0;
+    
+    // Read request, on TCP most commands come in one segment but an import can take a few
+    loop {
+        match link.read(&mut rx_buffer[len..]).await {
+            Ok(0) => break,
+            Ok(n) => {
+                info!("Read {} bytes", n);
+                len += n;
+            }
+            Err(e) => {
+                warn!("Failed to read from {}: {}", link.name(), e);
+                return false;
+            }
+        }
+        let complete = match link.one_shot() {
+            true => !import_incomplete(&rx_buffer[..len]),
+            false => rx_buffer[..len].contains(&b'\n'),
+        };
+        if complete || len == rx_buffer.len() {
+            break;
+        }
+    }
+    
+    // anything after the first line is dropped, clients wait for the reply anyway
+    let end = rx_buffer[..len].iter().position(|byte| *byte == b'\n').unwrap_or(len);
+    // on TCP an import that overflows the buffer or a request that isn't text
+    // is a broken client, reset it instead of answering
+    if link.one_shot() && (import_incomplete(&rx_buffer[..len]) || core::str::from_utf8(&rx_buffer[..end]).is_err()) {
+        warn!("Malformed request on {}, resetting", link.name());
+        link.reject().await;
+        return false;
+    }
+    let request = core::str::from_utf8(&rx_buffer[..end]).unwrap_or("").trim();
+    if request.is_empty() && !link.one_shot() {
+        return true;
+    }
+    
+    let command = Command::parse(request);
+    let role = session.role();
+    if let Ok(command) = &command {
+        if command.required_role() > role {
+            warn!("{} refused for {} on {}", request.split(' ').next().unwrap_or(""), role.name(), link.name());
+            let _ = link.write_all(b"ERR admin only, send AUTH <token> first\n").await;
+            return !link.one_shot();
+        }
+    }
+    
+    let response: Reply = match command {
+        Ok(Command::SetThreshold { zone, enter_cm, exit_cm }) => set_threshold(zone, enter_cm, exit_cm),
+        Ok(Command::Stream { hz, batch, flush_ms }) => {
+            // the request is parsed, its buffer can collect the batches
+            return stream_status(link, hz, batch, flush_ms, rx_buffer).await;
+        }
+        Ok(Command::ExportConfig) => match config::export_json() {
+            Ok(json) => {
+                if let Err(e) = link.write_all(json.as_bytes()).await {
+                    warn!("Failed to write to {}: {}", link.name(), e);
+                }
+                reply("")
+            }
+            Err(e) => error_reply(e),
+        },
+        Ok(Command::ImportConfig { json }) => match config::import_json(json) {
+            Ok(_) => {
+                info!("Configuration imported");
+                reply("OK")
+            }
+            Err(e) => error_reply(e),
+        },
+        Ok(Command::Simulate { distances_cm, duration_ms, token }) => {
+            let now_ms = Instant::now().as_millis();
+            match simulation::start(distances_cm, duration_ms, token, now_ms) {
+                Ok(()) => reply("OK"),
+                Err(e) => error_reply(e),
+            }
+        }
+        Ok(Command::Radio { on }) => match radio::set(on) {
+            Ok(()) => reply("OK"),
+            Err(e) => error_reply(e),
+        },
+        Ok(Command::Hello { version, capabilities }) => welcome(version, capabilities),
+        Ok(Command::Shutdown) => {
+            info!("Shutdown requested over {}", link.name());
+            shutdown::request(shutdown::Reason::Command);
+            reply("OK")
+        }
+        Ok(Command::Reboot { safe }) => {
+            info!("{} requested over {}", if safe { "Safe mode reboot" } else { "Reboot" }, link.name());
+            shutdown::request(if safe { shutdown::Reason::SafeReboot } else { shutdown::Reason::Reboot });
+            reply("OK")
+        }
+        Ok(Command::Locate { on }) => {
+            match on {
+                true => locate::start(),
+                false => locate::stop(),
+            }
+            reply("OK")
+        }
+        Ok(Command::Echoes { capture: Some(on) }) => {
+            echoes::set(on);
+            reply("OK")
+        }
+        Ok(Command::Echoes { capture: None }) => match write_echoes(link).await {
+            Some(count) => {
+                let mut response = Reply::new();
+                let _ = FmtWrite::write_fmt(&mut response, format_args!("OK {}\n", count));
+                response
+            }
+            None => return false,
+        },
+        Ok(Command::Fault { fault }) => match faults::inject(fault) {
+            Ok(()) => reply("OK"),
+            Err(e) => error_reply(e),
+        },
+        Ok(Command::BenchTest) => match benchtest::start() {
+            Ok(()) => match write_bench_samples(link).await {
+                Some(response) => response,
+                None => return false,
+            },
+            Err(e) => error_reply(e),
+        },
+        Ok(Command::Auth { token }) => {
+            let response = authenticate(session, token);
+            if let Err(e) = link.write_all(response.as_bytes()).await {
+                warn!("Failed to write to {}: {}", link.name(), e);
+                return false;
+            }
+            // a TCP client sends the command it authenticated for next
+            return true;
+        }
+        Ok(Command::Status) | Err(ParseError::UnknownCommand) => status_line(),
+        Err(ParseError::Invalid(e)) => error_reply(e),
+    };
+    
+    // Send response
+    if let Err(e) = link.write_all(response.as_bytes()).await {
+        warn!("Failed to write to {}: {}", link.name(), e);
+        return false;
+    }
+    !link.one_shot()
+}
+
+// Whether `token` is the admin token, any token is in builds without one
+pub fn admin_token_valid(token: &str) -> bool {
+    ADMIN_TOKEN.is_none_or(|admin_token| secrets::same_secret(admin_token, token))
+}
+
+// AUTH: a wrong token leaves the session as it was
+fn authenticate(session: &mut Session, token: &str) -> Reply {
+    if !admin_token_valid(token) {
+        warn!("AUTH with a wrong token");
+        return error_reply("bad token");
+    }
+    info!("Session authenticated as admin");
+    session.role = Role::Admin;
+    reply("OK admin")
+}
+
+// ECHOES: every recorded ping as an ECHO line, oldest first. Pings recorded
+// while it runs wait for the next download. Returns how many went out, or
+// None once the link is gone.
+async fn write_echoes<T: Transport>(link: &mut T) -> Option<u32> {
+    let Some(newest) = echoes::newest() else {
+        return Some(0);
+    };
+    let (mut seq, mut count) = (None, 0);
+    
+    while let Some(trace) = echoes::after(seq).filter(|trace| trace.seq <= newest) {
+        let mut line: String<128> = String::new();
+        let _ = FmtWrite::write_fmt(&mut line, format_args!("{}\n", trace));
+        if let Err(e) = link.write_all(line.as_bytes()).await {
+            warn!("Failed to write to {}: {}", link.name(), e);
+            return None;
+        }
+        seq = Some(trace.seq);
+        count += 1;
+    }
+    Some(count)
+}
+
+// BENCHTEST: a BENCH line every BENCH_SAMPLE_MS from when the feedback task
+// starts the script until it is over, then the reply. Returns None once the
+// link is gone; that doesn't stop the script, it is short.
+async fn write_bench_samples<T: Transport>(link: &mut T) -> Option<Reply> {
+    let started = Instant::now();
+    while !benchtest::running() {
+        if started.elapsed().as_millis() > 1000 {
+            return Some(error_reply("feedback busy, bench test not started"));
+        }
+        Timer::after_millis(10).await;
+    }
+    info!("Bench test on {}", link.name());
+    
+    while let Some(sample) = benchtest::sample() {
+        let mut line: String<64> = String::new();
+        let _ = FmtWrite::write_fmt(&mut line, format_args!("{}\n", sample));
+        if let Err(e) = link.write_all(line.as_bytes()).await {
+            warn!("Failed to write to {}: {}", link.name(), e);
+            return None;
+        }
+        Timer::after_millis(BENCH_SAMPLE_MS as u64).await;
+    }
+    Some(reply("OK"))
+}
+
+// An IMPORT is only complete once its line ends
+fn import_incomplete(received: &[u8]) -> bool {
+    received.len() >= 6 && received[..6].eq_ignore_ascii_case(b"IMPORT") && !received.contains(&b'\n')
+}
+
+fn status_line() -> Reply {
+    let mut response = Reply::new();
+    let _ = telemetry::latest().write_line(&mut response, None);
+    response
+}
+
+// What this firmware can do beyond the basic commands
+fn capabilities() -> Capabilities {
+    let capabilities = Capabilities::STREAM_BATCH
+        .union(Capabilities::ZONE_EVENTS)
+        .union(Capabilities::RADIO)
+        .union(Capabilities::CONFIG_TRANSFER);
+    let capabilities = match QA_TOKEN {
+        Some(_) => capabilities.union(Capabilities::SIMULATE),
+        None => capabilities,
+    };
+    let capabilities = match faults::ENABLED {
+        true => capabilities.union(Capabilities::FAULTS),
+        false => capabilities,
+    };
+    match ADMIN_TOKEN {
+        Some(_) => capabilities.union(Capabilities::AUTH),
+        None => capabilities,
+    }
+}
+
+// HELLO: tell the client what we speak, or that we can't talk to it
+fn welcome(version: Version, client: Capabilities) -> Reply {
+    let mut response = Reply::new();
+    if !Version::CURRENT.compatible(version) {
+        warn!("Client speaks protocol {}.{}, refused", version.major, version.minor);
+        let _ = FmtWrite::write_fmt(
+            &mut response,
+            format_args!("ERR unsupported protocol {}, device speaks {}\n", version, Version::CURRENT),
+        );
+        return response;
+    }
+    
+    info!("Client speaks protocol {}.{} (capabilities {:x})", version.major, version.minor, client.0);
+    let welcome = Welcome { version: Version::CURRENT, capabilities: capabilities() };
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("{}\n", welcome));
+    response
+}
+
+// The exit threshold keeps its hysteresis gap when left out. Goes through
+// `config::apply`, so the zones are checked as a whole (a NaN, an infinite
+// or an out of order threshold is refused) and the change is reported.
+fn set_threshold(zone: ZoneName, enter_cm: f32, exit_cm: Option<f32>) -> Reply {
+    let result = config::apply(|config| {
+        let threshold = match zone {
+            ZoneName::Critical => &mut config.zones.critical,
+            ZoneName::Warning => &mut config.zones.warning,
+            ZoneName::Notice => &mut config.zones.notice,
+        };
+        
+        threshold.exit_cm = exit_cm.unwrap_or(enter_cm + (threshold.exit_cm - threshold.enter_cm));
+        threshold.enter_cm = enter_cm;
+        Ok(())
+    });
+    
+    match result {
+        Ok(_) => {
+            info!("Zone {} threshold set to {} cm", zone.name(), enter_cm);
+            reply("OK")
+        }
+        Err(e) => error_reply(e),
+    }
+}
+
+fn reply(text: &str) -> Reply {
+    let mut response = String::new();
+    let _ = FmtWrite::write_str(&mut response, text);
+    let _ = FmtWrite::write_str(&mut response, "\n");
+    response
+}
+
+fn error_reply(reason: &str) -> Reply {
+    let mut response = String::new();
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("ERR {}\n", reason));
+    response
+}
+
+// STREAM <hz>: push status lines until the client goes away (or, on a
+// serial link, sends anything), and zone changes and assist requests in
+// between as soon as they happen. Samples are numbered by their
+// slot (every 1/hz s since the start); slots that pass while a slow write
+// holds things up are skipped and counted, so clients can spot the gaps.
+// With a batch size, samples are collected in `buffer` and written together.
+// Returns whether the link is still up.
+async fn stream_status<T: Transport>(
+    link: &mut T,
+    hz: u8,
+    batch: u8,
+    flush_ms: Option<u32>,
+    buffer: &mut [u8; REQUEST_SIZE],
+) -> bool {
+    let hz = hz.clamp(1, MAX_STREAM_HZ) as u64;
+    let batch = batch.clamp(1, MAX_STREAM_BATCH) as usize;
+    info!("Streaming status at {} Hz, {} samples per write", hz, batch);
+    
+    // without a free subscriber slot the stream still works, just without event lines
+    let mut subscriber = events::try_subscribe();
+    if subscriber.is_none() {
+        warn!("No event subscriber left, streaming without events");
+    }
+    
+    let period_ms = 1000 / hz;
+    let start = Instant::now();
+    let (mut seq, mut dropped) = (0u32, 0u32);
+    // what has been collected for the next write
+    let (mut len, mut samples) = (0, 0);
+    let mut oldest = start;
+    let mut frame = telemetry::latest();
+    
+    loop {
+        if link.interrupted() {
+            info!("Stream stopped by the client");
+            return true;
+        }
+        
+        let pushed = async {
+            match subscriber.as_mut() {
+                Some(subscriber) => loop {
+                    match events::next(subscriber).await {
+                        Event::ZoneChanged { side, zone, distance_cm } => {
+                            break Pushed::Zone(zone_event(side, zone, distance_cm));
+                        }
+                        Event::AssistRequested => break Pushed::Assist(assist::line()),
+                        _ => {}
+                    }
+                },
+                None => core::future::pending().await,
+            }
+        };
+        
+        let due = start + Duration::from_millis(period_ms * seq as u64);
+        let mut line: String<{ broadcast::LINE_LEN }> = String::new();
+        match select(Timer::at(due), pushed).await {
+            Either::First(()) => {
+                // formatted again only after the readings changed
+                if frame.version != telemetry::version() {
+                    frame = telemetry::latest();
+                }
+                let _ = frame.write_line(&mut line, Some((seq, dropped)));
+                if samples == 0 {
+                    oldest = Instant::now();
+                }
+                buffer[len..len + line.len()].copy_from_slice(line.as_bytes());
+                len += line.len();
+                samples += 1;
+                
+                let window_over = flush_ms.is_some_and(|ms| oldest.elapsed().as_millis() >= ms as u64);
+                if samples >= batch || window_over || buffer.len() - len < line.capacity() {
+                    if let Err(e) = link.write_all(&buffer[..len]).await {
+                        info!("Stream ended: {}", e);
+                        return false;
+                    }
+                    (len, samples) = (0, 0);
+                }
+                
+                // skip the slots that went by while we were busy
+                let current = (start.elapsed().as_millis() / period_ms) as u32;
+                if current > seq + 1 {
+                    dropped += current - seq - 1;
+                    seq = current;
+                } else {
+                    seq += 1;
+                }
+            }
+            Either::Second(pushed) => {
+                // events go out right away, after the samples taken before them
+                let _ = match pushed {
+                    Pushed::Zone(event) => FmtWrite::write_fmt(&mut line, format_args!("{}\n", event)),
+                    Pushed::Assist(assist) => FmtWrite::write_str(&mut line, &assist),
+                };
+                buffer[len..len + line.len()].copy_from_slice(line.as_bytes());
+                len += line.len();
+                if let Err(e) = link.write_all(&buffer[..len]).await {
+                    info!("Stream ended: {}", e);
+                    return false;
+                }
+                (len, samples) = (0, 0);
+            }
+        }
+    }
+}
+
+// Event lines a stream carries between its samples
+enum Pushed {
+    Zone(ZoneEvent),
+    // with its newline
+    Assist(String<{ broadcast::LINE_LEN }>),
+}
+
+// The trend isn't part of the event, the latest state has it
+fn zone_event(side: Side, zone: Zone, distance_cm: f32) -> ZoneEvent {
+    let trend = zone_state::latest().and_then(|state| state.side(side).map(|state| state.trend));
+    
+    ZoneEvent {
+        side: side.protocol_name(),
+        zone: match zone {
+            Zone::Critical => Some(ZoneName::Critical),
+            Zone::Warning => Some(ZoneName::Warning),
+            Zone::Notice => Some(ZoneName::Notice),
+            Zone::Clear => None,
+        },
+        distance_cm: distance_cm as u32,
+        trend: match trend.unwrap_or(Trend::Steady) {
+            Trend::Approaching => TrendName::Approaching,
+            Trend::Steady => TrendName::Steady,
+            Trend::Receding => TrendName::Receding,
+        },
+    }
+}
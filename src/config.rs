@@ -0,0 +1,834 @@
+//! Runtime configuration shared between the sensing, feedback and network tasks.
+//!
+//! Everything is kept in a single `Copy` struct behind a blocking mutex, so a
+//! task grabs a snapshot with [`get`] once per cycle and works with that.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as FmtWrite};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::{String, Vec};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub use vision_protocol::zoning::{ZoneConfig, ZoneThreshold};
+
+use crate::defaults::{AP_CHANNEL, ZONE_THRESHOLDS_CM};
+use crate::locale::Language;
+use crate::logging::{Scope, warn};
+use crate::theme::Theme;
+use crate::tuning;
+use crate::units::Units;
+
+const SCOPE: Scope = Scope::System;
+
+// Adaptive smoothing, see `filter.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct FilterConfig {
+    pub noisy_tau_ms: f32,         // time constant of the smoothing when the channel is noisy
+    pub quiet_tau_ms: f32,         // time constant of the smoothing when the channel is quiet
+    pub noise_reference_cm2: f32,  // noise variance at which we're halfway between the two
+    pub noise_tau_ms: f32,         // time constant of the noise estimate
+}
+
+// Mounting geometry of the sensor pair, see `geometry.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct GeometryConfig {
+    pub enabled: bool,
+    pub sensor_spacing_cm: f32,   // distance between the two sensors
+    pub toe_out_deg: f32,         // how far each sensor is turned outwards
+    pub max_wall_angle_deg: f32,  // steeper than this and we don't trust it's one wall
+}
+
+// Per-channel processing steps, see `pipeline.rs`
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StageKind {
+    Median,
+    Smoothing,
+    TemperatureCompensation,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    pub stages: [Option<StageKind>; 3],  // run in this order, None slots are skipped
+    pub ambient_temperature_c: f32,      // used by the temperature compensation stage
+}
+
+pub const MAX_PATTERN_STEPS: usize = 8;
+// Longest single on/off step we accept, keeps a bad pattern from locking up the motors
+const MAX_STEP_MS: u16 = 2000;
+
+// Vibration pattern as alternating on/off durations in ms, starting with on.
+// Written as text like "80,20,80"; unused steps are zero.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct HapticPattern {
+    pub steps: [u16; MAX_PATTERN_STEPS],
+}
+
+impl HapticPattern {
+    pub const fn new(on_off: &[u16]) -> Self {
+        let mut steps = [0; MAX_PATTERN_STEPS];
+        let mut i = 0;
+        while i < on_off.len() {
+            steps[i] = on_off[i];
+            i += 1;
+        }
+        Self { steps }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut steps = [0; MAX_PATTERN_STEPS];
+        let mut count = 0;
+
+        for step in text.trim().split(',') {
+            if count == MAX_PATTERN_STEPS {
+                return Err("Too many pattern steps");
+            }
+            let ms = step.trim().parse::<u16>().map_err(|_| "Pattern steps must be numbers")?;
+            if ms == 0 || ms > MAX_STEP_MS {
+                return Err("Pattern steps must be between 1 and 2000 ms");
+            }
+            steps[count] = ms;
+            count += 1;
+        }
+
+        Ok(Self { steps })
+    }
+
+    // Total length of the pattern in ms
+    pub fn duration_ms(&self) -> u32 {
+        self.active_steps().iter().map(|&ms| ms as u32).sum()
+    }
+
+    // Steps up to the first unused one
+    pub fn active_steps(&self) -> &[u16] {
+        let len = self.steps.iter().position(|&ms| ms == 0).unwrap_or(MAX_PATTERN_STEPS);
+        &self.steps[..len]
+    }
+}
+
+// Patterns show up as their text form in the JSON export too
+impl Serialize for HapticPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut text: String<48> = String::new();
+        let _ = FmtWrite::write_fmt(&mut text, format_args!("{}", self));
+        serializer.serialize_str(&text)
+    }
+}
+
+impl<'de> Deserialize<'de> for HapticPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = <&str>::deserialize(deserializer)?;
+        HapticPattern::parse(text).map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for HapticPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, ms) in self.active_steps().iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", ms)?;
+        }
+        Ok(())
+    }
+}
+
+pub const INTENSITY_LEVELS: usize = 10;
+
+// How the occupancy map is felt, see `rendering.rs`
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Rendering {
+    PerMotor,  // each motor for the nearest obstacle on its side
+    Sweep,     // obstacles wider than a sector sweep across the motors
+}
+
+// Vibration pattern for each intensity level, index 0 is level 1
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct HapticConfig {
+    pub patterns: [HapticPattern; INTENSITY_LEVELS],
+    pub ramp_per_s: u16,  // fastest the motor strength (0-255) may change per second
+    pub rendering: Rendering,
+}
+
+// Which physical motor output is worn on which side, how the motors are
+// driven and where tones go. Outputs are numbered 0 = GPIO19 and 1 = GPIO20;
+// by default 0 is left. Stored on its own, see `actuators.rs`.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ActuatorConfig {
+    pub swap_sides: bool,
+    pub invert: [bool; 2],  // per physical output, for active-low drivers
+    pub kick_ms: u16,       // full power burst to get an ERM motor spinning from rest
+    pub kick_below: u8,     // only kick when starting at a duty (0-255) below this
+    pub headset: bool,      // a bone-conduction headset is plugged in, tones go there instead of the buzzer
+    pub headset_volume: u8, // 0-100 %, see headset.rs
+}
+
+// Never scaled below this, so warnings can't be silenced altogether
+pub const MIN_LEVEL_PERCENT: u8 = 20;
+
+// Master output levels applied after all pattern logic, see `levels.rs`
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelsConfig {
+    pub volume_percent: u8,  // scales the headset volume, the buzzer is only on or off
+    pub haptic_percent: u8,  // scales every motor duty
+}
+
+impl LevelsConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let range = MIN_LEVEL_PERCENT..=100;
+        if !(range.contains(&self.volume_percent) && range.contains(&self.haptic_percent)) {
+            return Err("Levels must be between 20 and 100 %");
+        }
+        Ok(())
+    }
+}
+
+// Zone scaling from the user's motion, see `context.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ContextConfig {
+    pub enabled: bool,
+    pub fast_walking_scale: f32,  // zone distances are multiplied by this when walking fast
+    pub stationary_scale: f32,    // ... and by this when standing still
+}
+
+// Frozen reading detection, see `stuck.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct StuckConfig {
+    pub epsilon_cm: f32,  // readings closer together than this count as unchanged
+    pub window_ms: u32,   // walking time with unchanged readings before a channel is stuck
+}
+
+// Detection of the device being left lying somewhere, see `parked.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ParkedConfig {
+    pub enabled: bool,
+    pub after_ms: u32,        // time perfectly still with unchanged readings before going quiet
+    pub epsilon_cm: f32,      // readings closer together than this count as unchanged
+    pub cycle_delay_ms: u32,  // time from one sensing cycle to the next while parked
+}
+
+// Vibration limit when the chip runs hot, see `thermal.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ThermalConfig {
+    pub limit_c: f32,         // die temperature above which the motors are turned down
+    pub reduced_percent: u8,  // share of their duty the motors keep while hot
+}
+
+// Timing of the sensing loop and when its results count
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SensingConfig {
+    pub max_range_cm: f32,              // farthest an ultrasonic echo is waited for, farther readings are dropped
+    pub measurement_gap_ms: u32,        // pause between the pings of one stable reading
+    pub consensus_cm: f32,              // two pings this close make a stable reading
+    pub cycle_delay_ms: u32,            // time from one sensing cycle to the next
+    pub near_field_confirmations: u8,   // blind zone readings in a row before trusting them
+    pub degraded_after_failures: u8,    // cycles without a valid reading before a sensor is degraded
+    pub extreme_danger_cm: f32,         // closer than this sounds the extreme danger pattern
+    pub feedback_ttl_ms: u32,           // how long queued feedback stays relevant
+    pub interference_confirmations: u8, // readings in a row before a closer zone counts while interference is suspected
+    pub lidar_frame_rate_hz: u16,       // how often a TF-Luna/TFmini lidar sends a distance
+    pub lidar_low_power: bool,          // TF-Luna low power mode, limited to 10 Hz
+}
+
+// Upward angled third sensor for head height obstacles (branches, open
+// cabinet doors), with zones of its own since those need warning earlier
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct OverheadConfig {
+    pub enabled: bool,  // off without the sensor, every cycle would wait for its echo timeout
+    pub zones: ZoneConfig,
+}
+
+// Where the device is worn
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Mounting {
+    Body,  // at waist or chest height, looking straight ahead
+    Cane,  // on a white cane, looking down and forward at the floor
+    Wheelchair,  // on a wheelchair, with a rear sensor for reversing
+}
+
+impl Mounting {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mounting::Body => "body",
+            Mounting::Cane => "cane",
+            Mounting::Wheelchair => "wheelchair",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "body" => Some(Mounting::Body),
+            "cane" => Some(Mounting::Cane),
+            "wheelchair" => Some(Mounting::Wheelchair),
+            _ => None,
+        }
+    }
+}
+
+// Mounting geometry for removing the floor echo, see `ground.rs`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MountingConfig {
+    pub mode: Mounting,
+    pub height_cm: f32,            // sensors above the floor
+    pub tilt_deg: f32,             // how far the sensors point down from horizontal
+    pub beam_half_angle_deg: f32,  // half the sensor's beam width
+    pub margin_cm: f32,            // readings this close to the floor distance are still floor
+}
+
+// How WiFi starts and how its status changes are signalled on the device
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct WifiConfig {
+    pub link_cues: bool,  // beep when the WiFi link goes down or comes back
+    pub ap_channel: u8,   // access point channel (1-11), 0 picks the least busy one at startup
+    pub instant_on: bool, // start sensing right at power-on and bring WiFi up in the background
+}
+
+// Kinds of events the alert rules can forward, see `alerts.rs`
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AlertKind {
+    Critical,       // a sensor entered the critical zone
+    Warning,        // ... or the warning zone
+    SensorFault,    // a sensor degraded
+    Interference,   // ultrasound interference suspected
+    LowBattery,     // the charger board reported a low battery
+    WifiLink,       // the WiFi link went down or came back
+}
+
+impl AlertKind {
+    pub const ALL: [AlertKind; 6] = [
+        AlertKind::Critical,
+        AlertKind::Warning,
+        AlertKind::SensorFault,
+        AlertKind::Interference,
+        AlertKind::LowBattery,
+        AlertKind::WifiLink,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AlertKind::Critical => "critical",
+            AlertKind::Warning => "warning",
+            AlertKind::SensorFault => "sensor-fault",
+            AlertKind::Interference => "interference",
+            AlertKind::LowBattery => "low-battery",
+            AlertKind::WifiLink => "wifi-link",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        AlertKind::ALL.into_iter().find(|kind| name.trim().eq_ignore_ascii_case(kind.name()))
+    }
+}
+
+// Where an alert goes, a set of flags
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertChannels(pub u8);
+
+impl AlertChannels {
+    pub const NONE: AlertChannels = AlertChannels(0);
+    pub const LOG: AlertChannels = AlertChannels(1 << 0);    // a warning in the device log
+    pub const UDP: AlertChannels = AlertChannels(1 << 1);    // an ALERT line broadcast on the network
+    pub const BEEP: AlertChannels = AlertChannels(1 << 2);   // a double beep on the buzzer
+    pub const ALL: AlertChannels = AlertChannels(0b111);
+
+    const NAMES: [(AlertChannels, &'static str); 3] =
+        [(AlertChannels::LOG, "log"), (AlertChannels::UDP, "udp"), (AlertChannels::BEEP, "beep")];
+
+    pub const fn union(self, other: AlertChannels) -> AlertChannels {
+        AlertChannels(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: AlertChannels) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES.into_iter().filter(move |(flag, _)| self.contains(*flag)).map(|(_, name)| name)
+    }
+
+    // Comma separated names like "log,beep", or "none"
+    pub fn parse(text: &str) -> Option<Self> {
+        if text.trim().eq_ignore_ascii_case("none") {
+            return Some(AlertChannels::NONE);
+        }
+        text.split(',').try_fold(AlertChannels::NONE, |channels, name| {
+            let (flag, _) = Self::NAMES.iter().find(|(_, known)| name.trim().eq_ignore_ascii_case(known))?;
+            Some(channels.union(*flag))
+        })
+    }
+}
+
+// What happens with one kind of event
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub channels: AlertChannels,
+    pub throttle_ms: u32,  // at most one forward per this long, 0 forwards every one
+}
+
+// One rule per `AlertKind`, in the order of `AlertKind::ALL`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct AlertConfig {
+    pub rules: [AlertRule; AlertKind::ALL.len()],
+}
+
+impl AlertConfig {
+    pub fn rule(&self, kind: AlertKind) -> AlertRule {
+        self.rules[kind as usize]
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Config {
+    pub zones: ZoneConfig,
+    pub filter: FilterConfig,
+    pub geometry: GeometryConfig,
+    pub pipeline: PipelineConfig,
+    pub haptic: HapticConfig,
+    pub actuators: ActuatorConfig,
+    pub levels: LevelsConfig,
+    pub context: ContextConfig,
+    pub stuck: StuckConfig,
+    pub parked: ParkedConfig,
+    pub thermal: ThermalConfig,
+    pub sensing: SensingConfig,
+    pub wifi: WifiConfig,
+    pub mounting: MountingConfig,
+    pub overhead: OverheadConfig,
+    pub units: Units,  // for display only, everything in here stays in cm
+    pub language: Language,  // of the web pages, see locale.rs
+    pub theme: Theme,  // of the web pages unless a browser picked its own, see theme.rs
+    pub alerts: AlertConfig,
+}
+
+impl Config {
+    // these might need adjusting after testing
+    pub const DEFAULT: Config = Config {
+        // distances come from the build, see defaults.rs
+        zones: ZoneConfig {
+            critical: ZoneThreshold { enter_cm: ZONE_THRESHOLDS_CM[0].0, exit_cm: ZONE_THRESHOLDS_CM[0].1, min_dwell_ms: 300 },
+            warning: ZoneThreshold { enter_cm: ZONE_THRESHOLDS_CM[1].0, exit_cm: ZONE_THRESHOLDS_CM[1].1, min_dwell_ms: 300 },
+            notice: ZoneThreshold { enter_cm: ZONE_THRESHOLDS_CM[2].0, exit_cm: ZONE_THRESHOLDS_CM[2].1, min_dwell_ms: 500 },
+        },
+        filter: FilterConfig {
+            noisy_tau_ms: 150.0,
+            quiet_tau_ms: 40.0,
+            noise_reference_cm2: 25.0,
+            noise_tau_ms: 225.0,
+        },
+        geometry: GeometryConfig {
+            enabled: true,
+            sensor_spacing_cm: 12.0,
+            toe_out_deg: 15.0,
+            max_wall_angle_deg: 40.0,
+        },
+        pipeline: PipelineConfig {
+            stages: [
+                Some(StageKind::TemperatureCompensation),
+                Some(StageKind::Median),
+                Some(StageKind::Smoothing),
+            ],
+            ambient_temperature_c: 20.0,
+        },
+        haptic: HapticConfig {
+            patterns: [
+                HapticPattern::new(&[5, 95, 5]),    // Minimal
+                HapticPattern::new(&[10, 90, 10]),  // Very light
+                HapticPattern::new(&[20, 80, 20]),  // Light
+                HapticPattern::new(&[30, 70, 30]),  // Light-medium
+                HapticPattern::new(&[40, 60, 40]),  // Medium
+                HapticPattern::new(&[50, 50, 50]),  // Moderate
+                HapticPattern::new(&[60, 40, 60]),  // Moderate-strong
+                HapticPattern::new(&[70, 30, 70]),  // Strong
+                HapticPattern::new(&[80, 20, 80]),  // Very strong
+                HapticPattern::new(&[80]),          // Maximum intensity
+            ],
+            ramp_per_s: 1000,
+            rendering: Rendering::Sweep,
+        },
+        actuators: ActuatorConfig {
+            swap_sides: false,
+            invert: [false, false],
+            kick_ms: 15,
+            kick_below: 160,
+            headset: false,
+            headset_volume: 60,
+        },
+        levels: LevelsConfig {
+            volume_percent: 100,
+            haptic_percent: 100,
+        },
+        context: ContextConfig {
+            enabled: true,
+            fast_walking_scale: 1.3,
+            stationary_scale: 0.8,
+        },
+        stuck: StuckConfig {
+            epsilon_cm: 0.5,
+            window_ms: 5000,
+        },
+        parked: ParkedConfig {
+            enabled: true,
+            after_ms: 120_000,
+            epsilon_cm: 2.0,
+            cycle_delay_ms: 1000,
+        },
+        thermal: ThermalConfig {
+            limit_c: 60.0,
+            reduced_percent: 50,
+        },
+        sensing: SensingConfig {
+            max_range_cm: 400.0,  // what an HC-SR04 is rated for
+            measurement_gap_ms: 10,
+            consensus_cm: 3.0,
+            cycle_delay_ms: 50,
+            near_field_confirmations: 3,
+            degraded_after_failures: 10,
+            extreme_danger_cm: 10.0,
+            feedback_ttl_ms: 250,  // a couple of sensing cycles
+            interference_confirmations: 3,
+            lidar_frame_rate_hz: 100,
+            lidar_low_power: false,
+        },
+        wifi: WifiConfig {
+            link_cues: true,
+            ap_channel: match AP_CHANNEL {
+                Some(channel) => channel,
+                None => 0,
+            },
+            instant_on: true,
+        },
+        mounting: MountingConfig {
+            mode: Mounting::Body,
+            height_cm: 60.0,
+            tilt_deg: 30.0,
+            beam_half_angle_deg: 15.0,
+            margin_cm: 10.0,
+        },
+        overhead: OverheadConfig {
+            enabled: false,
+            zones: ZoneConfig {
+                critical: ZoneThreshold { enter_cm: 50.0, exit_cm: 55.0, min_dwell_ms: 200 },
+                warning: ZoneThreshold { enter_cm: 100.0, exit_cm: 110.0, min_dwell_ms: 300 },
+                notice: ZoneThreshold { enter_cm: 150.0, exit_cm: 165.0, min_dwell_ms: 500 },
+            },
+        },
+        units: Units::Metric,
+        language: Language::English,
+        theme: Theme::Light,
+        // in rules order: critical, warning, sensor fault, interference, low battery, WiFi link
+        alerts: AlertConfig {
+            rules: [
+                AlertRule { channels: AlertChannels::LOG, throttle_ms: 0 },
+                AlertRule { channels: AlertChannels::NONE, throttle_ms: 0 },
+                AlertRule { channels: AlertChannels::LOG.union(AlertChannels::UDP), throttle_ms: 60_000 },
+                AlertRule { channels: AlertChannels::LOG, throttle_ms: 60_000 },
+                AlertRule { channels: AlertChannels::ALL, throttle_ms: 0 },
+                AlertRule { channels: AlertChannels::LOG, throttle_ms: 10_000 },
+            ],
+        },
+    };
+
+    // Sanity checks for a configuration coming from outside, e.g. an import
+    pub fn validate(&self) -> Result<(), &'static str> {
+        Section::ALL.into_iter().try_for_each(|section| self.validate_section(section))
+    }
+
+    // Reset every section that fails its checks to the defaults, for settings
+    // read back from flash where rejecting them isn't an option. Returns the
+    // sections that were repaired.
+    pub fn repair(&mut self) -> Vec<Section, { Section::ALL.len() }> {
+        let mut repaired = Vec::new();
+        for section in Section::ALL {
+            if let Err(e) = self.validate_section(section) {
+                warn!("Invalid {} settings ({}), using the defaults", section.name(), e);
+                self.reset_section(section);
+                let _ = repaired.push(section);
+            }
+        }
+        repaired
+    }
+
+    fn validate_section(&self, section: Section) -> Result<(), &'static str> {
+        self.check_section(section)?;
+        // and every tuning value of the section within the tuning page's range
+        let out_of_range = tuning::PARAMS
+            .iter()
+            .filter(|param| param.name.split('.').next() == Some(section.name()))
+            .find(|param| !(param.min..=param.max).contains(&(param.get)(self)));
+        match out_of_range {
+            Some(param) => {
+                warn!("{} out of range", param.name);
+                Err("Tuning values must be within the ranges of the tuning page")
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_section(&self, section: Section) -> Result<(), &'static str> {
+        match section {
+            Section::Zones => self.zones.validate(),
+            Section::Overhead => self.overhead.zones.validate(),
+            Section::Filter => {
+                let filter = &self.filter;
+                if !(filter.quiet_tau_ms >= 1.0 && filter.quiet_tau_ms <= filter.noisy_tau_ms && filter.noisy_tau_ms <= 5000.0) {
+                    return Err("Filter time constants must satisfy 1 <= quiet <= noisy <= 5000 ms");
+                }
+                if !(filter.noise_reference_cm2 > 0.0 && (1.0..=10_000.0).contains(&filter.noise_tau_ms)) {
+                    return Err("Filter noise settings out of range");
+                }
+                Ok(())
+            }
+            Section::Geometry => {
+                if !(self.geometry.sensor_spacing_cm > 0.0 && self.geometry.sensor_spacing_cm.is_finite()) {
+                    return Err("Sensor spacing must be positive");
+                }
+                Ok(())
+            }
+            Section::Haptic => {
+                if self.haptic.ramp_per_s == 0 || self.haptic.patterns.iter().any(|pattern| pattern.active_steps().is_empty()) {
+                    return Err("Haptic patterns need at least one step and a ramp above 0");
+                }
+                Ok(())
+            }
+            Section::Context => {
+                let context = &self.context;
+                for scale in [context.fast_walking_scale, context.stationary_scale] {
+                    if !(0.5..=2.0).contains(&scale) {
+                        return Err("Zone scales must be between 0.5 and 2");
+                    }
+                }
+                Ok(())
+            }
+            Section::Stuck => {
+                if !(self.stuck.epsilon_cm >= 0.0 && self.stuck.window_ms > 0) {
+                    return Err("Stuck sensor settings out of range");
+                }
+                Ok(())
+            }
+            Section::Parked => {
+                let parked = &self.parked;
+                if !(parked.epsilon_cm >= 0.0 && parked.after_ms >= 10_000 && (1..=10_000).contains(&parked.cycle_delay_ms)) {
+                    return Err("Parked detection settings out of range");
+                }
+                Ok(())
+            }
+            Section::Thermal => {
+                let thermal = &self.thermal;
+                if !((30.0..=100.0).contains(&thermal.limit_c) && (10..=100).contains(&thermal.reduced_percent)) {
+                    return Err("Thermal limit must be 30-100 degrees and the reduced duty 10-100 %");
+                }
+                Ok(())
+            }
+            Section::Sensing => {
+                let sensing = &self.sensing;
+                if !(50.0..=500.0).contains(&sensing.max_range_cm) {
+                    return Err("Max range must be 50-500 cm");
+                }
+                if !(0.5..=50.0).contains(&sensing.consensus_cm) {
+                    return Err("Consensus tolerance must be 0.5-50 cm");
+                }
+                if sensing.cycle_delay_ms > 1000 {
+                    return Err("Cycle delay must be at most 1000 ms");
+                }
+                if sensing.near_field_confirmations == 0 || sensing.degraded_after_failures == 0 || sensing.interference_confirmations == 0 {
+                    return Err("Sensing counts must be at least 1");
+                }
+                if sensing.lidar_frame_rate_hz == 0 || sensing.lidar_frame_rate_hz > 250 {
+                    return Err("Lidar frame rate must be between 1 and 250 Hz");
+                }
+                if sensing.lidar_low_power && sensing.lidar_frame_rate_hz > 10 {
+                    return Err("Lidar low power mode allows at most 10 Hz");
+                }
+                Ok(())
+            }
+            Section::Levels => self.levels.validate(),
+            // nothing beyond the tuning ranges
+            Section::Pipeline | Section::Actuators => Ok(()),
+            Section::Wifi => {
+                if self.wifi.ap_channel > 11 {
+                    return Err("Access point channel must be 0 (automatic) or 1-11");
+                }
+                Ok(())
+            }
+            Section::Alerts => {
+                let rules = &self.alerts.rules;
+                if rules.iter().any(|rule| !AlertChannels::ALL.contains(rule.channels) || rule.throttle_ms > 3_600_000) {
+                    return Err("Alert rules need known channels and a throttle of at most an hour");
+                }
+                Ok(())
+            }
+            Section::Mounting => {
+                let mounting = &self.mounting;
+                if !(mounting.height_cm > 0.0 && (5.0..=85.0).contains(&(mounting.tilt_deg + mounting.beam_half_angle_deg))) {
+                    return Err("Mounting height must be positive and tilt plus beam angle between 5 and 85 degrees");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn reset_section(&mut self, section: Section) {
+        let defaults = Config::DEFAULT;
+        match section {
+            Section::Zones => self.zones = defaults.zones,
+            Section::Overhead => self.overhead.zones = defaults.overhead.zones,
+            Section::Filter => self.filter = defaults.filter,
+            Section::Geometry => self.geometry = defaults.geometry,
+            Section::Pipeline => self.pipeline = defaults.pipeline,
+            Section::Haptic => self.haptic = defaults.haptic,
+            Section::Actuators => self.actuators = defaults.actuators,
+            Section::Context => self.context = defaults.context,
+            Section::Stuck => self.stuck = defaults.stuck,
+            Section::Parked => self.parked = defaults.parked,
+            Section::Thermal => self.thermal = defaults.thermal,
+            Section::Sensing => self.sensing = defaults.sensing,
+            Section::Wifi => self.wifi = defaults.wifi,
+            Section::Mounting => self.mounting = defaults.mounting,
+            Section::Levels => self.levels = defaults.levels,
+            Section::Alerts => self.alerts = defaults.alerts,
+        }
+    }
+}
+
+// Parts of the configuration that are checked, and if need be reset, on their
+// own. Each one's name is the prefix of its values in `tuning::PARAMS`.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Section {
+    Zones,
+    Overhead,
+    Filter,
+    Geometry,
+    Pipeline,
+    Haptic,
+    Actuators,
+    Context,
+    Stuck,
+    Parked,
+    Thermal,
+    Sensing,
+    Wifi,
+    Mounting,
+    Levels,
+    Alerts,
+}
+
+impl Section {
+    pub const ALL: [Section; 16] = [
+        Section::Zones,
+        Section::Overhead,
+        Section::Filter,
+        Section::Geometry,
+        Section::Pipeline,
+        Section::Haptic,
+        Section::Actuators,
+        Section::Context,
+        Section::Stuck,
+        Section::Parked,
+        Section::Thermal,
+        Section::Sensing,
+        Section::Wifi,
+        Section::Mounting,
+        Section::Levels,
+        Section::Alerts,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Zones => "zones",
+            Section::Overhead => "overhead",
+            Section::Filter => "filter",
+            Section::Geometry => "geometry",
+            Section::Pipeline => "pipeline",
+            Section::Haptic => "haptic",
+            Section::Actuators => "actuators",
+            Section::Context => "context",
+            Section::Stuck => "stuck",
+            Section::Parked => "parked",
+            Section::Thermal => "thermal",
+            Section::Sensing => "sensing",
+            Section::Wifi => "wifi",
+            Section::Mounting => "mounting",
+            Section::Levels => "levels",
+            Section::Alerts => "alerts",
+        }
+    }
+}
+
+// Room for the whole configuration as JSON, the defaults need about 2050 bytes.
+// A request buffer holds this plus headers, see `buffers::REQUEST_SIZE`.
+pub const JSON_MAX_LEN: usize = 2560;
+
+// The whole configuration as a single JSON object
+pub fn export_json() -> Result<String<JSON_MAX_LEN>, &'static str> {
+    let mut buffer = [0; JSON_MAX_LEN];
+    let len = serde_json_core::to_slice(&get(), &mut buffer).map_err(|_| "Configuration too large to export")?;
+
+    let mut json = String::new();
+    let text = core::str::from_utf8(&buffer[..len]).map_err(|_| "Export is not valid UTF-8")?;
+    json.push_str(text).map_err(|_| "Configuration too large to export")?;
+    Ok(json)
+}
+
+// Replace the whole configuration with one produced by `export_json`
+pub fn import_json(json: &str) -> Result<ApplyResult, &'static str> {
+    let (imported, _) = serde_json_core::from_str::<Config>(json).map_err(|_| "Invalid configuration JSON")?;
+    apply(|config| {
+        *config = imported;
+        Ok(())
+    })
+}
+
+static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<Config>> =
+    Mutex::new(RefCell::new(Config::DEFAULT));
+
+// Snapshot of the current configuration
+pub fn get() -> Config {
+    CONFIG.lock(|config| *config.borrow())
+}
+
+// Change the configuration in place, takes effect on the next cycle of each
+// task. For changes already known to be valid; everyone is told which tuning
+// values changed, as with `apply`.
+pub fn update(change: impl FnOnce(&mut Config)) -> ApplyResult {
+    let (old, new) = CONFIG.lock(|config| {
+        let mut config = config.borrow_mut();
+        let old = *config;
+        change(&mut config);
+        (old, *config)
+    });
+    notify(&old, &new)
+}
+
+// Tuning values that actually changed in an `apply` or `update`
+pub struct ApplyResult {
+    #[cfg_attr(not(any(feature = "web", feature = "debug-tuning")), allow(dead_code))]
+    pub changed: Vec<&'static str, tuning::PARAM_COUNT>,
+}
+
+// Like `update`, but the change may fail and the result is validated, either
+// way nothing is applied
+pub fn apply(change: impl FnOnce(&mut Config) -> Result<(), &'static str>) -> Result<ApplyResult, &'static str> {
+    let (old, new) = CONFIG.lock(|config| {
+        let mut config = config.borrow_mut();
+        let old = *config;
+        let mut new = old;
+        change(&mut new)?;
+        new.validate()?;
+        *config = new;
+        Ok((old, new))
+    })?;
+    Ok(notify(&old, &new))
+}
+
+// Tells everyone which tuning values differ between the two
+fn notify(old: &Config, new: &Config) -> ApplyResult {
+    let changed: Vec<_, tuning::PARAM_COUNT> = tuning::PARAMS
+        .iter()
+        .filter(|param| (param.get)(old) != (param.get)(new))
+        .map(|param| param.name)
+        .collect();
+    if !changed.is_empty() {
+        crate::events::publish(crate::events::Event::ConfigChanged { values: changed.len() as u8 });
+    }
+    ApplyResult { changed }
+}
@@ -0,0 +1,39 @@
+//! Scales the zone distances to what the user is doing.
+//!
+//! Walking fast leaves less time to react, so obstacles should be flagged
+//! further out; standing still, nearby things matter less. The scale moves
+//! gradually so zones don't jump when the motion estimate flips.
+
+use crate::config::ContextConfig;
+use crate::imu::Motion;
+
+// Fastest the scale may change, per second
+const SCALE_RATE_PER_S: f32 = 0.5;
+
+pub struct ContextManager {
+    scale: f32,
+    updated_ms: u64,
+}
+
+impl ContextManager {
+    pub const fn new() -> Self {
+        Self { scale: 1.0, updated_ms: 0 }
+    }
+
+    // Move the scale towards the one for the current motion and return it
+    pub fn update(&mut self, motion: Motion, config: &ContextConfig, now_ms: u64) -> f32 {
+        let target = match motion {
+            _ if !config.enabled => 1.0,
+            Motion::FastWalking => config.fast_walking_scale,
+            Motion::Stationary => config.stationary_scale,
+            Motion::Walking | Motion::Unknown => 1.0,
+        };
+
+        let elapsed_s = now_ms.saturating_sub(self.updated_ms) as f32 / 1000.0;
+        let max_step = SCALE_RATE_PER_S * elapsed_s;
+        self.scale += (target - self.scale).clamp(-max_step, max_step);
+        self.updated_ms = now_ms;
+
+        self.scale
+    }
+}
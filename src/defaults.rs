@@ -0,0 +1,10 @@
+//! Build time defaults, generated by build.rs from the `VISIONASSIST_*`
+//! environment variables (see there for the format).
+//!
+//! `AP_SSID`, `AP_PASSPHRASE`, `ZONE_THRESHOLDS_CM` (enter and exit
+//! distance of the critical, warning and notice zones), `QA_TOKEN`,
+//! `ADMIN_TOKEN`, `AP_CHANNEL` (None picks one at startup),
+//! `SENSOR_KINDS` (left, right and overhead, see rangefinder.rs) and
+//! `SPARE_SOCKETS` (see wifi_utils.rs).
+
+include!(concat!(env!("OUT_DIR"), "/defaults.rs"));
@@ -0,0 +1,179 @@
+//! Sensor noise report, to tell a bad sensor from a bad mount.
+//!
+//! With the device held still in front of a flat target, a run collects the
+//! next [`ATTEMPTS`] single pings of every sensor (before any averaging or
+//! calibration) and reports per sensor the mean, standard deviation, range,
+//! dropout rate and a histogram around the mean. A sensor that is noisy
+//! against a good target spreads its readings wide; one that is mounted
+//! badly tends to read steadily but drop out a lot, or see the floor.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::Vec;
+
+use crate::channels;
+use crate::config;
+use crate::events::Side;
+use crate::logging::{Scope, info};
+
+const SCOPE: Scope = Scope::Sensing;
+
+// Pings per sensor in one run, failed ones included
+pub const ATTEMPTS: u16 = 300;
+pub const HISTOGRAM_BINS: usize = 16;
+pub const BIN_WIDTH_CM: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Collecting,
+    Done,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Collecting => "collecting",
+            State::Done => "done",
+        }
+    }
+}
+
+struct Run {
+    state: State,
+    // Channels taking part, the enabled ones looking forward
+    channels: [bool; channels::COUNT],
+    attempts: [u16; channels::COUNT],
+    // valid readings in mm, so a whole run fits in under 2 KB
+    readings: [Vec<u16, { ATTEMPTS as usize }>; channels::COUNT],
+}
+
+impl Run {
+    fn takes_part(&self, side: Side) -> bool {
+        self.channels[side as usize]
+    }
+}
+
+static RUN: Mutex<CriticalSectionRawMutex, RefCell<Run>> = Mutex::new(RefCell::new(Run {
+    state: State::Idle,
+    channels: [false; channels::COUNT],
+    attempts: [0; channels::COUNT],
+    readings: [const { Vec::new() }; channels::COUNT],
+}));
+
+// Throw away the last report and collect a new one
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn start() {
+    let config = config::get();
+    // the rear sensor can't see a target in front, and only pings when reversing
+    let taking_part = Side::ALL.map(|side| channels::placement(side).enabled(&config) && !channels::placement(side).rear());
+    RUN.lock(|run| {
+        let mut run = run.borrow_mut();
+        run.state = State::Collecting;
+        run.channels = taking_part;
+        run.attempts = [0; channels::COUNT];
+        run.readings.iter_mut().for_each(Vec::clear);
+    });
+    info!("Sensor diagnostics started, keep the device still in front of a flat target");
+}
+
+// For the sensing loop: one ping of a sensor, as it came from the hardware
+pub fn record(side: Side, reading: Result<f32, &'static str>) {
+    let finished = RUN.lock(|run| {
+        let mut run = run.borrow_mut();
+        if run.state != State::Collecting || !run.takes_part(side) || run.attempts[side as usize] >= ATTEMPTS {
+            return false;
+        }
+        run.attempts[side as usize] += 1;
+        if let Ok(distance_cm) = reading {
+            let _ = run.readings[side as usize].push(libm::roundf(distance_cm * 10.0) as u16);
+        }
+
+        let finished = Side::ALL
+            .iter()
+            .filter(|side| run.takes_part(**side))
+            .all(|side| run.attempts[*side as usize] >= ATTEMPTS);
+        if finished {
+            run.state = State::Done;
+        }
+        finished
+    });
+    if finished {
+        info!("Sensor diagnostics done");
+    }
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub struct SensorReport {
+    pub side: Side,
+    pub attempts: u16,
+    pub valid: u16,
+    pub mean_cm: f32,
+    pub stddev_cm: f32,
+    pub min_cm: f32,
+    pub max_cm: f32,
+    // Lower edge of the first bin; the outer bins also count everything beyond them
+    pub histogram_start_cm: f32,
+    pub histogram: [u16; HISTOGRAM_BINS],
+}
+
+impl SensorReport {
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn dropout_rate(&self) -> f32 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+        (self.attempts - self.valid) as f32 / self.attempts as f32
+    }
+}
+
+fn report(side: Side, attempts: u16, readings: &[u16]) -> SensorReport {
+    let valid = readings.len() as u16;
+    let cm = |mm: u16| mm as f32 / 10.0;
+    let mut report = SensorReport {
+        side,
+        attempts,
+        valid,
+        mean_cm: 0.0,
+        stddev_cm: 0.0,
+        min_cm: 0.0,
+        max_cm: 0.0,
+        histogram_start_cm: 0.0,
+        histogram: [0; HISTOGRAM_BINS],
+    };
+    if readings.is_empty() {
+        return report;
+    }
+
+    let n = valid as f32;
+    let mean_cm = readings.iter().map(|mm| cm(*mm)).sum::<f32>() / n;
+    let variance = readings.iter().map(|mm| (cm(*mm) - mean_cm) * (cm(*mm) - mean_cm)).sum::<f32>() / n;
+    report.mean_cm = mean_cm;
+    report.stddev_cm = libm::sqrtf(variance);
+    report.min_cm = readings.iter().copied().min().map(cm).unwrap_or(0.0);
+    report.max_cm = readings.iter().copied().max().map(cm).unwrap_or(0.0);
+
+    // bins centred on the mean
+    report.histogram_start_cm = mean_cm - BIN_WIDTH_CM * HISTOGRAM_BINS as f32 / 2.0;
+    for mm in readings {
+        let bin = libm::floorf((cm(*mm) - report.histogram_start_cm) / BIN_WIDTH_CM);
+        let bin = bin.clamp(0.0, (HISTOGRAM_BINS - 1) as f32) as usize;
+        report.histogram[bin] += 1;
+    }
+    report
+}
+
+// Where the run is, and a report per sensor taking part (none before the first run)
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn status() -> (State, Vec<SensorReport, { channels::COUNT }>) {
+    RUN.lock(|run| {
+        let run = run.borrow();
+        let reports = Side::ALL
+            .into_iter()
+            .filter(|side| run.state != State::Idle && run.takes_part(*side))
+            .map(|side| report(side, run.attempts[side as usize], &run.readings[side as usize]))
+            .collect();
+        (run.state, reports)
+    })
+}
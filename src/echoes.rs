@@ -0,0 +1,108 @@
+//! Echo capture for deep debugging of the ultrasonic sensors.
+//!
+//! Averaging and the pipeline hide what the echo pin actually did. With
+//! `ECHOES ON` every ping also records the times of the echo pin's edges,
+//! including ones after the echo a normal measurement stops watching at, so
+//! double echoes and ringing show. The last [`DEPTH`] pings are kept and
+//! `ECHOES` downloads them as `ECHO` lines. Capturing lengthens every ping
+//! by the listening tail, so it is off by default and after every boot.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+use heapless::{Deque, Vec};
+use vision_protocol::{EchoTrace, MAX_ECHO_EDGES};
+
+use crate::events::Side;
+use crate::logging::{Scope, info};
+
+const SCOPE: Scope = Scope::Sensing;
+
+// Pings kept for download
+pub const DEPTH: usize = 64;
+// How long a captured ping keeps watching the echo pin after the trigger,
+// past the farthest echo the sensor reports (400 cm is about 23.5 ms)
+pub const TAIL_MS: u64 = 30;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct Recorder {
+    next_seq: u32,
+    traces: Deque<EchoTrace, DEPTH>,
+}
+
+static RECORDER: Mutex<CriticalSectionRawMutex, RefCell<Recorder>> =
+    Mutex::new(RefCell::new(Recorder { next_seq: 0, traces: Deque::new() }));
+
+// One ping being captured, edges in µs after the trigger pulse
+pub struct Trace {
+    trigger: Instant,
+    edges_us: Vec<u32, MAX_ECHO_EDGES>,
+}
+
+impl Trace {
+    // Right after the trigger pulse
+    pub fn start() -> Self {
+        Self { trigger: Instant::now(), edges_us: Vec::new() }
+    }
+
+    // The echo pin just changed, edges past the limit are dropped
+    pub fn edge(&mut self) {
+        let _ = self.edges_us.push(self.trigger.elapsed().as_micros() as u32);
+    }
+
+    pub fn since_trigger(&self) -> embassy_time::Duration {
+        self.trigger.elapsed()
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Start capturing from the next ping on, or stop. Starting clears what was kept.
+pub fn set(on: bool) {
+    if on {
+        RECORDER.lock(|recorder| recorder.borrow_mut().traces.clear());
+    }
+    ENABLED.store(on, Ordering::Relaxed);
+    info!("Echo capture {}", if on { "on" } else { "off" });
+}
+
+// Keep a captured ping, the oldest makes room
+pub fn record(side: Side, trace: Trace) {
+    let side = side.protocol_name();
+    let mut edges_us = [0; MAX_ECHO_EDGES];
+    edges_us[..trace.edges_us.len()].copy_from_slice(&trace.edges_us);
+
+    RECORDER.lock(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        let seq = recorder.next_seq;
+        recorder.next_seq = seq.wrapping_add(1);
+        if recorder.traces.is_full() {
+            recorder.traces.pop_front();
+        }
+        let _ = recorder.traces.push_back(EchoTrace {
+            seq,
+            side,
+            at_ms: trace.trigger.as_millis(),
+            edge_count: trace.edges_us.len() as u8,
+            edges_us,
+        });
+    });
+}
+
+// Kept pings from oldest to newest, one at a time so a download doesn't
+// need a copy of all of them: the oldest after `seq`, or the oldest at all
+pub fn after(seq: Option<u32>) -> Option<EchoTrace> {
+    RECORDER.lock(|recorder| {
+        let recorder = recorder.borrow();
+        recorder.traces.iter().find(|trace| seq.is_none_or(|seq| trace.seq > seq)).copied()
+    })
+}
+
+// The newest kept ping, where a download stops even if capturing goes on
+pub fn newest() -> Option<u32> {
+    RECORDER.lock(|recorder| recorder.borrow().traces.back().map(|trace| trace.seq))
+}
@@ -0,0 +1,400 @@
+//! Device-wide event bus.
+//!
+//! Sensing, feedback and the network servers publish typed [`Event`]s here
+//! instead of calling into each other. Publishing never blocks: a subscriber
+//! that falls behind just loses the oldest events (and is told so).
+//!
+//! Events that report a state (a sensor's zone or health, interference, the
+//! WiFi link, ...) only go out when that state changes, so standing near a
+//! wall doesn't flood subscribers with the same zone over and over. A state
+//! that changes again within its type's minimum interval is held back and
+//! published once the interval is over, only if it still differs from what
+//! subscribers last saw. Repeated connections from the same client are
+//! reported once every 30 s.
+
+use core::cell::RefCell;
+use core::fmt::Write as FmtWrite;
+use embassy_net::IpAddress;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::pubsub::{PubSubChannel, Subscriber, WaitResult};
+use embassy_sync::signal::Signal;
+use embassy_time::{Instant, Timer};
+use heapless::{Deque, Vec};
+use vision_protocol::SideName;
+use vision_protocol::zoning::Zone;
+
+use crate::channels;
+use crate::lifecycle::State;
+use crate::logging::{Scope, info, warn};
+use crate::shutdown::Reason;
+use crate::units::Distance;
+
+const SCOPE: Scope = Scope::System;
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Side {
+    Left,
+    Right,
+    // Upward angled sensor for head height obstacles
+    Overhead,
+    // Rear facing sensor of the wheelchair mode, read only when reversing
+    Rear,
+}
+
+impl Side {
+    // Every channel, in the order per channel arrays use, see channels.rs
+    pub const ALL: [Side; 4] = [Side::Left, Side::Right, Side::Overhead, Side::Rear];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::Left => "left",
+            Side::Right => "right",
+            Side::Overhead => "overhead",
+            Side::Rear => "rear",
+        }
+    }
+
+    pub fn protocol_name(self) -> SideName {
+        match self {
+            Side::Left => SideName::Left,
+            Side::Right => SideName::Right,
+            Side::Overhead => SideName::Overhead,
+            Side::Rear => SideName::Rear,
+        }
+    }
+}
+
+// Why a sensor was reported as degraded
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DegradedReason {
+    // No valid echo for a while
+    NoReadings,
+    // Valid readings that don't change although the user is walking
+    Frozen,
+}
+
+impl DegradedReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            DegradedReason::NoReadings => "no readings",
+            DegradedReason::Frozen => "readings frozen",
+        }
+    }
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub enum Event {
+    // A channel moved into a different zone (after hysteresis)
+    ZoneChanged { side: Side, zone: Zone, distance_cm: f32 },
+    // A channel stopped producing usable readings
+    SensorDegraded { side: Side, reason: DegradedReason },
+    // ... and came back
+    SensorRecovered { side: Side },
+    // A client connected to one of the servers
+    #[cfg_attr(not(any(feature = "web", feature = "tcp")), allow(dead_code))]
+    ClientConnected { port: u16, peer: Option<IpAddress> },
+    // Switched to another configuration profile
+    ProfileChanged { index: u8 },
+    // Tuning values were changed at runtime, already in effect
+    ConfigChanged { values: u8 },
+    // The WiFi link went down or came back up
+    #[cfg_attr(not(feature = "wifi"), allow(dead_code))]
+    WifiLink { up: bool },
+    // Sensor readings are being replaced for testing, or went back to real ones
+    Simulation { active: bool },
+    // Invalid settings in a stored profile were reset to the defaults at boot
+    ConfigRepaired { profile: u8, sections: u8 },
+    // A sensor heard an echo without being triggered, zones need more confirmation for now
+    InterferenceSuspected { side: Side },
+    // ... and it has been quiet for a while
+    InterferenceCleared,
+    // WiFi was switched off or back on
+    Radio { on: bool },
+    // The device is powering down
+    ShuttingDown { reason: Reason },
+    // The find my device tone started or stopped
+    Locator { active: bool },
+    // The user asked for help with the button
+    AssistRequested,
+    // The other unit of a guide-runner pair came into reach or was lost
+    PairLink { up: bool },
+    // The device was left lying somewhere and went quiet, or was picked up again
+    Parked { parked: bool },
+    // The chip got hot and the motors were turned down, or it cooled off again
+    Thermal { hot: bool },
+    // VSYS dropped during a motor start, the motors' duty is capped lower
+    SupplySag { millivolts: u16, duty_cap: u8 },
+    // The device as a whole changed state, booted, guiding, standing by, ...
+    Lifecycle { state: State },
+}
+
+const EVENT_CAPACITY: usize = 16;
+// The log, alert and LED tasks, two long-poll requests and a TCP stream, plus one spare
+const MAX_SUBSCRIBERS: usize = 7;
+
+pub type EventSubscriber = Subscriber<'static, CriticalSectionRawMutex, Event, EVENT_CAPACITY, MAX_SUBSCRIBERS, 1>;
+
+static EVENTS: PubSubChannel<CriticalSectionRawMutex, Event, EVENT_CAPACITY, MAX_SUBSCRIBERS, 1> =
+    PubSubChannel::new();
+
+// Events with a state, each kind (per side where there is one) in a slot of its
+// own: zones, sensor health and interference per channel, then eight more
+const CHANNEL_SLOTS: usize = channels::COUNT;
+const STATE_SLOTS: usize = 3 * CHANNEL_SLOTS + 8;
+// How often the same client is reported connecting
+const CLIENT_REPEAT_MS: u64 = 30_000;
+// Checked for held back events this often while there are any
+const FLUSH_MS: u64 = 50;
+
+// Which slot an event's state goes in, and the state as a number
+fn state(event: &Event) -> Option<(usize, u8)> {
+    match *event {
+        Event::ZoneChanged { side, zone, .. } => Some((side as usize, zone as u8)),
+        Event::SensorDegraded { side, reason } => Some((CHANNEL_SLOTS + side as usize, 1 + reason as u8)),
+        Event::SensorRecovered { side } => Some((CHANNEL_SLOTS + side as usize, 0)),
+        Event::InterferenceSuspected { side } => Some((2 * CHANNEL_SLOTS + side as usize, 1)),
+        Event::WifiLink { up } => Some((3 * CHANNEL_SLOTS, up as u8)),
+        Event::Radio { on } => Some((3 * CHANNEL_SLOTS + 1, on as u8)),
+        Event::Simulation { active } => Some((3 * CHANNEL_SLOTS + 2, active as u8)),
+        Event::Locator { active } => Some((3 * CHANNEL_SLOTS + 3, active as u8)),
+        Event::PairLink { up } => Some((3 * CHANNEL_SLOTS + 4, up as u8)),
+        Event::Parked { parked } => Some((3 * CHANNEL_SLOTS + 5, parked as u8)),
+        Event::Thermal { hot } => Some((3 * CHANNEL_SLOTS + 6, hot as u8)),
+        Event::Lifecycle { state } => Some((3 * CHANNEL_SLOTS + 7, state as u8)),
+        _ => None,
+    }
+}
+
+// Shortest time between two changes of a slot's state
+fn min_interval_ms(slot: usize) -> u64 {
+    match slot {
+        // zones already have hysteresis and dwell times, this only catches flapping
+        _ if slot < CHANNEL_SLOTS => 250,
+        _ if slot < 3 * CHANNEL_SLOTS => 1000,
+        _ => 0,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    // what subscribers last saw
+    published: Option<u8>,
+    at_ms: u64,
+    // the latest change within the interval, with its state
+    held: Option<(Event, u8)>,
+}
+
+struct Gate {
+    slots: [Slot; STATE_SLOTS],
+    // port, peer and when it was last reported
+    clients: Vec<(u16, IpAddress, u64), 4>,
+}
+
+impl Gate {
+    const fn new() -> Self {
+        Self { slots: [Slot { published: None, at_ms: 0, held: None }; STATE_SLOTS], clients: Vec::new() }
+    }
+
+    // Whether the event should go out now, holding it back if it's too soon
+    fn pass(&mut self, event: &Event, now_ms: u64) -> bool {
+        if let Some((index, state)) = state(event) {
+            let slot = &mut self.slots[index];
+            if slot.published == Some(state) {
+                // a change back within the interval cancels the held one
+                slot.held = None;
+                return false;
+            }
+            if slot.published.is_some() && now_ms < slot.at_ms + min_interval_ms(index) {
+                slot.held = Some((*event, state));
+                HELD.signal(());
+                return false;
+            }
+            *slot = Slot { published: Some(state), at_ms: now_ms, held: None };
+            return true;
+        }
+
+        if let Event::InterferenceCleared = event {
+            for slot in &mut self.slots[2 * CHANNEL_SLOTS..3 * CHANNEL_SLOTS] {
+                *slot = Slot { published: None, at_ms: now_ms, held: None };
+            }
+        }
+
+        if let Event::ClientConnected { port, peer: Some(peer) } = *event {
+            self.clients.retain(|(_, _, at_ms)| now_ms < at_ms + CLIENT_REPEAT_MS);
+            if self.clients.iter().any(|(known_port, known_peer, _)| *known_port == port && *known_peer == peer) {
+                return false;
+            }
+            if self.clients.is_full() {
+                self.clients.remove(0);
+            }
+            let _ = self.clients.push((port, peer, now_ms));
+        }
+        true
+    }
+
+    // Held back events whose interval is over, marked as published
+    fn release(&mut self, now_ms: u64) -> Vec<Event, STATE_SLOTS> {
+        let mut released = Vec::new();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let Some((event, state)) = slot.held else {
+                continue;
+            };
+            if now_ms >= slot.at_ms + min_interval_ms(index) {
+                *slot = Slot { published: Some(state), at_ms: now_ms, held: None };
+                let _ = released.push(event);
+            }
+        }
+        released
+    }
+
+    fn holding(&self) -> bool {
+        self.slots.iter().any(|slot| slot.held.is_some())
+    }
+}
+
+static GATE: Mutex<CriticalSectionRawMutex, RefCell<Gate>> = Mutex::new(RefCell::new(Gate::new()));
+// Set when an event is held back
+static HELD: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Publish without waiting, lagging subscribers drop their oldest event.
+// Repeats of a state are dropped and changes that come too fast held back.
+pub fn publish(event: Event) {
+    let now_ms = Instant::now().as_millis();
+    // the client table counts every connection, repeats or not
+    if let Event::ClientConnected { port, peer: Some(peer) } = event {
+        crate::clients::record(peer, port, now_ms);
+    }
+    if GATE.lock(|gate| gate.borrow_mut().pass(&event, now_ms)) {
+        EVENTS.immediate_publisher().publish_immediate(event);
+    }
+}
+
+// Publishes held back events once their interval is over
+#[embassy_executor::task]
+pub async fn release_task() {
+    loop {
+        HELD.wait().await;
+        loop {
+            Timer::after_millis(FLUSH_MS).await;
+            let now_ms = Instant::now().as_millis();
+            let (released, holding) = GATE.lock(|gate| {
+                let mut gate = gate.borrow_mut();
+                (gate.release(now_ms), gate.holding())
+            });
+            for event in released {
+                EVENTS.immediate_publisher().publish_immediate(event);
+            }
+            if !holding {
+                break;
+            }
+        }
+    }
+}
+
+// Panics when more than MAX_SUBSCRIBERS tasks subscribe, that's a bug to fix at build time
+pub fn subscribe() -> EventSubscriber {
+    EVENTS.subscriber().unwrap()
+}
+
+// For short-lived subscriptions (e.g. per request), None when all slots are taken
+pub fn try_subscribe() -> Option<EventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+// Wait for the next event, logging (rather than failing on) any we missed
+pub async fn next(subscriber: &mut EventSubscriber) -> Event {
+    loop {
+        match subscriber.next_message().await {
+            WaitResult::Message(event) => return event,
+            WaitResult::Lagged(missed) => warn!("Event subscriber lagged, {} events missed", missed),
+        }
+    }
+}
+
+// One line of text for an event, as shown on the dashboard
+pub fn describe<W: FmtWrite>(response: &mut W, event: &Event) {
+    let _ = match event {
+        Event::ZoneChanged { side, zone, distance_cm } => FmtWrite::write_fmt(
+            response,
+            format_args!("{} sensor entered {} zone at {}", side.name(), zone.name(), Distance::new(*distance_cm)),
+        ),
+        Event::SensorDegraded { side, reason } => {
+            FmtWrite::write_fmt(response, format_args!("{} sensor degraded ({})", side.name(), reason.name()))
+        },
+        Event::SensorRecovered { side } => FmtWrite::write_fmt(response, format_args!("{} sensor recovered", side.name())),
+        Event::ClientConnected { port, peer: Some(peer) } => {
+            FmtWrite::write_fmt(response, format_args!("client {} connected on port {}", peer, port))
+        },
+        Event::ClientConnected { port, peer: None } => {
+            FmtWrite::write_fmt(response, format_args!("client connected on port {}", port))
+        },
+        Event::ConfigChanged { values } => FmtWrite::write_fmt(response, format_args!("{} tuning values changed", values)),
+        Event::WifiLink { up: true } => FmtWrite::write_str(response, "WiFi link up"),
+        Event::WifiLink { up: false } => FmtWrite::write_str(response, "WiFi link down"),
+        Event::Simulation { active: true } => FmtWrite::write_str(response, "Simulated readings started"),
+        Event::Simulation { active: false } => FmtWrite::write_str(response, "Simulated readings ended"),
+        Event::ConfigRepaired { profile, sections } => FmtWrite::write_fmt(
+            response,
+            format_args!("invalid settings in profile {} repaired, {} sections reset to defaults", profile, sections),
+        ),
+        Event::InterferenceSuspected { side } => FmtWrite::write_fmt(
+            response,
+            format_args!("ultrasound interference suspected on the {} sensor", side.name()),
+        ),
+        Event::InterferenceCleared => FmtWrite::write_str(response, "ultrasound interference gone"),
+        Event::Radio { on: true } => FmtWrite::write_str(response, "WiFi switched on"),
+        Event::Radio { on: false } => FmtWrite::write_str(response, "WiFi switched off"),
+        Event::ShuttingDown { reason } => FmtWrite::write_fmt(response, format_args!("shutting down ({})", reason.name())),
+        Event::Locator { active: true } => FmtWrite::write_str(response, "locator tone started"),
+        Event::Locator { active: false } => FmtWrite::write_str(response, "locator tone stopped"),
+        Event::AssistRequested => FmtWrite::write_str(response, "ASSIST REQUESTED by the user"),
+        Event::PairLink { up: true } => FmtWrite::write_str(response, "paired unit linked"),
+        Event::PairLink { up: false } => FmtWrite::write_str(response, "link to the paired unit lost"),
+        Event::Parked { parked: true } => FmtWrite::write_str(response, "left lying still, feedback paused"),
+        Event::Parked { parked: false } => FmtWrite::write_str(response, "picked up, feedback resumed"),
+        Event::Thermal { hot: true } => FmtWrite::write_str(response, "running hot, vibration turned down"),
+        Event::Thermal { hot: false } => FmtWrite::write_str(response, "cooled off, vibration back to normal"),
+        Event::SupplySag { millivolts, duty_cap } => FmtWrite::write_fmt(
+            response,
+            format_args!("supply sagged to {} mV during a motor start, motor duty capped at {}", millivolts, duty_cap),
+        ),
+        Event::Lifecycle { state } => FmtWrite::write_fmt(response, format_args!("device state now {}", state.name())),
+        Event::ProfileChanged { index } => {
+            let (_, names) = crate::profiles::list();
+            let name = names.get(*index as usize).map_or("?", |name| name.as_str());
+            FmtWrite::write_fmt(response, format_args!("switched to profile {}", name))
+        },
+    };
+}
+
+// Most recent events with their timestamp (ms since boot), shown on the dashboard
+pub const RECENT_EVENTS: usize = 8;
+static RECENT: Mutex<CriticalSectionRawMutex, RefCell<Deque<(u64, Event), RECENT_EVENTS>>> =
+    Mutex::new(RefCell::new(Deque::new()));
+
+// Copy of the recent events, oldest first
+pub fn recent() -> Deque<(u64, Event), RECENT_EVENTS> {
+    RECENT.lock(|recent| recent.borrow().clone())
+}
+
+// Logs every event and keeps the last few around for the dashboard. Gets
+// its subscriber when spawned, so events published during startup before
+// the task first runs aren't lost.
+#[embassy_executor::task]
+pub async fn event_log_task(mut subscriber: EventSubscriber) {
+
+    loop {
+        let event = next(&mut subscriber).await;
+        info!("Event: {}", event);
+        crate::session::record(&event);
+
+        let timestamp = Instant::now().as_millis();
+        RECENT.lock(|recent| {
+            let mut recent = recent.borrow_mut();
+            if recent.is_full() {
+                recent.pop_front();
+            }
+            let _ = recent.push_back((timestamp, event));
+        });
+    }
+}
@@ -0,0 +1,101 @@
+//! The record the production test fixture leaves in flash.
+//!
+//! Every assembled board goes through the fixture (see
+//! [`fixture`](crate::fixture)), which stores the unit's serial number and,
+//! once everything checked out, a tested flag in their own flash sector (see
+//! [`storage`](crate::storage)). A normal boot logs them and `GET /api/info`
+//! reports them, so a unit that never passed the fixture stands out.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::String;
+
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Storage;
+
+// Change when the stored layout changes, old data is then ignored
+const FACTORY_MAGIC: u32 = 0x4641_4331;
+const HEADER_LEN: usize = 6;
+pub const MAX_SERIAL_LEN: usize = 32;
+
+#[derive(Clone)]
+struct Record {
+    tested: bool,
+    serial: Option<String<MAX_SERIAL_LEN>>,
+}
+
+static RECORD: Mutex<CriticalSectionRawMutex, RefCell<Record>> =
+    Mutex::new(RefCell::new(Record { tested: false, serial: None }));
+
+// Read the record, call once at startup
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::FACTORY, &mut buffer) {
+        warn!("Failed to read the factory record: {}", e);
+        return;
+    }
+
+    let record = decode(&buffer);
+    match (&record.serial, record.tested) {
+        (Some(serial), true) => info!("Unit {} passed the production test", serial.as_str()),
+        (Some(serial), false) => warn!("Unit {} hasn't passed the production test", serial.as_str()),
+        (None, _) => warn!("No factory record, this board hasn't been through the production test"),
+    }
+    RECORD.lock(|stored| *stored.borrow_mut() = record);
+}
+
+pub fn tested() -> bool {
+    RECORD.lock(|record| record.borrow().tested)
+}
+
+#[cfg_attr(not(any(feature = "web", feature = "usb")), allow(dead_code))]
+pub fn serial_number() -> Option<String<MAX_SERIAL_LEN>> {
+    RECORD.lock(|record| record.borrow().serial.clone())
+}
+
+#[cfg_attr(not(feature = "usb"), allow(dead_code))]
+pub fn set_serial_number(serial: &str) -> Result<(), &'static str> {
+    if serial.is_empty() || !serial.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return Err("Serial number must be printable ASCII without spaces");
+    }
+    let serial = String::try_from(serial).map_err(|()| "Serial number too long")?;
+    save(|record| record.serial = Some(serial))
+}
+
+#[cfg_attr(not(feature = "usb"), allow(dead_code))]
+pub fn mark_tested() -> Result<(), &'static str> {
+    save(|record| record.tested = true)
+}
+
+fn decode(buffer: &[u8; ERASE_SIZE]) -> Record {
+    let len = buffer[5] as usize;
+    if buffer[..4] != FACTORY_MAGIC.to_le_bytes() || len > MAX_SERIAL_LEN {
+        return Record { tested: false, serial: None };
+    }
+
+    let serial = core::str::from_utf8(&buffer[HEADER_LEN..HEADER_LEN + len])
+        .ok()
+        .filter(|serial| !serial.is_empty())
+        .and_then(|serial| String::try_from(serial).ok());
+    Record { tested: buffer[4] == 1, serial }
+}
+
+// Change the record and write it back
+fn save(change: impl FnOnce(&mut Record)) -> Result<(), &'static str> {
+    let mut record = RECORD.lock(|record| record.borrow().clone());
+    change(&mut record);
+
+    let mut buffer = [0xFF; ERASE_SIZE];
+    buffer[..4].copy_from_slice(&FACTORY_MAGIC.to_le_bytes());
+    buffer[4] = record.tested as u8;
+    let serial = record.serial.as_deref().unwrap_or("");
+    buffer[5] = serial.len() as u8;
+    buffer[HEADER_LEN..HEADER_LEN + serial.len()].copy_from_slice(serial.as_bytes());
+    storage::write_sector(partitions::FACTORY, &buffer)?;
+
+    RECORD.lock(|stored| *stored.borrow_mut() = record);
+    Ok(())
+}
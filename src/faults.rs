@@ -0,0 +1,88 @@
+//! Fault injection for robustness testing.
+//!
+//! Built with `--features fault-injection`, the FAULT command makes the next
+//! few sensing cycles time out, TCP reads and writes fail or flash writes
+//! fail, exactly where the real failures would surface, so degraded mode,
+//! the health registry, retries and the watchdog can be tried out on the
+//! device on purpose. `FAULT HANG` blocks the firmware until the watchdog
+//! restarts it. Without the feature the hooks below are constant `false`
+//! and FAULT is refused.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use vision_protocol::Fault;
+
+use crate::channels;
+use crate::events::Side;
+use crate::logging::{Scope, warn};
+
+const SCOPE: Scope = Scope::System;
+
+pub const ENABLED: bool = cfg!(feature = "fault-injection");
+
+// Failures still to inject, counted down by the hooks
+static SENSOR_TIMEOUTS: Mutex<CriticalSectionRawMutex, Cell<[u16; channels::COUNT]>> =
+    Mutex::new(Cell::new([0; channels::COUNT]));
+static SOCKET_ERRORS: AtomicU16 = AtomicU16::new(0);
+static FLASH_WRITE_FAILURES: AtomicU16 = AtomicU16::new(0);
+static HANG: AtomicBool = AtomicBool::new(false);
+
+pub fn inject(fault: Fault) -> Result<(), &'static str> {
+    if !ENABLED {
+        return Err("fault injection disabled in this build");
+    }
+
+    warn!("Injecting {}", fault);
+    match fault {
+        Fault::SensorTimeout { side, count } => SENSOR_TIMEOUTS.lock(|timeouts| {
+            let mut current = timeouts.get();
+            for side in Side::ALL.into_iter().filter(|each| side.is_none_or(|side| each.protocol_name() == side)) {
+                current[side as usize] = count;
+            }
+            timeouts.set(current);
+        }),
+        Fault::SocketError { count } => SOCKET_ERRORS.store(count, Ordering::Relaxed),
+        Fault::FlashWrite { count } => FLASH_WRITE_FAILURES.store(count, Ordering::Relaxed),
+        Fault::Hang => HANG.store(true, Ordering::Relaxed),
+        Fault::Clear => {
+            SENSOR_TIMEOUTS.lock(|timeouts| timeouts.set([0; channels::COUNT]));
+            SOCKET_ERRORS.store(0, Ordering::Relaxed);
+            FLASH_WRITE_FAILURES.store(0, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+// Takes one from a counter, true while there was one left
+fn take(counter: &AtomicU16) -> bool {
+    ENABLED && counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1)).is_ok()
+}
+
+// Whether this sensing cycle of a channel should time out
+pub fn sensor_timeout(side: Side) -> bool {
+    ENABLED
+        && SENSOR_TIMEOUTS.lock(|timeouts| {
+            let mut current = timeouts.get();
+            let left = &mut current[side as usize];
+            let fail = *left > 0;
+            *left = left.saturating_sub(1);
+            timeouts.set(current);
+            fail
+        })
+}
+
+// Whether this read or write on a TCP connection should fail
+pub fn socket_error() -> bool {
+    take(&SOCKET_ERRORS)
+}
+
+// Whether this flash sector write should fail
+pub fn flash_write_failure() -> bool {
+    take(&FLASH_WRITE_FAILURES)
+}
+
+// Whether the sensing loop should block the firmware for the watchdog
+pub fn hang_requested() -> bool {
+    ENABLED && HANG.load(Ordering::Relaxed)
+}
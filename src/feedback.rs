@@ -0,0 +1,631 @@
+//! Feedback engine: a task owning the buzzer, the headset output and the
+//! vibration motors, fed through a small priority queue of [`FeedbackCommand`]s.
+//!
+//! [`planner_task`] turns every new zone state and the [`occupancy`] map into
+//! commands, other producers call [`submit`] directly; it never blocks.
+//! Repeated low-priority commands for the same pattern and side replace each
+//! other instead of piling up, and a command with a higher priority than the
+//! one currently playing cuts it short.
+
+use core::cell::RefCell;
+use embassy_futures::select::{Either, select};
+use embassy_rp::gpio::Output;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use vision_protocol::zoning::{self, Zone};
+
+use crate::channels::{self, Motors};
+use crate::config::{Config, HapticPattern, INTENSITY_LEVELS};
+use crate::events::Side;
+use crate::headset::Headset;
+use crate::logging::{Scope, debug, info, warn};
+use crate::motor::{Actuator, PwmMotor};
+use crate::occupancy;
+use crate::rendering;
+use crate::zone_state::{self, SideState};
+
+const SCOPE: Scope = Scope::Feedback;
+
+// Which actuators a previewed pattern is played on
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PreviewTarget {
+    Buzzer,
+    Left,
+    Right,
+    Both,
+}
+
+impl PreviewTarget {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "buzzer" => Some(PreviewTarget::Buzzer),
+            "left" => Some(PreviewTarget::Left),
+            "right" => Some(PreviewTarget::Right),
+            "both" => Some(PreviewTarget::Both),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Pattern {
+    // Vibration on one or both motors, intensity 1-255
+    Haptic { intensity: u8, motors: Motors },
+    // Vibration moving across the motors, for an obstacle spanning these
+    // bearings (0 straight ahead, negative to the left)
+    Sweep { from_deg: i8, to_deg: i8, intensity: u8 },
+    // Beeps getting faster the closer the obstacle
+    WarningSound { distance_cm: u16 },
+    // Alternating buzzer and motors for obstacles right in front of the user
+    ExtremeDanger,
+    // One-off pattern submitted from the dashboard to try it out
+    Preview { steps: HapticPattern, target: PreviewTarget },
+    // Head height obstacle: a double tap on both motors, 1-255
+    Overhead { intensity: u8 },
+    // Something behind a reversing wheelchair: beeps with a pulse on both
+    // motors, closer together the closer it is, 1-255
+    Reversing { intensity: u8 },
+    // Sensor fault on the command's side: one long beep, then a short beep
+    // per place in the channel list (one for left, two for right, three for
+    // the overhead sensor, four for the rear one)
+    FaultCode,
+    // No fresh readings: two long beeps with both motors, repeated until
+    // sensing resumes, so the user doesn't walk on trusting a silent device
+    NotSensing,
+    // A follower lost its guide's unit: three short beeps and buzzes, then a
+    // long one, repeated until the guide's obstacles come through again
+    LinkLost,
+    // The master levels were changed: a beep with a buzz on both motors, at
+    // the new levels
+    Levels,
+    // The bench test's script, see benchtest.rs
+    BenchTest,
+}
+
+impl Pattern {
+    // Same kind of pattern, regardless of its parameters. Vibration on
+    // different motors, or sweeps over different obstacles, are different
+    // kinds, all of them are wanted.
+    fn same_kind(&self, other: &Pattern) -> bool {
+        match (self, other) {
+            (Pattern::Haptic { motors, .. }, Pattern::Haptic { motors: other, .. }) => motors == other,
+            (Pattern::Sweep { from_deg, .. }, Pattern::Sweep { from_deg: other, .. }) => from_deg == other,
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Priority {
+    Normal,
+    High,
+    Critical,
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub struct FeedbackCommand {
+    pub pattern: Pattern,
+    pub side: Option<Side>,  // None for patterns that don't belong to one side
+    pub priority: Priority,
+    pub ttl_ms: u32,         // dropped if it hasn't started playing by then
+}
+
+const QUEUE_DEPTH: usize = 8;
+
+struct Queued {
+    command: FeedbackCommand,
+    expires_at_ms: u64,
+    seq: u32,
+}
+
+struct Queue {
+    entries: Vec<Queued, QUEUE_DEPTH>,
+    next_seq: u32,
+    playing: Option<Priority>,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self { entries: Vec::new(), next_seq: 0, playing: None }
+    }
+
+    // Queue a command, returns true if it should preempt what's playing
+    fn push(&mut self, command: FeedbackCommand, now_ms: u64) -> bool {
+        let expires_at_ms = now_ms + command.ttl_ms as u64;
+
+        // coalesce with a queued command of the same kind for the same side
+        if command.priority < Priority::High {
+            if let Some(queued) = self.entries.iter_mut().find(|queued| {
+                queued.command.side == command.side && queued.command.pattern.same_kind(&command.pattern)
+            }) {
+                queued.command = command;
+                queued.expires_at_ms = expires_at_ms;
+                return false;
+            }
+        }
+
+        if self.entries.is_full() {
+            self.entries.retain(|queued| queued.expires_at_ms > now_ms);
+        }
+
+        if self.entries.is_full() {
+            // make room by evicting the least important entry, or drop the new one
+            let lowest = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, queued)| (queued.command.priority, queued.seq))
+                .map(|(index, queued)| (index, queued.command.priority));
+            match lowest {
+                Some((index, priority)) if priority < command.priority => {
+                    self.entries.swap_remove(index);
+                },
+                _ => {
+                    crate::health::error(crate::health::Subsystem::Feedback);
+                    debug!("Feedback queue full, dropping {}", command);
+                    return false;
+                },
+            }
+        }
+
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let _ = self.entries.push(Queued { command, expires_at_ms, seq });
+
+        self.playing.is_some_and(|playing| command.priority > playing)
+    }
+
+    // Highest priority command that hasn't expired, oldest first within a priority
+    fn pop(&mut self, now_ms: u64) -> Option<FeedbackCommand> {
+        self.entries.retain(|queued| queued.expires_at_ms > now_ms);
+
+        let index = self
+            .entries
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, queued)| (queued.command.priority, core::cmp::Reverse(queued.seq)))
+            .map(|(index, _)| index)?;
+        Some(self.entries.swap_remove(index).command)
+    }
+}
+
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<Queue>> = Mutex::new(RefCell::new(Queue::new()));
+static WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static PREEMPT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Queue a command for the feedback task, returns immediately
+pub fn submit(command: FeedbackCommand) {
+    let now_ms = Instant::now().as_millis();
+    let preempt = QUEUE.lock(|queue| queue.borrow_mut().push(command, now_ms));
+    if preempt {
+        PREEMPT.signal(());
+    }
+    WAKE.signal(());
+}
+
+// Priority of the pattern playing right now, if any
+pub fn playing() -> Option<Priority> {
+    QUEUE.lock(|queue| queue.borrow().playing)
+}
+
+// Everything the feedback engine drives. The motors are indexed by physical
+// output (0 = GPIO19, 1 = GPIO20), the config maps them to the user's left and right.
+pub struct Actuators {
+    pub buzzer: Output<'static>,
+    pub headset: Headset,
+    pub motors: [PwmMotor; 2],
+}
+
+impl Actuators {
+    // Silences everything, with the configured polarity so that duty 0 also
+    // stops motors on drivers that switch on with a low level
+    fn all_off(&mut self) {
+        let mapping = crate::config::get().actuators;
+        self.buzzer.set_low();
+        self.headset.drive(0);
+        for (motor, inverted) in self.motors.iter_mut().zip(mapping.invert) {
+            motor.configure(inverted, mapping.kick_ms, mapping.kick_below);
+            motor.drive(0);
+        }
+    }
+
+    // Where tones go (the headset when one is plugged in, else the buzzer)
+    // plus the left and right motor, after applying the actuator mapping, the
+    // master levels, the thermal limit and the supply's duty cap
+    fn mapped(&mut self, config: &Config) -> (&mut dyn Actuator, &mut PwmMotor, &mut PwmMotor) {
+        let mapping = &config.actuators;
+        let [first, second] = &mut self.motors;
+        first.configure(mapping.invert[0], mapping.kick_ms, mapping.kick_below);
+        second.configure(mapping.invert[1], mapping.kick_ms, mapping.kick_below);
+        let strength_percent = (config.levels.haptic_percent as u16 * crate::thermal::duty_percent() as u16 / 100) as u8;
+        first.set_strength(strength_percent);
+        second.set_strength(strength_percent);
+        first.set_duty_cap(crate::supply::duty_cap());
+        second.set_duty_cap(crate::supply::duty_cap());
+        let volume_percent = (mapping.headset_volume as u16 * config.levels.volume_percent as u16 / 100) as u8;
+        self.headset.configure(mapping.headset, volume_percent);
+
+        let sound: &mut dyn Actuator = if self.headset.present() { &mut self.headset } else { &mut self.buzzer };
+        if mapping.swap_sides {
+            (sound, second, first)
+        } else {
+            (sound, first, second)
+        }
+    }
+
+    async fn play(&mut self, command: FeedbackCommand) {
+        if command.pattern == Pattern::BenchTest {
+            // drives the outputs directly, the mapping only sets up their polarity and duty cap
+            self.mapped(&crate::config::get());
+            crate::benchtest::run(&mut self.buzzer, &mut self.motors).await;
+            return;
+        }
+        
+        let (buzzer, vibration_left, vibration_right) = self.mapped(&crate::config::get());
+
+        match (command.pattern, command.side) {
+            (Pattern::Haptic { intensity, motors }, _) => match motors {
+                Motors::Left => provide_haptic_feedback(vibration_left, intensity).await,
+                Motors::Right => provide_haptic_feedback(vibration_right, intensity).await,
+                Motors::Both => {
+                    provide_haptic_feedback(vibration_left, intensity).await;
+                    vibration_left.drive(0);
+                    provide_haptic_feedback(vibration_right, intensity).await;
+                },
+            },
+            (Pattern::Sweep { from_deg, to_deg, intensity }, _) => {
+                play_sweep(vibration_left, vibration_right, from_deg as f32, to_deg as f32, intensity).await;
+            },
+            (Pattern::WarningSound { distance_cm }, _) => {
+                provide_warning_sound(buzzer, distance_cm as f32).await;
+            },
+            (Pattern::ExtremeDanger, _) => {
+                provide_extreme_danger_warning(buzzer, vibration_left, vibration_right).await;
+            },
+            (Pattern::Overhead { intensity }, _) => {
+                play_steps(&mut [vibration_left, vibration_right], &[60, 60, 60], intensity).await;
+            },
+            (Pattern::Reversing { intensity }, _) => {
+                let range_ms = (REVERSING_MAX_GAP_MS - REVERSING_MIN_GAP_MS) as u32;
+                let gap_ms = REVERSING_MAX_GAP_MS - (range_ms * intensity as u32 / u8::MAX as u32) as u16;
+                play_steps(&mut [buzzer, vibration_left, vibration_right], &[100, gap_ms], intensity).await;
+            },
+            (Pattern::FaultCode, side) => {
+                let mut steps: Vec<u16, { 1 + 2 * channels::COUNT }> = Vec::new();
+                let _ = steps.push(400);
+                for _ in 0..side.map_or(1, |side| side as usize + 1) {
+                    let _ = steps.extend_from_slice(&[150, 100]);
+                }
+                play_steps(&mut [buzzer], &steps, u8::MAX).await;
+            },
+            (Pattern::NotSensing, _) => {
+                play_steps(&mut [buzzer, vibration_left, vibration_right], &[400, 200, 400], u8::MAX).await;
+            },
+            (Pattern::Levels, _) => {
+                play_steps(&mut [buzzer, vibration_left, vibration_right], &[200], u8::MAX).await;
+            },
+            (Pattern::LinkLost, _) => {
+                let steps = [100, 100, 100, 100, 100, 100, 500];
+                play_steps(&mut [buzzer, vibration_left, vibration_right], &steps, u8::MAX).await;
+            },
+            (Pattern::Preview { steps, target }, _) => {
+                let steps = steps.active_steps();
+                match target {
+                    PreviewTarget::Buzzer => play_steps(&mut [buzzer], steps, u8::MAX).await,
+                    PreviewTarget::Left => play_steps(&mut [vibration_left], steps, u8::MAX).await,
+                    PreviewTarget::Right => play_steps(&mut [vibration_right], steps, u8::MAX).await,
+                    PreviewTarget::Both => {
+                        play_steps(&mut [vibration_left, vibration_right], steps, u8::MAX).await;
+                    },
+                }
+            },
+            // played above
+            (Pattern::BenchTest, _) => {},
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn feedback_task(mut actuators: Actuators) {
+    // The PWM slices start out with the default polarity, set the configured
+    // one before anything else so active-low drivers don't run from boot
+    actuators.all_off();
+
+    loop {
+        let now_ms = Instant::now().as_millis();
+        let next = QUEUE.lock(|queue| {
+            let mut queue = queue.borrow_mut();
+            let command = queue.pop(now_ms);
+            queue.playing = command.map(|command| command.priority);
+            command
+        });
+
+        let Some(command) = next else {
+            WAKE.wait().await;
+            continue;
+        };
+
+        PREEMPT.reset();
+        if let Either::Second(()) = select(actuators.play(command), PREEMPT.wait()).await {
+            debug!("Feedback {} preempted", command.pattern);
+        }
+
+        // Always leave the actuators off between patterns
+        actuators.all_off();
+        QUEUE.lock(|queue| queue.borrow_mut().playing = None);
+    }
+}
+
+// Strong warning pattern for very close obstacles
+async fn provide_extreme_danger_warning(
+    buzzer: &mut dyn Actuator,
+    vibration_left: &mut PwmMotor,
+    vibration_right: &mut PwmMotor,
+) {
+    // First pattern - left side
+    buzzer.drive(u8::MAX);
+    vibration_left.drive(u8::MAX);
+    Timer::after(Duration::from_millis(150)).await;
+    buzzer.drive(0);
+    vibration_left.drive(0);
+    Timer::after(Duration::from_millis(50)).await;
+    
+    // Second pattern - right side
+    buzzer.drive(u8::MAX);
+    vibration_right.drive(u8::MAX);
+    Timer::after(Duration::from_millis(150)).await;
+    buzzer.drive(0);
+    vibration_right.drive(0);
+    Timer::after(Duration::from_millis(50)).await;
+    
+    // Third pattern - both sides
+    buzzer.drive(u8::MAX);
+    vibration_left.drive(u8::MAX);
+    vibration_right.drive(u8::MAX);
+    Timer::after(Duration::from_millis(300)).await;
+    buzzer.drive(0);
+    vibration_left.drive(0);
+    vibration_right.drive(0);
+    
+    // Pause before next cycle
+    Timer::after(Duration::from_millis(100)).await;
+}
+
+// Haptic feedback for a 0-255 intensity. The motor's level ramps towards the
+// intensity and sets the PWM duty, the configured pattern for the matching
+// level (1-10) sets the rhythm.
+async fn provide_haptic_feedback(motor: &mut PwmMotor, intensity: u8) {
+    let haptic = crate::config::get().haptic;
+    let level = motor.ramp_towards(intensity, haptic.ramp_per_s, Instant::now().as_millis());
+    
+    if level == 0 {
+        // No vibration
+        motor.drive(0);
+        Timer::after(Duration::from_millis(10)).await;
+        return;
+    }
+    
+    // 1-255 onto pattern levels 1-10, rounding up so any vibration gets a pattern
+    let index = (level as usize * INTENSITY_LEVELS).div_ceil(u8::MAX as usize) - 1;
+    play_steps(&mut [motor], haptic.patterns[index].active_steps(), level).await;
+}
+
+// How long a sweep takes per degree of the obstacle, and how often it moves on
+const SWEEP_MS_PER_DEG: f32 = 8.0;
+const SWEEP_STEP_MS: u16 = 20;
+
+// Move a vibration from `from_deg` to `to_deg`, panning between the motors
+// as if they sat at the outer edges of the occupancy map
+async fn play_sweep(left: &mut PwmMotor, right: &mut PwmMotor, from_deg: f32, to_deg: f32, intensity: u8) {
+    let edge_deg = occupancy::SECTORS as f32 * occupancy::SECTOR_WIDTH_DEG / 2.0;
+    let steps = ((to_deg - from_deg) * SWEEP_MS_PER_DEG / SWEEP_STEP_MS as f32).max(1.0) as u32;
+    for step in 0..=steps {
+        let bearing_deg = from_deg + (to_deg - from_deg) * step as f32 / steps as f32;
+        // constant power panning, straight ahead is felt on both at 70%
+        let pan = ((bearing_deg + edge_deg) / (2.0 * edge_deg)).clamp(0.0, 1.0) * core::f32::consts::FRAC_PI_2;
+        left.drive((intensity as f32 * libm::cosf(pan)) as u8);
+        right.drive((intensity as f32 * libm::sinf(pan)) as u8);
+
+        let kick_ms = left.kick_ms().max(right.kick_ms()).min(SWEEP_STEP_MS);
+        if kick_ms > 0 {
+            Timer::after(Duration::from_millis(kick_ms as u64)).await;
+            left.settle();
+            right.settle();
+        }
+        Timer::after(Duration::from_millis((SWEEP_STEP_MS - kick_ms) as u64)).await;
+    }
+}
+
+// Alternate the outputs between `duty` and off for the given durations, starting with on
+async fn play_steps(outputs: &mut [&mut dyn Actuator], steps: &[u16], duty: u8) {
+    for (i, &ms) in steps.iter().enumerate() {
+        for output in outputs.iter_mut() {
+            output.drive(if i % 2 == 0 { duty } else { 0 });
+        }
+        
+        // let motors starting from rest finish their kick before settling at the duty
+        let kick_ms = outputs.iter().map(|output| output.kick_ms()).max().unwrap_or(0).min(ms);
+        if kick_ms > 0 {
+            Timer::after(Duration::from_millis(kick_ms as u64)).await;
+            for output in outputs.iter_mut() {
+                output.settle();
+            }
+        }
+        Timer::after(Duration::from_millis((ms - kick_ms) as u64)).await;
+    }
+}
+
+// Warning sounds for different distance ranges
+async fn provide_warning_sound(buzzer: &mut dyn Actuator, distance: f32) {
+    if distance < 10.0 {
+        // Very close - rapid beeping
+        for _ in 0..3 {
+            buzzer.drive(u8::MAX);
+            Timer::after(Duration::from_millis(25)).await;
+            buzzer.drive(0);
+            Timer::after(Duration::from_millis(25)).await;
+        }
+    } else if distance < 20.0 {
+        // Medium close - moderate beeping
+        for _ in 0..2 {
+            buzzer.drive(u8::MAX);
+            Timer::after(Duration::from_millis(50)).await;
+            buzzer.drive(0);
+            Timer::after(Duration::from_millis(50)).await;
+        }
+    } else {
+        // Not as close - single beep
+        buzzer.drive(u8::MAX);
+        Timer::after(Duration::from_millis(70)).await;
+        buzzer.drive(0);
+    }
+}
+
+// Pause after a reversing beep, from far to close
+const REVERSING_MAX_GAP_MS: u16 = 600;
+const REVERSING_MIN_GAP_MS: u16 = 80;
+
+// How often the not sensing alarm repeats
+const NOT_SENSING_REPEAT_MS: u64 = 2000;
+
+// Decides what to play for every new zone state and queues it. When no new
+// state arrives in time the last one isn't played on, the user gets the not
+// sensing alarm instead. Nothing is queued while the device stands by.
+#[embassy_executor::task]
+pub async fn planner_task() {
+    let mut receiver = zone_state::receiver();
+    let mut stale = false;
+
+    loop {
+        // nothing can go stale before the first reading
+        let timeout_ms = if stale { NOT_SENSING_REPEAT_MS } else { zone_state::STALE_MS };
+        let next = match zone_state::latest() {
+            Some(_) => select(receiver.changed(), Timer::after_millis(timeout_ms)).await,
+            None => Either::First(receiver.changed().await),
+        };
+        let state = match next {
+            Either::First(state) => state,
+            Either::Second(()) => {
+                // stopped on purpose, the farewell is playing, or sensing slowed down in standby
+                if crate::shutdown::requested() || crate::lifecycle::state() == crate::lifecycle::State::Standby {
+                    continue;
+                }
+                if !stale {
+                    warn!("No readings for {} ms, sensing stalled", zone_state::STALE_MS);
+                    crate::health::error(crate::health::Subsystem::Sensors);
+                    crate::health::set_status(crate::health::Subsystem::Sensors, crate::health::Status::Fault);
+                    crate::lifecycle::enter(crate::lifecycle::State::Fault);
+                    stale = true;
+                }
+                submit(FeedbackCommand {
+                    pattern: Pattern::NotSensing,
+                    side: None,
+                    priority: Priority::High,
+                    ttl_ms: NOT_SENSING_REPEAT_MS as u32,
+                });
+                continue;
+            }
+        };
+        if stale {
+            info!("Sensing resumed");
+            stale = false;
+        }
+        // left lying somewhere, nobody to warn
+        if crate::lifecycle::state() == crate::lifecycle::State::Standby {
+            continue;
+        }
+
+        let mut config = crate::config::get();
+        config.zones = config.zones.scaled(state.zone_scale);
+        config.overhead.zones = config.overhead.zones.scaled(state.zone_scale);
+        queue_feedback(&config);
+        for (side, sensor) in state.active().filter(|(side, _)| channels::placement(*side).head_height()) {
+            queue_overhead_feedback(side, sensor, &config);
+        }
+        for (side, sensor) in state.active().filter(|(side, _)| channels::placement(*side).rear()) {
+            queue_reversing_feedback(side, sensor, &config);
+        }
+    }
+}
+
+// Main feedback function for what's in front of the user, decides from the
+// occupancy map what to play and hands it to the feedback task. The sensing
+// loop updates the map before it publishes the state that got us here.
+fn queue_feedback(config: &Config) {
+    let now_ms = Instant::now().as_millis();
+    let sectors = occupancy::map();
+    let fresh = || sectors.iter().filter_map(|sector| Some((sector, sector.fresh(now_ms)?)));
+    let zones = &config.zones;
+    // How long a queued feedback command stays relevant
+    let ttl_ms = config.sensing.feedback_ttl_ms;
+    
+    // Check for extremely close obstacles
+    let extreme_danger_threshold = config.sensing.extreme_danger_cm;
+    let extreme_danger = fresh().any(|(_, reading)| reading.distance_cm < extreme_danger_threshold);
+    
+    if extreme_danger {
+        // Special warning for very close objects, preempts anything else
+        submit(FeedbackCommand {
+            pattern: Pattern::ExtremeDanger,
+            side: None,
+            priority: Priority::Critical,
+            ttl_ms,
+        });
+        return;
+    }
+    
+    // Vibration as the configured renderer sees the map, nothing when clear
+    for pattern in rendering::renderer(config.haptic.rendering).render(&sectors, now_ms, zones) {
+        submit(FeedbackCommand {
+            pattern,
+            side: None,
+            priority: Priority::Normal,
+            ttl_ms,
+        });
+    }
+    
+    // Sound only for close objects
+    if fresh().any(|(_, reading)| reading.zone == Zone::Critical) {
+        let closest_cm = fresh().map(|(_, reading)| reading.distance_cm).fold(f32::MAX, f32::min);
+        submit(FeedbackCommand {
+            pattern: Pattern::WarningSound { distance_cm: closest_cm as u16 },
+            side: None,
+            priority: Priority::High,
+            ttl_ms,
+        });
+    }
+}
+
+// Head height obstacles get their own pattern on both motors, so they can't be
+// mistaken for something on one side
+fn queue_overhead_feedback(side: Side, overhead: &SideState, config: &Config) {
+    let intensity = zoning::intensity(overhead.distance_cm, overhead.zone, &config.overhead.zones);
+    if intensity == 0 {
+        return;
+    }
+    
+    submit(FeedbackCommand {
+        pattern: Pattern::Overhead { intensity },
+        side: Some(side),
+        priority: if overhead.zone == Zone::Critical { Priority::High } else { Priority::Normal },
+        ttl_ms: config.sensing.feedback_ttl_ms,
+    });
+}
+
+// Rear channels are only read while reversing, anything they see gets the
+// reversing alert so it can't be confused with what's in front
+fn queue_reversing_feedback(side: Side, rear: &SideState, config: &Config) {
+    let intensity = zoning::intensity(rear.distance_cm, rear.zone, &config.zones);
+    if intensity == 0 {
+        return;
+    }
+    
+    submit(FeedbackCommand {
+        pattern: Pattern::Reversing { intensity },
+        side: Some(side),
+        priority: if rear.zone == Zone::Critical { Priority::High } else { Priority::Normal },
+        ttl_ms: config.sensing.feedback_ttl_ms,
+    });
+}
@@ -0,0 +1,58 @@
+//! Adaptive low-pass filter for the distance channels.
+//!
+//! Each channel keeps a running estimate of its reading-to-reading noise. When
+//! readings are steady the filter follows new samples closely, when they jump
+//! around (crowds, soft surfaces, cross-talk) it stiffens towards
+//! `noisy_tau_ms`. Both are time constants rather than per-sample weights:
+//! each sample is weighted by the time since the one before, so the
+//! smoothing stays the same whatever the sensing cycle takes.
+
+use crate::config::FilterConfig;
+
+pub struct AdaptiveFilter {
+    estimate: f32,
+    last_raw: f32,
+    variance: f32,
+}
+
+impl AdaptiveFilter {
+    pub const fn new(initial: f32) -> Self {
+        Self { estimate: initial, last_raw: initial, variance: 0.0 }
+    }
+
+    // Feed a raw reading taken `dt_ms` after the last one, returns the smoothed distance
+    pub fn update(&mut self, raw: f32, dt_ms: f32, config: &FilterConfig) -> f32 {
+        // half the squared step between readings estimates the noise variance
+        let step = raw - self.last_raw;
+        let sample_variance = step * step / 2.0;
+        self.variance += weight(dt_ms, config.noise_tau_ms) * (sample_variance - self.variance);
+        self.last_raw = raw;
+
+        let alpha = weight(dt_ms, self.tau_ms(config));
+        self.estimate = raw * alpha + self.estimate * (1.0 - alpha);
+        self.estimate
+    }
+
+    // Jump straight to a reading, used when it must not be averaged away
+    pub fn reset(&mut self, raw: f32) {
+        self.estimate = raw;
+        self.last_raw = raw;
+    }
+
+    // Time constant of the estimate, sliding from quiet_tau_ms up to noisy_tau_ms as noise grows
+    fn tau_ms(&self, config: &FilterConfig) -> f32 {
+        let stiffness = self.variance / (self.variance + config.noise_reference_cm2);
+        config.quiet_tau_ms + (config.noisy_tau_ms - config.quiet_tau_ms) * stiffness
+    }
+
+    // Current noise estimate as a standard deviation in cm
+    pub fn noise_cm(&self) -> f32 {
+        libm::sqrtf(self.variance)
+    }
+}
+
+// Weight of a new sample `dt_ms` after the last one for a first order
+// low-pass with time constant `tau_ms`
+fn weight(dt_ms: f32, tau_ms: f32) -> f32 {
+    1.0 - libm::expf(-dt_ms.max(0.0) / tau_ms)
+}
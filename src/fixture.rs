@@ -0,0 +1,265 @@
+//! Production test mode, for the fixture every assembled board goes through.
+//!
+//! A board that boots with GPIO2 held to ground (the fixture's strap pin)
+//! doesn't start the device at all: no sensing, no WiFi, no watchdog. Its USB
+//! serial port speaks a small line protocol for the fixture instead, one
+//! command per line, each answered by exactly one `OK ...` or `ERR <reason>`
+//! line and nothing else ever written to the port:
+//!
+//! ```text
+//! PING                            OK FIXTURE <version>
+//! ID                              OK <chip id, 16 hex digits>
+//! GPIO <n> HIGH|LOW               OK, the pin driven
+//! GPIO <n> READ UP|DOWN|NONE      OK 0|1, the pin as an input with that pull
+//! ECHO left|right|overhead|rear   OK <µs>, a 500 µs pulse on the channel's trigger
+//!                                 pin as its echo pin saw it
+//! SERIAL <text>                   OK, the serial number stored
+//! PASSPHRASE <text>               OK, the WiFi passphrase provisioned
+//! TESTED                          OK, the board recorded as passed
+//! STATUS                          OK tested=0|1 serial=<text|-> passphrase=0|1
+//! ```
+//!
+//! Every header pin but the strap can be exercised, GPIO0-22 and 26-28;
+//! GPIO23-25 and 29 belong to the WiFi chip. For `ECHO` the fixture connects
+//! the trigger and echo pins of each sensor connector. What it stores ends up
+//! in the [`factory`](crate::factory) record and the secrets. Only in builds
+//! with the `usb` feature.
+
+use core::fmt::Write as FmtWrite;
+use embassy_executor::Spawner;
+use embassy_rp::Peripherals;
+use embassy_rp::gpio::{Flex, Input, Pull};
+use embassy_rp::otp;
+use embassy_rp::peripherals::PIN_2;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{String, Vec};
+use vision_protocol::SideName;
+
+use crate::factory;
+use crate::logging::{Scope, info};
+use crate::secrets;
+use crate::serial;
+use crate::storage;
+use crate::transport::Transport;
+
+const SCOPE: Scope = Scope::Serial;
+
+// Bump when the protocol changes
+const FIXTURE_VERSION: u8 = 1;
+// Width of the ECHO test pulse, and how long the echo pin may lag behind it
+const PULSE_US: u64 = 500;
+const LOOPBACK_TIMEOUT_US: u64 = 1000;
+// For a pin to follow a new pull before it is read
+const SETTLE_US: u64 = 50;
+const LINE_LEN: usize = 96;
+
+type Reply = String<64>;
+
+// Whether the fixture holds the strap pin to ground
+pub async fn strapped(strap: &mut PIN_2) -> bool {
+    let strap = Input::new(strap, Pull::Up);
+    Timer::after_micros(SETTLE_US).await;
+    strap.is_low()
+}
+
+// The pins the fixture may exercise, by GPIO number
+struct Pins([(u8, Flex<'static>); 25]);
+
+impl Pins {
+    fn get(&mut self, gpio: u8) -> Option<&mut Flex<'static>> {
+        self.0.iter_mut().find(|(number, _)| *number == gpio).map(|(_, pin)| pin)
+    }
+
+    fn pair(&mut self, first: u8, second: u8) -> Option<(&mut Flex<'static>, &mut Flex<'static>)> {
+        let (mut first_pin, mut second_pin) = (None, None);
+        for (number, pin) in self.0.iter_mut() {
+            if *number == first {
+                first_pin = Some(pin);
+            } else if *number == second {
+                second_pin = Some(pin);
+            }
+        }
+        Some((first_pin?, second_pin?))
+    }
+}
+
+// Serve the fixture until it powers the board off
+pub async fn run(spawner: Spawner, p: Peripherals) -> ! {
+    info!("Strapped for the production test fixture");
+    storage::init(storage::StorageFlash::new_blocking(p.FLASH));
+    spawner.spawn(storage::writer_task()).unwrap();
+    secrets::load();
+    factory::load();
+
+    let mut pins = Pins([
+        (0, Flex::new(p.PIN_0)),
+        (1, Flex::new(p.PIN_1)),
+        (3, Flex::new(p.PIN_3)),
+        (4, Flex::new(p.PIN_4)),
+        (5, Flex::new(p.PIN_5)),
+        (6, Flex::new(p.PIN_6)),
+        (7, Flex::new(p.PIN_7)),
+        (8, Flex::new(p.PIN_8)),
+        (9, Flex::new(p.PIN_9)),
+        (10, Flex::new(p.PIN_10)),
+        (11, Flex::new(p.PIN_11)),
+        (12, Flex::new(p.PIN_12)),
+        (13, Flex::new(p.PIN_13)),
+        (14, Flex::new(p.PIN_14)),
+        (15, Flex::new(p.PIN_15)),
+        (16, Flex::new(p.PIN_16)),
+        (17, Flex::new(p.PIN_17)),
+        (18, Flex::new(p.PIN_18)),
+        (19, Flex::new(p.PIN_19)),
+        (20, Flex::new(p.PIN_20)),
+        (21, Flex::new(p.PIN_21)),
+        (22, Flex::new(p.PIN_22)),
+        (26, Flex::new(p.PIN_26)),
+        (27, Flex::new(p.PIN_27)),
+        (28, Flex::new(p.PIN_28)),
+    ]);
+
+    let mut link = serial::usb_link(&spawner, p.USB);
+    let mut line: Vec<u8, LINE_LEN> = Vec::new();
+    loop {
+        link.class.wait_connection().await;
+        info!("Fixture connected");
+        line.clear();
+        let mut buffer = [0; LINE_LEN];
+        'connected: while let Ok(n) = link.read(&mut buffer).await {
+            for &byte in &buffer[..n] {
+                if byte != b'\n' {
+                    // an overlong line is cut off and refused
+                    let _ = line.push(byte);
+                    continue;
+                }
+                let request = core::str::from_utf8(&line).unwrap_or("").trim();
+                let reply = handle(request, &mut pins).await;
+                line.clear();
+                if link.write_all(reply.as_bytes()).await.is_err() {
+                    break 'connected;
+                }
+            }
+        }
+        info!("Fixture disconnected");
+    }
+}
+
+// One request, one reply line
+async fn handle(request: &str, pins: &mut Pins) -> Reply {
+    let mut value = Reply::new();
+    let result = match request.split_once(' ').unwrap_or((request, "")) {
+        ("PING", "") => write(&mut value, format_args!("FIXTURE {}", FIXTURE_VERSION)),
+        ("ID", "") => match otp::get_chipid() {
+            Ok(id) => write(&mut value, format_args!("{:016x}", id)),
+            Err(_) => Err("chip id unreadable"),
+        },
+        ("GPIO", args) => gpio(args, pins, &mut value).await,
+        ("ECHO", side) => match echo_loopback(side, pins).await {
+            Ok(us) => write(&mut value, format_args!("{}", us)),
+            Err(e) => Err(e),
+        },
+        ("SERIAL", serial) => stored(factory::set_serial_number(serial)).await,
+        ("PASSPHRASE", passphrase) => stored(secrets::store(passphrase)).await,
+        ("TESTED", "") => stored(factory::mark_tested()).await,
+        ("STATUS", "") => {
+            let serial = factory::serial_number();
+            let (tested, provisioned) = (factory::tested() as u8, secrets::is_provisioned() as u8);
+            let serial = serial.as_deref().unwrap_or("-");
+            write(&mut value, format_args!("tested={} serial={} passphrase={}", tested, serial, provisioned))
+        },
+        _ => Err("unknown command"),
+    };
+
+    let mut line = Reply::new();
+    let _ = match result {
+        Ok(()) if value.is_empty() => FmtWrite::write_str(&mut line, "OK\n"),
+        Ok(()) => FmtWrite::write_fmt(&mut line, format_args!("OK {}\n", value)),
+        Err(e) => FmtWrite::write_fmt(&mut line, format_args!("ERR {}\n", e)),
+    };
+    line
+}
+
+// OK only once it is in flash, the fixture may power the board off right after
+async fn stored(result: Result<(), &'static str>) -> Result<(), &'static str> {
+    result?;
+    storage::flush().await
+}
+
+fn write(value: &mut Reply, args: core::fmt::Arguments) -> Result<(), &'static str> {
+    FmtWrite::write_fmt(value, args).map_err(|_| "reply too long")
+}
+
+// GPIO <n> HIGH|LOW, GPIO <n> READ UP|DOWN|NONE
+async fn gpio(args: &str, pins: &mut Pins, value: &mut Reply) -> Result<(), &'static str> {
+    let mut words = args.split_ascii_whitespace();
+    let pin = words.next().and_then(|gpio| gpio.parse().ok()).and_then(|gpio| pins.get(gpio));
+    let Some(pin) = pin else {
+        return Err("no such test pin");
+    };
+    match (words.next(), words.next(), words.next()) {
+        (Some("HIGH"), None, None) => {
+            pin.set_high();
+            pin.set_as_output();
+        },
+        (Some("LOW"), None, None) => {
+            pin.set_low();
+            pin.set_as_output();
+        },
+        (Some("READ"), Some(pull), None) => {
+            let pull = match pull {
+                "UP" => Pull::Up,
+                "DOWN" => Pull::Down,
+                "NONE" => Pull::None,
+                _ => return Err("expected UP, DOWN or NONE"),
+            };
+            pin.set_as_input();
+            pin.set_pull(pull);
+            Timer::after_micros(SETTLE_US).await;
+            write(value, format_args!("{}", pin.is_high() as u8))?;
+        },
+        _ => return Err("expected GPIO <n> HIGH|LOW|READ <pull>"),
+    }
+    Ok(())
+}
+
+// A pulse on the channel's trigger pin, timed on its echo pin
+async fn echo_loopback(side: &str, pins: &mut Pins) -> Result<u64, &'static str> {
+    // trigger and echo pin of each sensor connector, as rangefinder.rs uses them
+    let (trigger, echo) = match SideName::parse(side).ok_or("expected left, right, overhead or rear")? {
+        SideName::Left => (14, 15),
+        SideName::Right => (16, 17),
+        SideName::Overhead => (10, 11),
+        SideName::Rear => (12, 13),
+    };
+    let (trigger, echo) = pins.pair(trigger, echo).ok_or("no such test pin")?;
+    echo.set_as_input();
+    echo.set_pull(Pull::Down);
+    trigger.set_low();
+    trigger.set_as_output();
+    Timer::after_micros(SETTLE_US).await;
+
+    trigger.set_high();
+    let rise = wait_for(echo, true);
+    Timer::after(Duration::from_micros(PULSE_US)).await;
+    trigger.set_low();
+    let fall = wait_for(echo, false);
+    trigger.set_as_input();
+
+    match (rise, fall) {
+        (Some(rise), Some(fall)) => Ok((fall - rise).as_micros()),
+        (None, _) => Err("no echo"),
+        (Some(_), None) => Err("echo stuck high"),
+    }
+}
+
+// When the pin reaches the level, busy waiting since nothing else runs
+fn wait_for(pin: &Flex<'static>, high: bool) -> Option<Instant> {
+    let deadline = Instant::now() + Duration::from_micros(LOOPBACK_TIMEOUT_US);
+    while Instant::now() < deadline {
+        if pin.is_high() == high {
+            return Some(Instant::now());
+        }
+    }
+    None
+}
@@ -0,0 +1,64 @@
+//! Angle-of-incidence compensation for the left/right sensor pair.
+//!
+//! Both sensors sit `sensor_spacing_cm` apart and are turned outwards by
+//! `toe_out_deg`. When they both hit the same flat wall at an angle, the two
+//! echo points define the wall line, and the clearance that matters is the
+//! perpendicular distance from the device to that line rather than either
+//! slanted reading.
+
+use crate::config::GeometryConfig;
+
+// Walls within this angle count as square on, both sides get corrected
+const SQUARE_ON_DEG: f32 = 5.0;
+
+// A flat surface estimated from one pair of readings
+#[derive(Clone, Copy)]
+pub struct WallEstimate {
+    pub distance_cm: f32,  // perpendicular distance from the middle of the device
+    pub angle_deg: f32,    // 0 is square on, positive when the wall is nearer on the left
+}
+
+// Fit a wall through both echo points, None when the pair doesn't look like one surface
+pub fn estimate_wall(left_cm: f32, right_cm: f32, config: &GeometryConfig) -> Option<WallEstimate> {
+    let half_spacing = config.sensor_spacing_cm / 2.0;
+    let toe = config.toe_out_deg.to_radians();
+    let (sin, cos) = (libm::sinf(toe), libm::cosf(toe));
+
+    // echo points with the device in the origin, x to the right, y forward
+    let (lx, ly) = (-half_spacing - left_cm * sin, left_cm * cos);
+    let (rx, ry) = (half_spacing + right_cm * sin, right_cm * cos);
+
+    let (dx, dy) = (rx - lx, ry - ly);
+    let length = libm::sqrtf(dx * dx + dy * dy);
+    if length <= f32::EPSILON {
+        return None;
+    }
+
+    let angle_deg = libm::atan2f(dy, dx).to_degrees();
+    if angle_deg.abs() > config.max_wall_angle_deg {
+        return None;
+    }
+
+    Some(WallEstimate {
+        distance_cm: (lx * ry - ly * rx).abs() / length,
+        angle_deg,
+    })
+}
+
+// Replace the reading on the side the wall leans towards with the perpendicular
+// distance. The other side keeps its own reading so left/right cues still make
+// sense, unless the wall is square on and both sides are equally close.
+pub fn compensate(left_cm: f32, right_cm: f32, config: &GeometryConfig) -> (f32, f32) {
+    if !config.enabled {
+        return (left_cm, right_cm);
+    }
+
+    match estimate_wall(left_cm, right_cm, config) {
+        Some(wall) if wall.angle_deg.abs() < SQUARE_ON_DEG => {
+            (wall.distance_cm.min(left_cm), wall.distance_cm.min(right_cm))
+        },
+        Some(wall) if wall.angle_deg > 0.0 => (wall.distance_cm.min(left_cm), right_cm),
+        Some(wall) => (left_cm, wall.distance_cm.min(right_cm)),
+        None => (left_cm, right_cm),
+    }
+}
@@ -0,0 +1,31 @@
+//! Ground return removal for a cane mounted device.
+//!
+//! On a white cane the sensors look down and forward, so the floor always
+//! sends an echo back. With the mounting height and tilt from the config the
+//! distance to the floor is known: the lower edge of the beam meets it at
+//! `height / sin(tilt + beam half angle)`. Readings from about there on are
+//! the floor and count as clear; anything standing on it cuts the beam off
+//! earlier and still comes through.
+
+use crate::config::{Mounting, MountingConfig};
+
+// What a floor echo is replaced with, the HC-SR04's useful range
+pub const CLEAR_CM: f32 = 400.0;
+
+// Expected distance of the floor echo, None when mounted on the body
+pub fn ground_distance_cm(config: &MountingConfig) -> Option<f32> {
+    if config.mode != Mounting::Cane {
+        return None;
+    }
+
+    let angle = (config.tilt_deg + config.beam_half_angle_deg).to_radians();
+    Some(config.height_cm / libm::sinf(angle))
+}
+
+// Turn floor echoes into a clear reading, obstacles are left alone
+pub fn subtract(distance_cm: f32, config: &MountingConfig) -> f32 {
+    match ground_distance_cm(config) {
+        Some(ground_cm) if distance_cm >= ground_cm - config.margin_cm => CLEAR_CM,
+        _ => distance_cm,
+    }
+}
@@ -0,0 +1,63 @@
+//! Tone output for a bone-conduction headset.
+//!
+//! GPIO6 (channel A of PWM slice 3) plays a square wave at [`TONE_HZ`]. An RC
+//! low-pass after the pin (1 kΩ and 100 nF is enough) rounds it off for a
+//! small class D amplifier driving the headset's transducers. The volume is
+//! the wave's duty cycle: the tone's fundamental grows with it up to 50%,
+//! where the wave is symmetric and loudest.
+//!
+//! With `actuators.headset` set, everything the buzzer would play goes to the
+//! headset instead and the buzzer stays quiet; the feedback engine drives it
+//! like the buzzer, as an [`Actuator`] that is on or off. The volume is its
+//! own setting, `actuators.headset_volume`, so the buzzer can stay loud for
+//! when the headset is taken off; the master volume (see
+//! [`levels`](crate::levels)) scales it further.
+
+use embassy_rp::pwm::{Config, Pwm};
+use fixed::types::U12F4;
+
+use crate::motor::Actuator;
+
+// Low enough to carry well through bone, high enough to cut through traffic
+pub const TONE_HZ: u32 = 880;
+// 150 MHz / 16 leaves a period that fits the 16 bit counter at TONE_HZ
+const DIVIDER: u32 = 16;
+const PWM_TOP: u16 = (150_000_000 / DIVIDER / TONE_HZ - 1) as u16;
+
+pub struct Headset {
+    pwm: Pwm<'static>,
+    config: Config,
+    present: bool,
+    volume_percent: u8,
+}
+
+impl Headset {
+    pub fn new(mut pwm: Pwm<'static>) -> Self {
+        let mut config = Config::default();
+        config.top = PWM_TOP;
+        config.divider = U12F4::from_num(DIVIDER);
+        config.compare_a = 0;
+        pwm.set_config(&config);
+        Self { pwm, config, present: false, volume_percent: 0 }
+    }
+
+    // Whether tones go here rather than to the buzzer, and how loud (0-100)
+    pub fn configure(&mut self, present: bool, volume_percent: u8) {
+        self.present = present;
+        self.volume_percent = volume_percent.min(100);
+    }
+
+    pub fn present(&self) -> bool {
+        self.present
+    }
+}
+
+impl Actuator for Headset {
+    // Tone on or off, at the configured volume whatever the duty
+    fn drive(&mut self, duty: u8) {
+        let on = duty > 0 && self.present;
+        // full volume is a 50% duty cycle
+        self.config.compare_a = if on { (PWM_TOP as u32 * self.volume_percent as u32 / 200) as u16 } else { 0 };
+        self.pwm.set_config(&self.config);
+    }
+}
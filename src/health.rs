@@ -0,0 +1,175 @@
+//! Error counters and a coarse status per subsystem.
+//!
+//! The sensing loop, the WiFi link monitor, the flash driver and the feedback
+//! engine each report their errors with [`error`] and keep their own status
+//! up to date with [`set_status`]. `GET /api/health` returns both per
+//! subsystem with an overall verdict, the worst of the statuses, for
+//! companion apps to show the user.
+//!
+//! The error counts cover the device's whole life: they are kept in their own
+//! flash sector, written by [`storage_task`] every 15 minutes when they have
+//! changed and at a shutdown. Counts since the boot are reported next to them.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Ticker};
+
+use crate::logging::{Scope, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::System;
+
+// Change when the stored layout changes, old data is then ignored
+const HEALTH_MAGIC: u32 = 0x484c_5431;
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Subsystem {
+    Sensors,
+    Wifi,
+    Flash,
+    Feedback,
+}
+
+impl Subsystem {
+    pub const COUNT: usize = 4;
+    pub const ALL: [Subsystem; Self::COUNT] = [Subsystem::Sensors, Subsystem::Wifi, Subsystem::Flash, Subsystem::Feedback];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Sensors => "sensors",
+            Subsystem::Wifi => "wifi",
+            Subsystem::Flash => "flash",
+            Subsystem::Feedback => "feedback",
+        }
+    }
+}
+
+// Ordered from best to worst, the verdict is the maximum
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Status {
+    Ok,
+    Degraded,
+    Fault,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Degraded => "DEGRADED",
+            Status::Fault => "FAULT",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Report {
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub subsystem: Subsystem,
+    pub status: Status,
+    // over the device's life, this boot included
+    pub errors: u32,
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub errors_since_boot: u32,
+}
+
+struct Registry {
+    status: [Status; Subsystem::COUNT],
+    // as stored, the ones since the boot are added on top
+    stored: [u32; Subsystem::COUNT],
+    since_boot: [u32; Subsystem::COUNT],
+}
+
+static REGISTRY: Mutex<CriticalSectionRawMutex, RefCell<Registry>> = Mutex::new(RefCell::new(Registry {
+    status: [Status::Ok; Subsystem::COUNT],
+    stored: [0; Subsystem::COUNT],
+    since_boot: [0; Subsystem::COUNT],
+}));
+
+// Count one error of a subsystem
+pub fn error(subsystem: Subsystem) {
+    REGISTRY.lock(|registry| {
+        let count = &mut registry.borrow_mut().since_boot[subsystem as usize];
+        *count = count.saturating_add(1);
+    });
+}
+
+pub fn set_status(subsystem: Subsystem, status: Status) {
+    REGISTRY.lock(|registry| registry.borrow_mut().status[subsystem as usize] = status);
+}
+
+pub fn reports() -> [Report; Subsystem::COUNT] {
+    REGISTRY.lock(|registry| {
+        let registry = registry.borrow();
+        Subsystem::ALL.map(|subsystem| {
+            let index = subsystem as usize;
+            Report {
+                subsystem,
+                status: registry.status[index],
+                errors: registry.stored[index].saturating_add(registry.since_boot[index]),
+                errors_since_boot: registry.since_boot[index],
+            }
+        })
+    })
+}
+
+// The worst status of any subsystem
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn verdict() -> Status {
+    REGISTRY.lock(|registry| registry.borrow().status.into_iter().max().unwrap_or(Status::Ok))
+}
+
+fn totals() -> [u32; Subsystem::COUNT] {
+    reports().map(|report| report.errors)
+}
+
+// Read the stored counts, call once at startup after storage::init
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::HEALTH, &mut buffer) {
+        warn!("Failed to read error counters: {}", e);
+        return;
+    }
+    if buffer[..4] != HEALTH_MAGIC.to_le_bytes() {
+        return;
+    }
+    let mut stored = [0; Subsystem::COUNT];
+    for (index, count) in stored.iter_mut().enumerate() {
+        let start = 4 + 4 * index;
+        *count = u32::from_le_bytes([buffer[start], buffer[start + 1], buffer[start + 2], buffer[start + 3]]);
+    }
+    REGISTRY.lock(|registry| registry.borrow_mut().stored = stored);
+}
+
+// Write the counts as they are now
+pub fn save() {
+    // copied out first, a failing write counts a flash error itself
+    let totals = totals();
+    let mut buffer = [0xFF; ERASE_SIZE];
+    buffer[..4].copy_from_slice(&HEALTH_MAGIC.to_le_bytes());
+    for (index, count) in totals.iter().enumerate() {
+        buffer[4 + 4 * index..8 + 4 * index].copy_from_slice(&count.to_le_bytes());
+    }
+    if let Err(e) = storage::write_sector(partitions::HEALTH, &buffer) {
+        warn!("Failed to save error counters: {}", e);
+    }
+}
+
+// Stores the counts every now and then, when there are new errors
+#[embassy_executor::task]
+pub async fn storage_task() {
+    let mut saved = totals();
+    let mut ticker = Ticker::every(SAVE_INTERVAL);
+
+    loop {
+        ticker.next().await;
+        let current = totals();
+        if current != saved {
+            save();
+            saved = current;
+        }
+    }
+}
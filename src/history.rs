@@ -0,0 +1,105 @@
+//! The last five minutes of distances, for the dashboard's chart.
+//!
+//! Once a second [`history_task`] takes the latest distance of every channel
+//! into a ring of [`SAMPLES`]. As JSON that many samples wouldn't fit a
+//! response, so `GET /api/history` sends them in a compact text form:
+//!
+//! ```text
+//! H1 <interval ms> <samples> <age of the newest in ms>
+//! L:<values>
+//! R:<values>
+//! OH:<values>
+//! RR:<values>
+//! ```
+//!
+//! A channel's values are its samples, oldest first: 0 without a reading
+//! (the channel off, or nothing measured for a while) and otherwise the
+//! distance in cm plus 1. Each is sent as its difference to the one before
+//! (the first to 0) in base64 VLQ, as source maps use it: the difference
+//! doubled, plus 1 if it is negative, split into groups of 5 bits, lowest
+//! first, each group a digit of `A-Za-z0-9+/` with 32 added to all but the
+//! last. A distance that holds steady costs one character per sample, so
+//! five minutes of four channels are a few KB at most. `assets/history.js`
+//! decodes it for the dashboard.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as FmtWrite};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::HistoryBuffer;
+
+use crate::channels;
+use crate::events::Side;
+use crate::zone_state;
+
+// Bump when the format changes
+const FORMAT_VERSION: u8 = 1;
+pub const INTERVAL: Duration = Duration::from_secs(1);
+pub const SAMPLES: usize = 300;
+const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// One value per channel, 0 without a reading and the distance in cm plus 1 otherwise
+type Sample = [u16; channels::COUNT];
+
+struct History {
+    samples: HistoryBuffer<Sample, SAMPLES>,
+    newest_ms: u64,
+}
+
+static HISTORY: Mutex<CriticalSectionRawMutex, RefCell<History>> =
+    Mutex::new(RefCell::new(History { samples: HistoryBuffer::new(), newest_ms: 0 }));
+
+// Records a sample every INTERVAL
+#[embassy_executor::task]
+pub async fn history_task() {
+    let mut ticker = Ticker::every(INTERVAL);
+    loop {
+        ticker.next().await;
+        let now_ms = Instant::now().as_millis();
+        // a stale state is a sensing loop that stopped, no readings then
+        let state = zone_state::latest().filter(|state| !state.is_stale(now_ms));
+        let sample = Side::ALL.map(|side| match state.as_ref().and_then(|state| state.side(side)) {
+            Some(reading) => (reading.distance_cm as u16).saturating_add(1),
+            None => 0,
+        });
+        HISTORY.lock(|history| {
+            let mut history = history.borrow_mut();
+            history.samples.write(sample);
+            history.newest_ms = now_ms;
+        });
+    }
+}
+
+// The history in the format above
+pub fn write(out: &mut impl FmtWrite, now_ms: u64) -> fmt::Result {
+    // copied out, formatting takes too long to hold the lock
+    let (samples, newest_ms) = HISTORY.lock(|history| {
+        let history = history.borrow();
+        (history.samples.clone(), history.newest_ms)
+    });
+    let age_ms = if samples.is_empty() { 0 } else { now_ms.saturating_sub(newest_ms) };
+    write!(out, "H{} {} {} {}\n", FORMAT_VERSION, INTERVAL.as_millis(), samples.len(), age_ms)?;
+    for side in Side::ALL {
+        write!(out, "{}:", side.protocol_name().tag())?;
+        let mut previous = 0;
+        for sample in samples.oldest_ordered() {
+            let value = sample[side as usize];
+            write_vlq(out, value as i32 - previous as i32)?;
+            previous = value;
+        }
+        out.write_char('\n')?;
+    }
+    Ok(())
+}
+
+fn write_vlq(out: &mut impl FmtWrite, value: i32) -> fmt::Result {
+    let mut rest = (value.unsigned_abs() << 1) | (value < 0) as u32;
+    loop {
+        let digit = rest & 0x1F;
+        rest >>= 5;
+        if rest == 0 {
+            return out.write_char(DIGITS[digit as usize] as char);
+        }
+        out.write_char(DIGITS[(digit | 0x20) as usize] as char)?;
+    }
+}
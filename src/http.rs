@@ -0,0 +1,127 @@
+//! Minimal HTTP/1.1 request parsing and response helpers for the web server.
+
+use core::fmt::Write as FmtWrite;
+use heapless::String;
+use serde::Serialize;
+
+use crate::clock;
+
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+    // The Cookie header, "" without one
+    pub cookies: &'a str,
+    // The X-Admin-Token header, "" without one
+    pub admin_token: &'a str,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    pub fn body_str(&self) -> Option<&'a str> {
+        core::str::from_utf8(self.body).ok()
+    }
+}
+
+// Parse a request from the bytes received so far. Ok(None) means the headers or
+// the body announced by Content-Length haven't fully arrived yet.
+pub fn parse(buffer: &[u8]) -> Result<Option<Request<'_>>, &'static str> {
+    let Some(header_end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+
+    let head = core::str::from_utf8(&buffer[..header_end]).map_err(|_| "Headers are not valid UTF-8")?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().filter(|method| !method.is_empty()).ok_or("Missing method")?;
+    let target = request_line.next().ok_or("Missing request target")?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_length = 0;
+    let mut cookies = "";
+    let mut admin_token = "";
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().map_err(|_| "Bad Content-Length")?;
+            } else if name.trim().eq_ignore_ascii_case("cookie") {
+                cookies = value.trim();
+            } else if name.trim().eq_ignore_ascii_case("x-admin-token") {
+                admin_token = value.trim();
+            }
+        }
+    }
+
+    let body_start = header_end + 4;
+    if buffer.len() < body_start + content_length {
+        return Ok(None);
+    }
+
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        cookies,
+        admin_token,
+        body: &buffer[body_start..body_start + content_length],
+    }))
+}
+
+// Value of `name` in a form encoded body or query string like "a=1&b=2"
+pub fn form_param<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.trim()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Value of cookie `name` in a Cookie header like "a=1; b=2"
+pub fn cookie<'a>(cookies: &'a str, name: &str) -> Option<&'a str> {
+    cookies
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Status line and headers, the body follows
+pub fn write_headers<const N: usize>(response: &mut String<N>, status: &str, content_type: &str) {
+    write_headers_with(response, status, content_type, format_args!(""));
+}
+
+// Same with more headers, each ending in "\r\n"
+pub fn write_headers_with<const N: usize>(response: &mut String<N>, status: &str, content_type: &str, extra: core::fmt::Arguments) {
+    let _ = FmtWrite::write_fmt(response, format_args!("HTTP/1.1 {}\r\n", status));
+    let _ = FmtWrite::write_fmt(response, format_args!("Content-Type: {}\r\n", content_type));
+    let _ = FmtWrite::write_fmt(response, extra);
+    // a client seeing it go backwards knows the device restarted
+    let _ = FmtWrite::write_fmt(response, format_args!("X-Uptime-Ms: {}\r\n", clock::uptime_ms()));
+    let _ = FmtWrite::write_str(response, "Connection: close\r\n");
+    let _ = FmtWrite::write_str(response, "\r\n");
+}
+
+// Complete response with a short plain text body
+pub fn text_response<const N: usize>(status: &str, body: &str) -> String<N> {
+    let mut response = String::new();
+    write_headers(&mut response, status, "text/plain");
+    let _ = FmtWrite::write_str(&mut response, body);
+    response
+}
+
+// Complete JSON response for `value`. It is serialized into `buffer` first, so
+// the caller decides how big the body may get, and a body that doesn't fit
+// (there or in the response) turns into a 500 rather than cut off JSON.
+pub fn json_response<T: Serialize, const N: usize>(value: &T, buffer: &mut [u8]) -> String<N> {
+    let body = serde_json_core::to_slice(value, buffer)
+        .ok()
+        .and_then(|len| core::str::from_utf8(&buffer[..len]).ok());
+
+    let mut response = String::new();
+    write_headers(&mut response, "200 OK", "application/json");
+    match body.map(|body| response.push_str(body)) {
+        Some(Ok(())) => response,
+        _ => text_response("500 Internal Server Error", "Response too large"),
+    }
+}
@@ -0,0 +1,171 @@
+//! MPU-6050 accelerometer on I2C0 (SDA GPIO4, SCL GPIO5), used to tell whether
+//! the user is standing still, walking or walking fast.
+//!
+//! Only the acceleration magnitude matters, so mounting orientation doesn't.
+//! The device works without the IMU; [`motion`] then stays `Unknown`.
+//!
+//! On a wheelchair it also watches for the chair rolling backwards, which
+//! switches on the rear sensor. That needs the board mounted with the IMU's
+//! X axis pointing forward: the acceleration along it, less its slow average
+//! (gravity on a tilted mount), is integrated into a leaky speed estimate.
+//! Without a working IMU [`reversing`] always says yes, so the rear sensor
+//! is read all the time rather than never.
+//!
+//! Lying on a table, no sample strays far from the slow average magnitude,
+//! which even a user standing still can't manage for long. [`still_for_ms`]
+//! tells [`parked`](crate::parked) how long that has been the case and
+//! [`moved`] wakes it the moment it isn't.
+
+use core::cell::Cell;
+use embassy_rp::i2c::{Async, I2c};
+use embassy_rp::peripherals::I2C0;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_hal_async::i2c::I2c as _;
+
+use crate::logging::{Scope, info, warn};
+
+const SCOPE: Scope = Scope::Sensing;
+
+const ADDRESS: u8 = 0x68;
+const REG_PWR_MGMT_1: u8 = 0x6B;
+const REG_ACCEL_XOUT_H: u8 = 0x3B;
+// At the default +-2 g range
+const LSB_PER_G: f32 = 16384.0;
+
+const SAMPLE_MS: u64 = 20;
+// How quickly the activity estimate follows changes, per sample
+const ACTIVITY_SMOOTHING: f32 = 0.05;
+// Average deviation from 1 g (in g) separating the motion classes
+const WALKING_ACTIVITY: f32 = 0.04;
+const FAST_WALKING_ACTIVITY: f32 = 0.25;
+
+// How quickly the gravity estimate on the forward axis follows, per sample
+const GRAVITY_SMOOTHING: f32 = 0.002;
+// Share of the speed estimate kept per sample, forgets drift within a few seconds
+const SPEED_LEAK: f32 = 0.98;
+// Backward speed (m/s) that counts as reversing
+const REVERSING_MPS: f32 = 0.05;
+// A steady speed doesn't show up as acceleration, so reversing holds this long
+const REVERSING_HOLD_MS: u64 = 5000;
+const STANDARD_GRAVITY: f32 = 9.81;
+
+// How quickly the average magnitude follows, per sample
+const AVERAGE_SMOOTHING: f32 = 0.01;
+// A sample further than this (in g) from the average moved the device, well
+// above the sensor's own noise
+const MOVED_G: f32 = 0.03;
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Motion {
+    Unknown,
+    Stationary,
+    Walking,
+    FastWalking,
+}
+
+impl Motion {
+    pub fn name(self) -> &'static str {
+        match self {
+            Motion::Unknown => "unknown",
+            Motion::Stationary => "stationary",
+            Motion::Walking => "walking",
+            Motion::FastWalking => "walking fast",
+        }
+    }
+
+    pub fn is_moving(self) -> bool {
+        matches!(self, Motion::Walking | Motion::FastWalking)
+    }
+}
+
+static MOTION: Mutex<CriticalSectionRawMutex, Cell<Motion>> = Mutex::new(Cell::new(Motion::Unknown));
+static REVERSING_UNTIL_MS: Mutex<CriticalSectionRawMutex, Cell<u64>> = Mutex::new(Cell::new(0));
+static MOVED_AT_MS: Mutex<CriticalSectionRawMutex, Cell<u64>> = Mutex::new(Cell::new(0));
+static MOVED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Latest motion estimate
+pub fn motion() -> Motion {
+    MOTION.lock(|motion| motion.get())
+}
+
+// Whether a wheelchair is rolling backwards, or might be since the IMU isn't working
+pub fn reversing() -> bool {
+    motion() == Motion::Unknown || Instant::now().as_millis() < REVERSING_UNTIL_MS.lock(|until| until.get())
+}
+
+// How long the device has been lying perfectly still, None without a working IMU
+pub fn still_for_ms(now_ms: u64) -> Option<u64> {
+    if motion() == Motion::Unknown {
+        return None;
+    }
+    Some(now_ms.saturating_sub(MOVED_AT_MS.lock(|at| at.get())))
+}
+
+// Returns on the first sample after the call that moved the device
+pub async fn moved() {
+    MOVED.reset();
+    MOVED.wait().await;
+}
+
+#[cfg_attr(not(feature = "imu"), allow(dead_code))]
+#[embassy_executor::task]
+pub async fn imu_task(mut i2c: I2c<'static, I2C0, Async>) {
+    // wake the sensor up, it starts in sleep mode
+    if let Err(e) = i2c.write(ADDRESS, &[REG_PWR_MGMT_1, 0]).await {
+        warn!("No IMU found ({:?}), motion context disabled", e);
+        return;
+    }
+    info!("IMU initialized");
+
+    let mut activity = 0.0;
+    let mut gravity_x: Option<f32> = None;
+    let mut speed_mps = 0.0;
+    let mut average: Option<f32> = None;
+    let mut ticker = Ticker::every(Duration::from_millis(SAMPLE_MS));
+
+    loop {
+        ticker.next().await;
+
+        let mut raw = [0; 6];
+        if let Err(e) = i2c.write_read(ADDRESS, &[REG_ACCEL_XOUT_H], &mut raw).await {
+            warn!("IMU read failed: {:?}", e);
+            MOTION.lock(|motion| motion.set(Motion::Unknown));
+            continue;
+        }
+
+        let axis = |i: usize| i16::from_be_bytes([raw[i], raw[i + 1]]) as f32 / LSB_PER_G;
+        let (x, y, z) = (axis(0), axis(2), axis(4));
+        let magnitude = libm::sqrtf(x * x + y * y + z * z);
+
+        // steps show up as the magnitude swinging around 1 g
+        activity += ACTIVITY_SMOOTHING * (libm::fabsf(magnitude - 1.0) - activity);
+
+        let current = if activity >= FAST_WALKING_ACTIVITY {
+            Motion::FastWalking
+        } else if activity >= WALKING_ACTIVITY {
+            Motion::Walking
+        } else {
+            Motion::Stationary
+        };
+        MOTION.lock(|motion| motion.set(current));
+
+        // any jolt at all counts, even one too small to change the motion class
+        let average = average.get_or_insert(magnitude);
+        if libm::fabsf(magnitude - *average) > MOVED_G {
+            MOVED_AT_MS.lock(|at| at.set(Instant::now().as_millis()));
+            MOVED.signal(());
+        }
+        *average += AVERAGE_SMOOTHING * (magnitude - *average);
+
+        // starting from the first sample, the mount is tilted from the start
+        let gravity = gravity_x.get_or_insert(x);
+        *gravity += GRAVITY_SMOOTHING * (x - *gravity);
+        speed_mps = speed_mps * SPEED_LEAK + (x - *gravity) * STANDARD_GRAVITY * SAMPLE_MS as f32 / 1000.0;
+        if speed_mps < -REVERSING_MPS {
+            let until_ms = Instant::now().as_millis() + REVERSING_HOLD_MS;
+            REVERSING_UNTIL_MS.lock(|until| until.set(until_ms));
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Ambient ultrasound interference detection.
+//!
+//! Another HC-SR04 nearby (a second user, a parking sensor, some motion
+//! detectors) can make our echo pin fire without anything in front of us,
+//! which shows up as phantom obstacles. Every few seconds each sensor listens
+//! for a short window without triggering. Our own sensor stays quiet then, so
+//! an echo means someone else is sending on 40 kHz. While that is suspected
+//! the pipeline needs more readings in a row before it moves to a closer zone.
+
+use crate::events::{self, Event, Side};
+use crate::logging::{Scope, info, warn};
+
+const SCOPE: Scope = Scope::Sensing;
+
+// Time between listen-only windows
+pub const LISTEN_INTERVAL_MS: u64 = 5000;
+// Long enough to catch the 60 ms cycle most ultrasonic rangers use
+pub const LISTEN_WINDOW_MS: u32 = 70;
+// How long one heard echo keeps interference suspected
+const HOLD_MS: u64 = 10_000;
+
+pub struct InterferenceDetector {
+    next_listen_ms: u64,
+    suspected_until_ms: Option<u64>,
+}
+
+impl InterferenceDetector {
+    pub const fn new() -> Self {
+        Self { next_listen_ms: LISTEN_INTERVAL_MS, suspected_until_ms: None }
+    }
+
+    // Whether it's time for another round of listening
+    pub fn listen_due(&mut self, now_ms: u64) -> bool {
+        if now_ms < self.next_listen_ms {
+            return false;
+        }
+        self.next_listen_ms = now_ms + LISTEN_INTERVAL_MS;
+        true
+    }
+
+    // Result of listening on one sensor
+    pub fn record(&mut self, side: Side, heard: bool, now_ms: u64) {
+        if !heard {
+            return;
+        }
+        if self.suspected_until_ms.is_none() {
+            warn!("Ultrasound interference suspected on {}", side);
+            events::publish(Event::InterferenceSuspected { side });
+        }
+        self.suspected_until_ms = Some(now_ms + HOLD_MS);
+    }
+
+    // Whether interference is currently suspected, also ends it once quiet long enough
+    pub fn suspected(&mut self, now_ms: u64) -> bool {
+        match self.suspected_until_ms {
+            Some(until_ms) if now_ms >= until_ms => {
+                self.suspected_until_ms = None;
+                info!("No more ultrasound interference");
+                events::publish(Event::InterferenceCleared);
+                false
+            }
+            suspected => suspected.is_some(),
+        }
+    }
+}
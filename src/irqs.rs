@@ -15,11 +15,21 @@
 
 use embassy_rp::bind_interrupts;
 // You can import here and alias the handlers.
+use embassy_rp::i2c::InterruptHandler as I2cInterruptHandler;
+use embassy_rp::peripherals::{I2C0, PIO1, PIO2, UART0, USB};
+use embassy_rp::pio::InterruptHandler as PioInterruptHandler;
+use embassy_rp::uart::BufferedInterruptHandler as UartInterruptHandler;
+use embassy_rp::usb::InterruptHandler as UsbInterruptHandler;
 
 bind_interrupts!(
     pub(super) struct Irqs {
         // Here you add all you bindings following the example from the module's top.
         // You can see all interrupt ids defined here: https://datasheets.raspberrypi.com/rp2350/rp2350-datasheet.pdf
         // in the "3.2. Interrupts" section.
+        I2C0_IRQ => I2cInterruptHandler<I2C0>;
+        PIO1_IRQ_0 => PioInterruptHandler<PIO1>;
+        PIO2_IRQ_0 => PioInterruptHandler<PIO2>;
+        UART0_IRQ => UartInterruptHandler<UART0>;
+        USBCTRL_IRQ => UsbInterruptHandler<USB>;
     }
 );
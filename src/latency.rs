@@ -0,0 +1,150 @@
+//! How long an obstacle takes from the sensor to the user's skin.
+//!
+//! Three points of every sensing cycle are timestamped: the first echo the
+//! cycle captures ([`echo`]), the zone state it ends in going out to feedback
+//! ([`output`]) and the next motor starting from rest ([`motor_on`]). A
+//! state that starts no motor before the next one replaces it isn't counted,
+//! there was nothing to feel. The last [`SAMPLES`] of each stretch are kept
+//! and `GET /api/metrics` reports their min, average, 99th percentile and
+//! max in µs.
+//!
+//! With the `latency-probe` feature GPIO7 is high from the echo until the
+//! motor starts, for a scope next to the echo and motor pins. A cycle that
+//! starts no motor ends its pulse when the next one's echo comes in.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+#[cfg(feature = "latency-probe")]
+use embassy_rp::gpio::Output;
+use heapless::HistoryBuffer;
+use serde::Serialize;
+
+pub const SAMPLES: usize = 128;
+
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct Distribution {
+    pub samples: usize,
+    pub min_us: u32,
+    pub avg_us: u32,
+    pub p99_us: u32,
+    pub max_us: u32,
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub struct Report {
+    pub echo_to_output: Distribution,
+    pub output_to_motor: Distribution,
+    pub echo_to_motor: Distribution,
+}
+
+struct Latency {
+    // the first echo of the cycle running now
+    echo_us: Option<u64>,
+    // echo and output of the last state out, until a motor starts
+    pending: Option<(u64, u64)>,
+    echo_to_output: HistoryBuffer<u32, SAMPLES>,
+    output_to_motor: HistoryBuffer<u32, SAMPLES>,
+    echo_to_motor: HistoryBuffer<u32, SAMPLES>,
+    #[cfg(feature = "latency-probe")]
+    probe: Option<Output<'static>>,
+}
+
+static LATENCY: Mutex<CriticalSectionRawMutex, RefCell<Latency>> = Mutex::new(RefCell::new(Latency {
+    echo_us: None,
+    pending: None,
+    echo_to_output: HistoryBuffer::new(),
+    output_to_motor: HistoryBuffer::new(),
+    echo_to_motor: HistoryBuffer::new(),
+    #[cfg(feature = "latency-probe")]
+    probe: None,
+}));
+
+// The scope pin, low until the first echo
+#[cfg(feature = "latency-probe")]
+pub fn init_probe(probe: Output<'static>) {
+    LATENCY.lock(|latency| latency.borrow_mut().probe = Some(probe));
+}
+
+// A sensor answered, only the cycle's first counts
+pub fn echo() {
+    let now_us = Instant::now().as_micros();
+    LATENCY.lock(|latency| {
+        let mut latency = latency.borrow_mut();
+        if latency.echo_us.is_some() {
+            return;
+        }
+        latency.echo_us = Some(now_us);
+        #[cfg(feature = "latency-probe")]
+        if let Some(probe) = latency.probe.as_mut() {
+            // the last cycle's pulse if it started no motor, then this one's
+            probe.set_low();
+            probe.set_high();
+        }
+    });
+}
+
+// The cycle's zone state went out to feedback
+pub fn output() {
+    let now_us = Instant::now().as_micros();
+    LATENCY.lock(|latency| {
+        let mut latency = latency.borrow_mut();
+        let Some(echo_us) = latency.echo_us.take() else {
+            return;
+        };
+        latency.echo_to_output.write(elapsed_us(echo_us, now_us));
+        latency.pending = Some((echo_us, now_us));
+    });
+}
+
+// A motor started from rest
+pub fn motor_on() {
+    let now_us = Instant::now().as_micros();
+    LATENCY.lock(|latency| {
+        let mut latency = latency.borrow_mut();
+        let Some((echo_us, output_us)) = latency.pending.take() else {
+            return;
+        };
+        latency.output_to_motor.write(elapsed_us(output_us, now_us));
+        latency.echo_to_motor.write(elapsed_us(echo_us, now_us));
+        #[cfg(feature = "latency-probe")]
+        if let Some(probe) = latency.probe.as_mut() {
+            probe.set_low();
+        }
+    });
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn report() -> Report {
+    LATENCY.lock(|latency| {
+        let latency = latency.borrow();
+        Report {
+            echo_to_output: distribution(&latency.echo_to_output),
+            output_to_motor: distribution(&latency.output_to_motor),
+            echo_to_motor: distribution(&latency.echo_to_motor),
+        }
+    })
+}
+
+fn elapsed_us(from_us: u64, to_us: u64) -> u32 {
+    to_us.saturating_sub(from_us).min(u32::MAX as u64) as u32
+}
+
+fn distribution(samples: &HistoryBuffer<u32, SAMPLES>) -> Distribution {
+    if samples.is_empty() {
+        return Distribution::default();
+    }
+    let mut sorted = [0; SAMPLES];
+    let sorted = &mut sorted[..samples.len()];
+    sorted.copy_from_slice(samples.as_slice());
+    sorted.sort_unstable();
+    let sum: u64 = sorted.iter().map(|&us| us as u64).sum();
+    Distribution {
+        samples: sorted.len(),
+        min_us: sorted[0],
+        avg_us: (sum / sorted.len() as u64) as u32,
+        // the sample 99% of the others are at or below
+        p99_us: sorted[(sorted.len() * 99).div_ceil(100) - 1],
+        max_us: sorted[sorted.len() - 1],
+    }
+}
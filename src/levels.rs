@@ -0,0 +1,101 @@
+//! Master volume and haptic strength.
+//!
+//! Two percentages in the configuration, applied after all pattern logic:
+//! `levels.haptic_percent` scales every duty the motors are driven at and
+//! `levels.volume_percent` the headset's volume (see
+//! [`headset`](crate::headset); the buzzer can only be on or off). Neither
+//! goes below [`MIN_LEVEL_PERCENT`], so warnings can't be silenced.
+//!
+//! Button chords step both together by [`STEP_PERCENT`]: a press followed by
+//! a held one turns them up, two presses followed by a held one down, and
+//! the device answers with a beep and a buzz at the new levels. The tuning
+//! page and `POST /api/levels` set them one at a time. However they change,
+//! [`storage_task`] writes them to their own flash sector a few seconds
+//! later, so they survive a reboot without a flash write per button press.
+
+use embassy_time::Timer;
+
+use crate::config::{self, Config, LevelsConfig, MIN_LEVEL_PERCENT};
+use crate::feedback::{self, FeedbackCommand, Pattern, Priority};
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+use crate::usage;
+
+const SCOPE: Scope = Scope::Feedback;
+
+// Change when the stored layout changes, old data is then ignored
+const LEVELS_MAGIC: u32 = 0x4c56_4c31;
+
+pub const STEP_PERCENT: u8 = 10;
+// How often the levels are checked for changes to store
+const SAVE_CHECK_MS: u64 = 5000;
+
+fn read() -> Option<LevelsConfig> {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::LEVELS, &mut buffer) {
+        warn!("Failed to read master levels: {}", e);
+        return None;
+    }
+    if buffer[..4] != LEVELS_MAGIC.to_le_bytes() {
+        return None;
+    }
+    let levels = LevelsConfig { volume_percent: buffer[4], haptic_percent: buffer[5] };
+    levels.validate().ok().map(|()| levels)
+}
+
+// Apply the stored levels, call once at startup after the profiles
+pub fn load() {
+    if let Some(levels) = read() {
+        info!("Master volume {}%, haptic strength {}%", levels.volume_percent, levels.haptic_percent);
+        config::update(|config| config.levels = levels);
+    }
+}
+
+// One chord's worth up or down, both levels together
+pub fn step(up: bool) {
+    let step = |percent: u8| {
+        if up {
+            percent.saturating_add(STEP_PERCENT).min(100)
+        } else {
+            percent.saturating_sub(STEP_PERCENT).max(MIN_LEVEL_PERCENT)
+        }
+    };
+    let mut levels = config::get().levels;
+    levels.volume_percent = step(levels.volume_percent);
+    levels.haptic_percent = step(levels.haptic_percent);
+    config::update(|config| config.levels = levels);
+    info!("Master volume {}%, haptic strength {}%", levels.volume_percent, levels.haptic_percent);
+
+    // felt and heard at the new levels, at either end the cue just stays the same
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Levels,
+        side: None,
+        priority: Priority::Normal,
+        ttl_ms: 1000,
+    });
+}
+
+// Stores the levels once they have changed, checking every few seconds. Not
+// in safe mode, where the defaults would replace the stored levels.
+#[embassy_executor::task]
+pub async fn storage_task() {
+    let mut stored = read().unwrap_or(Config::DEFAULT.levels);
+
+    loop {
+        Timer::after_millis(SAVE_CHECK_MS).await;
+        let levels = config::get().levels;
+        if levels == stored || usage::safe_mode() {
+            continue;
+        }
+
+        let mut buffer = [0xFF; ERASE_SIZE];
+        buffer[..4].copy_from_slice(&LEVELS_MAGIC.to_le_bytes());
+        buffer[4] = levels.volume_percent;
+        buffer[5] = levels.haptic_percent;
+        match storage::write_sector(partitions::LEVELS, &buffer) {
+            Ok(()) => stored = levels,
+            Err(e) => warn!("Failed to store master levels: {}", e),
+        }
+    }
+}
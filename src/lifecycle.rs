@@ -0,0 +1,139 @@
+//! What the device as a whole is doing.
+//!
+//! ```text
+//! Boot → SelfTest → Provisioning ⇄ Active ⇄ Standby
+//!                        ↘          ↓          ↙
+//!                               Fault
+//! ```
+//!
+//! The device boots into [`State::Boot`] while the flash is read and the tasks
+//! start. The first sensing cycles are the self-test, which lasts until every
+//! channel that is on has given a reading or been counted as degraded. After
+//! that the sensing loop settles the state once per cycle:
+//!
+//! - `Fault` when no sensor gives usable readings, the device can't guide
+//! - `Standby` while it lies still somewhere, see [`parked`](crate::parked)
+//! - `Provisioning` while no WiFi passphrase is set and the access point is
+//!   open, never in a build without WiFi
+//! - `Active` otherwise
+//!
+//! Each change runs the entry and exit actions below, is logged and goes out
+//! as a `Lifecycle` event. `GET /api/health` and the dashboard show the state.
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+use crate::config::HapticPattern;
+use crate::events::{self, Event};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::health::Status;
+use crate::logging::{Scope, info, warn};
+use crate::secrets;
+
+const SCOPE: Scope = Scope::System;
+
+// A short buzz on both motors, the device is guiding
+const READY_CUE: [u16; 1] = [150];
+// Three quick beeps, the access point is still open
+const PROVISIONING_CUE: [u16; 5] = [60, 120, 60, 120, 60];
+// One long beep, don't rely on the device
+const FAULT_CUE: [u16; 1] = [800];
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum State {
+    Boot,
+    SelfTest,
+    Provisioning,
+    Active,
+    Standby,
+    Fault,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Boot => "boot",
+            State::SelfTest => "self-test",
+            State::Provisioning => "provisioning",
+            State::Active => "active",
+            State::Standby => "standby",
+            State::Fault => "fault",
+        }
+    }
+
+    // Boot and the self-test only go forward, the running states change freely
+    fn allows(self, to: State) -> bool {
+        match (self, to) {
+            (State::Boot, State::SelfTest) => true,
+            (State::SelfTest, State::Provisioning | State::Active | State::Fault) => true,
+            (State::Provisioning | State::Active | State::Standby | State::Fault, State::Provisioning | State::Active | State::Standby | State::Fault) => {
+                self != to
+            },
+            _ => false,
+        }
+    }
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> = Mutex::new(Cell::new(State::Boot));
+
+pub fn state() -> State {
+    STATE.lock(|state| state.get())
+}
+
+// Change to another state, running the exit and entry actions
+pub fn enter(to: State) {
+    let from = state();
+    if from == to {
+        return;
+    }
+    if !from.allows(to) {
+        warn!("No transition from {} to {}", from.name(), to.name());
+        return;
+    }
+
+    exit_actions(from);
+    STATE.lock(|state| state.set(to));
+    info!("Lifecycle: {} -> {}", from.name(), to.name());
+    entry_actions(from, to);
+    events::publish(Event::Lifecycle { state: to });
+}
+
+// Once per sensing cycle after the self-test, from the sensors' health and
+// whether the device was left lying still
+pub fn update(sensors: Status, parked: bool) {
+    let to = if sensors == Status::Fault {
+        State::Fault
+    } else if parked {
+        State::Standby
+    } else if cfg!(feature = "wifi") && secrets::passphrase().is_none() {
+        State::Provisioning
+    } else {
+        State::Active
+    };
+    enter(to);
+}
+
+fn exit_actions(from: State) {
+    if from == State::Fault {
+        info!("Sensors answering again");
+    }
+}
+
+fn entry_actions(from: State, to: State) {
+    match to {
+        // picked up again the user knows it is on, only a fresh start gets the cue
+        State::Active if from != State::Standby => cue(&READY_CUE, PreviewTarget::Both, Priority::Normal),
+        State::Provisioning if from == State::SelfTest => cue(&PROVISIONING_CUE, PreviewTarget::Buzzer, Priority::Normal),
+        State::Fault => cue(&FAULT_CUE, PreviewTarget::Buzzer, Priority::High),
+        _ => {},
+    }
+}
+
+fn cue(steps: &[u16], target: PreviewTarget, priority: Priority) {
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Preview { steps: HapticPattern::new(steps), target },
+        side: None,
+        priority,
+        ttl_ms: 3000,
+    });
+}
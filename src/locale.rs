@@ -0,0 +1,318 @@
+//! Languages for the dashboard and the feedback settings page.
+//!
+//! Everything those pages say comes from a [`Strings`] table, one per
+//! [`Language`], picked by `language` in the configuration (set with
+//! `POST /api/language`). Entries with values are templates, every `{}`
+//! filled in order by [`fill`]; numbers with decimals go through
+//! [`Strings::number`] for the language's decimal separator, and distances
+//! pick it up in [`Distance`](crate::units::Distance).
+//!
+//! Adding a language is a new variant, its name and a table below; the web
+//! server doesn't change. Names that are also API values (profiles, states,
+//! subsystems, ...), the event list and the tuning page stay in English.
+
+use core::fmt;
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, INTENSITY_LEVELS};
+use crate::events::Side;
+use crate::theme::Theme;
+use crate::zone_state::Trend;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Language {
+    English,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::German];
+
+    pub fn name(self) -> &'static str {
+        self.strings().code
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|language| language.name() == name.trim())
+    }
+
+    pub fn strings(self) -> &'static Strings {
+        match self {
+            Language::English => &ENGLISH,
+            Language::German => &GERMAN,
+        }
+    }
+}
+
+// The table of the configured language
+pub fn strings() -> &'static Strings {
+    config::get().language.strings()
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub struct Strings {
+    // ISO 639-1, the language's API name and the pages' lang attribute
+    pub code: &'static str,
+    pub decimal_separator: char,
+
+    // dashboard
+    pub status_title: &'static str,
+    pub feedback_settings: &'static str,
+    pub tuning: &'static str,
+    pub device: &'static str,
+    pub owner: &'static str,
+    pub contact: &'static str,
+    pub maintenance_due: &'static str,
+    pub done: &'static str,
+    pub profile: &'static str,
+    pub motion: &'static str,
+    pub mounting: &'static str,
+    pub paired_linked: &'static str,
+    pub paired_unlinked: &'static str,
+    pub health: &'static str,
+    pub safe_mode: &'static str,
+    pub running_hot: &'static str,
+    pub supply_sagged: &'static str,
+    pub standby: &'static str,
+    pub fault: &'static str,
+    pub state: &'static str,
+    pub not_sensing: &'static str,
+    pub reading_age: &'static str,
+    pub waiting: &'static str,
+    pub sensor: &'static str,
+    pub distance: &'static str,
+    pub noise: &'static str,
+    pub connected_clients: &'static str,
+    pub client: &'static str,
+    pub recent_events: &'static str,
+    pub last_session: &'static str,
+    pub session_summary: &'static str,
+    pub history: &'static str,
+    pub theme: &'static str,
+    // in the order of Theme::ALL
+    pub themes: [&'static str; Theme::ALL.len()],
+    pub device_theme: &'static str,
+    // in the order of Side::ALL
+    pub sides: [&'static str; Side::ALL.len()],
+    // approaching, steady, receding
+    pub trends: [&'static str; 3],
+
+    // feedback settings page
+    pub feedback_title: &'static str,
+    pub haptic_patterns: &'static str,
+    pub pattern_hint: &'static str,
+    pub level: &'static str,
+    // what each intensity level feels like, weakest first
+    pub levels: [&'static str; INTENSITY_LEVELS],
+    pub try_it: &'static str,
+    pub admin_token: &'static str,
+    pub save: &'static str,
+    pub motors: &'static str,
+    pub swap_sides: &'static str,
+    pub invert: &'static str,
+    pub buzzer: &'static str,
+}
+
+impl Strings {
+    pub fn side(&self, side: Side) -> &'static str {
+        self.sides[side as usize]
+    }
+
+    pub fn trend(&self, trend: Trend) -> &'static str {
+        self.trends[trend as usize]
+    }
+
+    // `value` to `places` decimals, with this language's separator
+    #[cfg_attr(not(any(feature = "web", feature = "usb")), allow(dead_code))]
+    pub fn number(&self, value: f32, places: usize) -> Number {
+        Number { value, places, separator: self.decimal_separator }
+    }
+}
+
+pub struct Number {
+    value: f32,
+    places: usize,
+    separator: char,
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut text: String<24> = String::new();
+        fmt::Write::write_fmt(&mut text, format_args!("{:.*}", self.places, self.value))?;
+        match text.split_once('.') {
+            Some((whole, fraction)) => write!(f, "{}{}{}", whole, self.separator, fraction),
+            None => f.write_str(&text),
+        }
+    }
+}
+
+// A template with its `{}`s filled in, for use in format strings
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn fill<'a>(template: &'a str, args: &'a [&'a dyn fmt::Display]) -> Filled<'a> {
+    Filled { template, args }
+}
+
+pub struct Filled<'a> {
+    template: &'a str,
+    args: &'a [&'a dyn fmt::Display],
+}
+
+impl fmt::Display for Filled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = self.template.split("{}");
+        f.write_str(parts.next().unwrap_or(""))?;
+        // a template with more `{}`s than values leaves the rest empty
+        for (index, part) in parts.enumerate() {
+            if let Some(arg) = self.args.get(index) {
+                write!(f, "{}", arg)?;
+            }
+            f.write_str(part)?;
+        }
+        Ok(())
+    }
+}
+
+// Names users give things (profiles, the device and its owner) end up in the
+// pages as they are, so they are kept to printable ASCII without the
+// characters HTML treats specially
+pub fn check_plain(text: &str) -> Result<(), &'static str> {
+    if !text.bytes().all(|byte| (0x20..0x7F).contains(&byte) && !matches!(byte, b'"' | b'&' | b'<' | b'>')) {
+        return Err("Only printable ASCII without \", &, < or > is allowed");
+    }
+    Ok(())
+}
+
+const ENGLISH: Strings = Strings {
+    code: "en",
+    decimal_separator: '.',
+
+    status_title: "VisionAssist Status",
+    feedback_settings: "Feedback settings",
+    tuning: "Tuning",
+    device: "Device: {}",
+    owner: ", owner: {}",
+    contact: ", emergency contact: {}",
+    maintenance_due: "Maintenance due: {}",
+    done: "Done",
+    profile: "Profile: {}",
+    motion: "Motion: {}, zones x{}",
+    mounting: "Mounting: {}",
+    paired_linked: "Paired as the {}, linked, {} ms latency",
+    paired_unlinked: "Paired as the {}, not linked",
+    health: "Health: {}",
+    safe_mode: "Safe mode: default settings, stored ones untouched until changed here. Restart to leave it.",
+    running_hot: "Running hot ({} C), vibration turned down to {}%",
+    supply_sagged: "Supply sagged {} times during motor starts, motor duty capped at {}%",
+    standby: "Standby: left lying still, feedback paused until the device is moved",
+    fault: "Fault: no sensor is answering, don't rely on the device",
+    state: "State: {}",
+    not_sensing: "Not sensing! Last reading {} ms ago",
+    reading_age: "Reading age: {} ms",
+    waiting: "Waiting for the first reading...",
+    sensor: "{} Sensor",
+    distance: "Distance: {} ({})",
+    noise: "Noise: &plusmn;{} {}",
+    connected_clients: "Connected Clients",
+    client: "{} on port {}, {} connections, last {} s ago",
+    recent_events: "Recent Events",
+    last_session: "Last Session",
+    session_summary: "{} min, ended by {}: {} critical alerts, {} warnings, {} sensor faults",
+    history: "Last Five Minutes",
+    theme: "Theme",
+    themes: ["light", "dark", "high contrast"],
+    device_theme: "device default",
+    sides: ["left", "right", "overhead", "rear"],
+    trends: ["approaching", "steady", "receding"],
+
+    feedback_title: "VisionAssist Feedback Settings",
+    haptic_patterns: "Haptic Patterns",
+    pattern_hint: "On/off durations in ms, starting with on, e.g. 80,20,80",
+    level: "Level {} ({})",
+    levels: [
+        "minimal",
+        "very light",
+        "light",
+        "light-medium",
+        "medium",
+        "moderate",
+        "moderate-strong",
+        "strong",
+        "very strong",
+        "maximum",
+    ],
+    try_it: "Try",
+    admin_token: "Admin token",
+    save: "Save",
+    motors: "Motors",
+    swap_sides: "Swap left and right",
+    invert: "Invert GPIO{}",
+    buzzer: "Buzzer",
+};
+
+const GERMAN: Strings = Strings {
+    code: "de",
+    decimal_separator: ',',
+
+    status_title: "VisionAssist Status",
+    feedback_settings: "Rückmeldung einstellen",
+    tuning: "Feinabstimmung",
+    device: "Gerät: {}",
+    owner: ", Besitzer: {}",
+    contact: ", Notfallkontakt: {}",
+    maintenance_due: "Wartung fällig: {}",
+    done: "Erledigt",
+    profile: "Profil: {}",
+    motion: "Bewegung: {}, Zonen x{}",
+    mounting: "Montage: {}",
+    paired_linked: "Gekoppelt als {}, verbunden, {} ms Verzögerung",
+    paired_unlinked: "Gekoppelt als {}, nicht verbunden",
+    health: "Zustand: {}",
+    safe_mode: "Abgesicherter Modus: Standardeinstellungen, gespeicherte bleiben bis zur nächsten Änderung erhalten. Neustart beendet ihn.",
+    running_hot: "Zu warm ({} °C), Vibration auf {}% gedrosselt",
+    supply_sagged: "Versorgung {} Mal beim Motorstart eingebrochen, Motoren auf {}% begrenzt",
+    standby: "Ruhemodus: das Gerät liegt still, Rückmeldung pausiert bis es bewegt wird",
+    fault: "Fehler: kein Sensor antwortet, verlassen Sie sich nicht auf das Gerät",
+    state: "Status: {}",
+    not_sensing: "Keine Messung! Letzter Messwert vor {} ms",
+    reading_age: "Alter des Messwerts: {} ms",
+    waiting: "Warte auf den ersten Messwert...",
+    sensor: "Sensor {}",
+    distance: "Abstand: {} ({})",
+    noise: "Rauschen: &plusmn;{} {}",
+    connected_clients: "Verbundene Clients",
+    client: "{} an Port {}, {} Verbindungen, zuletzt vor {} s",
+    recent_events: "Letzte Ereignisse",
+    last_session: "Letzte Sitzung",
+    session_summary: "{} min, beendet durch {}: {} kritische Warnungen, {} Warnungen, {} Sensorfehler",
+    history: "Letzte fünf Minuten",
+    theme: "Darstellung",
+    themes: ["hell", "dunkel", "hoher Kontrast"],
+    device_theme: "Geräteeinstellung",
+    sides: ["links", "rechts", "oben", "hinten"],
+    trends: ["kommt näher", "gleichbleibend", "entfernt sich"],
+
+    feedback_title: "VisionAssist Rückmeldung",
+    haptic_patterns: "Vibrationsmuster",
+    pattern_hint: "An/Aus-Dauern in ms, beginnend mit an, z. B. 80,20,80",
+    level: "Stufe {} ({})",
+    levels: [
+        "minimal",
+        "sehr leicht",
+        "leicht",
+        "leicht bis mittel",
+        "mittel",
+        "mäßig",
+        "mäßig bis stark",
+        "stark",
+        "sehr stark",
+        "maximal",
+    ],
+    try_it: "Testen",
+    admin_token: "Admin-Token",
+    save: "Speichern",
+    motors: "Motoren",
+    swap_sides: "Links und rechts tauschen",
+    invert: "GPIO{} invertieren",
+    buzzer: "Summer",
+};
@@ -0,0 +1,79 @@
+//! Find my device: a loud, repeating locator tone for a misplaced unit.
+//!
+//! `LOCATE` on a command link or `POST /api/locate` (both admin only) start
+//! it. The buzzer beeps and both motors buzz every two seconds until the
+//! button is pressed, `LOCATE OFF` stops it, or five minutes have passed so a
+//! unit that is really lost doesn't run its battery flat beeping.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::config::HapticPattern;
+use crate::events::{self, Event};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::logging::{Scope, info};
+
+const SCOPE: Scope = Scope::System;
+
+// Three long beeps, then the motors so it can be heard and felt in a bag
+const LOCATOR_TONE: [u16; 5] = [250, 100, 250, 100, 250];
+const LOCATOR_BUZZ: [u16; 3] = [300, 100, 300];
+const REPEAT_MS: u64 = 2000;
+// Gives up after this long
+const TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// true to start, false to stop
+static REQUEST: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+pub fn start() {
+    REQUEST.signal(true);
+}
+
+pub fn stop() {
+    REQUEST.signal(false);
+}
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+fn submit(steps: &[u16], target: PreviewTarget) {
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Preview { steps: HapticPattern::new(steps), target },
+        side: None,
+        // over obstacle warnings, a locator tone with nobody carrying the device is all that matters
+        priority: Priority::High,
+        ttl_ms: REPEAT_MS as u32,
+    });
+}
+
+#[embassy_executor::task]
+pub async fn locator_task() {
+    loop {
+        if !REQUEST.wait().await {
+            continue;
+        }
+        info!("Locator tone started");
+        ACTIVE.store(true, Ordering::Relaxed);
+        events::publish(Event::Locator { active: true });
+
+        let deadline = Instant::now() + Duration::from_millis(TIMEOUT_MS);
+        while Instant::now() < deadline {
+            submit(&LOCATOR_TONE, PreviewTarget::Buzzer);
+            submit(&LOCATOR_BUZZ, PreviewTarget::Both);
+            match select(Timer::after_millis(REPEAT_MS), REQUEST.wait()).await {
+                Either::Second(false) => break,
+                // already on, keep going
+                Either::First(()) | Either::Second(true) => {}
+            }
+        }
+
+        info!("Locator tone stopped");
+        ACTIVE.store(false, Ordering::Relaxed);
+        events::publish(Event::Locator { active: false });
+    }
+}
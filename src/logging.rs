@@ -0,0 +1,141 @@
+//! Log scopes with levels that can be changed at runtime.
+//!
+//! Every module that logs declares which [`Scope`] it belongs to with a
+//! `const SCOPE: Scope`, and imports the macros from here instead of defmt.
+//! They check the scope's current level before handing the message to defmt,
+//! so e.g. feedback can be turned up to debug through `/api/log` during a
+//! tuning session while the web server stays quiet. `DEFMT_LOG` still decides
+//! what is compiled in at all. `target: Scope::X` overrides the scope for a
+//! single message.
+//!
+//! Log lines carry the uptime from embassy_time, so they line up with the
+//! `*_ms` timestamps in readings and events.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+defmt::timestamp!("{=u64:us}", embassy_time::Instant::now().as_micros());
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Scope {
+    System,
+    Sensing,
+    Feedback,
+    Wifi,
+    Web,
+    Tcp,
+    Storage,
+    Serial,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 8] = [
+        Scope::System,
+        Scope::Sensing,
+        Scope::Feedback,
+        Scope::Wifi,
+        Scope::Web,
+        Scope::Tcp,
+        Scope::Storage,
+        Scope::Serial,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::System => "system",
+            Scope::Sensing => "sensing",
+            Scope::Feedback => "feedback",
+            Scope::Wifi => "wifi",
+            Scope::Web => "web",
+            Scope::Tcp => "tcp",
+            Scope::Storage => "storage",
+            Scope::Serial => "serial",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.name() == name.trim())
+    }
+}
+
+// Ordered from most to least verbose
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|level| level.name() == name.trim())
+    }
+}
+
+// Lowest level logged per scope, indexed by Scope. Starts at info for all of
+// them, like DEFMT_LOG in .cargo/config.toml.
+static LEVELS: [AtomicU8; Scope::ALL.len()] = [const { AtomicU8::new(Level::Info as u8) }; Scope::ALL.len()];
+
+pub fn level(scope: Scope) -> Level {
+    Level::ALL[LEVELS[scope as usize].load(Ordering::Relaxed) as usize]
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn set_level(scope: Scope, level: Level) {
+    LEVELS[scope as usize].store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(scope: Scope, level: Level) -> bool {
+    level >= self::level(scope)
+}
+
+// Same as the defmt macros, but only logged when the scope's level allows it.
+// Defined under other names since `warn` would clash with the attribute.
+macro_rules! log_debug {
+    (target: $scope:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($scope, $crate::logging::Level::Debug) {
+            ::defmt::debug!($($arg)+);
+        }
+    };
+    ($($arg:tt)+) => {
+        $crate::logging::debug!(target: SCOPE, $($arg)+)
+    };
+}
+pub(crate) use log_debug as debug;
+
+macro_rules! log_info {
+    (target: $scope:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($scope, $crate::logging::Level::Info) {
+            ::defmt::info!($($arg)+);
+        }
+    };
+    ($($arg:tt)+) => {
+        $crate::logging::info!(target: SCOPE, $($arg)+)
+    };
+}
+pub(crate) use log_info as info;
+
+macro_rules! log_warn {
+    (target: $scope:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($scope, $crate::logging::Level::Warn) {
+            ::defmt::warn!($($arg)+);
+        }
+    };
+    ($($arg:tt)+) => {
+        $crate::logging::warn!(target: SCOPE, $($arg)+)
+    };
+}
+pub(crate) use log_warn as warn;
@@ -3,42 +3,176 @@
 
 use embassy_executor::Spawner;
 use embassy_rp::{
+    adc,
     gpio::{Input, Level, Output, Pull},
+    pwm::{self, Pwm},
+    watchdog::Watchdog,
 };
+#[cfg(feature = "imu")]
+use embassy_rp::i2c::{self, I2c};
+#[cfg(feature = "wifi")]
+use embassy_rp::peripherals::{DMA_CH2, PIN_23, PIN_24, PIN_25, PIN_29, PIO0};
+use embassy_futures::select::select;
 use embassy_time::{Duration, Timer, Instant};
-use defmt::info;
+use heapless::Vec;
+use vision_protocol::zoning::Zone;
+// With debug-tuning rtt_tuning sets up RTT and defmt goes to its channel 0
+#[cfg(not(feature = "debug-tuning"))]
 use defmt_rtt as _; // Import defmt RTT logger
-use panic_probe as _; // Import panic handler
 
 // for handling interrupts and wifi
+mod actuators;
+mod alerts;
+#[cfg(feature = "web")]
+mod assets;
+mod assist;
+mod benchtest;
+mod broadcast;
+mod buffers;
+#[cfg(any(feature = "tcp", feature = "web"))]
+mod busy;
+mod button;
+mod calibration;
+mod channels;
+mod clients;
+mod clock;
+mod commands;
+mod config;
+mod context;
+mod defaults;
+mod diagnostics;
+mod echoes;
+mod events;
+mod factory;
+mod faults;
+mod feedback;
+mod filter;
+#[cfg(feature = "usb")]
+mod fixture;
+mod geometry;
+mod ground;
+mod headset;
+mod health;
+mod history;
+#[cfg(feature = "web")]
+mod http;
+mod imu;
+mod interference;
 mod irqs;
+mod latency;
+mod levels;
+mod lifecycle;
+mod locale;
+mod locate;
+mod logging;
+mod maintenance;
+mod motor;
+mod occupancy;
+mod owner;
+mod pacing;
+mod pairing;
+mod parked;
+mod partitions;
+mod pipeline;
+mod profiles;
+mod radio;
+mod rangefinder;
+mod rendering;
+#[cfg(feature = "debug-tuning")]
+mod rtt_tuning;
+mod secrets;
+mod serial;
+mod session;
+mod shutdown;
+mod simulation;
+mod storage;
+mod stuck;
+mod supply;
+#[cfg(feature = "tcp")]
 mod tcp_server;
+mod telemetry;
+mod theme;
+mod thermal;
+mod transport;
+mod tuning;
+mod units;
+mod usage;
+#[cfg(feature = "web")]
 mod web_server;
+#[cfg(feature = "wifi")]
 mod wifi_utils;
+mod zone_state;
 
-// keeping track of previous distances for smoothing
-struct DistanceState {
-    prev_left: f32,
-    prev_right: f32,
+use context::ContextManager;
+use events::{DegradedReason, Event, Side};
+use feedback::{FeedbackCommand, Pattern, Priority};
+use headset::Headset;
+use health::{Status, Subsystem};
+use interference::{InterferenceDetector, LISTEN_WINDOW_MS};
+use lifecycle::State;
+use logging::{Scope, info, warn};
+use motor::{PwmChannel, PwmMotor};
+use pacing::Pacer;
+use parked::ParkedDetector;
+use pipeline::{Pipeline, RawReading};
+use rangefinder::{DistanceSensor, Rangefinder};
+use stuck::StuckDetector;
+use zone_state::{SideState, TrendTracker, ZoneState};
+
+const SCOPE: Scope = Scope::System;
+
+// One distance channel of the sensing loop, with what it tracks between cycles
+struct SensorChannel {
+    sensor: Rangefinder<'static>,
+    side: Side,
+    // consecutive readings that landed inside the blind zone
+    near_field_streak: u8,
+    // cycles in a row without a valid reading
+    failures: u8,
+    // last zone reported on the event bus
+    zone: Zone,
+    stuck: StuckDetector,
+    trend: TrendTracker,
 }
 
-// basic sensor structure
-struct UltrasonicSensor<'d> {
-    trigger: Output<'d>,
-    echo: Input<'d>,
+impl SensorChannel {
+    fn new(sensor: Rangefinder<'static>, side: Side) -> Self {
+        Self {
+            sensor,
+            side,
+            near_field_streak: 0,
+            failures: 0,
+            zone: Zone::Clear,
+            stuck: StuckDetector::new(),
+            trend: TrendTracker::new(),
+        }
+    }
 }
 
-// these might need adjusting after testing
-const CRITICAL_DISTANCE: f32 = 30.0;  // very close obstacles
-const WARNING_DISTANCE: f32 = 60.0;   // getting closer
-const NOTICE_DISTANCE: f32 = 100.0;   // far enough but worth noting
+// HC-SR04 can't resolve anything closer than this
+const MIN_DETECTABLE_DISTANCE: f32 = 2.0;
+// reported when the near-field pattern is confirmed, well inside the extreme danger zone
+const NEAR_FIELD_DISTANCE: f32 = 0.0;
+const NEAR_FIELD_ERROR: &str = "Echo too short (inside blind zone)";
+const NO_READINGS_ERROR: &str = "Failed to get any valid distance readings";
+// Pings a channel gets per cycle at most, an HC-SR04's three readings and two more tries
+const MAX_ATTEMPTS: usize = 5;
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    #[cfg(feature = "debug-tuning")]
+    let tuning_channels = rtt_tuning::init();
     info!("Starting VisionAssist with WiFi configuration...");
 
     // Initialize the RP2040 and keep a reference to the pins we'll need
-    let p = embassy_rp::init(Default::default());
+    #[cfg_attr(not(feature = "usb"), allow(unused_mut))]
+    let mut p = embassy_rp::init(Default::default());
+    
+    // On the production test fixture the board is tested instead of started
+    #[cfg(feature = "usb")]
+    if fixture::strapped(&mut p.PIN_2).await {
+        fixture::run(spawner, p).await;
+    }
     
     // Save the pins we need for our sensors and feedback BEFORE WiFi init
     let pin_14 = p.PIN_14;
@@ -48,427 +182,487 @@ async fn main(spawner: Spawner) {
     let pin_18 = p.PIN_18;
     let pin_19 = p.PIN_19;
     let pin_20 = p.PIN_20;
+    let pin_21 = p.PIN_21;
+    let pin_22 = p.PIN_22;
+    #[cfg(feature = "imu")]
+    let i2c_0 = p.I2C0;
+    let pin_10 = p.PIN_10;
+    let pin_11 = p.PIN_11;
+    #[cfg(feature = "imu")]
+    let pin_4 = p.PIN_4;
+    #[cfg(feature = "imu")]
+    let pin_5 = p.PIN_5;
+    let pwm_slice_1 = p.PWM_SLICE1;
+    let pwm_slice_2 = p.PWM_SLICE2;
     
-    // Initialize network stack
-    info!("Initializing network stack...");
-    let (stack, socket) = wifi_utils::init_network_stack(
-        &spawner,
-        p.PIN_23,
-        p.PIN_24,
-        p.PIN_25,
-        p.PIN_29,
-        p.PIO0,
-        p.DMA_CH2,
-    ).await;
-    info!("Network stack initialized successfully");
+    // Start logging device events first, loading the settings may report repairs
+    spawner.spawn(events::event_log_task(events::subscribe())).unwrap();
+    spawner.spawn(events::release_task()).unwrap();
+    spawner.spawn(alerts::alerts_task(events::subscribe())).unwrap();
     
-    // Start TCP server
-    spawner.spawn(tcp_server::tcp_server_task(stack, socket)).unwrap();
+    // Count this boot and find out why it happened, which decides whether the
+    // stored settings are used at all
+    storage::init(storage::StorageFlash::new_blocking(p.FLASH));
+    let mut watchdog = Watchdog::new(p.WATCHDOG);
+    usage::load(&mut watchdog);
+    let safe_mode = usage::safe_mode();
     
-    // Start web server
-    spawner.spawn(web_server::web_server_task(stack)).unwrap();
+    // Restore the active profile and master levels before anything reads the configuration,
+    // and the provisioned WiFi secrets, radio switch and pairing role before WiFi starts.
+    // Safe mode keeps only the secrets, so the device comes up on its own access point
+    // with the defaults. The actuator mapping describes the wiring, so it is restored
+    // either way, before the feedback task sets up the motors. The last session's
+    // summary is reported along the way.
+    if safe_mode {
+        warn!("Safe mode, stored settings not loaded");
+        profiles::load_defaults();
+    } else {
+        profiles::load();
+        levels::load();
+        radio::load();
+        pairing::load();
+        calibration::load();
+    }
+    actuators::load();
+    secrets::load();
+    session::load();
+    maintenance::load();
+    health::load();
+    factory::load();
+    owner::load();
+    
+    // Keep the watchdog fed from here on
+    spawner.spawn(usage::watchdog_task(watchdog)).unwrap();
+    spawner.spawn(profiles::storage_task()).unwrap();
+    spawner.spawn(levels::storage_task()).unwrap();
+    spawner.spawn(actuators::storage_task()).unwrap();
+    spawner.spawn(health::storage_task()).unwrap();
+    spawner.spawn(storage::writer_task()).unwrap();
+    spawner.spawn(history::history_task()).unwrap();
+    #[cfg(feature = "debug-tuning")]
+    spawner.spawn(rtt_tuning::tuning_task(tuning_channels)).unwrap();
     
-    // Now configure our sensor and feedback pins using the pins we saved
-    let trigger_left = Output::new(pin_14, Level::Low);
-    let echo_left = Input::new(pin_15, Pull::None);
+    // Bringing WiFi up takes seconds, with instant-on sensing starts first and
+    // the network comes up in the background
+    #[cfg(feature = "wifi")]
+    {
+        let wifi = WifiPeripherals { pin_23: p.PIN_23, pin_24: p.PIN_24, pin_25: p.PIN_25, pin_29: p.PIN_29, pio0: p.PIO0, dma: p.DMA_CH2 };
+        if config::get().wifi.instant_on {
+            info!("Instant-on, starting the network in the background");
+            spawner.spawn(network_task(spawner, wifi)).unwrap();
+        } else {
+            start_network(&spawner, wifi).await;
+        }
+    }
     
-    let trigger_right = Output::new(pin_16, Level::Low);
-    let echo_right = Input::new(pin_17, Pull::None);
+    // The same commands as on TCP over USB serial and a BLE serial module on UART0
+    #[cfg(feature = "usb")]
+    serial::init_usb(&spawner, p.USB);
+    serial::init_ble_uart(&spawner, p.UART0, p.PIN_0, p.PIN_1);
     
-    let mut buzzer = Output::new(pin_18, Level::Low);
+    // Now configure our sensor and feedback pins using the pins we saved.
+    // Each channel is an HC-SR04, a MaxSonar or a lidar, as the board config says;
+    // the upward angled overhead sensor is only read when enabled in the config,
+    // the rear one on GPIO12/13 only on a wheelchair that is reversing.
+    let ([sensor_left, sensor_right, sensor_overhead, sensor_rear], vsys_adc) = rangefinder::init(
+        &spawner,
+        p.PIO1,
+        p.PIO2,
+        p.ADC,
+        (pin_14, pin_15, p.PIN_26),
+        (pin_16, pin_17, p.PIN_27),
+        (pin_10, pin_11, p.PIN_28),
+        (p.PIN_12, p.PIN_13),
+    );
     
-    let mut vibration_left = Output::new(pin_19, Level::Low);
-    let mut vibration_right = Output::new(pin_20, Level::Low);
-
-    // Create sensor objects
-    let mut ultrasonic_left = UltrasonicSensor {
-        trigger: trigger_left,
-        echo: echo_left,
-    };
+    // VSYS through a divider on GPIO28 when the overhead sensor leaves it free,
+    // watched for sags while motors start
+    if let Some(channel) = vsys_adc {
+        spawner.spawn(supply::supply_task(channel)).unwrap();
+    }
     
-    let mut ultrasonic_right = UltrasonicSensor {
-        trigger: trigger_right,
-        echo: echo_right,
-    };
+    // High from a cycle's first echo until a motor starts, for a scope
+    #[cfg(feature = "latency-probe")]
+    latency::init_probe(Output::new(p.PIN_7, Level::Low));
     
-    // Initial distance state
-    let mut distance_state = DistanceState {
-        prev_left: 100.0,
-        prev_right: 100.0,
+    // Feedback runs in its own task so it never holds up sensing
+    let actuators = feedback::Actuators {
+        buzzer: Output::new(pin_18, Level::Low),
+        // GPIO6 is channel A of PWM slice 3, for a bone-conduction headset's amplifier
+        headset: Headset::new(Pwm::new_output_a(p.PWM_SLICE3, p.PIN_6, pwm::Config::default())),
+        // GPIO19 is channel B of PWM slice 1, GPIO20 channel A of slice 2
+        motors: [
+            PwmMotor::new(Pwm::new_output_b(pwm_slice_1, pin_19, pwm::Config::default()), PwmChannel::B),
+            PwmMotor::new(Pwm::new_output_a(pwm_slice_2, pin_20, pwm::Config::default()), PwmChannel::A),
+        ],
     };
+    spawner.spawn(feedback::feedback_task(actuators)).unwrap();
+    spawner.spawn(feedback::planner_task()).unwrap();
+    
+    // A soft tone when maintenance is due, the dashboard says what
+    maintenance::remind();
+    
+    // Button to ground, double press switches profiles
+    spawner.spawn(button::button_task(Input::new(pin_21, Pull::Up))).unwrap();
+    
+    // Find my device tone, started remotely and stopped with the button
+    spawner.spawn(locate::locator_task()).unwrap();
+    
+    // Orderly power down on command or when the charger board reports a low battery
+    spawner.spawn(shutdown::shutdown_task()).unwrap();
+    spawner.spawn(shutdown::low_battery_task(Input::new(pin_22, Pull::Up))).unwrap();
+    
+    // Die temperature on the shared ADC, turns the motors down when the chip runs hot
+    spawner.spawn(thermal::thermal_task(adc::Channel::new_temp_sensor(p.ADC_TEMP_SENSOR))).unwrap();
+    
+    // IMU on I2C0 for the motion context, SCL on GPIO5 and SDA on GPIO4
+    #[cfg(feature = "imu")]
+    if !safe_mode {
+        let i2c = I2c::new_async(i2c_0, pin_5, pin_4, irqs::Irqs, i2c::Config::default());
+        spawner.spawn(imu::imu_task(i2c)).unwrap();
+    }
+
+    // One channel per sensor, in Side::ALL order like every per channel array
+    let mut sensors = [
+        SensorChannel::new(sensor_left, Side::Left),
+        SensorChannel::new(sensor_right, Side::Right),
+        SensorChannel::new(sensor_overhead, Side::Overhead),
+        SensorChannel::new(sensor_rear, Side::Rear),
+    ];
+    
+    // Processing chain from raw readings to zones, starting out at a safe distance
+    let mut pipeline = Pipeline::new(100.0);
+    // Scales the zones with how fast the user is moving
+    let mut context = ContextManager::new();
+    // Listens for other ultrasonic sensors now and then
+    let mut interference = InterferenceDetector::new();
+    // Notices the device being left lying somewhere
+    let mut parked = ParkedDetector::new();
+    // Starts the cycles at a steady rate
+    let mut pacer = Pacer::new(Duration::from_millis(config::get().sensing.cycle_delay_ms.max(1) as u64));
     
     info!("Sensors and feedback systems initialized");
     info!("Connect to WiFi AP 'VisionAssist' to configure device");
     info!("TCP server running on port 8080, Web interface on port 80");
     
-    // Main loop
+    // The first cycles check that the sensors answer
+    lifecycle::enter(State::SelfTest);
+    
+    // Main loop, until a shutdown
     loop {
-        // Get left distance
-        let raw_left = match get_stable_distance(&mut ultrasonic_left).await {
-            Ok(dist) => dist,
-            Err(_) => 100.0, // Default safe value on error
-        };
-        let left_distance = filter_distance(raw_left, distance_state.prev_left);
-        distance_state.prev_left = left_distance;
-        
-        // Get right distance
-        let raw_right = match get_stable_distance(&mut ultrasonic_right).await {
-            Ok(dist) => dist,
-            Err(_) => 100.0, // Default safe value on error
-        };
-        let right_distance = filter_distance(raw_right, distance_state.prev_right);
-        distance_state.prev_right = right_distance;
-        
-        // Update the shared state for TCP server
-        unsafe {
-            tcp_server::LEFT_DISTANCE = left_distance;
-            tcp_server::RIGHT_DISTANCE = right_distance;
+        if shutdown::requested() {
+            break;
         }
         
-        // Log distances for debugging
-        info!("Left: {} cm | Right: {} cm", left_distance as u32, right_distance as u32);
-        
-        // Provide haptic and audio feedback
-        provide_feedback(
-            &mut buzzer, 
-            &mut vibration_left, 
-            &mut vibration_right, 
-            left_distance, 
-            right_distance
-        ).await;
-        
-        // Brief delay between measurements
-        Timer::after(Duration::from_millis(50)).await;
-    }
-}
-
-// Ultrasonic sensor implementation
-impl<'d> UltrasonicSensor<'d> {
-    async fn measure_distance(&mut self) -> Result<f32, &'static str> {
-        // Send trigger pulse
-        self.trigger.set_high();
-        Timer::after(Duration::from_micros(10)).await;
-        self.trigger.set_low();
-        
-        // wait for echo to start with timeout
-        let mut timeout = false;
-        let timeout_duration = Duration::from_millis(100);
-        let start = Instant::now();
-        
-        while self.echo.is_low() {
-            if start.elapsed() > timeout_duration {
-                timeout = true;
-                break;
+        // Injected hang, nothing else runs until the watchdog restarts the device
+        if faults::hang_requested() {
+            loop {
+                core::hint::spin_loop();
             }
-            Timer::after(Duration::from_micros(10)).await;
         }
         
-        if timeout {
-            return Err("Echo signal timed out (start)");
-        }
+        let mut config = config::get();
         
-        // start timing when echo goes high
-        let pulse_start = Instant::now();
+        // Widen or narrow the zones for the current motion
+        let motion = imu::motion();
+        let zone_scale = context.update(motion, &config.context, Instant::now().as_millis());
+        config.zones = config.zones.scaled(zone_scale);
+        config.overhead.zones = config.overhead.zones.scaled(zone_scale);
         
-        // wait for echo to end
-        timeout = false;
-        let start = Instant::now();
+        // Channels read this cycle, the rear one only while a wheelchair reverses
+        let reversing = imu::reversing();
+        let sensing = |side: Side| channels::placement(side).sensing(&config, reversing);
         
-        while self.echo.is_high() {
-            if start.elapsed() > timeout_duration {
-                timeout = true;
-                break;
+        // Listen without triggering, anything heard is someone else's ultrasound
+        if interference.listen_due(Instant::now().as_millis()) {
+            for channel in sensors.iter_mut().filter(|channel| sensing(channel.side)) {
+                let heard = channel.sensor.listen(LISTEN_WINDOW_MS).await;
+                interference.record(channel.side, heard, Instant::now().as_millis());
             }
-            Timer::after(Duration::from_micros(10)).await;
         }
+        let confirmations = if interference.suspected(Instant::now().as_millis()) {
+            config.sensing.interference_confirmations
+        } else {
+            1
+        };
+        pipeline.require_confirmations(confirmations);
         
-        if timeout {
-            return Err("Echo signal timed out (end)");
+        // Read every channel that is on
+        let wanted = Side::ALL.map(|side| sensing(side) && !faults::sensor_timeout(side));
+        let readings = get_stable_distances(&mut sensors, wanted, &config.sensing).await;
+        let mut raw = [None; channels::COUNT];
+        for channel in sensors.iter_mut().filter(|channel| sensing(channel.side)) {
+            let side = channel.side;
+            let reading = readings[side as usize];
+            let reading = calibration::process(side, reading);
+            track_sensor_health(side, reading.is_ok(), &mut channel.failures, &config.sensing);
+            track_frozen_readings(side, &mut channel.stuck, reading, motion.is_moving(), &config.stuck);
+            let distance_cm = reading.unwrap_or(100.0); // Default safe value on error
+            let distance_cm = simulation::reading(side, Instant::now().as_millis()).unwrap_or(distance_cm);
+            raw[side as usize] = Some(RawReading { distance_cm, timestamp_ms: Instant::now().as_millis() });
         }
         
-        // calculate pulse duration
-        let pulse_duration = pulse_start.elapsed();
+        // Some sensors gone quiet or frozen is degraded, all of them a fault
+        let sensing_channels = sensors.iter().filter(|channel| sensing(channel.side));
+        let (count, failing) = sensing_channels.fold((0, 0), |(count, failing), channel| {
+            let failed = channel.failures >= config.sensing.degraded_after_failures || channel.stuck.stuck();
+            (count + 1, failing + failed as usize)
+        });
+        let status = match failing {
+            0 => Status::Ok,
+            _ if failing == count => Status::Fault,
+            _ => Status::Degraded,
+        };
+        health::set_status(Subsystem::Sensors, status);
         
-        // calculate distance using speed of sound
-        let distance_cm = (pulse_duration.as_micros() as f32) * 0.034 / 2.0;
+        // Left lying still with nothing changing, feedback pauses until it is moved
+        let now_ms = Instant::now().as_millis();
+        parked.update(&raw, imu::still_for_ms(now_ms), now_ms, &config.parked);
         
-        // filter out unreasonable readings
-        if distance_cm < 2.0 || distance_cm > 400.0 {
-            return Err("Distance out of reasonable range");
+        // The self-test is over once every sensor has answered or been given up on
+        let tested = sensors
+            .iter()
+            .filter(|channel| sensing(channel.side))
+            .all(|channel| channel.failures == 0 || channel.failures >= config.sensing.degraded_after_failures);
+        if lifecycle::state() != State::SelfTest || tested {
+            lifecycle::update(status, parked::parked());
         }
         
-        Ok(distance_cm)
-    }
-}
-
-// Get stable distance readings by averaging
-async fn get_stable_distance(sensor: &mut UltrasonicSensor<'_>) -> Result<f32, &'static str> {
-    let mut valid_readings = 0;
-    let mut sum = 0.0;
-    
-    // Try up to 5 times to get 3 valid readings
-    for _ in 0..5 {
-        if valid_readings >= 3 {
-            break;
+        // Filter, correct and zone all channels
+        let zoned = pipeline.process(raw, &config);
+        
+        // Hand the result to feedback, the LED and the servers
+        let now_ms = Instant::now().as_millis();
+        let mut states = [None; channels::COUNT];
+        for (channel, reading) in sensors.iter_mut().zip(&zoned) {
+            let Some(reading) = reading else {
+                continue;
+            };
+            // Log distances for debugging
+            info!(target: Scope::Sensing, "{}: {} cm ({})", channel.side.name(), reading.distance_cm as u32, reading.zone);
+            states[channel.side as usize] = Some(SideState {
+                zone: reading.zone,
+                distance_cm: reading.distance_cm,
+                noise_cm: reading.noise_cm,
+                trend: channel.trend.update(reading.distance_cm, now_ms),
+                timestamp_ms: reading.timestamp_ms,
+            });
         }
+        let state = ZoneState { channels: states, zone_scale, timestamp_ms: now_ms };
+        occupancy::update(&state);
+        telemetry::update(&state);
+        zone_state::publish(state);
+        latency::output();
         
-        match sensor.measure_distance().await {
-            Ok(dist) => {
-                sum += dist;
-                valid_readings += 1;
-            },
-            Err(_) => {
-                // Skip invalid readings
+        // Let everyone else know about zone changes, a channel that is off starts from clear
+        for (channel, reading) in sensors.iter_mut().zip(&zoned) {
+            match reading {
+                Some(reading) if reading.zone != channel.zone => {
+                    channel.zone = reading.zone;
+                    events::publish(Event::ZoneChanged { side: channel.side, zone: reading.zone, distance_cm: reading.distance_cm });
+                },
+                Some(_) => {},
+                None => channel.zone = Zone::Clear,
             }
         }
-        Timer::after(Duration::from_millis(10)).await;
+        
+        // Next cycle on the tick, a slow one in standby unless the device moves
+        if lifecycle::state() == State::Standby {
+            let period = Duration::from_millis(config.parked.cycle_delay_ms as u64);
+            select(pacer.tick(period), imu::moved()).await;
+        } else {
+            let period = Duration::from_millis(config.sensing.cycle_delay_ms.max(1) as u64);
+            pacer.tick(period).await;
+        }
     }
     
-    if valid_readings > 0 {
-        // Return average
-        Ok(sum / (valid_readings as f32))
-    } else {
-        // No valid readings
-        Err("Failed to get any valid distance readings")
-    }
+    info!("Sensing stopped");
+    shutdown::sensing_stopped();
 }
 
-// Simple low-pass filter to smooth readings
-fn filter_distance(current: f32, previous: f32) -> f32 {
-    // Using 70/30 weighting for responsiveness
-    current * 0.7 + previous * 0.3
+// What the WiFi chip needs, handed to the network task with instant-on
+#[cfg(feature = "wifi")]
+struct WifiPeripherals {
+    pin_23: PIN_23,
+    pin_24: PIN_24,
+    pin_25: PIN_25,
+    pin_29: PIN_29,
+    pio0: PIO0,
+    dma: DMA_CH2,
 }
 
-// Main feedback function
-async fn provide_feedback(
-    buzzer: &mut Output<'_>,
-    vibration_left: &mut Output<'_>,
-    vibration_right: &mut Output<'_>,
-    left_distance: f32,
-    right_distance: f32,
-) {
-    // Always start with motors off
-    vibration_left.set_low();
-    vibration_right.set_low();
-    
-    // Check for extremely close obstacles
-    let extreme_danger_threshold = 10.0; // cm
-    let extreme_danger = left_distance < extreme_danger_threshold || right_distance < extreme_danger_threshold;
-    
-    if extreme_danger {
-        // Special warning for very close objects
-        provide_extreme_danger_warning(buzzer, vibration_left, vibration_right).await;
+// Network stack and everything that serves on it
+#[cfg(feature = "wifi")]
+async fn start_network(spawner: &Spawner, wifi: WifiPeripherals) {
+    // Initialize network stack
+    info!("Initializing network stack...");
+    // without firmware for the WiFi chip sensing carries on alone
+    let Some(stack) = wifi_utils::init_network_stack(
+        spawner,
+        wifi.pin_23,
+        wifi.pin_24,
+        wifi.pin_25,
+        wifi.pin_29,
+        wifi.pio0,
+        wifi.dma,
+    ).await else {
         return;
-    }
-    
-    // Left side intensity
-    let left_intensity = if left_distance < NOTICE_DISTANCE {
-        calculate_vibration_intensity(left_distance)
-    } else {
-        0 // no vibration
     };
+    info!("Network stack initialized successfully");
     
-    // Right side intensity
-    let right_intensity = if right_distance < NOTICE_DISTANCE {
-        calculate_vibration_intensity(right_distance)
-    } else {
-        0 // no vibration
-    };
+    // Start TCP server, it shares the connection buffers with the web server
+    #[cfg(any(feature = "tcp", feature = "web"))]
+    buffers::init();
+    #[cfg(feature = "tcp")]
+    spawner.spawn(tcp_server::tcp_server_task(stack)).unwrap();
     
-    // Apply left vibration
-    if left_intensity > 0 {
-        provide_haptic_feedback(vibration_left, left_intensity).await;
-        vibration_left.set_low();
+    // Start web server
+    #[cfg(feature = "web")]
+    for _ in 0..web_server::WEB_SERVER_TASKS {
+        spawner.spawn(web_server::web_server_task(stack)).unwrap();
     }
     
-    // Apply right vibration
-    if right_intensity > 0 {
-        provide_haptic_feedback(vibration_right, right_intensity).await;
-        vibration_right.set_low();
-    }
+    // Clients that find every listener taken are told to come back later
+    #[cfg(feature = "web")]
+    spawner.spawn(busy::busy_task(stack, busy::Server::Web)).unwrap();
+    #[cfg(feature = "tcp")]
+    spawner.spawn(busy::busy_task(stack, busy::Server::Tcp)).unwrap();
     
-    // Sound only for close objects
-    if left_distance < CRITICAL_DISTANCE || right_distance < CRITICAL_DISTANCE {
-        if left_distance < right_distance {
-            provide_warning_sound(buzzer, left_distance).await;
-        } else {
-            provide_warning_sound(buzzer, right_distance).await;
-        }
+    // Assist requests and forwarded alerts are broadcast on the network,
+    // not in safe mode where only the servers run
+    if usage::safe_mode() {
+        return;
     }
+    spawner.spawn(broadcast::broadcast_task(stack)).unwrap();
     
-    // Ensure buzzer is off
-    buzzer.set_low();
+    // A guide sends its obstacles to the follower's unit, which plays them
+    pairing::start(spawner, stack);
 }
 
-// Strong warning pattern for very close obstacles
-async fn provide_extreme_danger_warning(
-    buzzer: &mut Output<'_>,
-    vibration_left: &mut Output<'_>,
-    vibration_right: &mut Output<'_>,
-) {
-    // First pattern - left side
-    buzzer.set_high();
-    vibration_left.set_high();
-    Timer::after(Duration::from_millis(150)).await;
-    buzzer.set_low();
-    vibration_left.set_low();
-    Timer::after(Duration::from_millis(50)).await;
-    
-    // Second pattern - right side
-    buzzer.set_high();
-    vibration_right.set_high();
-    Timer::after(Duration::from_millis(150)).await;
-    buzzer.set_low();
-    vibration_right.set_low();
-    Timer::after(Duration::from_millis(50)).await;
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+async fn network_task(spawner: Spawner, wifi: WifiPeripherals) {
+    start_network(&spawner, wifi).await;
+}
+
+// Stable readings of the channels in `wanted`, taking turns: every round
+// pings each channel that isn't done yet once, so the pause one sensor needs
+// between its pings goes to the others. A channel is done as soon as two of
+// its readings agree within `sensing.consensus_cm` (their mean is the
+// reading), or else with as many readings as the sensor wants or two tries
+// more than that (the mean of the valid ones), so sensors that filter their
+// own output are done after one.
+async fn get_stable_distances(
+    sensors: &mut [SensorChannel; channels::COUNT],
+    wanted: [bool; channels::COUNT],
+    sensing: &config::SensingConfig,
+) -> [Result<f32, &'static str>; channels::COUNT] {
+    let gap = Duration::from_millis(sensing.measurement_gap_ms as u64);
+    let mut results = [Err(NO_READINGS_ERROR); channels::COUNT];
+    let mut readings: [Vec<f32, MAX_ATTEMPTS>; channels::COUNT] = Default::default();
+    let mut attempts = [0u8; channels::COUNT];
+    let mut done = wanted.map(|wanted| !wanted);
+    let mut pinged_at: [Option<Instant>; channels::COUNT] = [None; channels::COUNT];
     
-    // Third pattern - both sides
-    buzzer.set_high();
-    vibration_left.set_high();
-    vibration_right.set_high();
-    Timer::after(Duration::from_millis(300)).await;
-    buzzer.set_low();
-    vibration_left.set_low();
-    vibration_right.set_low();
+    while done.contains(&false) {
+        for (index, channel) in sensors.iter_mut().enumerate() {
+            if done[index] {
+                continue;
+            }
+            // the others' pings usually took longer than the gap already
+            if let Some(pinged_at) = pinged_at[index] {
+                Timer::at(pinged_at + gap).await;
+            }
+            
+            let reading = channel.sensor.measure(sensing.max_range_cm).await;
+            pinged_at[index] = Some(Instant::now());
+            latency::echo();
+            attempts[index] += 1;
+            diagnostics::record(channel.side, reading);
+            match reading {
+                Ok(distance_cm) => {
+                    channel.near_field_streak = 0;
+                    if let Some(agreeing) = readings[index].iter().find(|other| (*other - distance_cm).abs() <= sensing.consensus_cm) {
+                        results[index] = Ok((agreeing + distance_cm) / 2.0);
+                        done[index] = true;
+                    }
+                    let _ = readings[index].push(distance_cm);
+                },
+                Err(NEAR_FIELD_ERROR) => {
+                    channel.near_field_streak = channel.near_field_streak.saturating_add(1);
+                },
+                Err(_) => {
+                    // Skip invalid readings
+                }
+            }
+            
+            // as many as the sensor wants or out of tries: 3 of 5 for an HC-SR04
+            let wanted = channel.sensor.readings_per_cycle() as usize;
+            let enough = readings[index].len() >= wanted || attempts[index] as usize >= (wanted + 2).min(MAX_ATTEMPTS);
+            if !done[index] && enough {
+                // no two agreed, the mean of what there is
+                if !readings[index].is_empty() {
+                    results[index] = Ok(readings[index].iter().sum::<f32>() / readings[index].len() as f32);
+                }
+                done[index] = true;
+            }
+        }
+    }
     
-    // Pause before next cycle
-    Timer::after(Duration::from_millis(100)).await;
+    // a run of blind zone echoes means the obstacle is closer than we can measure
+    for ((result, channel), wanted) in results.iter_mut().zip(sensors.iter()).zip(wanted) {
+        if wanted && channel.near_field_streak >= sensing.near_field_confirmations {
+            *result = Ok(NEAR_FIELD_DISTANCE);
+        }
+    }
+    results
 }
 
-// Calculate vibration intensity (0-10 scale)
-fn calculate_vibration_intensity(distance: f32) -> u8 {
-    if distance < CRITICAL_DISTANCE {
-        // Critical zone (levels 7-10)
-        let critical_range = CRITICAL_DISTANCE;
-        let normalized = (critical_range - distance.min(critical_range)) / critical_range;
-        let level = 7.0 + normalized * 3.0;
-        level as u8
-    } else if distance < WARNING_DISTANCE {
-        // Warning zone (levels 4-6)
-        let warning_range = WARNING_DISTANCE - CRITICAL_DISTANCE;
-        let normalized = (WARNING_DISTANCE - distance) / warning_range;
-        let level = 4.0 + normalized * 2.0;
-        level as u8
-    } else if distance < NOTICE_DISTANCE {
-        // Notice zone (levels 1-3)
-        let notice_range = NOTICE_DISTANCE - WARNING_DISTANCE;
-        let normalized = (NOTICE_DISTANCE - distance) / notice_range;
-        let level = 1.0 + normalized * 2.0;
-        level as u8
+// Count failed cycles and report when a sensor goes quiet or comes back
+fn track_sensor_health(side: Side, ok: bool, failures: &mut u8, sensing: &config::SensingConfig) {
+    if ok {
+        if *failures >= sensing.degraded_after_failures {
+            events::publish(Event::SensorRecovered { side });
+        }
+        *failures = 0;
     } else {
-        // Beyond notice zone
-        0
+        *failures = failures.saturating_add(1);
+        if *failures == sensing.degraded_after_failures {
+            health::error(Subsystem::Sensors);
+            events::publish(Event::SensorDegraded { side, reason: DegradedReason::NoReadings });
+        }
     }
 }
 
-// Haptic feedback patterns for different intensities
-async fn provide_haptic_feedback(motor: &mut Output<'_>, intensity: u8) {
-    match intensity {
-        10 => { // Maximum intensity
-            motor.set_high();
-            Timer::after(Duration::from_millis(80)).await;
-        },
-        9 => { // Very strong
-            motor.set_high();
-            Timer::after(Duration::from_millis(80)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(20)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(80)).await;
-        },
-        8 => { // Strong
-            motor.set_high();
-            Timer::after(Duration::from_millis(70)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(30)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(70)).await;
-        },
-        7 => { // Moderate-strong
-            motor.set_high();
-            Timer::after(Duration::from_millis(60)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(40)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(60)).await;
-        },
-        6 => { // Moderate
-            motor.set_high();
-            Timer::after(Duration::from_millis(50)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(50)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(50)).await;
-        },
-        5 => { // Medium
-            motor.set_high();
-            Timer::after(Duration::from_millis(40)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(60)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(40)).await;
-        },
-        4 => { // Light-medium
-            motor.set_high();
-            Timer::after(Duration::from_millis(30)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(70)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(30)).await;
-        },
-        3 => { // Light
-            motor.set_high();
-            Timer::after(Duration::from_millis(20)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(80)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(20)).await;
-        },
-        2 => { // Very light
-            motor.set_high();
-            Timer::after(Duration::from_millis(10)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(90)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(10)).await;
-        },
-        1 => { // Minimal
-            motor.set_high();
-            Timer::after(Duration::from_millis(5)).await;
-            motor.set_low();
-            Timer::after(Duration::from_millis(95)).await;
-            motor.set_high();
-            Timer::after(Duration::from_millis(5)).await;
+// Report a channel whose readings stopped changing while the user walks, with
+// a fault code on the buzzer so the user knows not to rely on that side
+fn track_frozen_readings(
+    side: Side,
+    detector: &mut StuckDetector,
+    reading: Result<f32, &'static str>,
+    moving: bool,
+    config: &config::StuckConfig,
+) {
+    // failures are counted elsewhere, and a confirmed near-field reading is always the same
+    let Ok(distance_cm) = reading else {
+        return;
+    };
+    if distance_cm < MIN_DETECTABLE_DISTANCE {
+        return;
+    }
+    
+    match detector.update(distance_cm, moving, Instant::now().as_millis(), config) {
+        Some(true) => {
+            health::error(Subsystem::Sensors);
+            events::publish(Event::SensorDegraded { side, reason: DegradedReason::Frozen });
+            feedback::submit(FeedbackCommand {
+                pattern: Pattern::FaultCode,
+                side: Some(side),
+                priority: Priority::High,
+                ttl_ms: 2000,
+            });
         },
-        _ => { // No vibration
-            motor.set_low();
-            Timer::after(Duration::from_millis(10)).await;
-        }
+        Some(false) => events::publish(Event::SensorRecovered { side }),
+        None => {},
     }
 }
-
-// Warning sounds for different distance ranges
-async fn provide_warning_sound(buzzer: &mut Output<'_>, distance: f32) {
-    if distance < 10.0 {
-        // Very close - rapid beeping
-        for _ in 0..3 {
-            buzzer.set_high();
-            Timer::after(Duration::from_millis(25)).await;
-            buzzer.set_low();
-            Timer::after(Duration::from_millis(25)).await;
-        }
-    } else if distance < 20.0 {
-        // Medium close - moderate beeping
-        for _ in 0..2 {
-            buzzer.set_high();
-            Timer::after(Duration::from_millis(50)).await;
-            buzzer.set_low();
-            Timer::after(Duration::from_millis(50)).await;
-        }
-    } else {
-        // Not as close - single beep
-        buzzer.set_high();
-        Timer::after(Duration::from_millis(70)).await;
-        buzzer.set_low();
-    }
-}
\ No newline at end of file
@@ -0,0 +1,184 @@
+//! Maintenance reminders driven by the runtime counter (see
+//! [`usage`](crate::usage)).
+//!
+//! Each task comes due again after its interval of runtime hours, counted
+//! from when it was last acknowledged. A due task plays a soft tone at boot
+//! and shows a banner on the dashboard until it is acknowledged through the
+//! API. Intervals (0 turns a reminder off) and acknowledgements are kept in
+//! their own flash sector.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::config::HapticPattern;
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+use crate::usage;
+
+const SCOPE: Scope = Scope::System;
+
+// Change when the stored layout changes, old data is then ignored
+const MAINTENANCE_MAGIC: u32 = 0x4d4e_5431;
+const HEADER_LEN: usize = 6;
+
+// Two soft beeps, nothing like an obstacle alert
+const REMINDER_TONE: [u16; 3] = [60, 300, 60];
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Task {
+    Mounts,
+    Motors,
+    Battery,
+}
+
+impl Task {
+    pub const ALL: [Task; 3] = [Task::Mounts, Task::Motors, Task::Battery];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::Mounts => "mounts",
+            Task::Motors => "motors",
+            Task::Battery => "battery",
+        }
+    }
+
+    // What to do, for the dashboard
+    pub fn description(self) -> &'static str {
+        match self {
+            Task::Mounts => "check that the sensor mounts are tight and aligned",
+            Task::Motors => "check the vibration motors for wear",
+            Task::Battery => "check the battery holds its charge",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        Task::ALL.into_iter().find(|task| text.eq_ignore_ascii_case(task.name()))
+    }
+
+    const fn default_interval_h(self) -> u16 {
+        match self {
+            Task::Mounts => 200,
+            Task::Motors => 500,
+            Task::Battery => 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Reminder {
+    // Runtime hours between reminders, 0 is off
+    pub interval_h: u16,
+    // Runtime hours at the last acknowledgement
+    pub done_at_h: u32,
+}
+
+impl Reminder {
+    // Hours until due, negative once overdue. None when switched off.
+    pub fn due_in_h(&self, runtime_h: u32) -> Option<i64> {
+        (self.interval_h > 0).then(|| self.done_at_h as i64 + self.interval_h as i64 - runtime_h as i64)
+    }
+
+    pub fn due(&self, runtime_h: u32) -> bool {
+        self.due_in_h(runtime_h).is_some_and(|hours| hours <= 0)
+    }
+}
+
+static REMINDERS: Mutex<CriticalSectionRawMutex, RefCell<[Reminder; Task::ALL.len()]>> = Mutex::new(RefCell::new([
+    Reminder { interval_h: Task::Mounts.default_interval_h(), done_at_h: 0 },
+    Reminder { interval_h: Task::Motors.default_interval_h(), done_at_h: 0 },
+    Reminder { interval_h: Task::Battery.default_interval_h(), done_at_h: 0 },
+]));
+
+fn runtime_h() -> u32 {
+    usage::counters().runtime_s / 3600
+}
+
+// Every task with its reminder
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn list() -> [(Task, Reminder); Task::ALL.len()] {
+    let reminders = REMINDERS.lock(|reminders| *reminders.borrow());
+    Task::ALL.map(|task| (task, reminders[task as usize]))
+}
+
+// Tasks that are due now
+pub fn due() -> Vec<Task, { Task::ALL.len() }> {
+    let runtime_h = runtime_h();
+    list().into_iter().filter(|(_, reminder)| reminder.due(runtime_h)).map(|(task, _)| task).collect()
+}
+
+// Read the stored reminders, call once at startup after usage::load
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::MAINTENANCE, &mut buffer) {
+        warn!("Failed to read maintenance reminders: {}", e);
+        return;
+    }
+    if let Some(stored) = decode(&buffer) {
+        REMINDERS.lock(|reminders| *reminders.borrow_mut() = stored);
+    }
+}
+
+fn decode(buffer: &[u8]) -> Option<[Reminder; Task::ALL.len()]> {
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = u16::from_le_bytes(buffer[4..HEADER_LEN].try_into().ok()?) as usize;
+    if magic != MAINTENANCE_MAGIC || HEADER_LEN + len > buffer.len() {
+        return None;
+    }
+    postcard::from_bytes(&buffer[HEADER_LEN..HEADER_LEN + len]).ok()
+}
+
+fn save(reminders: &[Reminder; Task::ALL.len()]) -> Result<(), &'static str> {
+    let mut buffer = [0xFF; ERASE_SIZE];
+    let len = postcard::to_slice(reminders, &mut buffer[HEADER_LEN..])
+        .map_err(|_| "Reminders too large")?
+        .len();
+    buffer[..4].copy_from_slice(&MAINTENANCE_MAGIC.to_le_bytes());
+    buffer[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    storage::write_sector(partitions::MAINTENANCE, &buffer)
+}
+
+// Change a reminder and store the result
+fn change(task: Task, edit: impl FnOnce(&mut Reminder)) -> Result<(), &'static str> {
+    let mut reminders = REMINDERS.lock(|reminders| *reminders.borrow());
+    edit(&mut reminders[task as usize]);
+    save(&reminders)?;
+    REMINDERS.lock(|stored| *stored.borrow_mut() = reminders);
+    Ok(())
+}
+
+// The task was done, the next reminder is an interval from now
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn acknowledge(task: Task) -> Result<(), &'static str> {
+    let runtime_h = runtime_h();
+    change(task, |reminder| reminder.done_at_h = runtime_h)?;
+    info!("Maintenance of {} acknowledged at {} h", task.name(), runtime_h);
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn set_interval(task: Task, interval_h: u16) -> Result<(), &'static str> {
+    change(task, |reminder| reminder.interval_h = interval_h)?;
+    info!("Maintenance of {} every {} h", task.name(), interval_h);
+    Ok(())
+}
+
+// At boot: log what is due and play the reminder tone once
+pub fn remind() {
+    let due = due();
+    if due.is_empty() {
+        return;
+    }
+    for task in &due {
+        warn!("Maintenance due: {}", task.description());
+    }
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Preview { steps: HapticPattern::new(&REMINDER_TONE), target: PreviewTarget::Buzzer },
+        side: None,
+        priority: Priority::Normal,
+        ttl_ms: 5000,
+    });
+}
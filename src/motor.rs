@@ -0,0 +1,183 @@
+//! PWM backend and ERM driver for the vibration motors.
+//!
+//! Each motor sits on one channel of an RP2350 PWM slice. Besides setting a raw
+//! duty cycle, a motor keeps the "on" level its patterns play at and ramps that
+//! level towards new targets at a limited rate, so the vibration strength grows
+//! smoothly as an obstacle approaches.
+//!
+//! Small ERM motors won't spin up from rest at low duty, so starting a motor
+//! below `kick_below` first gives it a short full-power kick and only then
+//! settles at the requested duty. Stopping always cuts the drive at once rather
+//! than ramping down, letting the flyback diode brake the motor.
+//!
+//! Every duty a motor is driven at is scaled by its strength, the master
+//! haptic level (see [`levels`](crate::levels)) lowered further while the
+//! chip runs hot (see [`thermal`](crate::thermal)), after all pattern logic.
+//! Nothing, not even a kick, goes above the duty cap that supply sags set
+//! (see [`supply`](crate::supply)).
+
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::pwm::{Config, Pwm};
+
+// 150 MHz / 7500 = 20 kHz, inaudible and easy on the driver transistor
+const PWM_TOP: u16 = 7499;
+
+// After this long without a command the motor has been off, ramp up from zero again
+const IDLE_RESET_MS: u64 = 300;
+
+// The time the first step up from zero is given, one sensing cycle at the default
+// rate, since the time since the last command says nothing about the ramp
+const ONSET_STEP_MS: u64 = 50;
+
+#[derive(Clone, Copy)]
+pub enum PwmChannel {
+    A,
+    B,
+}
+
+pub struct PwmMotor {
+    pwm: Pwm<'static>,
+    config: Config,
+    channel: PwmChannel,
+    level: u8,
+    level_updated_ms: u64,
+    running: bool,
+    kick_ms: u16,
+    kick_below: u8,
+    strength_percent: u8,
+    duty_cap: u8,
+    // duty to settle at once a running kick is over
+    pending_hold: Option<u8>,
+}
+
+impl PwmMotor {
+    pub fn new(pwm: Pwm<'static>, channel: PwmChannel) -> Self {
+        let mut config = Config::default();
+        config.top = PWM_TOP;
+
+        let mut motor = Self {
+            pwm,
+            config,
+            channel,
+            level: 0,
+            level_updated_ms: 0,
+            running: false,
+            kick_ms: 0,
+            kick_below: 0,
+            strength_percent: 100,
+            duty_cap: u8::MAX,
+            pending_hold: None,
+        };
+        motor.set_duty(0);
+        motor
+    }
+
+    // Duty cycle on a 0-255 scale, at most the duty cap
+    pub fn set_duty(&mut self, duty: u8) {
+        let duty = duty.min(self.duty_cap);
+        let compare = (duty as u32 * (PWM_TOP as u32 + 1) / 255) as u16;
+        match self.channel {
+            PwmChannel::A => self.config.compare_a = compare,
+            PwmChannel::B => self.config.compare_b = compare,
+        }
+        self.pwm.set_config(&self.config);
+    }
+
+    // Output polarity (for drivers that switch the motor on with a low level) and kick settings
+    pub fn configure(&mut self, inverted: bool, kick_ms: u16, kick_below: u8) {
+        match self.channel {
+            PwmChannel::A => self.config.invert_a = inverted,
+            PwmChannel::B => self.config.invert_b = inverted,
+        }
+        self.pwm.set_config(&self.config);
+        self.kick_ms = kick_ms;
+        self.kick_below = kick_below;
+    }
+
+    // Master haptic level with any thermal limit, 0-100 %
+    pub fn set_strength(&mut self, percent: u8) {
+        self.strength_percent = percent.min(100);
+    }
+
+    // Highest duty the supply can take, 0-255
+    pub fn set_duty_cap(&mut self, cap: u8) {
+        self.duty_cap = cap;
+    }
+
+    // Move the level patterns play at towards `target`, by at most `ramp_per_s` per second
+    pub fn ramp_towards(&mut self, target: u8, ramp_per_s: u16, now_ms: u64) -> u8 {
+        let elapsed_ms = now_ms.saturating_sub(self.level_updated_ms);
+        let (from, step_ms) = if elapsed_ms > IDLE_RESET_MS {
+            (0, elapsed_ms.min(ONSET_STEP_MS))
+        } else {
+            (self.level, elapsed_ms.max(1))
+        };
+        let max_step = (ramp_per_s as u64 * step_ms / 1000).min(255) as u8;
+
+        self.level = if target > from {
+            from + (target - from).min(max_step)
+        } else {
+            from - (from - target).min(max_step)
+        };
+        self.level_updated_ms = now_ms;
+        self.level
+    }
+}
+
+// Anything the feedback engine can switch, `duty` is 0-255 and 0 means off
+pub trait Actuator {
+    fn drive(&mut self, duty: u8);
+
+    // How long a kick started by the last `drive` lasts, zero if there's none
+    fn kick_ms(&self) -> u16 {
+        0
+    }
+
+    // Called once the kick time has passed
+    fn settle(&mut self) {}
+}
+
+impl Actuator for PwmMotor {
+    fn drive(&mut self, duty: u8) {
+        // scaled, but anything on stays on
+        let duty = if duty == 0 { 0 } else { (duty as u16 * self.strength_percent as u16 / 100).max(1) as u8 };
+        if duty == 0 {
+            // stop right away, no ramping down
+            self.set_duty(0);
+            self.running = false;
+            self.pending_hold = None;
+        } else if !self.running && duty < self.kick_below && self.kick_ms > 0 {
+            // kick from rest, then hold at the requested duty
+            crate::supply::motor_starting();
+            crate::latency::motor_on();
+            self.set_duty(u8::MAX);
+            self.running = true;
+            self.pending_hold = Some(duty);
+        } else {
+            if !self.running {
+                crate::supply::motor_starting();
+                crate::latency::motor_on();
+            }
+            self.set_duty(duty);
+            self.running = true;
+            self.pending_hold = None;
+        }
+    }
+
+    fn kick_ms(&self) -> u16 {
+        if self.pending_hold.is_some() { self.kick_ms } else { 0 }
+    }
+
+    fn settle(&mut self) {
+        if let Some(duty) = self.pending_hold.take() {
+            self.set_duty(duty);
+        }
+    }
+}
+
+// Plain outputs like the buzzer are either on or off
+impl Actuator for Output<'_> {
+    fn drive(&mut self, duty: u8) {
+        self.set_level(Level::from(duty > 0));
+    }
+}
@@ -0,0 +1,95 @@
+//! Coarse occupancy map: the nearest obstacle in each of a few angular
+//! sectors in front of the user.
+//!
+//! Every level channel sees a cone (its beam, see
+//! [`channels::Placement::beam_half_angle_deg`]) and reports the nearest
+//! thing anywhere in it, so a reading also says that the rest of the cone is
+//! free up to that distance. A sector inside several cones therefore takes
+//! the farthest of their readings: the closer obstacle one beam saw must be
+//! in the part of its cone the others don't cover. A sector inside just one
+//! cone gets that reading. Sectors keep their last reading with its time, so
+//! a channel that stops reporting ages out instead of holding its obstacle
+//! forever. Head height channels have their own zones and pattern and stay
+//! out of the map.
+//!
+//! The sensing loop updates the map before publishing each zone state, the
+//! feedback planner turns it into vibration and the dashboard draws it.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use vision_protocol::zoning::Zone;
+
+use crate::channels::{self, Motors};
+use crate::zone_state::ZoneState;
+
+pub const SECTORS: usize = 7;
+pub const SECTOR_WIDTH_DEG: f32 = 15.0;
+// A reading older than this doesn't count anymore
+pub const MAX_AGE_MS: u64 = 1000;
+
+// The nearest obstacle in a sector, from one channel's reading
+#[derive(Clone, Copy)]
+pub struct SectorReading {
+    pub distance_cm: f32,
+    // The channel's zone, with its hysteresis
+    pub zone: Zone,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct Sector {
+    // Centre of the sector, 0 is straight ahead and negative to the left
+    pub bearing_deg: f32,
+    // None until some channel looked this way
+    pub reading: Option<SectorReading>,
+}
+
+impl Sector {
+    // The reading, unless it's too old to trust
+    pub fn fresh(&self, now_ms: u64) -> Option<&SectorReading> {
+        self.reading.as_ref().filter(|reading| now_ms.saturating_sub(reading.timestamp_ms) <= MAX_AGE_MS)
+    }
+
+    pub fn motors(&self) -> Motors {
+        Motors::towards(self.bearing_deg)
+    }
+}
+
+const fn sector_bearing_deg(index: usize) -> f32 {
+    (index as f32 - (SECTORS - 1) as f32 / 2.0) * SECTOR_WIDTH_DEG
+}
+
+static MAP: Mutex<CriticalSectionRawMutex, RefCell<[Sector; SECTORS]>> = Mutex::new(RefCell::new({
+    let mut sectors = [Sector { bearing_deg: 0.0, reading: None }; SECTORS];
+    let mut index = 0;
+    while index < SECTORS {
+        sectors[index].bearing_deg = sector_bearing_deg(index);
+        index += 1;
+    }
+    sectors
+}));
+
+// Fold the level channels of a new zone state into the map
+pub fn update(state: &ZoneState) {
+    MAP.lock(|map| {
+        for sector in map.borrow_mut().iter_mut() {
+            let covering = state.active().filter(|(side, _)| {
+                let placement = channels::placement(*side);
+                placement.ahead() && (sector.bearing_deg - placement.bearing_deg).abs() <= placement.beam_half_angle_deg()
+            });
+            // the farthest reading wins, every beam is clear up to its own
+            let farthest = covering.max_by(|(_, a), (_, b)| a.distance_cm.total_cmp(&b.distance_cm));
+            if let Some((_, reading)) = farthest {
+                sector.reading = Some(SectorReading {
+                    distance_cm: reading.distance_cm,
+                    zone: reading.zone,
+                    timestamp_ms: reading.timestamp_ms,
+                });
+            }
+        }
+    });
+}
+
+pub fn map() -> [Sector; SECTORS] {
+    MAP.lock(|map| *map.borrow())
+}
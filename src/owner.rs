@@ -0,0 +1,247 @@
+//! Who the device belongs to, and who to call when its user asks for help.
+//!
+//! During setup a caregiver can give the device a name, an owner label and an
+//! emergency contact through `POST /api/owner`. They are kept in their own
+//! flash sector (see [`storage`](crate::storage)) and go out with every
+//! assist request (see [`assist`](crate::assist)) and on the dashboard.
+//!
+//! Each is printable ASCII without `"`, `&`, `<` or `>`, so it travels in an
+//! `ASSIST` line, a form body and the dashboard's HTML as is, and is at most
+//! [`MAX_NAME_LEN`], [`MAX_OWNER_LEN`] or [`MAX_CONTACT_LEN`] characters long.
+//! The owner label and the contact are each shown in full, masked (only the
+//! first character of every word and the end of a phone number are left)
+//! or not at all; the setting covers everything the device sends,
+//! `GET /api/owner` included, so a hidden value can be replaced but not read
+//! back. Neither is ever logged.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::String;
+use vision_protocol::AssistEvent;
+
+use crate::defaults::AP_SSID;
+use crate::locale;
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Storage;
+
+// Change when the stored layout changes, old data is then ignored
+const OWNER_MAGIC: u32 = 0x4F57_4E31;
+const HEADER_LEN: usize = 6;
+// An SSID is at most 32 characters, so the default name always fits
+pub const MAX_NAME_LEN: usize = 32;
+pub const MAX_OWNER_LEN: usize = 32;
+pub const MAX_CONTACT_LEN: usize = 48;
+// Left unmasked at the end of a masked value, if its last word is longer
+// than MASK_LONG_WORD, like the digits of a phone number
+const MASK_KEEP_LAST: usize = 2;
+const MASK_LONG_WORD: usize = 4;
+
+// How much of the owner label or the contact the device gives away
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Sharing {
+    Full,
+    Masked,
+    Hidden,
+}
+
+impl Sharing {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sharing::Full => "full",
+            Sharing::Masked => "masked",
+            Sharing::Hidden => "hidden",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "full" => Some(Sharing::Full),
+            "masked" => Some(Sharing::Masked),
+            "hidden" => Some(Sharing::Hidden),
+            _ => None,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => Sharing::Masked,
+            2 => Sharing::Hidden,
+            _ => Sharing::Full,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Sharing::Full => 0,
+            Sharing::Masked => 1,
+            Sharing::Hidden => 2,
+        }
+    }
+
+    // The value as it may be shown
+    fn apply<const N: usize>(self, value: &Option<String<N>>) -> Option<String<N>> {
+        match self {
+            Sharing::Full => value.clone(),
+            Sharing::Masked => value.as_deref().map(mask),
+            Sharing::Hidden => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Record {
+    name: Option<String<MAX_NAME_LEN>>,
+    owner: Option<String<MAX_OWNER_LEN>>,
+    contact: Option<String<MAX_CONTACT_LEN>>,
+    owner_sharing: Sharing,
+    contact_sharing: Sharing,
+}
+
+const EMPTY: Record = Record { name: None, owner: None, contact: None, owner_sharing: Sharing::Full, contact_sharing: Sharing::Full };
+
+static RECORD: Mutex<CriticalSectionRawMutex, RefCell<Record>> = Mutex::new(RefCell::new(EMPTY));
+
+// The record with the sharing settings applied, what may go out
+pub struct Shared {
+    pub name: String<MAX_NAME_LEN>,
+    pub owner: Option<String<MAX_OWNER_LEN>>,
+    pub contact: Option<String<MAX_CONTACT_LEN>>,
+    pub owner_sharing: Sharing,
+    pub contact_sharing: Sharing,
+}
+
+impl Shared {
+    // An assist request from this device, without a position
+    pub fn assist_event(&self) -> AssistEvent<'_> {
+        AssistEvent { device: &self.name, position: None, owner: self.owner.as_deref(), contact: self.contact.as_deref() }
+    }
+}
+
+// What `update` changes, fields left at None stay as they are. An empty text
+// clears the value.
+#[derive(Default)]
+pub struct Changes<'a> {
+    pub name: Option<&'a str>,
+    pub owner: Option<&'a str>,
+    pub contact: Option<&'a str>,
+    pub owner_sharing: Option<Sharing>,
+    pub contact_sharing: Option<Sharing>,
+}
+
+// Read the record, call once at startup
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::OWNER, &mut buffer) {
+        warn!("Failed to read the owner record: {}", e);
+        return;
+    }
+
+    let record = decode(&buffer);
+    match &record.name {
+        Some(name) => info!("Device name {}", name.as_str()),
+        None => info!("No device name set, using the SSID"),
+    }
+    info!("Owner label {}, emergency contact {}", record.owner.is_some(), record.contact.is_some());
+    RECORD.lock(|stored| *stored.borrow_mut() = record);
+}
+
+pub fn shared() -> Shared {
+    let record = RECORD.lock(|record| record.borrow().clone());
+    let name = record.name.clone().unwrap_or_else(|| {
+        let mut name = String::new();
+        let _ = name.push_str(&AP_SSID[..AP_SSID.len().min(MAX_NAME_LEN)]);
+        name
+    });
+    Shared {
+        name,
+        owner: record.owner_sharing.apply(&record.owner),
+        contact: record.contact_sharing.apply(&record.contact),
+        owner_sharing: record.owner_sharing,
+        contact_sharing: record.contact_sharing,
+    }
+}
+
+// Check and store the changes, all or nothing
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn update(changes: Changes<'_>) -> Result<(), &'static str> {
+    let mut record = RECORD.lock(|record| record.borrow().clone());
+    if let Some(name) = changes.name {
+        record.name = checked(name, "Device name must be at most 32 characters")?;
+    }
+    if let Some(owner) = changes.owner {
+        record.owner = checked(owner, "Owner label must be at most 32 characters")?;
+    }
+    if let Some(contact) = changes.contact {
+        record.contact = checked(contact, "Emergency contact must be at most 48 characters")?;
+    }
+    record.owner_sharing = changes.owner_sharing.unwrap_or(record.owner_sharing);
+    record.contact_sharing = changes.contact_sharing.unwrap_or(record.contact_sharing);
+
+    let mut buffer = [0xFF; ERASE_SIZE];
+    buffer[..4].copy_from_slice(&OWNER_MAGIC.to_le_bytes());
+    buffer[4] = record.owner_sharing.to_byte();
+    buffer[5] = record.contact_sharing.to_byte();
+    let mut at = HEADER_LEN;
+    for text in [record.name.as_deref(), record.owner.as_deref(), record.contact.as_deref()] {
+        let text = text.unwrap_or("");
+        buffer[at] = text.len() as u8;
+        buffer[at + 1..at + 1 + text.len()].copy_from_slice(text.as_bytes());
+        at += 1 + text.len();
+    }
+    storage::write_sector(partitions::OWNER, &buffer)?;
+
+    RECORD.lock(|stored| *stored.borrow_mut() = record);
+    info!("Owner record updated");
+    Ok(())
+}
+
+// None for an empty text, an error for one that is too long or has a
+// character the record can't carry
+fn checked<const N: usize>(text: &str, too_long: &'static str) -> Result<Option<String<N>>, &'static str> {
+    let text = text.trim();
+    locale::check_plain(text)?;
+    if text.is_empty() {
+        return Ok(None);
+    }
+    String::try_from(text).map(Some).map_err(|()| too_long)
+}
+
+fn decode(buffer: &[u8; ERASE_SIZE]) -> Record {
+    if buffer[..4] != OWNER_MAGIC.to_le_bytes() {
+        return EMPTY;
+    }
+
+    let mut at = HEADER_LEN;
+    let mut field = || {
+        let len = buffer[at] as usize;
+        let text = core::str::from_utf8(&buffer[at + 1..at + 1 + len]).ok().filter(|text| locale::check_plain(text).is_ok());
+        at += 1 + len;
+        text
+    };
+    let (name, owner, contact) = (field(), field(), field());
+    Record {
+        name: name.and_then(|name| checked(name, "").ok().flatten()),
+        owner: owner.and_then(|owner| checked(owner, "").ok().flatten()),
+        contact: contact.and_then(|contact| checked(contact, "").ok().flatten()),
+        owner_sharing: Sharing::from_byte(buffer[4]),
+        contact_sharing: Sharing::from_byte(buffer[5]),
+    }
+}
+
+// "Jane Doe" becomes "J*** D**" and "+49 170 1234567" "+** 1** 1****67": the
+// first character of every word stays, and the last two of a long last word
+fn mask<const N: usize>(text: &str) -> String<N> {
+    let mut masked = String::new();
+    let last_word = text.rsplit(' ').next().unwrap_or("");
+    let keep_from = if last_word.len() > MASK_LONG_WORD { text.len() - MASK_KEEP_LAST } else { text.len() };
+    let mut previous = b' ';
+    for (index, byte) in text.bytes().enumerate() {
+        let keep = byte == b' ' || previous == b' ' || index >= keep_from;
+        let _ = masked.push(if keep { byte as char } else { '*' });
+        previous = byte;
+    }
+    masked
+}
@@ -0,0 +1,52 @@
+//! The sensing loop's clock.
+//!
+//! Cycles used to be separated by a fixed pause, so the time from one to the
+//! next grew with however long the cycle itself took, and the filters that
+//! assume a steady rate saw it wander. Now a [`Pacer`] starts them on a
+//! [`Ticker`], one every `sensing.cycle_delay_ms` (`parked.cycle_delay_ms`
+//! in standby). A cycle that runs past the next tick starts the following one
+//! at once and the schedule carries on from there: the ticks it missed are
+//! counted for `GET /api/metrics` instead of being caught up in a burst.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::{Duration, Instant, Ticker};
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+static MISSED: AtomicU32 = AtomicU32::new(0);
+
+pub struct Pacer {
+    ticker: Ticker,
+    period: Duration,
+    // when the ticker fires next
+    next: Instant,
+}
+
+impl Pacer {
+    pub fn new(period: Duration) -> Self {
+        Self { ticker: Ticker::every(period), period, next: Instant::now() + period }
+    }
+
+    // Waits for the next tick. A new period starts counting from now.
+    pub async fn tick(&mut self, period: Duration) {
+        let now = Instant::now();
+        if period != self.period {
+            *self = Self::new(period);
+        } else if now > self.next {
+            let missed = (now - self.next).as_ticks() / period.as_ticks().max(1) + 1;
+            MISSED.fetch_add(missed.min(u32::MAX as u64) as u32, Ordering::Relaxed);
+            self.ticker.reset();
+            self.next = now + period;
+            TICKS.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.ticker.next().await;
+        self.next += period;
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Cycles started on the ticker and ticks missed since boot
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn counts() -> (u32, u32) {
+    (TICKS.load(Ordering::Relaxed), MISSED.load(Ordering::Relaxed))
+}
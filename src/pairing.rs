@@ -0,0 +1,417 @@
+//! Guide-runner pairing: a second unit feels what the first one senses.
+//!
+//! For tandem walking or running, the sighted guide wears one unit and the
+//! user another. The guide's unit ([`Role::Guide`]) keeps its access point
+//! and sends every zone state it publishes to the follower as a small
+//! postcard [`Frame`] over UDP. The follower's unit ([`Role::Follower`]) joins
+//! the guide's access point instead of opening its own (both need the same
+//! provisioned passphrase), answers every frame with an [`Ack`] and plays the
+//! guide's obstacles on its own motors and buzzer, zoned with its own
+//! settings, on top of what its own sensors find.
+//!
+//! The guide times the acks and puts half the round trip, plus how old the
+//! reading already was, into every frame. The follower moves each obstacle on
+//! by its closing speed for that long, so something the pair is running
+//! towards is felt where it is now rather than where it was.
+//!
+//! Both ends count the link as lost after [`LINK_TIMEOUT_MS`] without hearing
+//! from the other and publish a `PairLink` event. The guide gets a beep cue,
+//! the follower repeats [`Pattern::LinkLost`] until frames come back, so the
+//! user doesn't run on trusting a unit that has gone quiet.
+//!
+//! The role is set with `POST /api/pairing` and kept in its own flash sector
+//! (see [`storage`](crate::storage)). It decides how WiFi starts, so a change
+//! takes effect at the next boot.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU16, Ordering};
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Stack};
+use embassy_time::{Instant, Timer};
+use serde::{Deserialize, Serialize};
+use vision_protocol::zoning::{self, Zone, ZoneTracker};
+
+use crate::channels::{self, Motors};
+use crate::clock;
+use crate::config::{self, Config, HapticPattern};
+use crate::events::{self, Event, Side};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::logging::{Scope, debug, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+use crate::zone_state::{self, TrendTracker, ZoneState};
+
+const SCOPE: Scope = Scope::Wifi;
+
+// Change when the stored layout changes, old data is then ignored
+const PAIRING_MAGIC: u32 = 0x5041_4952;
+
+pub const PAIR_UDP_PORT: u16 = 8082;
+// The follower's address on the guide's network, the guide keeps 192.168.4.1
+pub const FOLLOWER_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 2);
+// Not hearing from the other unit for this long means the link is lost
+pub const LINK_TIMEOUT_MS: u64 = 1000;
+// How often a follower repeats the link lost alarm
+const LINK_LOST_REPEAT_MS: u64 = 2000;
+// Frames the guide remembers the send time of, for timing their acks
+const IN_FLIGHT: usize = 8;
+// How quickly the guide's latency estimate follows changes
+const LATENCY_SMOOTHING: f32 = 0.2;
+// Never predicted further ahead, a longer delay is a link about to be lost
+const MAX_LATENCY_MS: u16 = 500;
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Role {
+    Off,
+    Guide,
+    Follower,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Off => "off",
+            Role::Guide => "guide",
+            Role::Follower => "follower",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Role::Off),
+            "guide" => Some(Role::Guide),
+            "follower" => Some(Role::Follower),
+            _ => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Role::Guide,
+            2 => Role::Follower,
+            _ => Role::Off,
+        }
+    }
+}
+
+// The role this boot runs with
+static ROLE: AtomicU8 = AtomicU8::new(Role::Off as u8);
+// The other unit was heard within LINK_TIMEOUT_MS
+static LINKED: AtomicBool = AtomicBool::new(false);
+// How late the guide's frames arrive, as the guide estimates it
+static LATENCY_MS: AtomicU16 = AtomicU16::new(0);
+
+// Read the stored role, call once at startup before WiFi starts
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::PAIRING, &mut buffer) {
+        warn!("Failed to read pairing role: {}", e);
+        return;
+    }
+
+    if buffer[..4] != PAIRING_MAGIC.to_le_bytes() {
+        return;
+    }
+    let role = Role::from_u8(buffer[4]);
+    if role != Role::Off {
+        info!("Paired as the {}", role.name());
+    }
+    ROLE.store(role as u8, Ordering::Relaxed);
+}
+
+pub fn role() -> Role {
+    Role::from_u8(ROLE.load(Ordering::Relaxed))
+}
+
+// Store the role for the next boot
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn set(role: Role) -> Result<(), &'static str> {
+    let mut buffer = [0xFF; ERASE_SIZE];
+    buffer[..4].copy_from_slice(&PAIRING_MAGIC.to_le_bytes());
+    buffer[4] = role as u8;
+    storage::write_sector(partitions::PAIRING, &buffer)?;
+    info!("Pairing role {} stored, restart to use it", role.name());
+    Ok(())
+}
+
+pub fn linked() -> bool {
+    LINKED.load(Ordering::Relaxed)
+}
+
+pub fn latency_ms() -> u16 {
+    LATENCY_MS.load(Ordering::Relaxed)
+}
+
+// One of the guide's channels
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Remote {
+    distance_cm: f32,
+    // Positive while the obstacle gets closer
+    closing_cm_per_s: f32,
+}
+
+// A zone state on its way to the follower
+#[derive(Serialize, Deserialize)]
+struct Frame {
+    seq: u32,
+    // The guide's uptime, wrapping after 49 days. Going backwards means it
+    // restarted and its seq started over.
+    uptime_ms: u32,
+    // How old the readings are by the time the frame arrives
+    latency_ms: u16,
+    // Per channel in Side::ALL order, None for a channel that is off
+    channels: [Option<Remote>; channels::COUNT],
+}
+
+#[derive(Serialize, Deserialize)]
+struct Ack {
+    seq: u32,
+}
+
+// Longest postcard encoding of a frame: varints for seq, uptime and latency,
+// then a tag and two floats per channel
+const FRAME_LEN: usize = 5 + 5 + 3 + channels::COUNT * 9;
+const ACK_LEN: usize = 5;
+
+// Start the link for this unit's role
+#[cfg_attr(not(feature = "wifi"), allow(dead_code))]
+pub fn start(spawner: &Spawner, stack: &'static Stack<'static>) {
+    match role() {
+        Role::Guide => spawner.spawn(guide_task(stack)).unwrap(),
+        Role::Follower => spawner.spawn(follower_task(stack)).unwrap(),
+        Role::Off => {},
+    }
+}
+
+fn link_changed(up: bool) {
+    LINKED.store(up, Ordering::Relaxed);
+    if up {
+        info!("Paired unit linked");
+    } else {
+        warn!("Link to the paired unit lost");
+    }
+    events::publish(Event::PairLink { up });
+}
+
+// The guide's channels as the follower needs them, closing speeds from each
+// channel's own readings so far
+fn remotes(
+    state: &ZoneState,
+    trends: &mut [TrendTracker; channels::COUNT],
+    read_at_ms: &mut [u64; channels::COUNT],
+) -> [Option<Remote>; channels::COUNT] {
+    let mut remotes = [None; channels::COUNT];
+    for (side, sensor) in state.active() {
+        let index = side as usize;
+        // a channel that wasn't read this cycle repeats its last reading
+        if sensor.timestamp_ms != read_at_ms[index] {
+            trends[index].update(sensor.distance_cm, sensor.timestamp_ms);
+            read_at_ms[index] = sensor.timestamp_ms;
+        }
+        remotes[index] = Some(Remote {
+            distance_cm: sensor.distance_cm,
+            closing_cm_per_s: -trends[index].speed_cm_per_s(),
+        });
+    }
+    remotes
+}
+
+// Sends every new zone state to the follower and times the acks
+#[embassy_executor::task]
+async fn guide_task(stack: &'static Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 4 * ACK_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * FRAME_LEN];
+    let mut socket = UdpSocket::new(*stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(PAIR_UDP_PORT) {
+        warn!("Failed to bind the pairing socket: {:?}", e);
+        return;
+    }
+
+    let mut zones = zone_state::receiver();
+    let mut trends = [const { TrendTracker::new() }; channels::COUNT];
+    let mut read_at_ms = [0; channels::COUNT];
+    let mut in_flight: [Option<(u32, u64)>; IN_FLIGHT] = [None; IN_FLIGHT];
+    let mut seq: u32 = 0;
+    let mut latency_ms: f32 = 0.0;
+    let mut acked_at_ms: Option<u64> = None;
+    let mut packet = [0; FRAME_LEN];
+
+    loop {
+        let next = select3(zones.changed(), socket.recv_from(&mut packet), Timer::after_millis(LINK_TIMEOUT_MS)).await;
+        let now_ms = Instant::now().as_millis();
+        match next {
+            Either3::First(state) => {
+                let frame = Frame {
+                    seq,
+                    uptime_ms: clock::uptime_ms() as u32,
+                    latency_ms: (latency_ms as u64 + state.age_ms(now_ms)).min(MAX_LATENCY_MS as u64) as u16,
+                    channels: remotes(&state, &mut trends, &mut read_at_ms),
+                };
+                let mut encoded = [0; FRAME_LEN];
+                if let Ok(bytes) = postcard::to_slice(&frame, &mut encoded) {
+                    // fails while the follower isn't on the network, the link state says so
+                    if let Err(e) = socket.send_to(bytes, (FOLLOWER_ADDRESS, PAIR_UDP_PORT)).await {
+                        debug!("Failed to send to the follower: {:?}", e);
+                    }
+                }
+                in_flight[seq as usize % IN_FLIGHT] = Some((seq, now_ms));
+                seq = seq.wrapping_add(1);
+            },
+            Either3::Second(Ok((len, _))) => {
+                let Ok(ack) = postcard::from_bytes::<Ack>(&packet[..len]) else {
+                    continue;
+                };
+                let sent = in_flight[ack.seq as usize % IN_FLIGHT].filter(|(seq, _)| *seq == ack.seq);
+                if let Some((_, sent_ms)) = sent {
+                    let one_way_ms = now_ms.saturating_sub(sent_ms) as f32 / 2.0;
+                    latency_ms += LATENCY_SMOOTHING * (one_way_ms - latency_ms);
+                    LATENCY_MS.store(latency_ms as u16, Ordering::Relaxed);
+                }
+                acked_at_ms = Some(now_ms);
+            },
+            Either3::Second(Err(e)) => warn!("Pairing socket error: {:?}", e),
+            Either3::Third(()) => {},
+        }
+
+        let up = acked_at_ms.is_some_and(|at_ms| now_ms.saturating_sub(at_ms) <= LINK_TIMEOUT_MS);
+        if up != linked() {
+            link_changed(up);
+            // short-long when the follower is back, long-short when it's gone
+            let steps = if up { [60, 80, 150] } else { [150, 80, 60] };
+            feedback::submit(FeedbackCommand {
+                pattern: Pattern::Preview { steps: HapticPattern::new(&steps), target: PreviewTarget::Buzzer },
+                side: None,
+                priority: Priority::Normal,
+                ttl_ms: 1000,
+            });
+        }
+    }
+}
+
+// Plays the guide's obstacles where they should be by now: zoned with this
+// unit's settings and felt like its own sensors would feel them, per channel
+// so they don't replace what this unit finds itself
+fn render(frame: &Frame, zones: &mut [ZoneTracker; channels::COUNT], now_ms: u64, config: &Config) {
+    let ahead_s = frame.latency_ms.min(MAX_LATENCY_MS) as f32 / 1000.0;
+    // the zones stretch with this user's motion, like their own
+    let scale = zone_state::latest().map_or(1.0, |state| state.zone_scale);
+    let level_zones = config.zones.scaled(scale);
+    let overhead_zones = config.overhead.zones.scaled(scale);
+    let ttl_ms = config.sensing.feedback_ttl_ms;
+    let mut closest_critical_cm: Option<f32> = None;
+
+    for (side, remote) in Side::ALL.into_iter().zip(frame.channels) {
+        let tracker = &mut zones[side as usize];
+        let Some(remote) = remote else {
+            *tracker = ZoneTracker::new();
+            continue;
+        };
+        let placement = channels::placement(side);
+        let zone_config = if placement.head_height() { &overhead_zones } else { &level_zones };
+
+        let distance_cm = (remote.distance_cm - remote.closing_cm_per_s * ahead_s).max(0.0);
+        let zone = tracker.update(distance_cm, now_ms, zone_config, 1);
+        let intensity = zoning::intensity(distance_cm, zone, zone_config);
+        if intensity == 0 {
+            continue;
+        }
+
+        let pattern = if placement.head_height() {
+            Pattern::Overhead { intensity }
+        } else if placement.rear() {
+            Pattern::Reversing { intensity }
+        } else {
+            Pattern::Haptic { intensity, motors: Motors::towards(placement.bearing_deg) }
+        };
+        submit(pattern, Some(side), zone, ttl_ms);
+
+        if zone == Zone::Critical && placement.ahead() {
+            closest_critical_cm = Some(closest_critical_cm.map_or(distance_cm, |closest| closest.min(distance_cm)));
+        }
+    }
+
+    if let Some(distance_cm) = closest_critical_cm {
+        submit(Pattern::WarningSound { distance_cm: distance_cm as u16 }, None, Zone::Critical, ttl_ms);
+    }
+}
+
+fn submit(pattern: Pattern, side: Option<Side>, zone: Zone, ttl_ms: u32) {
+    feedback::submit(FeedbackCommand {
+        pattern,
+        side,
+        priority: if zone == Zone::Critical { Priority::High } else { Priority::Normal },
+        ttl_ms,
+    });
+}
+
+// Acks the guide's frames and plays them, or the link lost alarm once they
+// stop coming
+#[embassy_executor::task]
+async fn follower_task(stack: &'static Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * FRAME_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * ACK_LEN];
+    let mut socket = UdpSocket::new(*stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(PAIR_UDP_PORT) {
+        warn!("Failed to bind the pairing socket: {:?}", e);
+        return;
+    }
+
+    let mut zones = [const { ZoneTracker::new() }; channels::COUNT];
+    // the alarm only starts once the guide has been heard, not while both boot
+    let mut heard = false;
+    // the uptime in the last frame
+    let mut guide_uptime_ms = 0;
+    let mut packet = [0; FRAME_LEN];
+
+    loop {
+        let timeout_ms = if linked() { LINK_TIMEOUT_MS } else { LINK_LOST_REPEAT_MS };
+        match select(socket.recv_from(&mut packet), Timer::after_millis(timeout_ms)).await {
+            Either::First(Ok((len, meta))) => {
+                let Ok(frame) = postcard::from_bytes::<Frame>(&packet[..len]) else {
+                    continue;
+                };
+                let mut ack = [0; ACK_LEN];
+                if let Ok(bytes) = postcard::to_slice(&Ack { seq: frame.seq }, &mut ack) {
+                    if let Err(e) = socket.send_to(bytes, meta.endpoint).await {
+                        debug!("Failed to ack the guide: {:?}", e);
+                    }
+                }
+
+                if frame.uptime_ms < guide_uptime_ms {
+                    // what the trackers hold is from before the restart
+                    info!("Guide restarted after {} s", guide_uptime_ms / 1000);
+                    zones = [const { ZoneTracker::new() }; channels::COUNT];
+                }
+                guide_uptime_ms = frame.uptime_ms;
+
+                LATENCY_MS.store(frame.latency_ms, Ordering::Relaxed);
+                heard = true;
+                if !linked() {
+                    link_changed(true);
+                }
+                render(&frame, &mut zones, Instant::now().as_millis(), &config::get());
+            },
+            Either::First(Err(e)) => warn!("Pairing socket error: {:?}", e),
+            Either::Second(()) => {
+                if !heard || crate::shutdown::requested() {
+                    continue;
+                }
+                if linked() {
+                    link_changed(false);
+                    zones = [const { ZoneTracker::new() }; channels::COUNT];
+                }
+                feedback::submit(FeedbackCommand {
+                    pattern: Pattern::LinkLost,
+                    side: None,
+                    priority: Priority::High,
+                    ttl_ms: LINK_LOST_REPEAT_MS as u32,
+                });
+            },
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Detection of the device being left lying somewhere.
+//!
+//! Taken off and put down on a table or a shelf, the device would otherwise
+//! buzz at the nearest wall all night. When the IMU has seen no movement at
+//! all and no channel's reading has moved by more than `parked.epsilon_cm`
+//! for `parked.after_ms`, it is parked: the planner queues no feedback, the
+//! sensing loop slows down to `parked.cycle_delay_ms` and WiFi stays up as
+//! before. The first IMU sample that moves the device ends it, without
+//! waiting for the slow cycle to finish.
+//!
+//! Without a working IMU the device can't tell lying still from a user
+//! standing in front of a wall, so it never parks.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::channels;
+use crate::config::ParkedConfig;
+use crate::events::{self, Event};
+use crate::logging::{Scope, info};
+use crate::pipeline::RawReading;
+
+const SCOPE: Scope = Scope::Sensing;
+
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+// Whether feedback is paused because the device was left lying somewhere
+pub fn parked() -> bool {
+    PARKED.load(Ordering::Relaxed)
+}
+
+pub struct ParkedDetector {
+    reference_cm: [Option<f32>; channels::COUNT],
+    since_ms: u64,
+}
+
+impl ParkedDetector {
+    pub const fn new() -> Self {
+        Self { reference_cm: [None; channels::COUNT], since_ms: 0 }
+    }
+
+    // Feed one cycle's raw readings and how long the IMU has been still,
+    // publishes the change when the device parks or is picked up
+    pub fn update(&mut self, raw: &[Option<RawReading>; channels::COUNT], still_for_ms: Option<u64>, now_ms: u64, config: &ParkedConfig) {
+        // readings that move, or channels switching on or off, start the wait over
+        let unchanged = raw.iter().zip(&self.reference_cm).all(|(reading, reference)| match (reading, reference) {
+            (Some(reading), Some(reference)) => libm::fabsf(reading.distance_cm - reference) <= config.epsilon_cm,
+            (None, None) => true,
+            _ => false,
+        });
+        if !unchanged {
+            self.reference_cm = raw.map(|reading| reading.map(|reading| reading.distance_cm));
+            self.since_ms = now_ms;
+        }
+
+        let after_ms = config.after_ms as u64;
+        let still = config.enabled && still_for_ms.is_some_and(|still_ms| still_ms >= after_ms);
+        // once parked only moving the device wakes it, not someone walking past
+        let parked = still && (parked() || now_ms.saturating_sub(self.since_ms) >= after_ms);
+        if parked != PARKED.swap(parked, Ordering::Relaxed) {
+            if parked {
+                info!("Left lying still, feedback paused");
+            } else {
+                info!("Picked up, feedback resumed");
+            }
+            events::publish(Event::Parked { parked });
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Where everything lives in flash, defined once.
+//!
+//! ```text
+//! 0x000000  program image        FLASH in memory.x, 1740K
+//! 0x1B3000  CYW43 firmware       256K, see vision_protocol::partition
+//! 0x1F3000  settings             a 4K sector each, the actuator mapping lowest
+//!                                and the profiles in the last one
+//! 0x200000  end of the 2 MiB
+//! ```
+//!
+//! Everything that reads or writes flash names its [`Region`] from here, and
+//! the checks below run at compile time: every region is sector aligned,
+//! inside the flash and clear of every other, and the program area from
+//! memory.x (read by build.rs) ends before the first of them. Growing the
+//! program or adding a settings sector that doesn't fit fails the build
+//! instead of overwriting settings.
+
+use embassy_rp::flash::ERASE_SIZE;
+use vision_protocol::partition;
+
+include!(concat!(env!("OUT_DIR"), "/memory.rs"));
+
+// 2 MiB is what memory.x assumes, although a Pico 2 has 4 MiB
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+// Where flash shows up in the RP2350's execute-in-place window
+const XIP_BASE: usize = 0x1000_0000;
+
+#[derive(Clone, Copy)]
+pub struct Region {
+    // from the start of flash
+    pub offset: u32,
+    pub size: usize,
+}
+
+impl Region {
+    // The `n`th sector from the end of flash
+    const fn setting(n: usize) -> Self {
+        Region { offset: (FLASH_SIZE - n * ERASE_SIZE) as u32, size: ERASE_SIZE }
+    }
+
+    pub const fn end(&self) -> usize {
+        self.offset as usize + self.size
+    }
+
+    // The region as memory, through the XIP window
+    #[cfg_attr(not(feature = "wifi"), allow(dead_code))]
+    pub const fn xip_address(&self) -> usize {
+        XIP_BASE + self.offset as usize
+    }
+
+    const fn overlaps(&self, other: &Region) -> bool {
+        (self.offset as usize) < other.end() && (other.offset as usize) < self.end()
+    }
+}
+
+pub const PROGRAM: Region = Region { offset: 0, size: PROGRAM_SIZE };
+// Loaded at boot, see wifi_utils.rs
+pub const CYW43: Region = Region { offset: partition::PARTITION_OFFSET, size: partition::PARTITION_SIZE };
+
+// Settings, one sector each
+pub const PROFILES: Region = Region::setting(1); // see profiles.rs
+pub const SECRETS: Region = Region::setting(2); // see secrets.rs
+pub const RADIO: Region = Region::setting(3); // see radio.rs
+// Summary of the last session before a shutdown, see session.rs
+pub const SESSION: Region = Region::setting(4);
+// Boot and runtime counters, see usage.rs
+pub const USAGE: Region = Region::setting(5);
+// Maintenance reminder intervals and acknowledgements, see maintenance.rs
+pub const MAINTENANCE: Region = Region::setting(6);
+// Gain and offset per sensor, see calibration.rs
+pub const CALIBRATION: Region = Region::setting(7);
+// Guide or follower of a pair, see pairing.rs
+pub const PAIRING: Region = Region::setting(8);
+// Master volume and haptic strength, see levels.rs
+pub const LEVELS: Region = Region::setting(9);
+// Error counts per subsystem, see health.rs
+pub const HEALTH: Region = Region::setting(10);
+// Serial number and test result from the production fixture, see factory.rs
+pub const FACTORY: Region = Region::setting(11);
+// Device name, owner label and emergency contact, see owner.rs
+pub const OWNER: Region = Region::setting(12);
+// Motor sides, polarity and kick, see actuators.rs
+pub const ACTUATORS: Region = Region::setting(13);
+
+const ALL: [Region; 15] = [
+    PROGRAM, CYW43, PROFILES, SECRETS, RADIO, SESSION, USAGE, MAINTENANCE, CALIBRATION, PAIRING, LEVELS, HEALTH,
+    FACTORY, OWNER, ACTUATORS,
+];
+
+const _: () = {
+    let mut i = 0;
+    while i < ALL.len() {
+        let region = ALL[i];
+        assert!(region.size > 0 && region.end() <= FLASH_SIZE, "flash region outside the flash");
+        assert!(
+            region.offset as usize % ERASE_SIZE == 0 && region.size % ERASE_SIZE == 0,
+            "flash region not sector aligned"
+        );
+        let mut j = i + 1;
+        while j < ALL.len() {
+            assert!(!region.overlaps(&ALL[j]), "flash regions overlap");
+            j += 1;
+        }
+        i += 1;
+    }
+};
@@ -0,0 +1,225 @@
+//! Signal processing pipeline from raw sensor readings to zoned readings.
+//!
+//! `RawReading -> [Stage] -> ground -> geometry -> zoning -> ZonedReading`
+//!
+//! The per-channel stages and their order come from `PipelineConfig`, so
+//! processing can be rearranged or switched off without touching main. The
+//! floor echo removal, the pair-wise geometry correction and the zoning (with
+//! its hysteresis) always run last since they need the fully processed
+//! distances. Every channel runs the same stages; head height channels (see
+//! channels.rs) look up, so they skip the floor and wall corrections and
+//! have zones of their own. Rear channels skip the corrections too. A channel
+//! that wasn't read for a while starts over from its next reading.
+use vision_protocol::zoning::{Zone, ZoneTracker};
+
+use crate::channels;
+use crate::config::{Config, StageKind, ZoneConfig};
+use crate::events::Side;
+use crate::filter::AdaptiveFilter;
+use crate::geometry;
+use crate::ground;
+
+// Reading straight from a sensor
+#[derive(Clone, Copy)]
+pub struct RawReading {
+    pub distance_cm: f32,
+    pub timestamp_ms: u64,
+}
+
+// Fully processed reading of one channel
+#[derive(Clone, Copy)]
+pub struct ZonedReading {
+    pub distance_cm: f32,
+    pub zone: Zone,
+    pub noise_cm: f32,
+    // When the raw reading was taken
+    pub timestamp_ms: u64,
+}
+
+// A single processing step on one channel, `dt_ms` after its last reading
+pub trait Stage {
+    fn process(&mut self, distance_cm: f32, dt_ms: f32, config: &Config) -> f32;
+
+    // Forget history and continue from this distance
+    fn reset(&mut self, distance_cm: f32);
+}
+
+impl Stage for AdaptiveFilter {
+    fn process(&mut self, distance_cm: f32, dt_ms: f32, config: &Config) -> f32 {
+        self.update(distance_cm, dt_ms, &config.filter)
+    }
+
+    fn reset(&mut self, distance_cm: f32) {
+        AdaptiveFilter::reset(self, distance_cm);
+    }
+}
+
+// Median of the last three readings, knocks out single spikes
+pub struct MedianStage {
+    window: [f32; 3],
+}
+
+impl MedianStage {
+    const fn new(initial: f32) -> Self {
+        Self { window: [initial; 3] }
+    }
+}
+
+impl Stage for MedianStage {
+    fn process(&mut self, distance_cm: f32, _dt_ms: f32, _config: &Config) -> f32 {
+        self.window = [self.window[1], self.window[2], distance_cm];
+        let [a, b, c] = self.window;
+        a.min(b).max(a.max(b).min(c))
+    }
+
+    fn reset(&mut self, distance_cm: f32) {
+        self.window = [distance_cm; 3];
+    }
+}
+
+// Distances are computed with a fixed speed of sound, scale them for the air temperature
+pub struct TemperatureStage;
+
+// Speed of sound (m/s) that the raw distance conversion assumes
+const ASSUMED_SPEED_OF_SOUND: f32 = 340.0;
+
+impl Stage for TemperatureStage {
+    fn process(&mut self, distance_cm: f32, _dt_ms: f32, config: &Config) -> f32 {
+        let speed_of_sound = 331.3 + 0.606 * config.pipeline.ambient_temperature_c;
+        distance_cm * speed_of_sound / ASSUMED_SPEED_OF_SOUND
+    }
+
+    fn reset(&mut self, _distance_cm: f32) {}
+}
+
+// Stage instances and zoning state for one channel
+struct ChannelPipeline {
+    median: MedianStage,
+    smoothing: AdaptiveFilter,
+    temperature: TemperatureStage,
+    zone: ZoneTracker,
+    // Not read last cycle, like the rear channel while going forward
+    idle: bool,
+    // when the last reading was taken, None before the first
+    last_ms: Option<u64>,
+}
+
+impl ChannelPipeline {
+    const fn new(initial: f32) -> Self {
+        Self {
+            median: MedianStage::new(initial),
+            smoothing: AdaptiveFilter::new(initial),
+            temperature: TemperatureStage,
+            zone: ZoneTracker::new(),
+            idle: false,
+            last_ms: None,
+        }
+    }
+
+    fn stage(&mut self, kind: StageKind) -> &mut dyn Stage {
+        match kind {
+            StageKind::Median => &mut self.median,
+            StageKind::Smoothing => &mut self.smoothing,
+            StageKind::TemperatureCompensation => &mut self.temperature,
+        }
+    }
+
+    fn process(&mut self, raw: RawReading, config: &Config) -> f32 {
+        // the first reading has nothing before it, assume a cycle went by
+        let dt_ms = match self.last_ms.replace(raw.timestamp_ms) {
+            Some(last_ms) => raw.timestamp_ms.saturating_sub(last_ms) as f32,
+            None => config.sensing.cycle_delay_ms as f32,
+        };
+
+        // what the channel saw before it went idle is long gone
+        if self.idle {
+            self.idle = false;
+            self.zone = ZoneTracker::new();
+            for kind in config.pipeline.stages.into_iter().flatten() {
+                self.stage(kind).reset(raw.distance_cm);
+            }
+        }
+
+        // near-field contact is passed through untouched so it can't be averaged away
+        if raw.distance_cm < crate::MIN_DETECTABLE_DISTANCE {
+            for kind in config.pipeline.stages.into_iter().flatten() {
+                self.stage(kind).reset(raw.distance_cm);
+            }
+            return raw.distance_cm;
+        }
+
+        let mut distance_cm = raw.distance_cm;
+        for kind in config.pipeline.stages.into_iter().flatten() {
+            distance_cm = self.stage(kind).process(distance_cm, dt_ms, config);
+        }
+        distance_cm
+    }
+
+    fn zone(&mut self, distance_cm: f32, timestamp_ms: u64, zones: &ZoneConfig, confirmations: u8) -> ZonedReading {
+        ZonedReading {
+            distance_cm,
+            zone: self.zone.update(distance_cm, timestamp_ms, zones, confirmations),
+            noise_cm: self.smoothing.noise_cm(),
+            timestamp_ms,
+        }
+    }
+}
+
+// The whole processing chain of all channels
+pub struct Pipeline {
+    channels: [ChannelPipeline; channels::COUNT],
+    // Readings in a row needed to enter a more severe zone
+    confirmations: u8,
+}
+
+impl Pipeline {
+    pub fn new(initial: f32) -> Self {
+        Self {
+            channels: core::array::from_fn(|_| ChannelPipeline::new(initial)),
+            confirmations: 1,
+        }
+    }
+
+    // Raised while other ultrasonic sources are around, see interference.rs
+    pub fn require_confirmations(&mut self, confirmations: u8) {
+        self.confirmations = confirmations.max(1);
+    }
+
+    // Readings of the channels that are on, in Side::ALL order, to zoned readings
+    pub fn process(&mut self, readings: [Option<RawReading>; channels::COUNT], config: &Config) -> [Option<ZonedReading>; channels::COUNT] {
+        let mut distances = [None; channels::COUNT];
+        for side in Side::ALL {
+            let Some(raw) = readings[side as usize] else {
+                self.channels[side as usize].idle = true;
+                continue;
+            };
+            let distance_cm = self.channels[side as usize].process(raw, config);
+            distances[side as usize] = Some(if channels::placement(side).ahead() {
+                ground::subtract(distance_cm, &config.mounting)
+            } else {
+                distance_cm
+            });
+        }
+
+        // Correct for walls seen at an angle, skipped when either side is in the blind zone
+        if let Some((left, right)) = channels::level_pair() {
+            if let (Some(left_cm), Some(right_cm)) = (distances[left as usize], distances[right as usize]) {
+                if left_cm >= crate::MIN_DETECTABLE_DISTANCE && right_cm >= crate::MIN_DETECTABLE_DISTANCE {
+                    let (left_cm, right_cm) = geometry::compensate(left_cm, right_cm, &config.geometry);
+                    distances[left as usize] = Some(left_cm);
+                    distances[right as usize] = Some(right_cm);
+                }
+            }
+        }
+
+        let mut zoned = [None; channels::COUNT];
+        for side in Side::ALL {
+            let (Some(raw), Some(distance_cm)) = (readings[side as usize], distances[side as usize]) else {
+                continue;
+            };
+            let zones = if channels::placement(side).head_height() { &config.overhead.zones } else { &config.zones };
+            zoned[side as usize] = Some(self.channels[side as usize].zone(distance_cm, raw.timestamp_ms, zones, self.confirmations));
+        }
+        zoned
+    }
+}
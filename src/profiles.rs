@@ -0,0 +1,319 @@
+//! Named configuration presets ("Indoor", "Outdoor", ...) kept in flash.
+//!
+//! A profile bundles the zone thresholds, filter tuning, haptic patterns and
+//! where the device is mounted, so e.g. the "Wheelchair" preset also turns on
+//! the rear sensor.
+//! Activating one copies those into the running [`config`](crate::config);
+//! the rest of the configuration is left alone. The whole set, plus which one
+//! is active, lives in the last sector of flash (see [`storage`](crate::storage))
+//! and is rewritten by [`storage_task`] whenever it changes, except in safe mode.
+
+use core::cell::RefCell;
+use core::fmt::Write as FmtWrite;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, Config, FilterConfig, HapticConfig, HapticPattern, Mounting, ZoneConfig, ZoneThreshold};
+use crate::events::{self, Event};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::locale;
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+use crate::usage;
+
+const SCOPE: Scope = Scope::Storage;
+
+pub const MAX_PROFILES: usize = 5;
+pub const MAX_NAME_LEN: usize = 16;
+
+// Change when the stored layout changes, old data is then ignored
+const STORAGE_MAGIC: u32 = 0x5052_4633;
+const HEADER_LEN: usize = 6;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String<MAX_NAME_LEN>,
+    pub zones: ZoneConfig,
+    pub filter: FilterConfig,
+    pub haptic: HapticConfig,
+    pub mounting: Mounting,
+}
+
+impl Profile {
+    fn from_config(name: &str, config: &Config) -> Self {
+        let mut profile_name = String::new();
+        let _ = profile_name.push_str(name);
+        Self {
+            name: profile_name,
+            zones: config.zones,
+            filter: config.filter,
+            haptic: config.haptic,
+            mounting: config.mounting.mode,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ProfileStore {
+    active: u8,
+    profiles: Vec<Profile, MAX_PROFILES>,
+}
+
+impl ProfileStore {
+    const fn empty() -> Self {
+        Self { active: 0, profiles: Vec::new() }
+    }
+
+    fn defaults() -> Self {
+        let zone = |enter_cm, exit_cm, min_dwell_ms| ZoneThreshold { enter_cm, exit_cm, min_dwell_ms };
+
+        // Indoors: the current defaults
+        let indoor = Profile::from_config("Indoor", &Config::DEFAULT);
+
+        // Outdoors: walking faster, so warn earlier and react quicker
+        let mut outdoor = Profile::from_config("Outdoor", &Config::DEFAULT);
+        outdoor.zones = ZoneConfig {
+            critical: zone(45.0, 52.0, 300),
+            warning: zone(90.0, 100.0, 300),
+            notice: zone(150.0, 165.0, 500),
+        };
+        outdoor.filter.noisy_tau_ms = 100.0;
+        outdoor.filter.quiet_tau_ms = 30.0;
+
+        // Crowded: people all around, only flag what's really close and smooth harder
+        let mut crowded = Profile::from_config("Crowded", &Config::DEFAULT);
+        crowded.zones = ZoneConfig {
+            critical: zone(20.0, 24.0, 200),
+            warning: zone(40.0, 45.0, 200),
+            notice: zone(60.0, 66.0, 300),
+        };
+        crowded.filter.noisy_tau_ms = 225.0;
+        crowded.filter.quiet_tau_ms = 55.0;
+
+        // Wheelchair: a wider footprint that can't sidestep, so warn a bit
+        // earlier, and the rear sensor for reversing
+        let mut wheelchair = Profile::from_config("Wheelchair", &Config::DEFAULT);
+        wheelchair.zones = ZoneConfig {
+            critical: zone(40.0, 46.0, 300),
+            warning: zone(80.0, 88.0, 300),
+            notice: zone(130.0, 143.0, 500),
+        };
+        wheelchair.mounting = Mounting::Wheelchair;
+
+        let mut profiles = Vec::new();
+        let _ = profiles.push(indoor);
+        let _ = profiles.push(outdoor);
+        let _ = profiles.push(crowded);
+        let _ = profiles.push(wheelchair);
+        Self { active: 0, profiles }
+    }
+}
+
+static STORE: Mutex<CriticalSectionRawMutex, RefCell<ProfileStore>> = Mutex::new(RefCell::new(ProfileStore::empty()));
+static SAVE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Read the stored profiles (or the defaults) and apply the active one, call once at startup
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    let store = match storage::read_sector(partitions::PROFILES, &mut buffer) {
+        Ok(()) => decode(&buffer),
+        Err(e) => {
+            warn!("Failed to read profiles: {}", e);
+            None
+        }
+    };
+
+    let mut store = store.unwrap_or_else(|| {
+        info!("No stored profiles, using defaults");
+        ProfileStore::defaults()
+    });
+
+    // Settings that got corrupted (or predate a check) are reset rather than
+    // used, and the repaired profiles written back
+    let mut repaired = false;
+    for (index, profile) in store.profiles.iter_mut().enumerate() {
+        // saved before names were checked
+        if locale::check_plain(&profile.name).is_err() {
+            warn!("Profile {} had an invalid name, renamed", index);
+            profile.name.clear();
+            let _ = write!(profile.name, "Profile {}", index + 1);
+            repaired = true;
+        }
+
+        let mut config = Config::DEFAULT;
+        config.zones = profile.zones;
+        config.filter = profile.filter;
+        config.haptic = profile.haptic;
+        let sections = config.repair();
+        if !sections.is_empty() {
+            warn!("Profile {} ({}) had invalid settings, {} sections reset", index, profile.name.as_str(), sections.len());
+            profile.zones = config.zones;
+            profile.filter = config.filter;
+            profile.haptic = config.haptic;
+            events::publish(Event::ConfigRepaired { profile: index as u8, sections: sections.len() as u8 });
+            repaired = true;
+        }
+    }
+    if repaired {
+        SAVE.signal(());
+    }
+
+    let active = store.active as usize;
+    STORE.lock(|stored| *stored.borrow_mut() = store);
+    apply(active);
+}
+
+// The built-in profiles instead of the stored ones, for safe mode. They can be
+// switched between, but never written over the stored ones (see `storage_task`).
+pub fn load_defaults() {
+    STORE.lock(|stored| *stored.borrow_mut() = ProfileStore::defaults());
+    apply(0);
+}
+
+fn decode(buffer: &[u8]) -> Option<ProfileStore> {
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = u16::from_le_bytes(buffer[4..HEADER_LEN].try_into().ok()?) as usize;
+    if magic != STORAGE_MAGIC || HEADER_LEN + len > buffer.len() {
+        return None;
+    }
+
+    // the settings themselves are checked (and repaired) in `load`
+    let store: ProfileStore = postcard::from_bytes(&buffer[HEADER_LEN..HEADER_LEN + len]).ok()?;
+    ((store.active as usize) < store.profiles.len()).then_some(store)
+}
+
+// Writes the profiles to flash whenever they change, except in safe mode
+// where they are the built-in ones and would replace the user's
+#[embassy_executor::task]
+pub async fn storage_task() {
+    loop {
+        SAVE.wait().await;
+        if usage::safe_mode() {
+            continue;
+        }
+
+        let mut buffer = [0xFF; ERASE_SIZE];
+        let encoded = STORE.lock(|store| {
+            postcard::to_slice(&*store.borrow(), &mut buffer[HEADER_LEN..]).map(|encoded| encoded.len())
+        });
+        let len = match encoded {
+            Ok(len) => len,
+            Err(_) => {
+                warn!("Profiles don't fit in one flash sector, not saved");
+                continue;
+            }
+        };
+        buffer[..4].copy_from_slice(&STORAGE_MAGIC.to_le_bytes());
+        buffer[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+
+        match storage::write_sector(partitions::PROFILES, &buffer) {
+            Ok(()) => info!("Profiles saved ({} bytes)", len),
+            Err(e) => warn!("Failed to save profiles: {}", e),
+        }
+    }
+}
+
+// Copy a profile into the running configuration
+fn apply(index: usize) -> bool {
+    let Some(profile) = STORE.lock(|store| store.borrow().profiles.get(index).cloned()) else {
+        return false;
+    };
+
+    config::update(|config| {
+        config.zones = profile.zones;
+        config.filter = profile.filter;
+        config.haptic = profile.haptic;
+        config.mounting.mode = profile.mounting;
+    });
+    info!("Profile {} ({}) active", index, profile.name.as_str());
+    true
+}
+
+// Switch to a profile, confirmed on the device with one beep per profile number
+pub fn activate(index: usize) -> Result<(), &'static str> {
+    if !apply(index) {
+        return Err("Unknown profile");
+    }
+    STORE.lock(|store| store.borrow_mut().active = index as u8);
+    SAVE.signal(());
+
+    events::publish(Event::ProfileChanged { index: index as u8 });
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Preview { steps: beeps(index + 1), target: PreviewTarget::Buzzer },
+        side: None,
+        priority: Priority::Normal,
+        ttl_ms: 1000,
+    });
+    Ok(())
+}
+
+// Next profile, wrapping around
+pub fn cycle() {
+    let (active, count) = STORE.lock(|store| {
+        let store = store.borrow();
+        (store.active as usize, store.profiles.len())
+    });
+    if count > 0 {
+        let _ = activate((active + 1) % count);
+    }
+}
+
+// Index of the profile with this name, ignoring case
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn find(name: &str) -> Option<usize> {
+    STORE.lock(|store| {
+        store.borrow().profiles.iter().position(|profile| profile.name.eq_ignore_ascii_case(name.trim()))
+    })
+}
+
+// Store the running configuration under a name, replacing a profile with the
+// same name or adding a new one. Returns its index.
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn save_current(name: &str) -> Result<usize, &'static str> {
+    if usage::safe_mode() {
+        return Err("Profiles can't be saved in safe mode");
+    }
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err("Profile names must be 1 to 16 characters");
+    }
+    locale::check_plain(name)?;
+
+    let profile = Profile::from_config(name, &config::get());
+    let index = STORE.lock(|store| {
+        let mut store = store.borrow_mut();
+        match store.profiles.iter().position(|existing| existing.name.eq_ignore_ascii_case(name)) {
+            Some(index) => {
+                store.profiles[index] = profile;
+                Ok(index)
+            }
+            None => {
+                store.profiles.push(profile).map_err(|_| "No room for another profile")?;
+                Ok(store.profiles.len() - 1)
+            }
+        }
+    })?;
+
+    SAVE.signal(());
+    Ok(index)
+}
+
+// Index of the active profile and all profile names
+pub fn list() -> (usize, Vec<String<MAX_NAME_LEN>, MAX_PROFILES>) {
+    STORE.lock(|store| {
+        let store = store.borrow();
+        (store.active as usize, store.profiles.iter().map(|profile| profile.name.clone()).collect())
+    })
+}
+
+// Short beeps, 60 ms on and 80 ms off
+fn beeps(count: usize) -> HapticPattern {
+    let mut steps = [0; config::MAX_PATTERN_STEPS];
+    for (i, step) in steps.iter_mut().take(count * 2 - 1).enumerate() {
+        *step = if i % 2 == 0 { 60 } else { 80 };
+    }
+    HapticPattern { steps }
+}
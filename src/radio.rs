@@ -0,0 +1,111 @@
+//! WiFi on/off switch.
+//!
+//! The access point can be switched off to save battery, or where radios
+//! aren't allowed, with `RADIO OFF` over TCP, `POST /api/radio` or by holding
+//! the button; holding it again brings it back. The setting survives a reboot
+//! in its own flash sector (see [`storage`](crate::storage)). The CYW43's
+//! power pin belongs to its driver, so "off" closes the access point and puts
+//! the chip into its deepest power save mode rather than cutting power. The
+//! LED task does the actual switching since it owns the chip's control handle.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::config::HapticPattern;
+use crate::events::{self, Event};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Wifi;
+
+// Change when the stored layout changes, old data is then ignored
+const RADIO_MAGIC: u32 = 0x5244_4f31;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+// Wakes the LED task to switch the chip
+static SWITCH: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+// Set by the LED task once the access point is closed
+static CLOSED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Read the stored setting, call once at startup before the access point starts
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::RADIO, &mut buffer) {
+        warn!("Failed to read radio setting: {}", e);
+        return;
+    }
+
+    // anything but a stored "off" leaves the radio on
+    let off = buffer[..4] == RADIO_MAGIC.to_le_bytes() && buffer[4] == 0;
+    if off {
+        info!("WiFi is switched off, hold the button to turn it back on");
+    }
+    ENABLED.store(!off, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Switch the radio and remember it for the next boot
+pub fn set(on: bool) -> Result<(), &'static str> {
+    if !cfg!(feature = "wifi") {
+        return Err("Built without WiFi");
+    }
+    if on == enabled() {
+        return Ok(());
+    }
+
+    let mut buffer = [0xFF; ERASE_SIZE];
+    buffer[..4].copy_from_slice(&RADIO_MAGIC.to_le_bytes());
+    buffer[4] = on as u8;
+    storage::write_sector(partitions::RADIO, &buffer)?;
+
+    ENABLED.store(on, Ordering::Relaxed);
+    SWITCH.signal(on);
+    info!("WiFi switched {}", if on { "on" } else { "off" });
+    events::publish(Event::Radio { on });
+    Ok(())
+}
+
+pub fn toggle() -> Result<(), &'static str> {
+    let on = !enabled();
+    set(on)?;
+
+    // the user can't see the LED, so confirm by ear: two short beeps for on, one long for off
+    let steps = if on { HapticPattern::new(&[80, 80, 80]) } else { HapticPattern::new(&[400]) };
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Preview { steps, target: PreviewTarget::Buzzer },
+        side: None,
+        priority: Priority::Normal,
+        ttl_ms: 1000,
+    });
+    Ok(())
+}
+
+// Next requested radio state, for the task that owns the chip
+#[cfg_attr(not(feature = "wifi"), allow(dead_code))]
+pub async fn switched() -> bool {
+    SWITCH.wait().await
+}
+
+// For the task that owns the chip, after switching it off
+#[cfg_attr(not(feature = "wifi"), allow(dead_code))]
+pub fn closed() {
+    CLOSED.signal(());
+}
+
+// Close the access point for a shutdown, leaving the stored setting alone.
+// Returns once the chip is in power save.
+pub async fn power_down() {
+    // no chip to put to sleep, and no task to do it
+    if !cfg!(feature = "wifi") {
+        return;
+    }
+    CLOSED.reset();
+    SWITCH.signal(false);
+    CLOSED.wait().await;
+}
@@ -0,0 +1,628 @@
+//! Distance sensor backends behind one [`DistanceSensor`] trait.
+//!
+//! Every channel (left, right, overhead, rear) is an HC-SR04 unless the build's
+//! board config says otherwise (`VISIONASSIST_SENSORS`, see build.rs). The
+//! MaxBotix MaxSonar rangers have a narrower, better shaped beam for a
+//! walking aid and can be used instead, either way they output a distance:
+//!
+//! - serial: the sensor ranges continuously and sends `R<digits>\r` frames
+//!   at 9600 baud on its TX pin, wired to the channel's echo pin and read by
+//!   a PIO1 UART. The MaxSonar's RS232-style output is inverted, so use a
+//!   TTL-output model or an inverter.
+//! - analog: the sensor's AN pin goes to the channel's ADC pin (GPIO26 left,
+//!   27 right, 28 overhead) and is read as a fraction of the 3.3 V supply.
+//!   The rear channel has no ADC pin left, so it can't be analog.
+//!
+//! For both the channel's trigger pin is held high on the sensor's RX pin,
+//! which keeps it ranging. The MaxSonars filter their own readings and the
+//! unit of their output depends on the model, hence the unit in the config.
+//!
+//! A Benewake TF-Luna or TFmini lidar is a serial sensor too: its TX goes to
+//! the echo pin (PIO1 at 115200 baud) and its RX to the trigger pin, which a
+//! PIO2 UART drives to set the frame rate and low power mode from the
+//! sensing config. Its beam is a couple of degrees wide, so it suits the
+//! overhead channel or a narrow corridor better than a general sweep.
+//!
+//! Interference listening and echo capture only exist for the HC-SR04, the
+//! only one whose echo pin shows the raw ultrasound.
+
+use core::cell::RefCell;
+use embassy_rp::adc::{self, Adc};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::{ADC, PIN_10, PIN_11, PIN_12, PIN_13, PIN_14, PIN_15, PIN_16, PIN_17, PIN_26, PIN_27, PIN_28, PIO1, PIO2};
+use embassy_rp::pio::Pio;
+use embassy_rp::pio_programs::uart::{PioUartRx, PioUartRxProgram, PioUartTx, PioUartTxProgram};
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::{Read, Write};
+use heapless::Vec;
+
+use crate::channels;
+use crate::config::{self, SensingConfig};
+use crate::defaults::SENSOR_KINDS;
+use crate::echoes;
+use crate::events::Side;
+use crate::irqs::Irqs;
+use crate::logging::{Scope, info, warn};
+use crate::{MIN_DETECTABLE_DISTANCE, NEAR_FIELD_ERROR};
+
+const SCOPE: Scope = Scope::Sensing;
+
+const MAXBOTIX_BAUD: u32 = 9600;
+const LIDAR_BAUD: u32 = 115_200;
+// The slowest MaxSonars range every 100 ms, a serial sensor quiet for this
+// long is gone
+const SERIAL_TIMEOUT_MS: u64 = 250;
+// Below every MaxSonar's minimum range, an analog reading this low means
+// nothing is driving the pin
+const ANALOG_FLOOR_CM: f32 = 10.0;
+// Speed of sound the echo times are converted with, in cm/µs
+const SOUND_CM_PER_US: f32 = 0.034;
+// An HC-SR04 raises its echo pin about 0.5 ms after the trigger, once its
+// burst is out, whatever the distance
+const ECHO_START_TIMEOUT: Duration = Duration::from_millis(10);
+// Waited on top of the round trip to the max range
+const ECHO_MARGIN_US: u64 = 2000;
+// An HC-SR04 that heard nothing holds its echo pin high for about 38 ms and
+// ignores triggers until then
+const BUSY_TIMEOUT: Duration = Duration::from_millis(60);
+
+const LIDAR_FRAME_LEN: usize = 9;
+const LIDAR_HEADER: u8 = 0x59;
+// Below this signal strength the lidar's distance isn't reliable
+const LIDAR_MIN_STRENGTH: u16 = 100;
+// Command ids
+const LIDAR_FRAME_RATE: u8 = 0x03;
+const LIDAR_LOW_POWER: u8 = 0x35;
+
+// What the MaxSonar's output counts in, depends on the model family
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RangeUnit {
+    // LV-MaxSonar: serial in inches, analog Vcc/512 per inch
+    Inch,
+    // XL-MaxSonar: serial in cm, analog Vcc/1024 per cm
+    Cm,
+    // HRLV-MaxSonar: serial in mm, analog Vcc/1024 per 5 mm
+    Mm,
+}
+
+impl RangeUnit {
+    fn serial_cm(self, value: u16) -> f32 {
+        match self {
+            RangeUnit::Inch => value as f32 * 2.54,
+            RangeUnit::Cm => value as f32,
+            RangeUnit::Mm => value as f32 / 10.0,
+        }
+    }
+
+    // From a 12-bit ADC reading against the sensor's own supply
+    fn analog_cm(self, counts: u16) -> f32 {
+        match self {
+            RangeUnit::Inch => counts as f32 / 8.0 * 2.54,
+            RangeUnit::Cm => counts as f32 / 4.0,
+            RangeUnit::Mm => counts as f32 / 8.0,
+        }
+    }
+}
+
+// Per channel, from the board config
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SensorKind {
+    HcSr04,
+    MaxbotixSerial(RangeUnit),
+    MaxbotixAnalog(RangeUnit),
+    // Benewake TF-Luna or TFmini lidar on a UART
+    TfLuna,
+}
+
+impl SensorKind {
+    // Half the width of the cone the sensor sees obstacles in, roughly
+    pub fn beam_half_angle_deg(self) -> f32 {
+        match self {
+            SensorKind::HcSr04 => 15.0,
+            SensorKind::MaxbotixSerial(_) | SensorKind::MaxbotixAnalog(_) => 10.0,
+            SensorKind::TfLuna => 1.0,
+        }
+    }
+}
+
+pub trait DistanceSensor {
+    // One reading in cm, NEAR_FIELD_ERROR for an obstacle inside the blind
+    // zone. Ultrasonic sensors don't wait for echoes from beyond `max_range_cm`.
+    async fn measure(&mut self, max_range_cm: f32) -> Result<f32, &'static str>;
+
+    // Watch for someone else's ultrasound for a while without ranging.
+    // Sensors that can't tell never hear anything.
+    async fn listen(&mut self, _window_ms: u32) -> bool {
+        false
+    }
+
+    // How many readings a sensing cycle averages; sensors that filter their
+    // own output only need one
+    fn readings_per_cycle(&self) -> u8 {
+        1
+    }
+}
+
+pub struct HcSr04<'d> {
+    trigger: Output<'d>,
+    echo: Input<'d>,
+    side: Side,
+}
+
+impl DistanceSensor for HcSr04<'_> {
+    // Watch the echo pin for a while without triggering. The sensor only
+    // raises it after its own trigger, so true means it picked up a burst
+    // from another ultrasonic source.
+    async fn listen(&mut self, window_ms: u32) -> bool {
+        let window = Duration::from_millis(window_ms as u64);
+        let start = Instant::now();
+
+        while start.elapsed() < window {
+            if self.echo.is_high() {
+                return true;
+            }
+            Timer::after(Duration::from_micros(10)).await;
+        }
+        false
+    }
+
+    async fn measure(&mut self, max_range_cm: f32) -> Result<f32, &'static str> {
+        // an echo later than the round trip to the max range is out of range anyway
+        let round_trip_us = (2.0 * max_range_cm / SOUND_CM_PER_US) as u64;
+        let timeout = Duration::from_micros(round_trip_us + ECHO_MARGIN_US);
+        let mut trace = None;
+        let pulse = self.echo_pulse(timeout, &mut trace).await;
+        if let Some(mut trace) = trace {
+            self.listen_tail(&mut trace).await;
+            echoes::record(self.side, trace);
+        }
+        let pulse_duration = pulse?;
+
+        // calculate distance using speed of sound
+        let distance_cm = (pulse_duration.as_micros() as f32) * SOUND_CM_PER_US / 2.0;
+
+        // too short means something is right against the sensor, keep that apart
+        if distance_cm < MIN_DETECTABLE_DISTANCE {
+            return Err(NEAR_FIELD_ERROR);
+        }
+
+        // filter out unreasonable readings
+        if distance_cm > max_range_cm {
+            return Err("Distance out of reasonable range");
+        }
+
+        Ok(distance_cm)
+    }
+
+    fn readings_per_cycle(&self) -> u8 {
+        3
+    }
+}
+
+impl HcSr04<'_> {
+    // Trigger a ping and time its echo pulse, giving up on a pulse longer
+    // than `timeout`. While echo capture is on the edges go into `trace` as well.
+    async fn echo_pulse(&mut self, timeout: Duration, trace: &mut Option<echoes::Trace>) -> Result<Duration, &'static str> {
+        // a sensor still busy with a ping that heard nothing wouldn't take the trigger
+        let start = Instant::now();
+        while self.echo.is_high() {
+            if start.elapsed() > BUSY_TIMEOUT {
+                return Err("Echo signal stuck high");
+            }
+            Timer::after(Duration::from_micros(10)).await;
+        }
+
+        // Send trigger pulse
+        self.trigger.set_high();
+        Timer::after(Duration::from_micros(10)).await;
+        self.trigger.set_low();
+        if echoes::enabled() {
+            *trace = Some(echoes::Trace::start());
+        }
+
+        // wait for echo to start with timeout
+        let mut timed_out = false;
+        let start = Instant::now();
+
+        while self.echo.is_low() {
+            if start.elapsed() > ECHO_START_TIMEOUT {
+                timed_out = true;
+                break;
+            }
+            Timer::after(Duration::from_micros(10)).await;
+        }
+
+        if timed_out {
+            return Err("Echo signal timed out (start)");
+        }
+
+        // start timing when echo goes high
+        let pulse_start = Instant::now();
+        if let Some(trace) = trace {
+            trace.edge();
+        }
+
+        // wait for echo to end
+        timed_out = false;
+        let start = Instant::now();
+
+        while self.echo.is_high() {
+            if start.elapsed() > timeout {
+                timed_out = true;
+                break;
+            }
+            Timer::after(Duration::from_micros(10)).await;
+        }
+
+        if timed_out {
+            return Err("Echo signal timed out (end)");
+        }
+
+        // calculate pulse duration
+        let pulse_duration = pulse_start.elapsed();
+        if let Some(trace) = trace {
+            trace.edge();
+        }
+        Ok(pulse_duration)
+    }
+
+    // Keep watching the echo pin after a captured ping, for the ringing and
+    // second echoes a measurement never looks at
+    async fn listen_tail(&mut self, trace: &mut echoes::Trace) {
+        let tail = Duration::from_millis(echoes::TAIL_MS);
+        let mut high = self.echo.is_high();
+
+        while trace.since_trigger() < tail {
+            if self.echo.is_high() != high {
+                high = !high;
+                trace.edge();
+            }
+            Timer::after(Duration::from_micros(10)).await;
+        }
+    }
+}
+
+// Latest distance of each serial sensor, from its reader task
+static SERIAL_READINGS: [Signal<CriticalSectionRawMutex, f32>; channels::COUNT] = [const { Signal::new() }; channels::COUNT];
+
+// A MaxSonar or lidar sending its distance on a serial line
+pub struct SerialSensor<'d> {
+    // held high to keep a MaxSonar ranging, a lidar's RX is a UART TX instead
+    _enable: Option<Output<'d>>,
+    side: Side,
+}
+
+impl DistanceSensor for SerialSensor<'_> {
+    // The next frame, or the one that came in since the last cycle
+    async fn measure(&mut self, _max_range_cm: f32) -> Result<f32, &'static str> {
+        with_timeout(Duration::from_millis(SERIAL_TIMEOUT_MS), SERIAL_READINGS[self.side as usize].wait())
+            .await
+            .map_err(|_| "No frame from serial sensor")
+    }
+}
+
+// "R" followed by the digits of the distance, the CR already stripped
+fn parse_frame(frame: &[u8], unit: RangeUnit) -> Option<f32> {
+    let digits = frame.strip_prefix(b"R")?;
+    if digits.is_empty() || digits.len() > 4 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let value = digits.iter().fold(0u16, |value, digit| value * 10 + (digit - b'0') as u16);
+    Some(unit.serial_cm(value))
+}
+
+async fn read_frames(side: Side, unit: RangeUnit, rx: &mut impl Read) -> ! {
+    let mut frame: Vec<u8, 6> = Vec::new();
+    let mut byte = [0; 1];
+    loop {
+        if !matches!(rx.read(&mut byte).await, Ok(1)) {
+            continue;
+        }
+        match byte[0] {
+            b'R' => {
+                frame.clear();
+                let _ = frame.push(b'R');
+            }
+            b'\r' => {
+                if let Some(distance_cm) = parse_frame(&frame, unit) {
+                    SERIAL_READINGS[side as usize].signal(distance_cm);
+                }
+                frame.clear();
+            }
+            // a frame too long is garbage, parse_frame refuses it
+            other => {
+                let _ = frame.push(other);
+            }
+        }
+    }
+}
+
+// Benewake lidar frame: 0x59 0x59, distance in cm, signal strength and
+// temperature (all little endian u16), then the low byte of the sum
+fn parse_lidar_frame(frame: &[u8; LIDAR_FRAME_LEN]) -> Option<f32> {
+    let sum = frame[..LIDAR_FRAME_LEN - 1].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if frame[..2] != [LIDAR_HEADER; 2] || sum != frame[LIDAR_FRAME_LEN - 1] {
+        return None;
+    }
+    let distance_cm = u16::from_le_bytes([frame[2], frame[3]]);
+    let strength = u16::from_le_bytes([frame[4], frame[5]]);
+    // too weak a return or an overexposed one, the lidar's distance means nothing then
+    if strength < LIDAR_MIN_STRENGTH || strength == u16::MAX || distance_cm == 0 {
+        return None;
+    }
+    Some(distance_cm as f32)
+}
+
+// A command with its length and checksum around it
+async fn send_lidar_command(tx: &mut impl Write, id: u8, payload: [u8; 2]) {
+    let mut command = [0x5A, 6, id, payload[0], payload[1], 0];
+    command[5] = command[..5].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if tx.write_all(&command).await.is_err() {
+        warn!("Failed to configure lidar");
+    }
+}
+
+async fn configure_lidar(side: Side, tx: &mut impl Write, sensing: &SensingConfig) {
+    info!(
+        "{} lidar at {} Hz{}",
+        side.name(),
+        sensing.lidar_frame_rate_hz,
+        if sensing.lidar_low_power { ", low power" } else { "" }
+    );
+    send_lidar_command(tx, LIDAR_FRAME_RATE, sensing.lidar_frame_rate_hz.to_le_bytes()).await;
+    // 0 leaves low power mode, TFminis don't have it and ignore the command
+    let low_power_hz = if sensing.lidar_low_power { sensing.lidar_frame_rate_hz } else { 0 };
+    send_lidar_command(tx, LIDAR_LOW_POWER, low_power_hz.to_le_bytes()).await;
+}
+
+// Reads frames from a TF-Luna or TFmini and reconfigures it whenever its
+// settings change
+async fn read_lidar(side: Side, rx: &mut impl Read, tx: &mut impl Write) -> ! {
+    let mut applied = None;
+    let mut frame = [0; LIDAR_FRAME_LEN];
+    let mut len = 0;
+    let mut byte = [0; 1];
+    loop {
+        if len == 0 {
+            let sensing = config::get().sensing;
+            let settings = (sensing.lidar_frame_rate_hz, sensing.lidar_low_power);
+            if applied != Some(settings) {
+                configure_lidar(side, tx, &sensing).await;
+                applied = Some(settings);
+            }
+        }
+
+        if !matches!(rx.read(&mut byte).await, Ok(1)) {
+            continue;
+        }
+        // resynchronise on the two header bytes
+        if len < 2 && byte[0] != LIDAR_HEADER {
+            len = 0;
+            continue;
+        }
+        frame[len] = byte[0];
+        len += 1;
+        if len == LIDAR_FRAME_LEN {
+            len = 0;
+            if let Some(distance_cm) = parse_lidar_frame(&frame) {
+                SERIAL_READINGS[side as usize].signal(distance_cm);
+            }
+        }
+    }
+}
+
+async fn serve_serial(side: Side, kind: SensorKind, rx: &mut impl Read, tx: Option<&mut impl Write>) -> ! {
+    match (kind, tx) {
+        (SensorKind::TfLuna, Some(tx)) => read_lidar(side, rx, tx).await,
+        (SensorKind::MaxbotixSerial(unit), _) => read_frames(side, unit, rx).await,
+        // init only starts readers for serial sensors
+        _ => loop {
+            Timer::after_secs(3600).await;
+        },
+    }
+}
+
+// One task per channel, the state machines are different types
+#[embassy_executor::task]
+async fn serial_left_task(mut rx: PioUartRx<'static, PIO1, 0>, mut tx: Option<PioUartTx<'static, PIO2, 0>>, kind: SensorKind) -> ! {
+    serve_serial(Side::Left, kind, &mut rx, tx.as_mut()).await
+}
+
+#[embassy_executor::task]
+async fn serial_right_task(mut rx: PioUartRx<'static, PIO1, 1>, mut tx: Option<PioUartTx<'static, PIO2, 1>>, kind: SensorKind) -> ! {
+    serve_serial(Side::Right, kind, &mut rx, tx.as_mut()).await
+}
+
+#[embassy_executor::task]
+async fn serial_overhead_task(mut rx: PioUartRx<'static, PIO1, 2>, mut tx: Option<PioUartTx<'static, PIO2, 2>>, kind: SensorKind) -> ! {
+    serve_serial(Side::Overhead, kind, &mut rx, tx.as_mut()).await
+}
+
+#[embassy_executor::task]
+async fn serial_rear_task(mut rx: PioUartRx<'static, PIO1, 3>, mut tx: Option<PioUartTx<'static, PIO2, 3>>, kind: SensorKind) -> ! {
+    serve_serial(Side::Rear, kind, &mut rx, tx.as_mut()).await
+}
+
+// Shared by the analog channels and the die temperature sensor (see
+// `thermal.rs`), a conversion takes a couple of µs
+static SHARED_ADC: Mutex<CriticalSectionRawMutex, RefCell<Option<Adc<'static, adc::Blocking>>>> = Mutex::new(RefCell::new(None));
+
+pub struct MaxbotixAnalog<'d> {
+    // held high to keep the sensor ranging
+    _enable: Output<'d>,
+    channel: adc::Channel<'d>,
+    unit: RangeUnit,
+}
+
+impl DistanceSensor for MaxbotixAnalog<'_> {
+    async fn measure(&mut self, _max_range_cm: f32) -> Result<f32, &'static str> {
+        let counts = read_adc(&mut self.channel)?;
+        if self.unit.analog_cm(counts) < ANALOG_FLOOR_CM {
+            return Err("No signal from analog sensor");
+        }
+        Ok(self.unit.analog_cm(counts))
+    }
+}
+
+// One conversion on the shared ADC, in counts of 12 bits
+pub fn read_adc(channel: &mut adc::Channel<'_>) -> Result<u16, &'static str> {
+    let counts = SHARED_ADC.lock(|adc| adc.borrow_mut().as_mut().map(|adc| adc.blocking_read(channel)));
+    match counts {
+        Some(Ok(counts)) => Ok(counts),
+        Some(Err(_)) | None => Err("ADC read failed"),
+    }
+}
+
+// Whichever backend a channel has
+pub enum Rangefinder<'d> {
+    HcSr04(HcSr04<'d>),
+    Serial(SerialSensor<'d>),
+    MaxbotixAnalog(MaxbotixAnalog<'d>),
+}
+
+impl DistanceSensor for Rangefinder<'_> {
+    async fn measure(&mut self, max_range_cm: f32) -> Result<f32, &'static str> {
+        match self {
+            Rangefinder::HcSr04(sensor) => sensor.measure(max_range_cm).await,
+            Rangefinder::Serial(sensor) => sensor.measure(max_range_cm).await,
+            Rangefinder::MaxbotixAnalog(sensor) => sensor.measure(max_range_cm).await,
+        }
+    }
+
+    async fn listen(&mut self, window_ms: u32) -> bool {
+        match self {
+            Rangefinder::HcSr04(sensor) => sensor.listen(window_ms).await,
+            Rangefinder::Serial(sensor) => sensor.listen(window_ms).await,
+            Rangefinder::MaxbotixAnalog(sensor) => sensor.listen(window_ms).await,
+        }
+    }
+
+    fn readings_per_cycle(&self) -> u8 {
+        match self {
+            Rangefinder::HcSr04(sensor) => sensor.readings_per_cycle(),
+            Rangefinder::Serial(sensor) => sensor.readings_per_cycle(),
+            Rangefinder::MaxbotixAnalog(sensor) => sensor.readings_per_cycle(),
+        }
+    }
+}
+
+// The pins of one channel: trigger, echo and ADC (none for the rear)
+pub type LeftPins = (PIN_14, PIN_15, PIN_26);
+pub type RightPins = (PIN_16, PIN_17, PIN_27);
+pub type OverheadPins = (PIN_10, PIN_11, PIN_28);
+pub type RearPins = (PIN_12, PIN_13);
+
+// Set up the left, right, overhead and rear sensors as the board config says.
+// Serial sensors are received on PIO1 and lidars configured through PIO2,
+// one state machine per channel on each. The overhead channel's ADC pin is
+// handed back when its sensor doesn't need it, for measuring VSYS (see
+// supply.rs).
+pub fn init(
+    spawner: &Spawner,
+    pio_rx: PIO1,
+    pio_tx: PIO2,
+    adc: ADC,
+    left: LeftPins,
+    right: RightPins,
+    overhead: OverheadPins,
+    rear: RearPins,
+) -> ([Rangefinder<'static>; channels::COUNT], Option<adc::Channel<'static>>) {
+    let Pio { common: mut rx_common, sm0: rx0, sm1: rx1, sm2: rx2, sm3: rx3, .. } = Pio::new(pio_rx, Irqs);
+    let Pio { common: mut tx_common, sm0: tx0, sm1: tx1, sm2: tx2, sm3: tx3, .. } = Pio::new(pio_tx, Irqs);
+    let rx_program = PioUartRxProgram::new(&mut rx_common);
+    let tx_program = PioUartTxProgram::new(&mut tx_common);
+    // always there, the die temperature is read on it too
+    SHARED_ADC.lock(|shared| *shared.borrow_mut() = Some(Adc::new_blocking(adc, adc::Config::default())));
+    for (side, kind) in Side::ALL.into_iter().zip(SENSOR_KINDS) {
+        info!("{} sensor: {}", side.name(), kind);
+    }
+
+    let kind = SENSOR_KINDS[0];
+    let left = match kind {
+        SensorKind::HcSr04 => hc_sr04(Output::new(left.0, Level::Low), Input::new(left.1, Pull::None), Side::Left),
+        SensorKind::MaxbotixSerial(_) => {
+            let rx = PioUartRx::new(MAXBOTIX_BAUD, &mut rx_common, rx0, left.1, &rx_program);
+            spawner.spawn(serial_left_task(rx, None, kind)).unwrap();
+            serial(Some(Output::new(left.0, Level::High)), Side::Left)
+        }
+        SensorKind::TfLuna => {
+            let rx = PioUartRx::new(LIDAR_BAUD, &mut rx_common, rx0, left.1, &rx_program);
+            let tx = PioUartTx::new(LIDAR_BAUD, &mut tx_common, tx0, left.0, &tx_program);
+            spawner.spawn(serial_left_task(rx, Some(tx), kind)).unwrap();
+            serial(None, Side::Left)
+        }
+        SensorKind::MaxbotixAnalog(unit) => {
+            maxbotix_analog(Output::new(left.0, Level::High), adc::Channel::new_pin(left.2, Pull::None), unit)
+        }
+    };
+    let kind = SENSOR_KINDS[1];
+    let right = match kind {
+        SensorKind::HcSr04 => hc_sr04(Output::new(right.0, Level::Low), Input::new(right.1, Pull::None), Side::Right),
+        SensorKind::MaxbotixSerial(_) => {
+            let rx = PioUartRx::new(MAXBOTIX_BAUD, &mut rx_common, rx1, right.1, &rx_program);
+            spawner.spawn(serial_right_task(rx, None, kind)).unwrap();
+            serial(Some(Output::new(right.0, Level::High)), Side::Right)
+        }
+        SensorKind::TfLuna => {
+            let rx = PioUartRx::new(LIDAR_BAUD, &mut rx_common, rx1, right.1, &rx_program);
+            let tx = PioUartTx::new(LIDAR_BAUD, &mut tx_common, tx1, right.0, &tx_program);
+            spawner.spawn(serial_right_task(rx, Some(tx), kind)).unwrap();
+            serial(None, Side::Right)
+        }
+        SensorKind::MaxbotixAnalog(unit) => {
+            maxbotix_analog(Output::new(right.0, Level::High), adc::Channel::new_pin(right.2, Pull::None), unit)
+        }
+    };
+    let kind = SENSOR_KINDS[2];
+    let mut overhead_adc = Some(adc::Channel::new_pin(overhead.2, Pull::None));
+    let overhead = match kind {
+        SensorKind::HcSr04 => hc_sr04(Output::new(overhead.0, Level::Low), Input::new(overhead.1, Pull::None), Side::Overhead),
+        SensorKind::MaxbotixSerial(_) => {
+            let rx = PioUartRx::new(MAXBOTIX_BAUD, &mut rx_common, rx2, overhead.1, &rx_program);
+            spawner.spawn(serial_overhead_task(rx, None, kind)).unwrap();
+            serial(Some(Output::new(overhead.0, Level::High)), Side::Overhead)
+        }
+        SensorKind::TfLuna => {
+            let rx = PioUartRx::new(LIDAR_BAUD, &mut rx_common, rx2, overhead.1, &rx_program);
+            let tx = PioUartTx::new(LIDAR_BAUD, &mut tx_common, tx2, overhead.0, &tx_program);
+            spawner.spawn(serial_overhead_task(rx, Some(tx), kind)).unwrap();
+            serial(None, Side::Overhead)
+        }
+        SensorKind::MaxbotixAnalog(unit) => {
+            maxbotix_analog(Output::new(overhead.0, Level::High), overhead_adc.take().unwrap(), unit)
+        }
+    };
+    let kind = SENSOR_KINDS[3];
+    let rear = match kind {
+        SensorKind::HcSr04 => hc_sr04(Output::new(rear.0, Level::Low), Input::new(rear.1, Pull::None), Side::Rear),
+        SensorKind::MaxbotixSerial(_) => {
+            let rx = PioUartRx::new(MAXBOTIX_BAUD, &mut rx_common, rx3, rear.1, &rx_program);
+            spawner.spawn(serial_rear_task(rx, None, kind)).unwrap();
+            serial(Some(Output::new(rear.0, Level::High)), Side::Rear)
+        }
+        SensorKind::TfLuna => {
+            let rx = PioUartRx::new(LIDAR_BAUD, &mut rx_common, rx3, rear.1, &rx_program);
+            let tx = PioUartTx::new(LIDAR_BAUD, &mut tx_common, tx3, rear.0, &tx_program);
+            spawner.spawn(serial_rear_task(rx, Some(tx), kind)).unwrap();
+            serial(None, Side::Rear)
+        }
+        // build.rs doesn't allow it, there's no ADC pin for the rear
+        SensorKind::MaxbotixAnalog(_) => unreachable!("analog rear sensor"),
+    };
+    ([left, right, overhead, rear], overhead_adc)
+}
+
+fn hc_sr04(trigger: Output<'static>, echo: Input<'static>, side: Side) -> Rangefinder<'static> {
+    Rangefinder::HcSr04(HcSr04 { trigger, echo, side })
+}
+
+fn serial(enable: Option<Output<'static>>, side: Side) -> Rangefinder<'static> {
+    Rangefinder::Serial(SerialSensor { _enable: enable, side })
+}
+
+fn maxbotix_analog(enable: Output<'static>, channel: adc::Channel<'static>, unit: RangeUnit) -> Rangefinder<'static> {
+    Rangefinder::MaxbotixAnalog(MaxbotixAnalog { _enable: enable, channel, unit })
+}
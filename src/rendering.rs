@@ -0,0 +1,105 @@
+//! How the occupancy map is felt.
+//!
+//! A [`Renderer`] turns the fresh sectors of the map into vibration patterns
+//! for the feedback engine; `haptic.rendering` picks which one:
+//! - per motor (0): each motor vibrates for the nearest obstacle on its side,
+//!   sectors straight ahead count for both
+//! - sweep (1, the default): an obstacle across neighbouring sectors, like a
+//!   wall, is felt as one vibration moving over the motors from its left end
+//!   to its right end, taking longer the wider it is. Obstacles in a single
+//!   sector are felt per motor.
+//!
+//! Another strategy implements [`Renderer`] and gets a [`Rendering`] value
+//! to be picked by.
+
+use heapless::Vec;
+use vision_protocol::zoning;
+
+use crate::channels::Motors;
+use crate::config::{Rendering, ZoneConfig};
+use crate::feedback::Pattern;
+use crate::occupancy::{SECTOR_WIDTH_DEG, SECTORS, Sector};
+
+// A haptic pattern per motor and two sweeps, the most seven sectors can need
+pub const MAX_PATTERNS: usize = 4;
+
+pub type Patterns = Vec<Pattern, MAX_PATTERNS>;
+
+pub trait Renderer {
+    // Vibration for the map, nothing when it's clear
+    fn render(&self, sectors: &[Sector; SECTORS], now_ms: u64, zones: &ZoneConfig) -> Patterns;
+}
+
+pub fn renderer(rendering: Rendering) -> &'static dyn Renderer {
+    match rendering {
+        Rendering::PerMotor => &PerMotorRenderer,
+        Rendering::Sweep => &SweepRenderer,
+    }
+}
+
+// How strongly a sector is felt, 0 when it's clear or its reading is too old
+fn intensity(sector: &Sector, now_ms: u64, zones: &ZoneConfig) -> u8 {
+    sector.fresh(now_ms).map_or(0, |reading| zoning::intensity(reading.distance_cm, reading.zone, zones))
+}
+
+// Each motor for the strongest of the sectors on its side
+fn per_motor<'a>(sectors: impl Iterator<Item = (&'a Sector, u8)> + Clone) -> Patterns {
+    let mut patterns = Patterns::new();
+    for motors in [Motors::Left, Motors::Right] {
+        let intensity = sectors
+            .clone()
+            .filter(|(sector, _)| sector.motors() == motors || sector.motors() == Motors::Both)
+            .map(|(_, intensity)| intensity)
+            .max()
+            .unwrap_or(0);
+        if intensity > 0 {
+            let _ = patterns.push(Pattern::Haptic { intensity, motors });
+        }
+    }
+    patterns
+}
+
+pub struct PerMotorRenderer;
+
+impl Renderer for PerMotorRenderer {
+    fn render(&self, sectors: &[Sector; SECTORS], now_ms: u64, zones: &ZoneConfig) -> Patterns {
+        per_motor(sectors.iter().map(|sector| (sector, intensity(sector, now_ms, zones))))
+    }
+}
+
+pub struct SweepRenderer;
+
+impl Renderer for SweepRenderer {
+    fn render(&self, sectors: &[Sector; SECTORS], now_ms: u64, zones: &ZoneConfig) -> Patterns {
+        let intensities = sectors.map(|sector| intensity(&sector, now_ms, zones));
+        let mut sweeps = Patterns::new();
+        let mut single = [false; SECTORS];
+
+        // every run of neighbouring sectors with something in them
+        let mut start = 0;
+        while start < SECTORS {
+            if intensities[start] == 0 {
+                start += 1;
+                continue;
+            }
+            let end = (start..SECTORS).find(|index| intensities[*index] == 0).unwrap_or(SECTORS);
+            if end - start == 1 {
+                single[start] = true;
+            } else {
+                let _ = sweeps.push(Pattern::Sweep {
+                    from_deg: (sectors[start].bearing_deg - SECTOR_WIDTH_DEG / 2.0) as i8,
+                    to_deg: (sectors[end - 1].bearing_deg + SECTOR_WIDTH_DEG / 2.0) as i8,
+                    intensity: intensities[start..end].iter().copied().max().unwrap_or(0),
+                });
+            }
+            start = end;
+        }
+
+        let singles = sectors.iter().zip(intensities).zip(single).filter(|(_, single)| *single).map(|(sector, _)| sector);
+        let mut patterns = per_motor(singles);
+        for sweep in sweeps {
+            let _ = patterns.push(sweep);
+        }
+        patterns
+    }
+}
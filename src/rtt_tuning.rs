@@ -0,0 +1,168 @@
+//! Live tuning over the debug probe, built with `--features debug-tuning`.
+//!
+//! On the bench the board usually hangs off a debug probe anyway, and
+//! reflashing for every filter or zone tweak is slow. With this feature the
+//! RTT control block gets a text channel pair next to defmt's: lines written
+//! to down channel 1 are read every [`POLL_MS`] and answered on up channel 1,
+//! the same values by the same names as the tuning page:
+//!
+//! ```text
+//! list                                                every value with its range
+//! filter.noisy_tau_ms                                 one value
+//! filter.noisy_tau_ms=200&zones.critical.enter_cm=40  set, all or nothing
+//! watch filter.noisy_tau_ms sensing.cycle_delay_ms    print them every second
+//! watch                                               stop watching
+//! ```
+//!
+//! Changes go to the running configuration like `POST /api/tuning`; save the
+//! profile to keep them. Watched values are printed once a second.
+
+use core::fmt::Write as FmtWrite;
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::{String, Vec};
+use rtt_target::{DownChannel, UpChannel};
+
+use crate::config;
+use crate::logging::{Scope, info};
+use crate::tuning::{self, Param};
+
+const SCOPE: Scope = Scope::Serial;
+
+pub const POLL_MS: u64 = 50;
+const WATCH_MS: u64 = 1000;
+const LINE_LEN: usize = 128;
+const MAX_WATCHED: usize = 8;
+
+pub struct Channels {
+    input: DownChannel,
+    output: UpChannel,
+}
+
+// Set up RTT with defmt on up channel 0 and the tuning channels. Call first
+// thing in main, before anything logs.
+pub fn init() -> Channels {
+    let channels = rtt_target::rtt_init! {
+        up: {
+            0: { size: 1024, name: "defmt" }
+            1: { size: 1024, name: "Tuning" }
+        }
+        down: {
+            // unused, so the tuning channel has the same number both ways
+            0: { size: 16, name: "Unused" }
+            1: { size: LINE_LEN, name: "Tuning" }
+        }
+    };
+    rtt_target::set_defmt_channel(channels.up.0);
+    Channels { input: channels.down.1, output: channels.up.1 }
+}
+
+fn reply(output: &mut UpChannel, text: &str) {
+    output.write(text.as_bytes());
+    output.write(b"\n");
+}
+
+fn write_value(output: &mut UpChannel, param: &Param, config: &config::Config) {
+    let mut line: String<LINE_LEN> = String::new();
+    let _ = FmtWrite::write_fmt(&mut line, format_args!("{} = {}", param.name, (param.get)(config)));
+    reply(output, &line);
+}
+
+fn handle(line: &str, output: &mut UpChannel, watched: &mut Vec<&'static Param, MAX_WATCHED>) {
+    let config = config::get();
+    if line == "list" {
+        for param in tuning::PARAMS {
+            let mut text: String<LINE_LEN> = String::new();
+            let _ = FmtWrite::write_fmt(&mut text, format_args!("{} = {} ({} to {})", param.name, (param.get)(&config), param.min, param.max));
+            reply(output, &text);
+        }
+        return;
+    }
+
+    if let Some(names) = line.strip_prefix("watch") {
+        watched.clear();
+        for name in names.split_whitespace() {
+            match tuning::find(name) {
+                Some(param) if watched.push(param).is_ok() => {},
+                Some(_) => return reply(output, "Watching too many values"),
+                None => return reply(output, "Unknown tuning value"),
+            }
+        }
+        return reply(output, if watched.is_empty() { "Not watching" } else { "Watching" });
+    }
+
+    if !line.contains('=') {
+        return match tuning::find(line) {
+            Some(param) => write_value(output, param, &config),
+            None => reply(output, "Unknown tuning value"),
+        };
+    }
+
+    let mut failed = "";
+    let result = config::apply(|config| {
+        for (name, value) in line.split('&').filter_map(|pair| pair.split_once('=')) {
+            failed = name;
+            tuning::find(name).ok_or("Unknown tuning value")?.parse_into(config, value)?;
+        }
+        failed = "";
+        Ok(())
+    });
+    let mut text: String<LINE_LEN> = String::new();
+    match result {
+        Err(e) if !failed.is_empty() => {
+            let _ = FmtWrite::write_fmt(&mut text, format_args!("{}: {}", failed, e));
+        }
+        Err(e) => {
+            let _ = FmtWrite::write_str(&mut text, e);
+        }
+        Ok(result) if result.changed.is_empty() => {
+            let _ = FmtWrite::write_str(&mut text, "No changes");
+        }
+        Ok(result) => {
+            let _ = FmtWrite::write_str(&mut text, "Changed:");
+            for name in result.changed.iter() {
+                let _ = FmtWrite::write_fmt(&mut text, format_args!(" {}", name));
+            }
+            info!("{} tuning values changed over RTT", result.changed.len());
+        }
+    }
+    reply(output, &text);
+}
+
+#[embassy_executor::task]
+pub async fn tuning_task(mut channels: Channels) {
+    let mut line: Vec<u8, LINE_LEN> = Vec::new();
+    let mut watched: Vec<&'static Param, MAX_WATCHED> = Vec::new();
+    let mut watched_at_ms = 0;
+    let mut ticker = Ticker::every(Duration::from_millis(POLL_MS));
+    info!("Live tuning on RTT channel 1");
+
+    loop {
+        ticker.next().await;
+
+        let mut buffer = [0; LINE_LEN];
+        let n = channels.input.read(&mut buffer);
+        for &byte in &buffer[..n] {
+            if byte != b'\n' && byte != b'\r' {
+                // an overlong line is cut off and most likely refused
+                let _ = line.push(byte);
+                continue;
+            }
+            if let Ok(text) = core::str::from_utf8(&line) {
+                let text = text.trim();
+                if !text.is_empty() {
+                    handle(text, &mut channels.output, &mut watched);
+                }
+            }
+            line.clear();
+        }
+
+        let now_ms = Instant::now().as_millis();
+        if !watched.is_empty() && now_ms.saturating_sub(watched_at_ms) >= WATCH_MS {
+            watched_at_ms = now_ms;
+            let config = config::get();
+            for param in watched.iter() {
+                write_value(&mut channels.output, param, &config);
+            }
+        }
+    }
+}
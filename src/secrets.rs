@@ -0,0 +1,141 @@
+//! WiFi secrets provisioned on the device instead of compiled into it.
+//!
+//! The access point passphrase is written once through `/api/provision` and
+//! kept in its own flash sector (see [`storage`](crate::storage)), XORed with
+//! a key derived from the chip's private OTP random number so a plain flash
+//! dump doesn't give it away. Nothing sends or logs it back; the API only
+//! tells whether one is set. Without one the access point uses the build's
+//! `VISIONASSIST_PASSWORD`, or stays open if that wasn't set either.
+
+use core::cell::RefCell;
+use embassy_rp::otp;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::String;
+
+use crate::defaults::AP_PASSPHRASE;
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Storage;
+
+// WPA2 passphrase limits
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+pub const MAX_PASSPHRASE_LEN: usize = 63;
+
+// Change when the stored layout changes, old data is then ignored
+const SECRETS_MAGIC: u32 = 0x5345_4331;
+const HEADER_LEN: usize = 5;
+
+static PASSPHRASE: Mutex<CriticalSectionRawMutex, RefCell<Option<String<MAX_PASSPHRASE_LEN>>>> =
+    Mutex::new(RefCell::new(None));
+
+// Read the provisioned secrets, call once at startup before the AP starts
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::SECRETS, &mut buffer) {
+        warn!("Failed to read secrets: {}", e);
+        return;
+    }
+
+    let passphrase = device_key().ok().and_then(|key| decode(&buffer, key));
+    match (&passphrase, AP_PASSPHRASE) {
+        (Some(_), _) => info!("WiFi passphrase provisioned"),
+        (None, Some(_)) => info!("No WiFi passphrase provisioned, using the build default"),
+        (None, None) => info!("No WiFi passphrase provisioned, access point is open"),
+    }
+    PASSPHRASE.lock(|stored| *stored.borrow_mut() = passphrase);
+}
+
+#[cfg_attr(not(any(feature = "web", feature = "usb")), allow(dead_code))]
+pub fn is_provisioned() -> bool {
+    PASSPHRASE.lock(|stored| stored.borrow().is_some())
+}
+
+// The access point passphrase, only for handing to the WiFi chip
+pub fn passphrase() -> Option<String<MAX_PASSPHRASE_LEN>> {
+    PASSPHRASE.lock(|stored| stored.borrow().clone()).or_else(|| {
+        let mut passphrase = String::new();
+        passphrase.push_str(AP_PASSPHRASE?).ok()?;
+        Some(passphrase)
+    })
+}
+
+// Store a new passphrase, effective from the next boot. Once one is in use
+// (provisioned or from the build), changing it needs the current one.
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn provision(passphrase: &str, current: Option<&str>) -> Result<(), &'static str> {
+    let authorized = match (self::passphrase(), current) {
+        (None, _) => true,
+        (Some(in_use), Some(current)) => same_secret(&in_use, current),
+        (Some(_), None) => false,
+    };
+    if !authorized {
+        return Err("Current passphrase required");
+    }
+    store(passphrase)
+}
+
+// Store a passphrase without asking for the current one, for the production
+// fixture (see factory.rs), which has the board in hand anyway
+pub fn store(passphrase: &str) -> Result<(), &'static str> {
+    if !(MIN_PASSPHRASE_LEN..=MAX_PASSPHRASE_LEN).contains(&passphrase.len()) || !is_printable(passphrase) {
+        return Err("Passphrase must be 8 to 63 printable ASCII characters");
+    }
+
+    let key = device_key()?;
+    let mut buffer = [0xFF; ERASE_SIZE];
+    buffer[..4].copy_from_slice(&SECRETS_MAGIC.to_le_bytes());
+    buffer[4] = passphrase.len() as u8;
+    let encrypted = &mut buffer[HEADER_LEN..HEADER_LEN + passphrase.len()];
+    for ((byte, plain), key) in encrypted.iter_mut().zip(passphrase.bytes()).zip(keystream(key)) {
+        *byte = plain ^ key;
+    }
+    storage::write_sector(partitions::SECRETS, &buffer)?;
+
+    let mut stored = String::new();
+    let _ = stored.push_str(passphrase);
+    PASSPHRASE.lock(|current| *current.borrow_mut() = Some(stored));
+    info!("WiFi passphrase provisioned, used from the next boot");
+    Ok(())
+}
+
+fn decode(buffer: &[u8; ERASE_SIZE], key: u128) -> Option<String<MAX_PASSPHRASE_LEN>> {
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = buffer[4] as usize;
+    if magic != SECRETS_MAGIC || !(MIN_PASSPHRASE_LEN..=MAX_PASSPHRASE_LEN).contains(&len) {
+        return None;
+    }
+
+    let mut passphrase = String::new();
+    for (byte, key) in buffer[HEADER_LEN..HEADER_LEN + len].iter().zip(keystream(key)) {
+        passphrase.push((byte ^ key) as char).ok()?;
+    }
+    // garbage here means the sector was written by another chip
+    is_printable(&passphrase).then_some(passphrase)
+}
+
+fn device_key() -> Result<u128, &'static str> {
+    otp::get_private_random_number().map_err(|_| "Device key unavailable")
+}
+
+// splitmix64 seeded from the device key, one byte at a time
+fn keystream(key: u128) -> impl Iterator<Item = u8> {
+    let mut state = (key as u64) ^ ((key >> 64) as u64);
+    core::iter::repeat_with(move || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u8
+    })
+}
+
+fn is_printable(text: &str) -> bool {
+    text.bytes().all(|byte| (0x20..0x7F).contains(&byte))
+}
+
+// Compares without bailing out at the first difference
+pub fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
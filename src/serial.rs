@@ -0,0 +1,125 @@
+//! Serial links for the command protocol, next to the TCP server.
+//!
+//! The USB port shows up as a CDC serial device, handy on a desk without
+//! joining the access point. A BLE serial module (HM-10 style, 9600 baud) on
+//! UART0 (TX on GPIO0, RX on GPIO1) lets a phone talk to the device without
+//! WiFi. Both take one command per line and answer like the TCP server does.
+//! An AUTH holds for the rest of the connection; the BLE module never
+//! reports one ending, there it lapses when idle (see `commands::Session`).
+//! The USB port is left out of builds without the `usb` feature.
+
+use embassy_executor::Spawner;
+use embassy_rp::peripherals::{PIN_0, PIN_1, UART0};
+#[cfg(feature = "usb")]
+use embassy_rp::peripherals::USB;
+use embassy_rp::uart::{self, BufferedUart};
+#[cfg(feature = "usb")]
+use embassy_rp::usb::Driver;
+#[cfg(feature = "usb")]
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+#[cfg(feature = "usb")]
+use embassy_usb::{Builder, UsbDevice};
+use static_cell::ConstStaticCell;
+#[cfg(feature = "usb")]
+use static_cell::StaticCell;
+
+use crate::buffers::REQUEST_SIZE;
+use crate::commands::{self, Session};
+use crate::irqs::Irqs;
+use crate::logging::{Scope, info};
+use crate::transport::Transport;
+#[cfg(feature = "usb")]
+use crate::transport::{USB_PACKET_SIZE, UsbSerial};
+
+const SCOPE: Scope = Scope::Serial;
+
+// What BLE serial modules ship configured for
+const BLE_UART_BAUD: u32 = 9600;
+
+// Commands are read into these, one per link since both stay open
+#[cfg(feature = "usb")]
+static USB_REQUEST: ConstStaticCell<[u8; REQUEST_SIZE]> = ConstStaticCell::new([0; REQUEST_SIZE]);
+static UART_REQUEST: ConstStaticCell<[u8; REQUEST_SIZE]> = ConstStaticCell::new([0; REQUEST_SIZE]);
+
+// Set up the USB serial port and start serving commands on it
+#[cfg(feature = "usb")]
+pub fn init_usb(spawner: &Spawner, usb: USB) {
+    let link = usb_link(spawner, usb);
+    spawner.spawn(usb_serial_task(link)).unwrap();
+}
+
+// Start the USB device with its serial port, for whoever serves the port.
+// The factory test mode talks its own protocol on it.
+#[cfg(feature = "usb")]
+pub fn usb_link(spawner: &Spawner, usb: USB) -> UsbSerial {
+    let driver = Driver::new(usb, Irqs);
+
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("VisionAssist");
+    config.product = Some("VisionAssist serial");
+    config.max_power = 100;
+    config.max_packet_size_0 = USB_PACKET_SIZE as u8;
+
+    static CONFIG_DESCRIPTOR: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+    static BOS_DESCRIPTOR: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+    static CONTROL_BUF: ConstStaticCell<[u8; 64]> = ConstStaticCell::new([0; 64]);
+    static STATE: StaticCell<State> = StaticCell::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.take(),
+        BOS_DESCRIPTOR.take(),
+        &mut [],
+        CONTROL_BUF.take(),
+    );
+    let class = CdcAcmClass::new(&mut builder, STATE.init(State::new()), USB_PACKET_SIZE as u16);
+
+    spawner.spawn(usb_device_task(builder.build())).unwrap();
+    UsbSerial { class }
+}
+
+// Set up UART0 for a BLE serial module and start serving commands on it
+pub fn init_ble_uart(spawner: &Spawner, uart: UART0, tx: PIN_0, rx: PIN_1) {
+    static TX_BUF: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+    static RX_BUF: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+
+    let mut config = uart::Config::default();
+    config.baudrate = BLE_UART_BAUD;
+    let uart = BufferedUart::new(uart, Irqs, tx, rx, TX_BUF.take(), RX_BUF.take(), config);
+
+    spawner.spawn(ble_uart_task(uart)).unwrap();
+}
+
+#[cfg(feature = "usb")]
+#[embassy_executor::task]
+async fn usb_device_task(mut device: UsbDevice<'static, Driver<'static, USB>>) -> ! {
+    device.run().await
+}
+
+#[cfg(feature = "usb")]
+#[embassy_executor::task]
+async fn usb_serial_task(mut link: UsbSerial) -> ! {
+    let request = USB_REQUEST.take();
+    loop {
+        link.class.wait_connection().await;
+        info!("USB serial connected");
+        serve(&mut link, request).await;
+        info!("USB serial disconnected");
+    }
+}
+
+#[embassy_executor::task]
+async fn ble_uart_task(mut link: BufferedUart<'static, UART0>) -> ! {
+    let request = UART_REQUEST.take();
+    info!("BLE UART listening at {} baud", BLE_UART_BAUD);
+    loop {
+        serve(&mut link, request).await;
+    }
+}
+
+// Commands until the link goes away, a new connection starts over as viewer
+async fn serve<T: Transport>(link: &mut T, request: &mut [u8; REQUEST_SIZE]) {
+    let mut session = Session::new();
+    while commands::handle(link, request, &mut session).await {}
+}
@@ -0,0 +1,162 @@
+//! Statistics of the running session and the summary kept across a shutdown.
+//!
+//! The event log task counts alerts and sensor faults as they happen. A
+//! graceful shutdown (see [`shutdown`](crate::shutdown)) writes them, with
+//! the most recent events, to their own flash sector; the next boot logs
+//! that summary, shows it on the dashboard and clears the sector, so a
+//! session that ended without a shutdown doesn't repeat the one before.
+
+use core::cell::RefCell;
+use core::fmt::Write as FmtWrite;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+use vision_protocol::zoning::Zone;
+
+use crate::events::{self, Event, RECENT_EVENTS};
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::shutdown::Reason;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::Storage;
+
+// Change when the stored layout changes, old data is then ignored
+const SESSION_MAGIC: u32 = 0x5345_5331;
+const HEADER_LEN: usize = 6;
+// Longer event texts are cut off
+const EVENT_TEXT_LEN: usize = 80;
+
+// Counted since boot
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Stats {
+    pub critical_alerts: u32,
+    pub warnings: u32,
+    pub sensor_faults: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    // ms since that session's boot
+    pub timestamp_ms: u64,
+    pub text: String<EVENT_TEXT_LEN>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub reason: Reason,
+    pub uptime_s: u32,
+    pub stats: Stats,
+    // Oldest first
+    pub events: Vec<LoggedEvent, RECENT_EVENTS>,
+}
+
+static STATS: Mutex<CriticalSectionRawMutex, RefCell<Stats>> = Mutex::new(RefCell::new(Stats {
+    critical_alerts: 0,
+    warnings: 0,
+    sensor_faults: 0,
+}));
+// What the previous session left behind, for the dashboard
+static LAST: Mutex<CriticalSectionRawMutex, RefCell<Option<Summary>>> = Mutex::new(RefCell::new(None));
+
+// Count an event, called by the event log task
+pub fn record(event: &Event) {
+    STATS.lock(|stats| {
+        let mut stats = stats.borrow_mut();
+        match event {
+            Event::ZoneChanged { zone: Zone::Critical, .. } => stats.critical_alerts += 1,
+            Event::ZoneChanged { zone: Zone::Warning, .. } => stats.warnings += 1,
+            Event::SensorDegraded { .. } => stats.sensor_faults += 1,
+            _ => {}
+        }
+    });
+}
+
+pub fn stats() -> Stats {
+    STATS.lock(|stats| *stats.borrow())
+}
+
+// The previous session's summary, when it was shut down properly
+pub fn last() -> Option<Summary> {
+    LAST.lock(|last| last.borrow().clone())
+}
+
+// Pick up the summary the last shutdown left, call once at startup
+pub fn load() {
+    let mut buffer = [0; ERASE_SIZE];
+    if let Err(e) = storage::read_sector(partitions::SESSION, &mut buffer) {
+        warn!("Failed to read last session: {}", e);
+        return;
+    }
+    let Some(summary) = decode(&buffer) else {
+        return;
+    };
+
+    info!(
+        "Last session: {} min, ended by {}, {} critical alerts, {} warnings, {} sensor faults",
+        summary.uptime_s / 60,
+        summary.reason.name(),
+        summary.stats.critical_alerts,
+        summary.stats.warnings,
+        summary.stats.sensor_faults
+    );
+    for event in &summary.events {
+        info!("  {}.{:03} s: {}", event.timestamp_ms / 1000, event.timestamp_ms % 1000, event.text.as_str());
+    }
+    LAST.lock(|last| *last.borrow_mut() = Some(summary));
+
+    // reported once, a session that ends without a shutdown leaves nothing
+    if let Err(e) = storage::write_sector(partitions::SESSION, &[0xFF; ERASE_SIZE]) {
+        warn!("Failed to clear last session: {}", e);
+    }
+}
+
+fn decode(buffer: &[u8]) -> Option<Summary> {
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = u16::from_le_bytes(buffer[4..HEADER_LEN].try_into().ok()?) as usize;
+    if magic != SESSION_MAGIC || HEADER_LEN + len > buffer.len() {
+        return None;
+    }
+    postcard::from_bytes(&buffer[HEADER_LEN..HEADER_LEN + len]).ok()
+}
+
+// Write this session's summary and recent events to flash
+pub fn save(reason: Reason) -> Result<(), &'static str> {
+    let mut summary = Summary {
+        reason,
+        uptime_s: Instant::now().as_secs() as u32,
+        stats: stats(),
+        events: Vec::new(),
+    };
+    for (timestamp_ms, event) in events::recent().iter() {
+        let mut text = String::new();
+        // cut off rather than lost
+        events::describe(&mut Truncating(&mut text), event);
+        let _ = summary.events.push(LoggedEvent { timestamp_ms: *timestamp_ms, text });
+    }
+
+    let mut buffer = [0xFF; ERASE_SIZE];
+    let len = postcard::to_slice(&summary, &mut buffer[HEADER_LEN..])
+        .map_err(|_| "Session summary too large")?
+        .len();
+    buffer[..4].copy_from_slice(&SESSION_MAGIC.to_le_bytes());
+    buffer[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    storage::write_sector(partitions::SESSION, &buffer)?;
+    info!("Session summary saved ({} bytes)", len);
+    Ok(())
+}
+
+// Keeps what fits instead of failing the whole write
+struct Truncating<'a, const N: usize>(&'a mut String<N>);
+
+impl<const N: usize> FmtWrite for Truncating<'_, N> {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        for c in text.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Orderly power down.
+//!
+//! `SHUTDOWN` on a command link, `POST /api/shutdown` or a low battery
+//! signal from the charger board (its LBO pin pulling GPIO22 low) stop the
+//! device the same way: sensing stops, the buzzer plays a falling farewell,
+//! the session summary, recent events, usage and error counters go to flash
+//! (see [`session`](crate::session)), the access point is closed and the
+//! chip halts until it is power cycled. The next boot reports the summary.
+//!
+//! `REBOOT` (`POST /api/reboot`) goes the same way without the farewell and
+//! restarts instead of halting, `REBOOT SAFE` into safe mode (see
+//! [`usage::safe_mode`]).
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_rp::gpio::Input;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer, with_timeout};
+use serde::{Deserialize, Serialize};
+
+use crate::config::HapticPattern;
+use crate::events::{self, Event};
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::health;
+use crate::logging::{Scope, info, warn};
+use crate::radio;
+use crate::session;
+use crate::storage;
+use crate::usage;
+
+const SCOPE: Scope = Scope::System;
+
+// Long, shorter, shortest, so it can't be mistaken for an alert
+const FAREWELL: [u16; 5] = [400, 120, 200, 120, 80];
+// The LBO pin has to stay low this long, load spikes pull it down briefly
+const LOW_BATTERY_MS: u64 = 5000;
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format, Serialize, Deserialize)]
+pub enum Reason {
+    // Asked for over TCP, serial or the web server
+    Command,
+    LowBattery,
+    // REBOOT, restarts instead of halting
+    Reboot,
+    // REBOOT SAFE
+    SafeReboot,
+}
+
+impl Reason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::Command => "command",
+            Reason::LowBattery => "low battery",
+            Reason::Reboot => "reboot",
+            Reason::SafeReboot => "reboot to safe mode",
+        }
+    }
+
+    fn restarts(self) -> bool {
+        matches!(self, Reason::Reboot | Reason::SafeReboot)
+    }
+}
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static REQUEST: Signal<CriticalSectionRawMutex, Reason> = Signal::new();
+// Set by the sensing loop once it has stopped
+static SENSING_STOPPED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Start shutting down, the first request wins
+pub fn request(reason: Reason) {
+    if !REQUESTED.swap(true, Ordering::Relaxed) {
+        REQUEST.signal(reason);
+    }
+}
+
+// Whether a shutdown is underway, the sensing loop stops when it is
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+// For the sensing loop, once it has stopped
+pub fn sensing_stopped() {
+    SENSING_STOPPED.signal(());
+}
+
+#[embassy_executor::task]
+pub async fn shutdown_task() {
+    let reason = REQUEST.wait().await;
+    warn!("Shutting down ({})", reason.name());
+    events::publish(Event::ShuttingDown { reason });
+
+    // the loop checks at the start of each cycle
+    if with_timeout(Duration::from_secs(2), SENSING_STOPPED.wait()).await.is_err() {
+        warn!("Sensing didn't stop, shutting down anyway");
+    }
+
+    // the device is back in seconds after a reboot, nothing to say goodbye to
+    let farewell_ms: u16 = match reason.restarts() {
+        true => 0,
+        false => {
+            feedback::submit(FeedbackCommand {
+                pattern: Pattern::Preview { steps: HapticPattern::new(&FAREWELL), target: PreviewTarget::Buzzer },
+                side: None,
+                priority: Priority::Critical,
+                ttl_ms: 1000,
+            });
+            FAREWELL.iter().sum()
+        }
+    };
+    // also gives the event log task time to take the shutdown event
+    Timer::after_millis(farewell_ms as u64 + 200).await;
+
+    if let Err(e) = session::save(reason) {
+        warn!("Failed to save the session summary: {}", e);
+    }
+    usage::save();
+    health::save();
+    match with_timeout(Duration::from_secs(3), storage::flush()).await {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => warn!("Failed to write settings: {}", e),
+        Err(_) => warn!("Settings not all written in time"),
+    }
+
+    if with_timeout(Duration::from_secs(2), radio::power_down()).await.is_err() {
+        warn!("WiFi chip didn't power down");
+    }
+
+    if reason.restarts() {
+        info!("Restarting");
+        Timer::after_millis(100).await;
+        usage::restart(reason == Reason::SafeReboot)
+    }
+    info!("Halted, power cycle to start again");
+    // give the last log lines a moment to get out
+    Timer::after_millis(100).await;
+    halt()
+}
+
+// Stop the CPU for good. With interrupts off no task runs again and
+// the outputs keep their idle levels.
+fn halt() -> ! {
+    usage::stop_watchdog();
+    cortex_m::interrupt::disable();
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+// Watches the charger board's low battery output (active low, GPIO22 with
+// a pull-up so the pin can stay unconnected)
+#[embassy_executor::task]
+pub async fn low_battery_task(mut lbo: Input<'static>) {
+    loop {
+        lbo.wait_for_low().await;
+        if with_timeout(Duration::from_millis(LOW_BATTERY_MS), lbo.wait_for_high()).await.is_err() {
+            warn!("Battery low");
+            request(Reason::LowBattery);
+            return;
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Simulated sensor readings for testing.
+//!
+//! The SIMULATE command replaces the readings of some channels for a few
+//! seconds, right where they enter the pipeline, so feedback, logging and
+//! apps can be checked end to end without waving boards at walls. Health
+//! tracking still sees the real sensors. It needs the build's QA token
+//! (`VISIONASSIST_QA_TOKEN`) and is disabled without one.
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+use crate::channels;
+use crate::defaults::QA_TOKEN;
+use crate::events::{self, Event, Side};
+use crate::logging::{Scope, info};
+use crate::secrets;
+
+const SCOPE: Scope = Scope::System;
+
+#[derive(Clone, Copy)]
+struct Override {
+    distances_cm: [Option<f32>; channels::COUNT],
+    until_ms: u64,
+}
+
+static OVERRIDE: Mutex<CriticalSectionRawMutex, Cell<Option<Override>>> = Mutex::new(Cell::new(None));
+
+// Start (or replace) a simulation after checking the token
+pub fn start(distances_cm: [Option<f32>; channels::COUNT], duration_ms: u32, token: &str, now_ms: u64) -> Result<(), &'static str> {
+    let Some(qa_token) = QA_TOKEN else {
+        return Err("simulation disabled in this build");
+    };
+    if !secrets::same_secret(qa_token, token) {
+        return Err("bad token");
+    }
+
+    let until_ms = now_ms + duration_ms as u64;
+    OVERRIDE.lock(|current| current.set(Some(Override { distances_cm, until_ms })));
+    info!("Simulating readings for {} ms", duration_ms);
+    events::publish(Event::Simulation { active: true });
+    Ok(())
+}
+
+// Simulated distance for a channel, None when it should use the real one.
+// The sensing loop asks for every channel; this also ends the simulation
+// once its time is up.
+pub fn reading(side: Side, now_ms: u64) -> Option<f32> {
+    let (current, expired) = OVERRIDE.lock(|current| match current.get() {
+        Some(o) if now_ms >= o.until_ms => {
+            current.set(None);
+            (None, true)
+        }
+        o => (o, false),
+    });
+    if expired {
+        info!("Simulation ended");
+        events::publish(Event::Simulation { active: false });
+    }
+
+    current?.distances_cm[side as usize]
+}
@@ -0,0 +1,264 @@
+//! The flash sectors at the end of flash that hold settings.
+//!
+//! Where each one is comes from [`partitions`](crate::partitions), which
+//! also checks that they stay clear of the program. The flash driver lives here
+//! so the profiles, the provisioned secrets, the radio switch, the session
+//! summary, the usage counters, the maintenance reminders, the sensor
+//! calibration, the pairing role, the master levels, the error counters, the
+//! factory test record and the owner record can all use it; every sector is
+//! read and rewritten as a whole. Failures count as flash errors in the
+//! health registry.
+//!
+//! Erasing a sector stalls both cores for tens of ms, since the program runs
+//! from the same flash. So [`write_sector`] only queues the data and returns,
+//! and the [`writer_task`] does the work in steps: checking and erasing the
+//! sector, then programming a few pages at a time. Each step waits for the
+//! moment right after a sensing cycle, when the loop sleeps anyway, and for a
+//! critical alert to finish playing, but not longer than `MAX_DEFER`. A
+//! sector that already holds the data isn't touched, and a blank one isn't
+//! erased. Another write to a queued sector replaces the queued data, and
+//! reads see it before it gets to flash. [`flush`] waits for everything to
+//! be written, for a shutdown and the production fixture.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_rp::flash::{self, Blocking, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use heapless::Deque;
+
+pub use embassy_rp::flash::ERASE_SIZE;
+
+use crate::faults;
+use crate::feedback::{self, Priority};
+use crate::health::{self, Status, Subsystem};
+use crate::logging::{Scope, warn};
+use crate::partitions::{FLASH_SIZE, Region};
+use crate::zone_state::{self, ZoneReceiver};
+
+const SCOPE: Scope = Scope::Storage;
+
+// Sectors waiting for the writer, a full queue writes right away
+const QUEUE_LEN: usize = 4;
+const PAGE_SIZE: usize = 256;
+const PAGES: usize = ERASE_SIZE / PAGE_SIZE;
+// Programmed in one step, a page takes up to about 3 ms
+const PAGES_PER_STEP: usize = 4;
+// Longest a step waits for a quiet moment
+const MAX_DEFER: Duration = Duration::from_millis(500);
+const FLUSH_POLL_MS: u64 = 10;
+
+pub type StorageFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+static FLASH_DRIVER: Mutex<CriticalSectionRawMutex, RefCell<Option<StorageFlash>>> = Mutex::new(RefCell::new(None));
+
+struct Pending {
+    sector: Region,
+    data: [u8; ERASE_SIZE],
+    // None until the sector was checked and erased, then the next page to program
+    next_page: Option<usize>,
+}
+
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<Deque<Pending, QUEUE_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+static QUEUED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// Set while someone waits in `flush`, the writer doesn't wait for quiet moments then
+static HURRY: AtomicBool = AtomicBool::new(false);
+// A queued write failed since the last flush
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+// Call once at startup, before anything loads its settings
+pub fn init(flash: StorageFlash) {
+    FLASH_DRIVER.lock(|driver| *driver.borrow_mut() = Some(flash));
+}
+
+pub fn read_sector(sector: Region, buffer: &mut [u8; ERASE_SIZE]) -> Result<(), &'static str> {
+    // a write that hasn't made it to flash yet is what the sector holds
+    let queued = QUEUE.lock(|queue| {
+        let queue = queue.borrow();
+        let pending = queue.iter().find(|pending| pending.sector.offset == sector.offset);
+        pending.map(|pending| buffer.copy_from_slice(&pending.data)).is_some()
+    });
+    if queued {
+        return Ok(());
+    }
+
+    let offset = sector.offset;
+    let result = FLASH_DRIVER.lock(|driver| {
+        let mut driver = driver.borrow_mut();
+        let flash = driver.as_mut().ok_or("Storage not initialized")?;
+        flash.blocking_read(offset, buffer).map_err(|e| {
+            warn!("Flash read at {:x} failed: {:?}", offset, e);
+            "Flash read failed"
+        })
+    });
+    if result.is_err() {
+        health::error(Subsystem::Flash);
+        health::set_status(Subsystem::Flash, Status::Degraded);
+    }
+    result
+}
+
+// Queue `data` for the sector, the writer task puts it in flash. Failures
+// from then on only show in the health registry.
+pub fn write_sector(sector: Region, data: &[u8; ERASE_SIZE]) -> Result<(), &'static str> {
+    let queued = QUEUE.lock(|queue| {
+        let mut queue = queue.borrow_mut();
+        if let Some(pending) = queue.iter_mut().find(|pending| pending.sector.offset == sector.offset) {
+            // starts over if the writer was already at it
+            pending.data.copy_from_slice(data);
+            pending.next_page = None;
+            return true;
+        }
+        queue.push_back(Pending { sector, data: *data, next_page: None }).is_ok()
+    });
+    if queued {
+        QUEUED.signal(());
+        return Ok(());
+    }
+
+    // a stall is better than losing the settings
+    warn!("Flash write queue full, writing at {:x} right away", sector.offset);
+    write_now(sector, data)
+}
+
+// Wait until everything queued is in flash
+pub async fn flush() -> Result<(), &'static str> {
+    HURRY.store(true, Ordering::Relaxed);
+    QUEUED.signal(());
+    while QUEUE.lock(|queue| !queue.borrow().is_empty()) {
+        Timer::after_millis(FLUSH_POLL_MS).await;
+    }
+    HURRY.store(false, Ordering::Relaxed);
+
+    if FAILED.swap(false, Ordering::Relaxed) {
+        return Err("Flash write failed");
+    }
+    Ok(())
+}
+
+// Erase the sector and write `data` to it, all at once
+fn write_now(sector: Region, data: &[u8; ERASE_SIZE]) -> Result<(), &'static str> {
+    let offset = sector.offset;
+    let result = FLASH_DRIVER.lock(|driver| {
+        if faults::flash_write_failure() {
+            warn!("Flash write at {:x} failed: injected", offset);
+            return Err("Flash write failed");
+        }
+        let mut driver = driver.borrow_mut();
+        let flash = driver.as_mut().ok_or("Storage not initialized")?;
+        flash
+            .blocking_erase(offset, offset + sector.size as u32)
+            .and_then(|()| flash.blocking_write(offset, data))
+            .map_err(|e| {
+                warn!("Flash write at {:x} failed: {:?}", offset, e);
+                "Flash write failed"
+            })
+    });
+    written(result.is_ok());
+    result
+}
+
+// A sector that can't be written loses settings, one that can clears a fault
+fn written(ok: bool) {
+    if ok {
+        health::set_status(Subsystem::Flash, Status::Ok);
+    } else {
+        FAILED.store(true, Ordering::Relaxed);
+        health::error(Subsystem::Flash);
+        health::set_status(Subsystem::Flash, Status::Fault);
+    }
+}
+
+// Works through the queued writes, a step at a time
+#[embassy_executor::task]
+pub async fn writer_task() {
+    let mut cycles = zone_state::receiver();
+
+    loop {
+        if QUEUE.lock(|queue| queue.borrow().is_empty()) {
+            QUEUED.wait().await;
+            continue;
+        }
+        if !HURRY.load(Ordering::Relaxed) {
+            quiet_moment(&mut cycles).await;
+        }
+
+        let outcome = QUEUE.lock(|queue| {
+            let mut queue = queue.borrow_mut();
+            let pending = queue.front_mut()?;
+            let outcome = FLASH_DRIVER.lock(|driver| match driver.borrow_mut().as_mut() {
+                Some(flash) => step(flash, pending),
+                None => Err("Storage not initialized"),
+            });
+            // a failed write is dropped, it would most likely fail again
+            if outcome != Ok(false) {
+                queue.pop_front();
+            }
+            Some(outcome)
+        });
+        match outcome {
+            Some(Ok(true)) => written(true),
+            Some(Err(_)) => written(false),
+            _ => {},
+        }
+    }
+}
+
+// Right after a sensing cycle, while the loop waits out its cycle delay, and
+// not while a critical alert plays. Without sensing it's any time after MAX_DEFER.
+async fn quiet_moment(cycles: &mut ZoneReceiver) {
+    let deadline = Instant::now() + MAX_DEFER;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let cycle = with_timeout(remaining, cycles.changed()).await;
+        if cycle.is_err() || feedback::playing() != Some(Priority::Critical) {
+            return;
+        }
+    }
+}
+
+// One bounded piece of a write: checking and erasing the sector, or
+// programming a few of its pages. True once the sector holds the data.
+fn step(flash: &mut StorageFlash, pending: &mut Pending) -> Result<bool, &'static str> {
+    let offset = pending.sector.offset;
+    let failed = |e: flash::Error| {
+        warn!("Flash write at {:x} failed: {:?}", offset, e);
+        "Flash write failed"
+    };
+
+    let Some(first) = pending.next_page else {
+        if faults::flash_write_failure() {
+            warn!("Flash write at {:x} failed: injected", offset);
+            return Err("Flash write failed");
+        }
+        // nothing to do if the sector holds the data already, no erase if it's blank
+        let (mut same, mut blank) = (true, true);
+        let mut page = [0; PAGE_SIZE];
+        for (index, expected) in pending.data.chunks(PAGE_SIZE).enumerate() {
+            flash.blocking_read(offset + (index * PAGE_SIZE) as u32, &mut page).map_err(failed)?;
+            same &= page[..] == *expected;
+            blank &= page.iter().all(|&byte| byte == 0xFF);
+        }
+        if same {
+            return Ok(true);
+        }
+        if !blank {
+            flash.blocking_erase(offset, offset + ERASE_SIZE as u32).map_err(failed)?;
+        }
+        pending.next_page = Some(0);
+        return Ok(false);
+    };
+
+    let last = (first + PAGES_PER_STEP).min(PAGES);
+    for index in first..last {
+        let page = &pending.data[index * PAGE_SIZE..(index + 1) * PAGE_SIZE];
+        // erased flash reads as 0xFF already
+        if page.iter().any(|&byte| byte != 0xFF) {
+            flash.blocking_write(offset + (index * PAGE_SIZE) as u32, page).map_err(failed)?;
+        }
+    }
+    pending.next_page = Some(last);
+    Ok(last == PAGES)
+}
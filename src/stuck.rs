@@ -0,0 +1,47 @@
+//! Frozen reading detection.
+//!
+//! A loose echo wire can leave a sensor reporting the same distance forever,
+//! which looks perfectly healthy to the failure counter in main. While the user
+//! is walking the distances always move a little, so a channel that stays
+//! within `epsilon_cm` for `window_ms` of walking is flagged as stuck.
+
+use crate::config::StuckConfig;
+
+pub struct StuckDetector {
+    reference_cm: f32,
+    since_ms: u64,
+    stuck: bool,
+}
+
+impl StuckDetector {
+    pub const fn new() -> Self {
+        Self { reference_cm: 0.0, since_ms: 0, stuck: false }
+    }
+
+    pub fn stuck(&self) -> bool {
+        self.stuck
+    }
+
+    // Feed a valid raw reading. Returns Some(true) when the channel just got
+    // stuck and Some(false) when a stuck channel starts moving again.
+    pub fn update(&mut self, distance_cm: f32, moving: bool, now_ms: u64, config: &StuckConfig) -> Option<bool> {
+        if libm::fabsf(distance_cm - self.reference_cm) > config.epsilon_cm {
+            self.reference_cm = distance_cm;
+            self.since_ms = now_ms;
+            if self.stuck {
+                self.stuck = false;
+                return Some(false);
+            }
+            return None;
+        }
+
+        if !moving {
+            // standing still, identical readings are expected, start over
+            self.since_ms = now_ms;
+        } else if !self.stuck && now_ms.saturating_sub(self.since_ms) >= config.window_ms as u64 {
+            self.stuck = true;
+            return Some(true);
+        }
+        None
+    }
+}
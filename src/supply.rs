@@ -0,0 +1,164 @@
+//! Supply sag detection during motor starts.
+//!
+//! A vibration motor spinning up, a full power kick most of all, draws enough
+//! to pull VSYS down, and on a tired battery far enough to reset the board.
+//! The Pico's own VSYS sense on GPIO29 belongs to the WiFi chip's SPI, so VSYS
+//! is measured through two equal resistors (100 kΩ each) that halve it onto
+//! GPIO28. That pin is the overhead channel's ADC input, so this only works
+//! when the overhead sensor isn't an analog MaxSonar.
+//!
+//! Between motor starts VSYS is sampled now and then for a resting level.
+//! Every time a motor starts from rest it is sampled every millisecond for
+//! [`BURST_MS`], and a drop of more than [`SAG_V`] below the resting level is
+//! a sag: it is logged, goes out as a `SupplySag` event and lowers the duty
+//! cap of both motors by [`CAP_STEP`], kicks included, down to
+//! [`CAP_FLOOR`]. The cap holds until the next boot. `GET
+//! /api/diagnostics/supply` shows it with the voltages.
+//!
+//! While a bench test runs (see [`benchtest`](crate::benchtest)) VSYS is
+//! sampled every [`BENCH_SAMPLE_MS`] instead, for the voltage it streams,
+//! and the resting level and sag detection are left alone.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_futures::select::{Either, select};
+use embassy_rp::adc;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+
+use crate::events::{self, Event};
+use crate::health::{self, Subsystem};
+use crate::logging::{Scope, info, warn};
+use crate::rangefinder;
+
+const SCOPE: Scope = Scope::Feedback;
+
+// Resting level sampling while the motors are left alone
+const IDLE_SAMPLE_MS: u64 = 1000;
+// How quickly the resting level follows, per sample
+const IDLE_SMOOTHING: f32 = 0.2;
+// Sampling during a bench test, faster than it reports
+pub const BENCH_SAMPLE_MS: u64 = 20;
+// Long enough for a kick and the spin-up after it
+pub const BURST_MS: u64 = 100;
+// Drop below the resting level that counts as a sag
+pub const SAG_V: f32 = 0.4;
+pub const CAP_STEP: u8 = 32;
+// Half duty, enough to still feel every pattern
+pub const CAP_FLOOR: u8 = 128;
+// Sags are reported at most this often, the cap still drops with each one
+const REPORT_MS: u64 = 10_000;
+
+// The divider halves VSYS, the ADC reads up to 3.3 V in 12 bits
+const VOLTS_PER_COUNT: f32 = 2.0 * 3.3 / 4096.0;
+
+#[derive(Clone, Copy)]
+pub struct Status {
+    pub measured: bool,
+    pub resting_v: Option<f32>,
+    // lowest VSYS seen during a motor start
+    pub lowest_v: Option<f32>,
+    // the last sample, whatever the motors were doing
+    pub latest_v: Option<f32>,
+    pub sags: u16,
+}
+
+static STATUS: Mutex<CriticalSectionRawMutex, Cell<Status>> =
+    Mutex::new(Cell::new(Status { measured: false, resting_v: None, lowest_v: None, latest_v: None, sags: 0 }));
+static DUTY_CAP: AtomicU8 = AtomicU8::new(u8::MAX);
+static MOTOR_STARTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn status() -> Status {
+    STATUS.lock(|status| status.get())
+}
+
+// Highest duty either motor may be driven at, 255 until the supply sags
+pub fn duty_cap() -> u8 {
+    DUTY_CAP.load(Ordering::Relaxed)
+}
+
+// Called by a motor starting from rest, just before it is switched on
+pub fn motor_starting() {
+    MOTOR_STARTED.signal(());
+}
+
+fn read(channel: &mut adc::Channel<'_>) -> Result<f32, &'static str> {
+    let volts = rangefinder::read_adc(channel)? as f32 * VOLTS_PER_COUNT;
+    update(|status| status.latest_v = Some(volts));
+    Ok(volts)
+}
+
+fn update(change: impl FnOnce(&mut Status)) {
+    STATUS.lock(|status| {
+        let mut current = status.get();
+        change(&mut current);
+        status.set(current);
+    });
+}
+
+#[embassy_executor::task]
+pub async fn supply_task(mut channel: adc::Channel<'static>) {
+    info!("Measuring VSYS on GPIO28");
+    update(|status| status.measured = true);
+    let mut reported_ms: Option<u64> = None;
+
+    loop {
+        // the bench test drives the motors itself and only wants the voltage
+        if crate::benchtest::running() {
+            let _ = read(&mut channel);
+            Timer::after_millis(BENCH_SAMPLE_MS).await;
+            MOTOR_STARTED.reset();
+            continue;
+        }
+
+        let resting_v = match select(MOTOR_STARTED.wait(), Timer::after_millis(IDLE_SAMPLE_MS)).await {
+            Either::First(()) => status().resting_v,
+            Either::Second(()) => {
+                if let Ok(volts) = read(&mut channel) {
+                    update(|status| {
+                        let resting = status.resting_v.get_or_insert(volts);
+                        *resting += IDLE_SMOOTHING * (volts - *resting);
+                    });
+                }
+                continue;
+            }
+        };
+        // nothing to compare against yet
+        let Some(resting_v) = resting_v else {
+            continue;
+        };
+
+        // the lowest point of the start
+        let mut lowest_v = f32::MAX;
+        let mut ticker = Ticker::every(Duration::from_millis(1));
+        for _ in 0..BURST_MS {
+            if let Ok(volts) = read(&mut channel) {
+                lowest_v = lowest_v.min(volts);
+            }
+            ticker.next().await;
+        }
+        if lowest_v == f32::MAX {
+            continue;
+        }
+        update(|status| status.lowest_v = Some(status.lowest_v.map_or(lowest_v, |lowest| lowest.min(lowest_v))));
+        if resting_v - lowest_v <= SAG_V {
+            continue;
+        }
+
+        let cap = duty_cap().saturating_sub(CAP_STEP).max(CAP_FLOOR);
+        DUTY_CAP.store(cap, Ordering::Relaxed);
+        update(|status| status.sags = status.sags.saturating_add(1));
+        health::error(Subsystem::Feedback);
+        health::set_status(Subsystem::Feedback, health::Status::Degraded);
+
+        let now_ms = Instant::now().as_millis();
+        if reported_ms.is_some_and(|reported| now_ms.saturating_sub(reported) < REPORT_MS) {
+            continue;
+        }
+        reported_ms = Some(now_ms);
+        let millivolts = (lowest_v * 1000.0) as u16;
+        warn!("VSYS sagged to {} mV during a motor start, motor duty capped at {}", millivolts, cap);
+        events::publish(Event::SupplySag { millivolts, duty_cap: cap });
+    }
+}
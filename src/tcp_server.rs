@@ -1,65 +1,52 @@
 use embassy_net::{Stack, tcp::TcpSocket};
-use defmt::*;
-use embedded_io_async::{Read, Write};
-use core::fmt::Write as FmtWrite;
-use heapless::String;
+use vision_protocol::TCP_PORT;
 
-// Shared state for the current sensor readings
-pub static mut LEFT_DISTANCE: f32 = 100.0;
-pub static mut RIGHT_DISTANCE: f32 = 100.0;
+use crate::buffers::{self, ConnectionBuffers, REQUEST_SIZE};
+use crate::busy;
+use crate::commands::{self, Session};
+use crate::events::{self, Event};
+use crate::logging::{Scope, info, warn};
+use crate::transport;
+
+const SCOPE: Scope = Scope::Tcp;
 
 #[embassy_executor::task]
-pub async fn tcp_server_task(_stack: &'static Stack<'static>, mut socket: TcpSocket<'static>) {
+pub async fn tcp_server_task(stack: &'static Stack<'static>) {
     info!("TCP server task started");
     
     loop {
+        // New socket for each connection, with buffers from the pool
+        let mut buffers = buffers::acquire().await;
+        let ConnectionBuffers { socket_rx, socket_tx, request } = &mut *buffers;
+        let mut socket = TcpSocket::new(*stack, socket_rx, socket_tx);
+        
         // Listen for connections on port 8080
-        info!("TCP server listening on port 8080...");
-        if let Err(e) = socket.accept(8080).await {
+        info!("TCP server listening on port {}...", TCP_PORT);
+        if let Err(e) = socket.accept(TCP_PORT).await {
             warn!("Failed to accept connection: {:?}", e);
             continue;
         }
         
         info!("TCP connection accepted!");
+        let _taken = busy::TCP.take();
+        events::publish(Event::ClientConnected {
+            port: TCP_PORT,
+            peer: socket.remote_endpoint().map(|endpoint| endpoint.addr),
+        });
         
-        // Handle the connection
-        handle_tcp_connection(&mut socket).await;
+        // Handle the connection, a client that goes quiet is reset by the transport
+        handle_tcp_connection(&mut socket, request).await;
         
-        // Close the connection
-        socket.close();
+        // Close the connection, nothing left to do if it was reset
+        transport::close(&mut socket).await;
         
         // Small delay before accepting next connection
         embassy_time::Timer::after_secs(1).await;
     }
 }
 
-async fn handle_tcp_connection(socket: &mut TcpSocket<'_>) {
-    let mut rx_buffer = [0; 512];
-    
-    // Read request (we don't actually use it, but we need to read it)
-    match socket.read(&mut rx_buffer).await {
-        Ok(n) => {
-            info!("Read {} bytes", n);
-        }
-        Err(e) => {
-            warn!("Failed to read from socket: {:?}", e);
-            return;
-        }
-    }
-    
-    // Get current distances
-    let left = unsafe { LEFT_DISTANCE };
-    let right = unsafe { RIGHT_DISTANCE };
-    
-    // Format response
-    let mut response: String<64> = String::new();
-    let _ = FmtWrite::write_str(&mut response, "L:");
-    let _ = FmtWrite::write_fmt(&mut response, format_args!("{}", left as u32));
-    let _ = FmtWrite::write_str(&mut response, " R:");
-    let _ = FmtWrite::write_fmt(&mut response, format_args!("{}", right as u32));
-    
-    // Send response
-    if let Err(e) = socket.write_all(response.as_bytes()).await {
-        warn!("Failed to write to socket: {:?}", e);
-    }
-}
\ No newline at end of file
+// One command per connection (plus an AUTH before it)
+async fn handle_tcp_connection(socket: &mut TcpSocket<'_>, request: &mut [u8; REQUEST_SIZE]) {
+    let mut session = Session::new();
+    while commands::handle(socket, request, &mut session).await {}
+}
@@ -0,0 +1,134 @@
+//! The status line, formatted once for everyone who sends it.
+//!
+//! STATUS replies, every STREAM sample and `GET /api/telemetry` carry the same
+//! readings. Instead of each of them formatting the latest zone state again,
+//! the sensing loop hands every state to [`update`], which formats the line
+//! only when something in it would read differently: a distance by a whole
+//! cm, a noise estimate by a tenth or the zone scale by a hundredth. The new
+//! line is formatted aside and swapped in whole under a new version number,
+//! so a reader never sees half of one and a stream holding the current
+//! version doesn't copy it again. What changes with every send, the age of
+//! the readings and a stream's sample numbers, is added by
+//! [`Frame::write_line`].
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as FmtWrite};
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+use heapless::String;
+use vision_protocol::{Telemetry, TelemetryTail};
+
+use crate::channels;
+use crate::clock;
+use crate::config;
+use crate::events::Side;
+use crate::zone_state::ZoneState;
+
+// Every channel with its noise estimate, and the zone scale
+pub const BODY_LEN: usize = 128;
+
+// The readings part of a status line
+#[derive(Clone)]
+pub struct Frame {
+    body: String<BODY_LEN>,
+    pub version: u32,
+}
+
+impl Frame {
+    // The whole status line with its newline, with the device's clock and on
+    // a stream the sample slot and the slots dropped so far
+    pub fn write_line(&self, out: &mut impl fmt::Write, stream: Option<(u32, u32)>) -> fmt::Result {
+        let age_ms = age_ms(Instant::now().as_millis());
+        let (seq, dropped) = (stream.map(|(seq, _)| seq), stream.map(|(_, dropped)| dropped));
+        let (uptime_ms, clock_s) = (Some(clock::uptime_ms()), clock::unix_s());
+        write!(out, "{}{}\n", self.body, TelemetryTail { age_ms, uptime_ms, clock_s, seq, dropped })
+    }
+}
+
+// The values as the line shows them, a change here is a new line
+#[derive(Clone, Copy, PartialEq)]
+struct Shown {
+    distances_cm: [Option<u32>; channels::COUNT],
+    noise_dcm: [Option<i32>; channels::COUNT],
+    scale_percent: i32,
+}
+
+struct Latest {
+    frame: Frame,
+    shown: Shown,
+    // when the readings were taken
+    timestamp_ms: u64,
+}
+
+static LATEST: Mutex<CriticalSectionRawMutex, RefCell<Option<Latest>>> = Mutex::new(RefCell::new(None));
+// The version of the frame in LATEST, 0 before the first
+static VERSION: AtomicU32 = AtomicU32::new(0);
+
+// A new zone state from the sensing loop
+pub fn update(state: &ZoneState) {
+    let telemetry = Telemetry {
+        distances_cm: state.channels.map(|side| side.map(|side| side.distance_cm as u32)),
+        noise_cm: state.channels.map(|side| side.map(|side| side.noise_cm)),
+        zone_scale: state.zone_scale,
+        age_ms: None,
+        uptime_ms: None,
+        clock_s: None,
+        seq: None,
+        dropped: None,
+    };
+    let shown = Shown {
+        distances_cm: telemetry.distances_cm,
+        noise_dcm: telemetry.noise_cm.map(|noise| noise.map(|cm| libm::roundf(cm * 10.0) as i32)),
+        scale_percent: libm::roundf(telemetry.zone_scale * 100.0) as i32,
+    };
+
+    let unchanged = LATEST.lock(|latest| {
+        let mut latest = latest.borrow_mut();
+        let current = latest.as_mut().filter(|latest| latest.shown == shown);
+        current.map(|current| current.timestamp_ms = state.timestamp_ms).is_some()
+    });
+    if unchanged {
+        return;
+    }
+
+    let frame = Frame { body: body(&telemetry), version: VERSION.load(Ordering::Relaxed).wrapping_add(1).max(1) };
+    VERSION.store(frame.version, Ordering::Relaxed);
+    LATEST.lock(|latest| *latest.borrow_mut() = Some(Latest { frame, shown, timestamp_ms: state.timestamp_ms }));
+}
+
+// Cheap to check before asking for the frame itself
+pub fn version() -> u32 {
+    VERSION.load(Ordering::Relaxed)
+}
+
+pub fn latest() -> Frame {
+    LATEST.lock(|latest| latest.borrow().as_ref().map(|latest| latest.frame.clone())).unwrap_or_else(|| {
+        // nothing measured yet, report the same safe distance the pipeline starts from
+        let config = config::get();
+        let enabled = Side::ALL.map(|side| channels::placement(side).enabled(&config));
+        let telemetry = Telemetry {
+            distances_cm: enabled.map(|on| on.then_some(100)),
+            noise_cm: enabled.map(|on| on.then_some(0.0)),
+            zone_scale: 1.0,
+            age_ms: None,
+            uptime_ms: None,
+            clock_s: None,
+            seq: None,
+            dropped: None,
+        };
+        Frame { body: body(&telemetry), version: 0 }
+    })
+}
+
+// How old the readings in the frame are, None before the first
+fn age_ms(now_ms: u64) -> Option<u32> {
+    let timestamp_ms = LATEST.lock(|latest| latest.borrow().as_ref().map(|latest| latest.timestamp_ms))?;
+    Some(now_ms.saturating_sub(timestamp_ms).min(u32::MAX as u64) as u32)
+}
+
+fn body(telemetry: &Telemetry) -> String<BODY_LEN> {
+    let mut body = String::new();
+    let _ = FmtWrite::write_fmt(&mut body, format_args!("{}", telemetry));
+    body
+}
@@ -0,0 +1,79 @@
+//! Looks of the dashboard and the feedback settings page.
+//!
+//! Each [`Theme`] is a stylesheet from the asset store (see
+//! [`assets`](crate::assets)). The device has a default, `theme` in the
+//! configuration (set with `POST /api/theme`), but every browser can pick its
+//! own: opening a page with `?theme=<name>` switches that browser to it and
+//! keeps it in a cookie, `?theme=device` goes back to the device's default.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "web")]
+use crate::config;
+#[cfg(feature = "web")]
+use crate::http::{self, Request};
+
+#[cfg(feature = "web")]
+const COOKIE: &str = "theme";
+// How long a browser remembers its pick
+#[cfg(feature = "web")]
+pub const COOKIE_MAX_AGE_S: u32 = 365 * 24 * 3600;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Theme {
+    Light,
+    Dark,
+    HighContrast,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Light, Theme::Dark, Theme::HighContrast];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+            Theme::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|theme| theme.name() == name.trim())
+    }
+
+    #[cfg(feature = "web")]
+    pub fn stylesheet(self) -> &'static str {
+        match self {
+            Theme::Light => "/theme/light.css",
+            Theme::Dark => "/theme/dark.css",
+            Theme::HighContrast => "/theme/high-contrast.css",
+        }
+    }
+}
+
+// What a page request asks to do with the browser's theme cookie
+#[cfg(feature = "web")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pick {
+    Keep,
+    Remember(Theme),
+    Forget,
+}
+
+// The theme a page is shown in: picked in the query, else the cookie's, else
+// the device's default
+#[cfg(feature = "web")]
+pub fn select(request: &Request<'_>) -> (Theme, Pick) {
+    let default = config::get().theme;
+    match http::form_param(request.query, COOKIE) {
+        Some("device") => return (default, Pick::Forget),
+        Some(name) => {
+            if let Some(theme) = Theme::parse(name) {
+                return (theme, Pick::Remember(theme));
+            }
+        }
+        None => {}
+    }
+    let remembered = http::cookie(request.cookies, COOKIE).and_then(Theme::parse);
+    (remembered.unwrap_or(default), Pick::Keep)
+}
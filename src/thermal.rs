@@ -0,0 +1,96 @@
+//! Die temperature and vibration limiting.
+//!
+//! Worn in a closed case in summer the board warms up, and the motors and
+//! their driver are what heats it most. The RP2350's internal temperature
+//! sensor is read on the shared ADC every few seconds; above `thermal.limit_c`
+//! every motor duty is cut to `thermal.reduced_percent` on top of the master
+//! haptic level, until the chip has cooled [`HYSTERESIS_C`] below the limit.
+//! Either change is logged and goes out as a `Thermal` event. The latest
+//! temperature is in `GET /api/metrics`.
+
+use core::cell::Cell;
+use embassy_rp::adc;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Ticker};
+
+use crate::config;
+use crate::events::{self, Event};
+use crate::health::{self, Status, Subsystem};
+use crate::logging::{Scope, info, warn};
+use crate::rangefinder;
+use crate::supply;
+
+const SCOPE: Scope = Scope::System;
+
+const CHECK_MS: u64 = 5000;
+// Conversions averaged per check, the sensor is noisy
+const SAMPLES: u32 = 8;
+// How far below the limit the chip has to cool before full duty returns
+pub const HYSTERESIS_C: f32 = 5.0;
+
+// From the datasheet: 0.706 V at 27 °C, falling 1.721 mV per degree
+const ADC_REFERENCE_V: f32 = 3.3;
+const SENSOR_V_AT_27C: f32 = 0.706;
+const SENSOR_V_PER_C: f32 = 0.001721;
+
+static TEMPERATURE_C: Mutex<CriticalSectionRawMutex, Cell<Option<f32>>> = Mutex::new(Cell::new(None));
+static HOT: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Latest die temperature, None before the first reading or when the ADC fails
+pub fn temperature_c() -> Option<f32> {
+    TEMPERATURE_C.lock(|temperature| temperature.get())
+}
+
+pub fn hot() -> bool {
+    HOT.lock(|hot| hot.get())
+}
+
+// Share of their duty the motors may use right now, 0-100
+pub fn duty_percent() -> u8 {
+    if hot() { config::get().thermal.reduced_percent } else { 100 }
+}
+
+fn read(channel: &mut adc::Channel<'_>) -> Result<f32, &'static str> {
+    let mut sum = 0;
+    for _ in 0..SAMPLES {
+        sum += rangefinder::read_adc(channel)? as u32;
+    }
+    let volts = sum as f32 / SAMPLES as f32 * ADC_REFERENCE_V / 4096.0;
+    Ok(27.0 - (volts - SENSOR_V_AT_27C) / SENSOR_V_PER_C)
+}
+
+#[embassy_executor::task]
+pub async fn thermal_task(mut channel: adc::Channel<'static>) {
+    let mut ticker = Ticker::every(Duration::from_millis(CHECK_MS));
+
+    loop {
+        ticker.next().await;
+
+        let temperature_c = match read(&mut channel) {
+            Ok(temperature_c) => temperature_c,
+            Err(e) => {
+                warn!("Die temperature read failed: {}", e);
+                TEMPERATURE_C.lock(|temperature| temperature.set(None));
+                continue;
+            }
+        };
+        TEMPERATURE_C.lock(|temperature| temperature.set(Some(temperature_c)));
+
+        let limit_c = config::get().thermal.limit_c;
+        let was_hot = hot();
+        let now_hot = if was_hot { temperature_c > limit_c - HYSTERESIS_C } else { temperature_c > limit_c };
+        // feedback is degraded while the motors are turned down for either reason
+        let limited = now_hot || supply::duty_cap() < u8::MAX;
+        health::set_status(Subsystem::Feedback, if limited { Status::Degraded } else { Status::Ok });
+        if now_hot == was_hot {
+            continue;
+        }
+        HOT.lock(|hot| hot.set(now_hot));
+        if now_hot {
+            warn!("Die at {} C, above {} C, turning the motors down", temperature_c as i32, limit_c as i32);
+        } else {
+            info!("Die cooled to {} C, motors back to full duty", temperature_c as i32);
+        }
+        events::publish(Event::Thermal { hot: now_hot });
+    }
+}
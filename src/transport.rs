@@ -0,0 +1,176 @@
+//! Links the command protocol runs over.
+//!
+//! [`commands::handle`](crate::commands::handle) only needs to read requests
+//! and write replies, so anything that can do that is a [`Transport`]: a TCP
+//! socket, the USB serial port and the UART a BLE serial module hangs off
+//! (see [`serial`](crate::serial)).
+
+use embassy_net::tcp::TcpSocket;
+use embassy_rp::peripherals::UART0;
+#[cfg(feature = "usb")]
+use embassy_rp::peripherals::USB;
+use embassy_rp::uart::BufferedUart;
+#[cfg(feature = "usb")]
+use embassy_rp::usb::Driver;
+#[cfg(feature = "usb")]
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Read, ReadReady, Write};
+
+use crate::faults;
+
+// How long a TCP client may keep us waiting before the connection is reset
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+// For the last of a reply, a FIN or a reset to go out before the socket is dropped
+const LINGER_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub trait Transport {
+    // For log messages
+    fn name(&self) -> &'static str;
+
+    // Whatever arrived, at least one byte. Ok(0) means the other side is gone.
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, &'static str>;
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), &'static str>;
+
+    // One command and the link is closed (TCP), rather than a command per line
+    fn one_shot(&self) -> bool {
+        false
+    }
+
+    // The client wants a running STREAM to stop. TCP clients just hang up.
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+
+    // The client broke the protocol, drop it at once rather than say goodbye
+    async fn reject(&mut self) {}
+}
+
+impl Transport for TcpSocket<'_> {
+    fn name(&self) -> &'static str {
+        "TCP"
+    }
+
+    // A client that connects and sends nothing would hold the listener
+    // forever, so it gets TCP_IDLE_TIMEOUT for each command
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        if faults::socket_error() {
+            return Err("connection reset");
+        }
+        match with_timeout(TCP_IDLE_TIMEOUT, Read::read(self, buffer)).await {
+            Ok(result) => result.map_err(|_| "connection reset"),
+            Err(_) => {
+                abort(self).await;
+                Err("timed out")
+            }
+        }
+    }
+
+    // Same for one that stops reading, a STREAM would block on it
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        if faults::socket_error() {
+            return Err("connection reset");
+        }
+        match with_timeout(TCP_IDLE_TIMEOUT, Write::write_all(self, data)).await {
+            Ok(result) => result.map_err(|_| "connection reset"),
+            Err(_) => {
+                abort(self).await;
+                Err("timed out")
+            }
+        }
+    }
+
+    fn one_shot(&self) -> bool {
+        true
+    }
+
+    async fn reject(&mut self) {
+        abort(self).await;
+    }
+}
+
+// Ends a connection normally: what's left of the reply, then a FIN
+#[cfg_attr(not(any(feature = "web", feature = "tcp")), allow(dead_code))]
+pub async fn close(socket: &mut TcpSocket<'_>) {
+    socket.close();
+    let _ = with_timeout(LINGER_TIMEOUT, socket.flush()).await;
+}
+
+// Resets a connection, so a broken or stuck client doesn't keep its socket
+// half-closed in the stack's few slots
+pub async fn abort(socket: &mut TcpSocket<'_>) {
+    socket.abort();
+    let _ = with_timeout(LINGER_TIMEOUT, socket.flush()).await;
+}
+
+// Full speed bulk endpoints
+#[cfg(feature = "usb")]
+pub const USB_PACKET_SIZE: usize = 64;
+
+// USB CDC ACM serial port
+#[cfg(feature = "usb")]
+pub struct UsbSerial {
+    pub class: CdcAcmClass<'static, Driver<'static, USB>>,
+}
+
+#[cfg(feature = "usb")]
+impl Transport for UsbSerial {
+    fn name(&self) -> &'static str {
+        "USB"
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let mut packet = [0; USB_PACKET_SIZE];
+        loop {
+            let n = self.class.read_packet(&mut packet).await.map_err(|_| "USB disconnected")?;
+            // empty packets end a transfer, there's nothing in them
+            if n > 0 {
+                let n = n.min(buffer.len());
+                buffer[..n].copy_from_slice(&packet[..n]);
+                return Ok(n);
+            }
+        }
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        if !self.class.dtr() {
+            return Err("USB port closed");
+        }
+        for packet in data.chunks(USB_PACKET_SIZE) {
+            self.class.write_packet(packet).await.map_err(|_| "USB disconnected")?;
+        }
+        // a full last packet needs an empty one after it, or the host keeps waiting
+        if !data.is_empty() && data.len() % USB_PACKET_SIZE == 0 {
+            self.class.write_packet(&[]).await.map_err(|_| "USB disconnected")?;
+        }
+        Ok(())
+    }
+
+    // Terminal programs drop DTR when they close the port
+    fn interrupted(&mut self) -> bool {
+        !self.class.dtr()
+    }
+}
+
+// UART to a BLE serial module (HM-10 style), which relays whatever a phone
+// writes to its serial characteristic
+impl Transport for BufferedUart<'static, UART0> {
+    fn name(&self) -> &'static str {
+        "BLE UART"
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        Read::read(self, buffer).await.map_err(|_| "UART error")
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        Write::write_all(self, data).await.map_err(|_| "UART error")
+    }
+
+    // The module doesn't tell us when the phone goes away, so a stream runs
+    // until the next client sends something
+    fn interrupted(&mut self) -> bool {
+        ReadReady::read_ready(self).unwrap_or(false)
+    }
+}
@@ -0,0 +1,96 @@
+//! Every numeric tuning value of the configuration, by name.
+//!
+//! The tuning page and `/api/tuning` work off this table, and
+//! [`config::apply`](crate::config::apply) uses it to report what changed.
+//! Values are handled as `f32` and converted to the field's own type.
+
+use crate::config::{Config, MIN_LEVEL_PERCENT, Rendering};
+
+pub struct Param {
+    pub name: &'static str,
+    pub get: fn(&Config) -> f32,
+    pub set: fn(&mut Config, f32),
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Param {
+    // Set from text, within the parameter's range
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn parse_into(&self, config: &mut Config, text: &str) -> Result<(), &'static str> {
+        let value = text.trim().parse::<f32>().map_err(|_| "Values must be numbers")?;
+        if !(self.min..=self.max).contains(&value) {
+            return Err("Value out of range");
+        }
+        (self.set)(config, value);
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn find(name: &str) -> Option<&'static Param> {
+    PARAMS.iter().find(|param| param.name == name)
+}
+
+pub const PARAM_COUNT: usize = PARAMS.len();
+
+pub const PARAMS: &[Param] = &[
+    Param { name: "zones.critical.enter_cm", get: |c| c.zones.critical.enter_cm, set: |c, v| c.zones.critical.enter_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "zones.critical.exit_cm", get: |c| c.zones.critical.exit_cm, set: |c, v| c.zones.critical.exit_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "zones.critical.min_dwell_ms", get: |c| c.zones.critical.min_dwell_ms as f32, set: |c, v| c.zones.critical.min_dwell_ms = v as u32, min: 0.0, max: 10000.0 },
+    Param { name: "zones.warning.enter_cm", get: |c| c.zones.warning.enter_cm, set: |c, v| c.zones.warning.enter_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "zones.warning.exit_cm", get: |c| c.zones.warning.exit_cm, set: |c, v| c.zones.warning.exit_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "zones.warning.min_dwell_ms", get: |c| c.zones.warning.min_dwell_ms as f32, set: |c, v| c.zones.warning.min_dwell_ms = v as u32, min: 0.0, max: 10000.0 },
+    Param { name: "zones.notice.enter_cm", get: |c| c.zones.notice.enter_cm, set: |c, v| c.zones.notice.enter_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "zones.notice.exit_cm", get: |c| c.zones.notice.exit_cm, set: |c, v| c.zones.notice.exit_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "zones.notice.min_dwell_ms", get: |c| c.zones.notice.min_dwell_ms as f32, set: |c, v| c.zones.notice.min_dwell_ms = v as u32, min: 0.0, max: 10000.0 },
+    Param { name: "overhead.enabled", get: |c| c.overhead.enabled as u8 as f32, set: |c, v| c.overhead.enabled = v != 0.0, min: 0.0, max: 1.0 },
+    Param { name: "overhead.critical.enter_cm", get: |c| c.overhead.zones.critical.enter_cm, set: |c, v| c.overhead.zones.critical.enter_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "overhead.critical.exit_cm", get: |c| c.overhead.zones.critical.exit_cm, set: |c, v| c.overhead.zones.critical.exit_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "overhead.warning.enter_cm", get: |c| c.overhead.zones.warning.enter_cm, set: |c, v| c.overhead.zones.warning.enter_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "overhead.warning.exit_cm", get: |c| c.overhead.zones.warning.exit_cm, set: |c, v| c.overhead.zones.warning.exit_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "overhead.notice.enter_cm", get: |c| c.overhead.zones.notice.enter_cm, set: |c, v| c.overhead.zones.notice.enter_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "overhead.notice.exit_cm", get: |c| c.overhead.zones.notice.exit_cm, set: |c, v| c.overhead.zones.notice.exit_cm = v, min: 1.0, max: 400.0 },
+    Param { name: "filter.noisy_tau_ms", get: |c| c.filter.noisy_tau_ms, set: |c, v| c.filter.noisy_tau_ms = v, min: 1.0, max: 5000.0 },
+    Param { name: "filter.quiet_tau_ms", get: |c| c.filter.quiet_tau_ms, set: |c, v| c.filter.quiet_tau_ms = v, min: 1.0, max: 5000.0 },
+    Param { name: "filter.noise_reference_cm2", get: |c| c.filter.noise_reference_cm2, set: |c, v| c.filter.noise_reference_cm2 = v, min: 0.1, max: 1000.0 },
+    Param { name: "filter.noise_tau_ms", get: |c| c.filter.noise_tau_ms, set: |c, v| c.filter.noise_tau_ms = v, min: 1.0, max: 10000.0 },
+    Param { name: "geometry.sensor_spacing_cm", get: |c| c.geometry.sensor_spacing_cm, set: |c, v| c.geometry.sensor_spacing_cm = v, min: 1.0, max: 100.0 },
+    Param { name: "geometry.toe_out_deg", get: |c| c.geometry.toe_out_deg, set: |c, v| c.geometry.toe_out_deg = v, min: 0.0, max: 45.0 },
+    Param { name: "geometry.max_wall_angle_deg", get: |c| c.geometry.max_wall_angle_deg, set: |c, v| c.geometry.max_wall_angle_deg = v, min: 0.0, max: 80.0 },
+    Param { name: "pipeline.ambient_temperature_c", get: |c| c.pipeline.ambient_temperature_c, set: |c, v| c.pipeline.ambient_temperature_c = v, min: -30.0, max: 60.0 },
+    Param { name: "haptic.ramp_per_s", get: |c| c.haptic.ramp_per_s as f32, set: |c, v| c.haptic.ramp_per_s = v as u16, min: 1.0, max: 10000.0 },
+    Param { name: "haptic.rendering", get: |c| c.haptic.rendering as u8 as f32, set: |c, v| c.haptic.rendering = if v != 0.0 { Rendering::Sweep } else { Rendering::PerMotor }, min: 0.0, max: 1.0 },
+    Param { name: "actuators.kick_ms", get: |c| c.actuators.kick_ms as f32, set: |c, v| c.actuators.kick_ms = v as u16, min: 0.0, max: 100.0 },
+    Param { name: "actuators.kick_below", get: |c| c.actuators.kick_below as f32, set: |c, v| c.actuators.kick_below = v as u8, min: 0.0, max: 255.0 },
+    Param { name: "actuators.headset", get: |c| c.actuators.headset as u8 as f32, set: |c, v| c.actuators.headset = v != 0.0, min: 0.0, max: 1.0 },
+    Param { name: "actuators.headset_volume", get: |c| c.actuators.headset_volume as f32, set: |c, v| c.actuators.headset_volume = v as u8, min: 0.0, max: 100.0 },
+    Param { name: "levels.volume_percent", get: |c| c.levels.volume_percent as f32, set: |c, v| c.levels.volume_percent = v as u8, min: MIN_LEVEL_PERCENT as f32, max: 100.0 },
+    Param { name: "levels.haptic_percent", get: |c| c.levels.haptic_percent as f32, set: |c, v| c.levels.haptic_percent = v as u8, min: MIN_LEVEL_PERCENT as f32, max: 100.0 },
+    Param { name: "context.fast_walking_scale", get: |c| c.context.fast_walking_scale, set: |c, v| c.context.fast_walking_scale = v, min: 0.5, max: 2.0 },
+    Param { name: "context.stationary_scale", get: |c| c.context.stationary_scale, set: |c, v| c.context.stationary_scale = v, min: 0.5, max: 2.0 },
+    Param { name: "stuck.epsilon_cm", get: |c| c.stuck.epsilon_cm, set: |c, v| c.stuck.epsilon_cm = v, min: 0.0, max: 10.0 },
+    Param { name: "stuck.window_ms", get: |c| c.stuck.window_ms as f32, set: |c, v| c.stuck.window_ms = v as u32, min: 1000.0, max: 60000.0 },
+    Param { name: "parked.enabled", get: |c| c.parked.enabled as u8 as f32, set: |c, v| c.parked.enabled = v != 0.0, min: 0.0, max: 1.0 },
+    Param { name: "parked.after_ms", get: |c| c.parked.after_ms as f32, set: |c, v| c.parked.after_ms = v as u32, min: 10000.0, max: 3600000.0 },
+    Param { name: "parked.epsilon_cm", get: |c| c.parked.epsilon_cm, set: |c, v| c.parked.epsilon_cm = v, min: 0.0, max: 20.0 },
+    Param { name: "parked.cycle_delay_ms", get: |c| c.parked.cycle_delay_ms as f32, set: |c, v| c.parked.cycle_delay_ms = v as u32, min: 1.0, max: 10000.0 },
+    Param { name: "thermal.limit_c", get: |c| c.thermal.limit_c, set: |c, v| c.thermal.limit_c = v, min: 30.0, max: 100.0 },
+    Param { name: "thermal.reduced_percent", get: |c| c.thermal.reduced_percent as f32, set: |c, v| c.thermal.reduced_percent = v as u8, min: 10.0, max: 100.0 },
+    Param { name: "sensing.max_range_cm", get: |c| c.sensing.max_range_cm, set: |c, v| c.sensing.max_range_cm = v, min: 50.0, max: 500.0 },
+    Param { name: "sensing.consensus_cm", get: |c| c.sensing.consensus_cm, set: |c, v| c.sensing.consensus_cm = v, min: 0.5, max: 50.0 },
+    Param { name: "sensing.measurement_gap_ms", get: |c| c.sensing.measurement_gap_ms as f32, set: |c, v| c.sensing.measurement_gap_ms = v as u32, min: 0.0, max: 100.0 },
+    Param { name: "sensing.cycle_delay_ms", get: |c| c.sensing.cycle_delay_ms as f32, set: |c, v| c.sensing.cycle_delay_ms = v as u32, min: 0.0, max: 1000.0 },
+    Param { name: "sensing.near_field_confirmations", get: |c| c.sensing.near_field_confirmations as f32, set: |c, v| c.sensing.near_field_confirmations = v as u8, min: 1.0, max: 10.0 },
+    Param { name: "sensing.degraded_after_failures", get: |c| c.sensing.degraded_after_failures as f32, set: |c, v| c.sensing.degraded_after_failures = v as u8, min: 1.0, max: 100.0 },
+    Param { name: "sensing.extreme_danger_cm", get: |c| c.sensing.extreme_danger_cm, set: |c, v| c.sensing.extreme_danger_cm = v, min: 0.0, max: 50.0 },
+    Param { name: "sensing.feedback_ttl_ms", get: |c| c.sensing.feedback_ttl_ms as f32, set: |c, v| c.sensing.feedback_ttl_ms = v as u32, min: 50.0, max: 2000.0 },
+    Param { name: "sensing.interference_confirmations", get: |c| c.sensing.interference_confirmations as f32, set: |c, v| c.sensing.interference_confirmations = v as u8, min: 1.0, max: 10.0 },
+    Param { name: "sensing.lidar_frame_rate_hz", get: |c| c.sensing.lidar_frame_rate_hz as f32, set: |c, v| c.sensing.lidar_frame_rate_hz = v as u16, min: 1.0, max: 250.0 },
+    Param { name: "sensing.lidar_low_power", get: |c| c.sensing.lidar_low_power as u8 as f32, set: |c, v| c.sensing.lidar_low_power = v != 0.0, min: 0.0, max: 1.0 },
+    Param { name: "wifi.instant_on", get: |c| c.wifi.instant_on as u8 as f32, set: |c, v| c.wifi.instant_on = v != 0.0, min: 0.0, max: 1.0 },
+    Param { name: "mounting.height_cm", get: |c| c.mounting.height_cm, set: |c, v| c.mounting.height_cm = v, min: 10.0, max: 200.0 },
+    Param { name: "mounting.tilt_deg", get: |c| c.mounting.tilt_deg, set: |c, v| c.mounting.tilt_deg = v, min: 0.0, max: 80.0 },
+    Param { name: "mounting.beam_half_angle_deg", get: |c| c.mounting.beam_half_angle_deg, set: |c, v| c.mounting.beam_half_angle_deg = v, min: 0.0, max: 45.0 },
+    Param { name: "mounting.margin_cm", get: |c| c.mounting.margin_cm, set: |c, v| c.mounting.margin_cm = v, min: 0.0, max: 100.0 },
+];
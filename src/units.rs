@@ -0,0 +1,103 @@
+//! Distance units for everything shown to the user.
+//!
+//! Internally distances are always cm, and so are the configuration and the
+//! TCP status line. Only what a person reads (the dashboard, the JSON API)
+//! goes through [`Distance`] in the configured [`Units`], so conversions
+//! live here rather than in every format string. On screen the decimal
+//! separator is the configured language's (see [`locale`](crate::locale)).
+
+use core::fmt;
+use serde::{Deserialize, Serialize, Serializer};
+
+const CM_PER_INCH: f32 = 2.54;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    pub fn name(self) -> &'static str {
+        match self {
+            Units::Metric => "metric",
+            Units::Imperial => "imperial",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "metric" | "cm" => Some(Units::Metric),
+            "imperial" | "in" => Some(Units::Imperial),
+            _ => None,
+        }
+    }
+
+    // Short unit name, also used as the JSON "unit" field
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Units::Metric => "cm",
+            Units::Imperial => "in",
+        }
+    }
+
+    pub fn from_cm(self, cm: f32) -> f32 {
+        match self {
+            Units::Metric => cm,
+            Units::Imperial => cm / CM_PER_INCH,
+        }
+    }
+}
+
+// A distance for display, whole cm or inches to one decimal
+#[derive(Clone, Copy)]
+pub struct Distance {
+    pub cm: f32,
+    pub units: Units,
+    pub decimal_separator: char,
+}
+
+impl Distance {
+    // In the configured units and language
+    pub fn new(cm: f32) -> Self {
+        let config = crate::config::get();
+        Self { cm, units: config.units, decimal_separator: config.language.strings().decimal_separator }
+    }
+
+    // Just the number, e.g. for JSON next to a "unit" field
+    pub fn value(&self) -> DistanceValue {
+        DistanceValue(*self)
+    }
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value(), self.units.symbol())
+    }
+}
+
+pub struct DistanceValue(Distance);
+
+impl fmt::Display for DistanceValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Distance { cm, units, decimal_separator } = self.0;
+        match units {
+            Units::Metric => write!(f, "{:.0}", cm),
+            Units::Imperial => {
+                let tenths = libm::roundf(units.from_cm(cm) * 10.0) as i32;
+                write!(f, "{}{}{}", tenths / 10, decimal_separator, (tenths % 10).abs())
+            }
+        }
+    }
+}
+
+// Same precision in JSON as on screen
+impl Serialize for DistanceValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Distance { cm, units, .. } = self.0;
+        match units {
+            Units::Metric => serializer.serialize_u32(libm::roundf(cm) as u32),
+            Units::Imperial => serializer.serialize_f32(libm::roundf(units.from_cm(cm) * 10.0) / 10.0),
+        }
+    }
+}
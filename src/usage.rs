@@ -0,0 +1,250 @@
+//! Usage counters kept in flash for maintenance: boots, total runtime, how
+//! often the device restarted after a hang or a panic or because it was told
+//! to, and why the current boot happened.
+//!
+//! The hardware watchdog restarts the device when the executor stops running
+//! for 8 s. A panic marks a watchdog scratch register (which survives the
+//! reset) and forces a watchdog reset right away, so the device comes back
+//! instead of sitting halted, and the next boot can tell the two apart.
+//! `REBOOT` and `REBOOT SAFE` restart the same way with marks of their own;
+//! the second boots into safe mode (see [`safe_mode`]). The runtime is added
+//! up every 15 minutes and at a shutdown, so at most that much is lost when
+//! the power goes.
+
+use core::cell::RefCell;
+use core::panic::PanicInfo;
+use embassy_rp::pac;
+use embassy_rp::watchdog::{ResetReason, Watchdog};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Ticker};
+use serde::{Deserialize, Serialize};
+
+use crate::logging::{Scope, info, warn};
+use crate::partitions;
+use crate::storage::{self, ERASE_SIZE};
+
+const SCOPE: Scope = Scope::System;
+
+// Change when the stored layout changes, old data is then ignored
+const USAGE_MAGIC: u32 = 0x5553_4732;
+// The layout before boot reasons, still read so the counts carry over
+const USAGE_MAGIC_V1: u32 = 0x5553_4731;
+const HEADER_LEN: usize = 6;
+
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(8);
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+const SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+// Left in scratch register 0 by the panic handler and by [`restart`]. 4 to 7
+// belong to the boot ROM.
+const PANIC_MARK: u32 = 0x5041_4e43;
+const REBOOT_MARK: u32 = 0x5242_4f54;
+const SAFE_MARK: u32 = 0x5341_4645;
+
+// Why the device is running now
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format, Serialize, Deserialize)]
+pub enum BootReason {
+    // Power on, the reset pin or a debugger
+    PowerOn,
+    Watchdog,
+    Panic,
+    // REBOOT
+    Reboot,
+    // REBOOT SAFE
+    SafeMode,
+}
+
+impl BootReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            BootReason::PowerOn => "power-on",
+            BootReason::Watchdog => "watchdog",
+            BootReason::Panic => "panic",
+            BootReason::Reboot => "reboot",
+            BootReason::SafeMode => "safe-mode",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Counters {
+    pub boots: u32,
+    // Up to the last save
+    pub runtime_s: u32,
+    pub watchdog_resets: u32,
+    pub panic_resets: u32,
+    // Restarts asked for with REBOOT, safe mode ones included
+    pub reboots: u32,
+    // Of the current boot
+    pub boot_reason: BootReason,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self { boots: 0, runtime_s: 0, watchdog_resets: 0, panic_resets: 0, reboots: 0, boot_reason: BootReason::PowerOn }
+    }
+}
+
+#[derive(Deserialize)]
+struct CountersV1 {
+    boots: u32,
+    runtime_s: u32,
+    watchdog_resets: u32,
+    panic_resets: u32,
+}
+
+// As loaded at boot, plus this boot and its reset reason
+static COUNTERS: Mutex<CriticalSectionRawMutex, RefCell<Counters>> = Mutex::new(RefCell::new(Counters::new()));
+
+// Counters right now, with the runtime of this session
+pub fn counters() -> Counters {
+    let mut counters = COUNTERS.lock(|counters| *counters.borrow());
+    counters.runtime_s = counters.runtime_s.saturating_add(Instant::now().as_secs() as u32);
+    counters
+}
+
+// Count this boot and what caused it, call once at startup after storage::init
+pub fn load(watchdog: &mut Watchdog) {
+    let mut buffer = [0; ERASE_SIZE];
+    let mut counters = match storage::read_sector(partitions::USAGE, &mut buffer) {
+        Ok(()) => decode(&buffer).unwrap_or(Counters::new()),
+        Err(e) => {
+            warn!("Failed to read usage counters: {}", e);
+            Counters::new()
+        }
+    };
+
+    counters.boots += 1;
+    let mark = watchdog.get_scratch(0);
+    watchdog.set_scratch(0, 0);
+    counters.boot_reason = match mark {
+        PANIC_MARK => BootReason::Panic,
+        REBOOT_MARK => BootReason::Reboot,
+        SAFE_MARK => BootReason::SafeMode,
+        _ if matches!(watchdog.reset_reason(), Some(ResetReason::TimedOut)) => BootReason::Watchdog,
+        _ => BootReason::PowerOn,
+    };
+    match counters.boot_reason {
+        BootReason::Panic => {
+            warn!("Restarted after a panic");
+            counters.panic_resets += 1;
+        }
+        BootReason::Watchdog => {
+            warn!("Restarted by the watchdog");
+            counters.watchdog_resets += 1;
+        }
+        BootReason::Reboot => {
+            info!("Restarted on request");
+            counters.reboots += 1;
+        }
+        BootReason::SafeMode => {
+            warn!("Restarted into safe mode on request");
+            counters.reboots += 1;
+        }
+        BootReason::PowerOn => {}
+    }
+
+    info!(
+        "Boot {}, {} h runtime, {} watchdog and {} panic resets and {} reboots so far",
+        counters.boots,
+        counters.runtime_s / 3600,
+        counters.watchdog_resets,
+        counters.panic_resets,
+        counters.reboots
+    );
+    COUNTERS.lock(|stored| *stored.borrow_mut() = counters);
+    save();
+}
+
+fn decode(buffer: &[u8]) -> Option<Counters> {
+    let magic = u32::from_le_bytes(buffer[..4].try_into().ok()?);
+    let len = u16::from_le_bytes(buffer[4..HEADER_LEN].try_into().ok()?) as usize;
+    if HEADER_LEN + len > buffer.len() {
+        return None;
+    }
+    let encoded = &buffer[HEADER_LEN..HEADER_LEN + len];
+    match magic {
+        USAGE_MAGIC => postcard::from_bytes(encoded).ok(),
+        USAGE_MAGIC_V1 => {
+            let v1: CountersV1 = postcard::from_bytes(encoded).ok()?;
+            Some(Counters {
+                boots: v1.boots,
+                runtime_s: v1.runtime_s,
+                watchdog_resets: v1.watchdog_resets,
+                panic_resets: v1.panic_resets,
+                ..Counters::new()
+            })
+        }
+        _ => None,
+    }
+}
+
+pub fn boot_reason() -> BootReason {
+    COUNTERS.lock(|counters| counters.borrow().boot_reason)
+}
+
+// Booted with REBOOT SAFE: default settings and none of the optional
+// subsystems, so a configuration that breaks the device can be replaced over
+// the network. Lasts until the next restart.
+pub fn safe_mode() -> bool {
+    boot_reason() == BootReason::SafeMode
+}
+
+// Write the counters with the runtime so far
+pub fn save() {
+    let mut buffer = [0xFF; ERASE_SIZE];
+    let len = match postcard::to_slice(&counters(), &mut buffer[HEADER_LEN..]) {
+        Ok(encoded) => encoded.len(),
+        Err(_) => return,
+    };
+    buffer[..4].copy_from_slice(&USAGE_MAGIC.to_le_bytes());
+    buffer[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    if let Err(e) = storage::write_sector(partitions::USAGE, &buffer) {
+        warn!("Failed to save usage counters: {}", e);
+    }
+}
+
+// For a shutdown, which halts the CPU on purpose
+pub fn stop_watchdog() {
+    pac::WATCHDOG.ctrl().modify(|w| w.set_enable(false));
+}
+
+// Restart through the watchdog, marked so the next boot knows it was asked
+// for and whether to come up in safe mode
+pub fn restart(safe: bool) -> ! {
+    pac::WATCHDOG.scratch0().write(|w| *w = if safe { SAFE_MARK } else { REBOOT_MARK });
+    pac::WATCHDOG.ctrl().write(|w| w.set_trigger(true));
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+// Feeds the watchdog and saves the runtime now and then
+#[embassy_executor::task]
+pub async fn watchdog_task(mut watchdog: Watchdog) {
+    // a debugger stopping the CPU isn't a hang
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_TIMEOUT);
+
+    let mut ticker = Ticker::every(FEED_INTERVAL);
+    let mut next_save = Instant::now() + SAVE_INTERVAL;
+    loop {
+        ticker.next().await;
+        watchdog.feed();
+        if Instant::now() >= next_save {
+            save();
+            next_save += SAVE_INTERVAL;
+        }
+    }
+}
+
+// Log the panic and restart through the watchdog, marked so the next boot
+// counts it as a panic
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    defmt::error!("{}", defmt::Display2Format(info));
+    pac::WATCHDOG.scratch0().write(|w| *w = PANIC_MARK);
+    pac::WATCHDOG.ctrl().write(|w| w.set_trigger(true));
+    loop {
+        core::hint::spin_loop();
+    }
+}
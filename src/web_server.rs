@@ -1,18 +1,85 @@
 use embassy_net::{Stack, tcp::TcpSocket};
-use defmt::*;
 use embedded_io_async::{Read, Write};
 use core::fmt::Write as FmtWrite;
-use heapless::String;
+use heapless::{String, Vec};
+use serde::Serialize;
+use serde::ser::{SerializeStruct, Serializer};
 
-#[embassy_executor::task]
+use embassy_time::{Duration, Instant, with_timeout};
+use vision_protocol::zoning::Zone;
+
+use crate::assets::{self, Asset};
+use crate::buffers::{self, ConnectionBuffers, REQUEST_SIZE};
+use crate::busy;
+use crate::calibration::{self, Fit, Step};
+use crate::channels;
+use crate::clients::{self, MAX_CLIENTS};
+use crate::clock;
+use crate::commands;
+use crate::config::{self, AlertChannels, AlertKind, HapticPattern, INTENSITY_LEVELS, Mounting};
+use crate::defaults::ADMIN_TOKEN;
+use crate::diagnostics;
+use crate::events::{self, Event, Side};
+use crate::factory;
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::health::{self, Status, Subsystem};
+use crate::history;
+use crate::http::{self, Request};
+use crate::latency;
+use crate::lifecycle::{self, State};
+use crate::locale::{self, Language, Strings};
+use crate::locate;
+use crate::logging::{self, Level, Scope, info, warn};
+use crate::maintenance::{self, Task};
+use crate::occupancy;
+use crate::owner::{self, Changes, Sharing};
+use crate::pacing;
+use crate::pairing::{self, Role};
+use crate::profiles::{self, MAX_NAME_LEN};
+use crate::radio;
+use crate::secrets;
+use crate::session;
+use crate::shutdown;
+use crate::supply;
+use crate::telemetry;
+use crate::theme::{self, Pick, Theme};
+use crate::thermal;
+use crate::transport;
+use crate::tuning;
+use crate::units::{Distance, DistanceValue, Units};
+use crate::usage;
+use crate::wifi_utils::{self, AP_SSID};
+use crate::zone_state::{self, SideState};
+
+const SCOPE: Scope = Scope::Web;
+
+// Any whole response, the tuning page is the biggest
+type Response = String<8192>;
+
+// The dashboard reloads itself this often
+const DASHBOARD_REFRESH_S: u8 = 2;
+// Of each channel's line in the history chart, in Side::ALL order
+const HISTORY_COLORS: [&str; 4] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd"];
+
+// A client gets this long to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest a connection may take, a long poll's wait and time to send the reply
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(MAX_WAIT_S + 20);
+// For an error reply to go out before the reset
+const REJECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Two listeners, so a parked long-poll request doesn't lock out the dashboard
+pub const WEB_SERVER_TASKS: usize = 2;
+
+#[embassy_executor::task(pool_size = WEB_SERVER_TASKS)]
 pub async fn web_server_task(stack: &'static Stack<'static>) {
     info!("Web server task started");
     
     loop {
-        // Create a new socket for each connection with fresh buffers
-        let mut rx_buffer = [0; 1024];
-        let mut tx_buffer = [0; 4096];
-        let mut socket = TcpSocket::new(*stack, &mut rx_buffer, &mut tx_buffer);
+        // Create a new socket for each connection, with buffers from the pool
+        let mut buffers = buffers::acquire().await;
+        let ConnectionBuffers { socket_rx, socket_tx, request } = &mut *buffers;
+        let mut socket = TcpSocket::new(*stack, socket_rx, socket_tx);
         
         // Listen for connections on port 80
         info!("Web server listening on port 80...");
@@ -22,34 +89,45 @@ pub async fn web_server_task(stack: &'static Stack<'static>) {
         }
         
         info!("Web connection accepted!");
+        let _taken = busy::WEB.take();
+        events::publish(Event::ClientConnected {
+            port: 80,
+            peer: socket.remote_endpoint().map(|endpoint| endpoint.addr),
+        });
         
-        // Handle the connection
-        handle_web_connection(&mut socket).await;
+        // Handle the connection, a client that stops reading the reply gets dropped
+        if with_timeout(CONNECTION_TIMEOUT, handle_web_connection(&mut socket, request)).await.is_err() {
+            warn!("Web connection timed out, aborting it");
+            transport::abort(&mut socket).await;
+            continue;
+        }
         
         // Close the connection
-        socket.close();
+        transport::close(&mut socket).await;
         
         // Small delay before accepting next connection
         embassy_time::Timer::after_millis(100).await;
     }
 }
 
-async fn handle_web_connection(socket: &mut TcpSocket<'_>) {
-    let mut rx_buffer = [0; 512];
-    
-    // Read request (we don't actually use it, but we need to read it)
-    match socket.read(&mut rx_buffer).await {
-        Ok(n) => {
-            info!("Read {} bytes", n);
-        }
-        Err(e) => {
-            warn!("Failed to read from socket: {:?}", e);
+async fn handle_web_connection(socket: &mut TcpSocket<'_>, rx_buffer: &mut [u8; REQUEST_SIZE]) {
+    // A client that connects and sends nothing would hold the listener forever
+    let len = match with_timeout(REQUEST_TIMEOUT, read_request(socket, rx_buffer)).await {
+        Ok(Some(len)) => len,
+        Ok(None) => return,
+        Err(_) => {
+            warn!("No request within {} s, dropping the connection", REQUEST_TIMEOUT.as_secs());
+            reject(socket, "408 Request Timeout", "Request timed out").await;
             return;
         }
-    }
+    };
+    
+    let Ok(Some(request)) = http::parse(&rx_buffer[..len]) else {
+        return;
+    };
     
     // Generate HTTP response
-    let response = generate_http_response();
+    let response = route(&request).await;
     
     // Send response
     if let Err(e) = socket.write_all(response.as_bytes()).await {
@@ -57,66 +135,1511 @@ async fn handle_web_connection(socket: &mut TcpSocket<'_>) {
     }
 }
 
-fn generate_http_response() -> String<2048> {
+// Reads until we have the whole request and returns its length, None when
+// the client went away or was answered with an error already
+async fn read_request(socket: &mut TcpSocket<'_>, rx_buffer: &mut [u8; REQUEST_SIZE]) -> Option<usize> {
+    let mut len = 0;
+    
+    loop {
+        match socket.read(&mut rx_buffer[len..]).await {
+            Ok(0) => return None,
+            Ok(n) => {
+                info!("Read {} bytes", n);
+                len += n;
+            }
+            Err(e) => {
+                warn!("Failed to read from socket: {:?}", e);
+                return None;
+            }
+        }
+        
+        match http::parse(&rx_buffer[..len]) {
+            Ok(Some(_)) => return Some(len),
+            Ok(None) if len < rx_buffer.len() => continue,
+            Ok(None) => {
+                reject(socket, "413 Payload Too Large", "Request too large").await;
+                return None;
+            }
+            Err(e) => {
+                reject(socket, "400 Bad Request", e).await;
+                return None;
+            }
+        }
+    }
+}
+
+// Changing anything over HTTP needs the admin token, like the admin commands
+// on the other links. It may come as an X-Admin-Token header, a `token`
+// query parameter or a `token` field of a form body. Builds without an
+// admin token accept any request, the same as they make every session admin.
+fn require_admin(request: &Request<'_>) -> Result<(), Response> {
+    let token = Some(request.admin_token)
+        .filter(|token| !token.is_empty())
+        .or_else(|| http::form_param(request.query, "token"))
+        .or_else(|| request.body_str().and_then(|body| http::form_param(body, "token")))
+        .unwrap_or("");
+    if commands::admin_token_valid(token) {
+        return Ok(());
+    }
+    warn!("{} without the admin token", request.path);
+    Err(http::text_response("403 Forbidden", "Admin token required"))
+}
+
+// Tells a client what was wrong with its request and resets the connection,
+// the rest of what it sends isn't worth reading
+async fn reject(socket: &mut TcpSocket<'_>, status: &str, message: &str) {
+    let _ = socket.write_all(http::text_response::<128>(status, message).as_bytes()).await;
+    let _ = with_timeout(REJECT_TIMEOUT, socket.flush()).await;
+    transport::abort(socket).await;
+}
+
+async fn route(request: &Request<'_>) -> Response {
+    // every POST changes something on the device
+    if request.method == "POST" {
+        if let Err(response) = require_admin(request) {
+            return response;
+        }
+    }
+    
+    match (request.method, request.path) {
+        ("GET", "/") => generate_http_response(request),
+        ("GET", "/api/wait") => wait_for_zone_change(request).await,
+        ("GET", "/patterns") => generate_patterns_page(request),
+        ("GET", "/tuning") => generate_tuning_page(),
+        ("POST", "/api/tuning") => update_tuning(request),
+        ("GET", "/api/patterns") => patterns_json(),
+        ("GET", "/api/config/export") => export_config(),
+        ("POST", "/api/config/import") => import_config(request),
+        ("GET", "/api/profiles") => profiles_json(),
+        ("GET", "/api/clients") => clients_json(),
+        ("GET", "/api/buffers") => buffers_json(),
+        ("GET", "/api/info") => info_json(),
+        ("GET", "/api/metrics") => metrics_json(),
+        ("GET", "/api/health") => health_json(),
+        ("GET", "/api/history") => history_text(),
+        ("GET", "/api/telemetry") => telemetry_line(),
+        ("GET", "/api/provision") => provision_status(),
+        ("POST", "/api/provision") => provision(request),
+        ("GET", "/api/owner") => owner_json(),
+        ("POST", "/api/owner") => update_owner(request),
+        ("POST", "/api/profiles/activate") => activate_profile(request),
+        ("POST", "/api/profiles/save") => save_profile(request),
+        ("POST", "/api/preview/feedback") => preview_feedback(request),
+        ("POST", "/api/actuators") => update_actuators(request),
+        ("GET", "/api/levels") => levels_json(),
+        ("POST", "/api/levels") => update_levels(request),
+        ("POST", "/api/mounting") => update_mounting(request),
+        ("POST", "/api/units") => update_units(request),
+        ("POST", "/api/clock") => update_clock(request),
+        ("POST", "/api/language") => update_language(request),
+        ("POST", "/api/theme") => update_theme(request),
+        ("POST", "/api/radio") => update_radio(request),
+        ("GET", "/api/pairing") => pairing_json(),
+        ("POST", "/api/pairing") => update_pairing(request),
+        ("POST", "/api/shutdown") => shutdown_device(),
+        ("POST", "/api/reboot") => reboot_device(request),
+        ("POST", "/api/locate") => update_locator(request),
+        ("GET", "/api/alerts") => alerts_json(),
+        ("POST", "/api/alerts") => update_alert_rule(request),
+        ("GET", "/api/maintenance") => maintenance_json(),
+        ("POST", "/api/maintenance") => update_maintenance(request),
+        ("POST", "/api/maintenance/ack") => acknowledge_maintenance(request),
+        ("GET", "/api/calibration") => calibration_json(),
+        ("POST", "/api/calibration") => update_calibration(request),
+        ("GET", "/api/diagnostics/sensor") => sensor_diagnostics_json(),
+        ("POST", "/api/diagnostics/sensor") => start_sensor_diagnostics(),
+        ("GET", "/api/diagnostics/supply") => supply_diagnostics_json(),
+        ("GET", "/api/log") => log_levels_json(),
+        ("POST", "/api/log") => update_log_level(request),
+        ("POST", path) if path.starts_with("/api/patterns/") => update_pattern(&path["/api/patterns/".len()..], request),
+        ("GET", path) => match assets::find(path) {
+            Some(asset) => asset_response(asset),
+            None => http::text_response("404 Not Found", "Not found"),
+        },
+        _ => http::text_response("404 Not Found", "Not found"),
+    }
+}
+
+// All haptic patterns as {"patterns":["5,95,5",...]}, level 1 first
+fn patterns_json() -> Response {
+    #[derive(Serialize)]
+    struct Body<'a> {
+        patterns: &'a [HapticPattern],
+    }
+    
+    // eight steps of up to four digits make at most 42 bytes per pattern
+    let patterns = config::get().haptic.patterns;
+    http::json_response(&Body { patterns: &patterns }, &mut [0; INTENSITY_LEVELS * 42 + 16])
+}
+
+// Longest a long-poll request may park the connection
+const MAX_WAIT_S: u64 = 30;
+
+// GET /api/wait?timeout=10 blocks until a sensor changes zone or the timeout (in
+// seconds) runs out, for clients that can only do plain requests. Answers with
+// {"timeout":false,"side":"left","zone":"warning","distance":55,"unit":"cm"} or {"timeout":true}
+async fn wait_for_zone_change(request: &Request<'_>) -> Response {
+    let timeout_s = match http::form_param(request.query, "timeout").map(|value| value.parse::<u64>()) {
+        Some(Ok(timeout_s)) => timeout_s.clamp(1, MAX_WAIT_S),
+        Some(Err(_)) => return http::text_response("400 Bad Request", "Timeout must be a number of seconds"),
+        None => 10,
+    };
+    
+    let Some(mut subscriber) = events::try_subscribe() else {
+        return http::text_response("503 Service Unavailable", "Too many waiting clients");
+    };
+    
+    let zone_change = async {
+        loop {
+            if let Event::ZoneChanged { side, zone, distance_cm } = events::next(&mut subscriber).await {
+                return (side, zone, distance_cm);
+            }
+        }
+    };
+    
+    #[derive(Serialize)]
+    struct Body {
+        timeout: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        side: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        zone: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        distance: Option<DistanceValue>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        unit: Option<&'static str>,
+    }
+    
+    let body = match with_timeout(Duration::from_secs(timeout_s), zone_change).await {
+        Ok((side, zone, distance_cm)) => {
+            let distance = Distance::new(distance_cm);
+            Body {
+                timeout: false,
+                side: Some(side.name()),
+                zone: Some(zone.name()),
+                distance: Some(distance.value()),
+                unit: Some(distance.units.symbol()),
+            }
+        }
+        Err(_) => Body { timeout: true, side: None, zone: None, distance: None, unit: None },
+    };
+    
+    http::json_response(&body, &mut [0; 128])
+}
+
+// The whole device configuration as one JSON object, to be loaded into another
+// device with /api/config/import
+fn export_config() -> Response {
+    match config::export_json() {
+        Ok(json) => {
+            let mut response = String::new();
+            http::write_headers(&mut response, "200 OK", "application/json");
+            let _ = FmtWrite::write_str(&mut response, &json);
+            response
+        }
+        Err(e) => http::text_response("500 Internal Server Error", e),
+    }
+}
+
+// POST /api/config/import with a body from /api/config/export, replaces everything
+fn import_config(request: &Request<'_>) -> Response {
+    match request.body_str().ok_or("Body must be text").and_then(config::import_json) {
+        Ok(_) => {
+            info!("Configuration imported");
+            http::text_response("200 OK", "Configuration imported")
+        }
+        Err(e) => http::text_response("400 Bad Request", e),
+    }
+}
+
+// Profiles as {"active":"Indoor","profiles":["Indoor","Outdoor","Crowded","Wheelchair"]}
+fn profiles_json() -> Response {
+    #[derive(Serialize)]
+    struct Body<'a> {
+        active: &'a str,
+        profiles: &'a [String<MAX_NAME_LEN>],
+    }
+    
+    let (active, names) = profiles::list();
+    let active = names.get(active).map_or("", |name| name.as_str());
+    // five names of up to 16 characters, plus room for escaping
+    http::json_response(&Body { active, profiles: &names }, &mut [0; 256])
+}
+
+// Known clients as {"clients":[{"address":"192.168.4.2","port":80,"first_seen_ms":1200,
+// "last_seen_ms":5400,"connections":3,"active":true}]}, most recently seen first
+fn clients_json() -> Response {
+    #[derive(Serialize)]
+    struct ClientJson {
+        address: String<40>,
+        port: u16,
+        first_seen_ms: u64,
+        last_seen_ms: u64,
+        connections: u32,
+        active: bool,
+    }
+    
+    #[derive(Serialize)]
+    struct Body {
+        clients: Vec<ClientJson, MAX_CLIENTS>,
+    }
+    
+    let now_ms = Instant::now().as_millis();
+    let mut body = Body { clients: Vec::new() };
+    for client in clients::list() {
+        let mut address = String::new();
+        let _ = FmtWrite::write_fmt(&mut address, format_args!("{}", client.address));
+        let _ = body.clients.push(ClientJson {
+            address,
+            port: client.port,
+            first_seen_ms: client.first_seen_ms,
+            last_seen_ms: client.last_seen_ms,
+            connections: client.connections,
+            active: client.is_active(now_ms),
+        });
+    }
+    
+    http::json_response(&body, &mut [0; MAX_CLIENTS * 160 + 16])
+}
+
+// The last minutes of distances, in the compact form history.rs describes
+fn history_text() -> Response {
     let mut response = String::new();
+    http::write_headers(&mut response, "200 OK", "text/plain");
+    if history::write(&mut response, Instant::now().as_millis()).is_err() {
+        return http::text_response("500 Internal Server Error", "Response too large");
+    }
+    response
+}
+
+// The status line a STATUS command gets, "L:120 R:95 NL:1.2 NR:0.8 ZS:1.00 AGE:12"
+fn telemetry_line() -> Response {
+    let mut line: String<{ telemetry::BODY_LEN + 32 }> = String::new();
+    let _ = telemetry::latest().write_line(&mut line, None);
+    http::text_response("200 OK", &line)
+}
+
+// Connection buffer pool usage as {"capacity":3,"in_use":2,"peak":3,"acquired":41,"exhausted":0}
+fn buffers_json() -> Response {
+    http::json_response(&buffers::stats(), &mut [0; 128])
+}
+
+// Firmware, access point and usage details as
+// {"version":"0.1.0","ssid":"VisionAssist","channel":6,"auto_channel":true,
+//  "boots":42,"runtime_hours":118.5,"watchdog_resets":0,"panic_resets":1,
+//  "reboots":3,"boot_reason":"reboot","safe_mode":false}
+fn info_json() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        version: &'static str,
+        ssid: &'static str,
+        channel: u8,
+        // picked by the startup scan rather than set in the config
+        auto_channel: bool,
+        boots: u32,
+        // to a tenth of an hour
+        runtime_hours: f32,
+        watchdog_resets: u32,
+        panic_resets: u32,
+        reboots: u32,
+        // why the device is running now, see usage::BootReason
+        boot_reason: &'static str,
+        safe_mode: bool,
+        // from the production test fixture, null on a board that never saw it
+        serial: Option<String<{ factory::MAX_SERIAL_LEN }>>,
+        tested: bool,
+    }
+    
+    let usage = usage::counters();
+    let body = Body {
+        version: env!("CARGO_PKG_VERSION"),
+        ssid: AP_SSID,
+        channel: wifi_utils::ap_channel(),
+        auto_channel: wifi_utils::ap_channel_scanned(),
+        boots: usage.boots,
+        runtime_hours: (usage.runtime_s / 360) as f32 / 10.0,
+        watchdog_resets: usage.watchdog_resets,
+        panic_resets: usage.panic_resets,
+        reboots: usage.reboots,
+        boot_reason: usage.boot_reason.name(),
+        safe_mode: usage::safe_mode(),
+        serial: factory::serial_number(),
+        tested: factory::tested(),
+    };
+    http::json_response(&body, &mut [0; 384])
+}
+
+// Readings about the device itself, as they are right now
+fn metrics_json() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        uptime_s: u64,
+        // null until the first reading
+        die_temperature_c: Option<f32>,
+        // share of their duty the motors may use, below 100 while hot
+        motor_duty_percent: u8,
+        // connections open, the most at once and clients turned away, per server
+        web_connections: busy::Stats,
+        #[cfg(feature = "tcp")]
+        tcp_connections: busy::Stats,
+        // sensing cycles started on their tick, and ticks a cycle ran past
+        sensing_cycles: u32,
+        missed_ticks: u32,
+        // µs from the first echo of a cycle to its state going out and to the motor starting
+        latency: latency::Report,
+    }
+    
+    let (sensing_cycles, missed_ticks) = pacing::counts();
+    let body = Body {
+        uptime_s: Instant::now().as_secs(),
+        die_temperature_c: thermal::temperature_c(),
+        motor_duty_percent: thermal::duty_percent(),
+        web_connections: busy::WEB.stats(),
+        #[cfg(feature = "tcp")]
+        tcp_connections: busy::TCP.stats(),
+        sensing_cycles,
+        missed_ticks,
+        latency: latency::report(),
+    };
+    http::json_response(&body, &mut [0; 768])
+}
+
+// Overall verdict and per subsystem status and error counts, as
+// {"verdict":"DEGRADED","uptime_ms":81230,"clock_s":null,"subsystems":
+// [{"name":"sensors","status":"DEGRADED","errors":12,"errors_since_boot":3},...]}
+fn health_json() -> Response {
+    #[derive(Serialize)]
+    struct SubsystemJson {
+        name: &'static str,
+        status: &'static str,
+        errors: u32,
+        errors_since_boot: u32,
+    }
+    
+    #[derive(Serialize)]
+    struct Body {
+        // the device's lifecycle state, "active" while it guides
+        state: &'static str,
+        verdict: &'static str,
+        // going backwards means the device restarted
+        uptime_ms: u64,
+        // seconds since 1970, null until a client set the clock
+        clock_s: Option<u64>,
+        subsystems: Vec<SubsystemJson, { Subsystem::COUNT }>,
+    }
     
-    // Get current distances
-    let left = unsafe { crate::tcp_server::LEFT_DISTANCE };
-    let right = unsafe { crate::tcp_server::RIGHT_DISTANCE };
+    let subsystems = health::reports()
+        .into_iter()
+        .map(|report| SubsystemJson {
+            name: report.subsystem.name(),
+            status: report.status.name(),
+            errors: report.errors,
+            errors_since_boot: report.errors_since_boot,
+        })
+        .collect();
+    let body = Body {
+        state: lifecycle::state().name(),
+        verdict: health::verdict().name(),
+        uptime_ms: clock::uptime_ms(),
+        clock_s: clock::unix_s(),
+        subsystems,
+    };
+    http::json_response(&body, &mut [0; 576])
+}
+
+// Only whether a passphrase is set, never the passphrase itself
+fn provision_status() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        provisioned: bool,
+    }
+    
+    http::json_response(&Body { provisioned: secrets::is_provisioned() }, &mut [0; 32])
+}
+
+// POST /api/provision with "passphrase=..." (plus "&current=..." to change an
+// existing one) sets the access point passphrase for the next boot
+fn provision(request: &Request<'_>) -> Response {
+    let Some(body) = request.body_str() else {
+        return http::text_response("400 Bad Request", "Body must be text");
+    };
+    let Some(passphrase) = http::form_param(body, "passphrase") else {
+        return http::text_response("400 Bad Request", "Missing passphrase");
+    };
+    
+    match secrets::provision(passphrase, http::form_param(body, "current")) {
+        Ok(()) => http::text_response("200 OK", "Passphrase stored, restart the device to use it"),
+        Err(e) => http::text_response("400 Bad Request", e),
+    }
+}
+
+// The owner record as it is shared, e.g. {"name":"Jane's cane","owner":"J*** D**",
+// "contact":"+49 170 1234567","owner_shown":"masked","contact_shown":"full"}.
+// A hidden value is null, like one that isn't set.
+fn owner_json() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        name: String<{ owner::MAX_NAME_LEN }>,
+        owner: Option<String<{ owner::MAX_OWNER_LEN }>>,
+        contact: Option<String<{ owner::MAX_CONTACT_LEN }>>,
+        owner_shown: &'static str,
+        contact_shown: &'static str,
+    }
+    
+    let shared = owner::shared();
+    let body = Body {
+        name: shared.name,
+        owner: shared.owner,
+        contact: shared.contact,
+        owner_shown: shared.owner_sharing.name(),
+        contact_shown: shared.contact_sharing.name(),
+    };
+    http::json_response(&body, &mut [0; 256])
+}
+
+// POST /api/owner with any of "name=...&owner=...&contact=..." and
+// "owner_shown=full|masked|hidden&contact_shown=...", an empty value clears it
+fn update_owner(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    let sharing = |name| http::form_param(body, name).map(|value| Sharing::parse(value).ok_or(()));
+    let (Ok(owner_sharing), Ok(contact_sharing)) = (sharing("owner_shown").transpose(), sharing("contact_shown").transpose()) else {
+        return http::text_response("400 Bad Request", "Expected full, masked or hidden");
+    };
+    let changes = Changes {
+        name: http::form_param(body, "name"),
+        owner: http::form_param(body, "owner"),
+        contact: http::form_param(body, "contact"),
+        owner_sharing,
+        contact_sharing,
+    };
+    
+    match owner::update(changes) {
+        Ok(()) => http::text_response("200 OK", "Owner record stored"),
+        Err(e) => http::text_response("400 Bad Request", e),
+    }
+}
+
+// POST /api/profiles/activate with the profile name as body
+fn activate_profile(request: &Request<'_>) -> Response {
+    let Some(index) = request.body_str().and_then(profiles::find) else {
+        return http::text_response("404 Not Found", "Unknown profile");
+    };
+    
+    match profiles::activate(index) {
+        Ok(()) => http::text_response("200 OK", "Profile activated"),
+        Err(e) => http::text_response("404 Not Found", e),
+    }
+}
+
+// POST /api/profiles/save with a name as body, stores the current settings under it
+fn save_profile(request: &Request<'_>) -> Response {
+    match request.body_str().ok_or("Body must be text").and_then(profiles::save_current) {
+        Ok(_) => http::text_response("200 OK", "Profile saved"),
+        Err(e) => http::text_response("400 Bad Request", e),
+    }
+}
+
+// POST /api/patterns/<level> with a body like "80,20,80"
+fn update_pattern(level: &str, request: &Request<'_>) -> Response {
+    let level = match level.parse::<usize>() {
+        Ok(level) if (1..=INTENSITY_LEVELS).contains(&level) => level,
+        _ => return http::text_response("404 Not Found", "Unknown intensity level"),
+    };
+    
+    let pattern = match request.body_str().ok_or("Body must be text").and_then(HapticPattern::parse) {
+        Ok(pattern) => pattern,
+        Err(e) => return http::text_response("400 Bad Request", e),
+    };
+    
+    config::update(|config| config.haptic.patterns[level - 1] = pattern);
+    info!("Haptic pattern for level {} updated", level);
+    
+    http::text_response("200 OK", "Pattern updated")
+}
+
+// Longest pattern we'll play as a preview
+const MAX_PREVIEW_MS: u32 = 3000;
+
+// POST /api/preview/feedback with a body like "buzzer 100,50,100" plays the
+// pattern once on buzzer, left, right or both motors without saving anything
+fn preview_feedback(request: &Request<'_>) -> Response {
+    let Some((target, steps)) = request.body_str().and_then(|body| body.trim().split_once(' ')) else {
+        return http::text_response("400 Bad Request", "Expected \"<buzzer|left|right|both> <steps>\"");
+    };
+    
+    let Some(target) = PreviewTarget::parse(target) else {
+        return http::text_response("400 Bad Request", "Unknown target");
+    };
+    
+    let steps = match HapticPattern::parse(steps) {
+        Ok(steps) if steps.duration_ms() <= MAX_PREVIEW_MS => steps,
+        Ok(_) => return http::text_response("400 Bad Request", "Preview is limited to 3000 ms"),
+        Err(e) => return http::text_response("400 Bad Request", e),
+    };
+    
+    // Normal priority, so a real warning still cuts the preview short
+    feedback::submit(FeedbackCommand {
+        pattern: Pattern::Preview { steps, target },
+        side: None,
+        priority: Priority::Normal,
+        ttl_ms: 1000,
+    });
+    
+    http::text_response("200 OK", "Playing preview")
+}
+
+// POST /api/units with "metric" or "imperial" (or "cm"/"in") as body
+fn update_units(request: &Request<'_>) -> Response {
+    let Some(units) = request.body_str().and_then(Units::parse) else {
+        return http::text_response("400 Bad Request", "Expected \"metric\" or \"imperial\"");
+    };
+    
+    config::update(|config| config.units = units);
+    info!("Units set to {}", units.name());
+    
+    http::text_response("200 OK", "Units updated")
+}
+
+// POST /api/clock with the time in seconds since 1970, e.g. a phone's, as
+// body. The device has no other way of knowing it.
+fn update_clock(request: &Request<'_>) -> Response {
+    let Some(unix_s) = request.body_str().and_then(|body| body.trim().parse().ok()) else {
+        return http::text_response("400 Bad Request", "Expected seconds since 1970");
+    };
+    
+    if let Err(e) = clock::set(unix_s) {
+        return http::text_response("400 Bad Request", e);
+    }
+    info!("Clock set to {}", unix_s);
     
-    // HTTP headers
-    let _ = FmtWrite::write_str(&mut response, "HTTP/1.1 200 OK\r\n");
-    let _ = FmtWrite::write_str(&mut response, "Content-Type: text/html\r\n");
-    let _ = FmtWrite::write_str(&mut response, "Connection: close\r\n");
-    let _ = FmtWrite::write_str(&mut response, "\r\n");
+    http::text_response("200 OK", "Clock set")
+}
+
+// POST /api/language with "en" or "de", for the dashboard and the feedback
+// settings page
+fn update_language(request: &Request<'_>) -> Response {
+    let Some(language) = request.body_str().and_then(Language::parse) else {
+        return http::text_response("400 Bad Request", "Expected \"en\" or \"de\"");
+    };
+    
+    config::update(|config| config.language = language);
+    info!("Language set to {}", language.name());
+    
+    http::text_response("200 OK", "Language updated")
+}
+
+// POST /api/theme with "light", "dark" or "high-contrast", the look of the
+// pages for browsers that didn't pick their own
+fn update_theme(request: &Request<'_>) -> Response {
+    let Some(theme) = request.body_str().and_then(Theme::parse) else {
+        return http::text_response("400 Bad Request", "Expected \"light\", \"dark\" or \"high-contrast\"");
+    };
+    
+    config::update(|config| config.theme = theme);
+    info!("Theme set to {}", theme.name());
+    
+    http::text_response("200 OK", "Theme updated")
+}
+
+// POST /api/radio with "on" or "off". Switching off drops this connection
+// too; holding the button brings WiFi back.
+fn update_radio(request: &Request<'_>) -> Response {
+    let on = match request.body_str().map(str::trim) {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return http::text_response("400 Bad Request", "Expected \"on\" or \"off\""),
+    };
+    
+    match radio::set(on) {
+        Ok(()) if on => http::text_response("200 OK", "WiFi on"),
+        Ok(()) => http::text_response("200 OK", "WiFi switching off, hold the button to turn it back on"),
+        Err(e) => http::text_response("500 Internal Server Error", e),
+    }
+}
+
+// Guide-runner pairing as {"role":"guide","linked":true,"latency_ms":12}
+fn pairing_json() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        role: &'static str,
+        linked: bool,
+        latency_ms: u16,
+    }
+    
+    let body = Body { role: pairing::role().name(), linked: pairing::linked(), latency_ms: pairing::latency_ms() };
+    http::json_response(&body, &mut [0; 64])
+}
+
+// POST /api/pairing with "off", "guide" or "follower", used from the next boot
+// on. A follower joins the guide's network at 192.168.4.2.
+fn update_pairing(request: &Request<'_>) -> Response {
+    let Some(role) = request.body_str().map(str::trim).and_then(Role::parse) else {
+        return http::text_response("400 Bad Request", "Expected \"off\", \"guide\" or \"follower\"");
+    };
+    
+    match pairing::set(role) {
+        Ok(()) => http::text_response("200 OK", "Pairing role stored, restart the device to use it"),
+        Err(e) => http::text_response("500 Internal Server Error", e),
+    }
+}
+
+// Maintenance reminders as {"runtime_hours":212,"reminders":[{"task":"mounts",
+// "interval_hours":200,"due_in_hours":-12,"due":true},...]}, due_in_hours is
+// null for reminders that are off
+fn maintenance_json() -> Response {
+    #[derive(Serialize)]
+    struct ReminderJson {
+        task: &'static str,
+        interval_hours: u16,
+        due_in_hours: Option<i64>,
+        due: bool,
+    }
+    
+    #[derive(Serialize)]
+    struct Body {
+        runtime_hours: u32,
+        reminders: Vec<ReminderJson, { Task::ALL.len() }>,
+    }
+    
+    let runtime_hours = usage::counters().runtime_s / 3600;
+    let reminders = maintenance::list()
+        .into_iter()
+        .map(|(task, reminder)| ReminderJson {
+            task: task.name(),
+            interval_hours: reminder.interval_h,
+            due_in_hours: reminder.due_in_h(runtime_hours),
+            due: reminder.due(runtime_hours),
+        })
+        .collect();
+    http::json_response(&Body { runtime_hours, reminders }, &mut [0; 320])
+}
+
+// POST /api/maintenance with "task=mounts&hours=300", 0 hours turns the reminder off
+fn update_maintenance(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    let Some(task) = http::form_param(body, "task").and_then(Task::parse) else {
+        return http::text_response("400 Bad Request", "Expected task=mounts|motors|battery");
+    };
+    let Some(hours) = http::form_param(body, "hours").and_then(|hours| hours.parse::<u16>().ok()) else {
+        return http::text_response("400 Bad Request", "Expected hours=<runtime hours>");
+    };
+    
+    match maintenance::set_interval(task, hours) {
+        Ok(()) => http::text_response("200 OK", "Reminder updated"),
+        Err(e) => http::text_response("500 Internal Server Error", e),
+    }
+}
+
+// POST /api/maintenance/ack with "task=mounts" once it has been done
+fn acknowledge_maintenance(request: &Request<'_>) -> Response {
+    let Some(task) = request.body_str().and_then(|body| http::form_param(body, "task")).and_then(Task::parse) else {
+        return http::text_response("400 Bad Request", "Expected task=mounts|motors|battery");
+    };
+    
+    match maintenance::acknowledge(task) {
+        Ok(()) => http::text_response("200 OK", "Maintenance acknowledged"),
+        Err(e) => http::text_response("500 Internal Server Error", e),
+    }
+}
+
+// Calibration wizard as {"step":"waiting","point_cm":60,"samples":0,
+// "samples_needed":40,"reason":null,"fits":[{"sensor":"left","gain":1.02,
+// "offset_cm":-1.5,"residual_cm":0.4},...],"candidate":null}. point_cm is
+// where the target goes next, candidate holds the fits of the last run.
+fn calibration_json() -> Response {
+    #[derive(Serialize)]
+    struct FitJson {
+        sensor: &'static str,
+        gain: f32,
+        offset_cm: f32,
+        residual_cm: f32,
+    }
+    
+    #[derive(Serialize)]
+    struct Body {
+        step: &'static str,
+        point_cm: Option<f32>,
+        samples: u16,
+        samples_needed: u16,
+        reason: Option<&'static str>,
+        fits: Vec<FitJson, { channels::COUNT }>,
+        candidate: Option<Vec<FitJson, { channels::COUNT }>>,
+    }
+    
+    let to_json = |fits: [(Side, Fit); channels::COUNT]| -> Vec<FitJson, { channels::COUNT }> {
+        fits.into_iter()
+            .map(|(side, fit)| FitJson { sensor: side.name(), gain: fit.gain, offset_cm: fit.offset_cm, residual_cm: fit.residual_cm })
+            .collect()
+    };
+    let status = calibration::status();
+    let (point_cm, reason) = match status.step {
+        Step::Waiting { point } | Step::Capturing { point, .. } => (Some(calibration::POINTS_CM[point]), None),
+        Step::Failed { reason } => (None, Some(reason)),
+        Step::Idle | Step::Stored => (None, None),
+    };
+    let body = Body {
+        step: status.step.name(),
+        point_cm,
+        samples: status.samples,
+        samples_needed: calibration::SAMPLES_PER_POINT,
+        reason,
+        fits: to_json(calibration::fits()),
+        candidate: status.candidate.map(to_json),
+    };
+    http::json_response(&body, &mut [0; 768])
+}
+
+// POST /api/calibration with "action=start", then "action=capture" once the
+// target is at each point_cm; "action=cancel" stops a run, "action=reset"
+// goes back to uncorrected readings
+fn update_calibration(request: &Request<'_>) -> Response {
+    match request.body_str().and_then(|body| http::form_param(body, "action")) {
+        Some("start") => {
+            calibration::start();
+            http::text_response("200 OK", "Calibration started, place the target at the first point")
+        }
+        Some("capture") => match calibration::capture() {
+            Ok(()) => http::text_response("200 OK", "Capturing, hold the target still"),
+            Err(e) => http::text_response("400 Bad Request", e),
+        },
+        Some("cancel") => {
+            calibration::cancel();
+            http::text_response("200 OK", "Calibration cancelled")
+        }
+        Some("reset") => match calibration::reset() {
+            Ok(()) => http::text_response("200 OK", "Calibration reset"),
+            Err(e) => http::text_response("500 Internal Server Error", e),
+        },
+        _ => http::text_response("400 Bad Request", "Expected action=start|capture|cancel|reset"),
+    }
+}
+
+// VSYS during motor starts and the duty cap its sags have set, the voltages are
+// null when VSYS isn't measured
+fn supply_diagnostics_json() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        measured: bool,
+        resting_v: Option<f32>,
+        lowest_v: Option<f32>,
+        sags: u16,
+        // 0-255, 255 is no cap
+        duty_cap: u8,
+    }
+    
+    let status = supply::status();
+    let body = Body {
+        measured: status.measured,
+        resting_v: status.resting_v,
+        lowest_v: status.lowest_v,
+        sags: status.sags,
+        duty_cap: supply::duty_cap(),
+    };
+    http::json_response(&body, &mut [0; 128])
+}
+
+// Sensor noise report as {"state":"done","attempts":300,"bin_width_cm":0.5,
+// "sensors":[{"sensor":"left","valid":288,"dropout_rate":0.04,"mean_cm":100.2,
+// "stddev_cm":0.6,"min_cm":98.9,"max_cm":101.7,"histogram_start_cm":96.2,
+// "histogram":[0,0,...]},...]}. While collecting it covers the pings so far.
+fn sensor_diagnostics_json() -> Response {
+    #[derive(Serialize)]
+    struct SensorJson {
+        sensor: &'static str,
+        attempts: u16,
+        valid: u16,
+        dropout_rate: f32,
+        mean_cm: f32,
+        stddev_cm: f32,
+        min_cm: f32,
+        max_cm: f32,
+        histogram_start_cm: f32,
+        histogram: [u16; diagnostics::HISTOGRAM_BINS],
+    }
+    
+    #[derive(Serialize)]
+    struct Body {
+        state: &'static str,
+        attempts: u16,
+        bin_width_cm: f32,
+        sensors: Vec<SensorJson, { channels::COUNT }>,
+    }
+    
+    let (state, reports) = diagnostics::status();
+    let sensors = reports
+        .into_iter()
+        .map(|report| SensorJson {
+            sensor: report.side.name(),
+            attempts: report.attempts,
+            valid: report.valid,
+            dropout_rate: report.dropout_rate(),
+            mean_cm: report.mean_cm,
+            stddev_cm: report.stddev_cm,
+            min_cm: report.min_cm,
+            max_cm: report.max_cm,
+            histogram_start_cm: report.histogram_start_cm,
+            histogram: report.histogram,
+        })
+        .collect();
+    let body = Body { state: state.name(), attempts: diagnostics::ATTEMPTS, bin_width_cm: diagnostics::BIN_WIDTH_CM, sensors };
+    http::json_response(&body, &mut [0; 1536])
+}
+
+// POST /api/diagnostics/sensor starts a new run
+fn start_sensor_diagnostics() -> Response {
+    diagnostics::start();
+    http::text_response("200 OK", "Collecting, keep the device still in front of a flat target")
+}
+
+// POST /api/shutdown, the device saves the session and powers down; like every
+// POST it only gets here past require_admin
+fn shutdown_device() -> Response {
+    shutdown::request(shutdown::Reason::Command);
+    http::text_response("200 OK", "Shutting down, power cycle to start again")
+}
+
+// POST /api/reboot, with "mode=safe" to come back in safe mode
+fn reboot_device(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    match http::form_param(body, "mode") {
+        None => {
+            shutdown::request(shutdown::Reason::Reboot);
+            http::text_response("200 OK", "Restarting")
+        }
+        Some("safe") => {
+            shutdown::request(shutdown::Reason::SafeReboot);
+            http::text_response("200 OK", "Restarting in safe mode")
+        }
+        Some(_) => http::text_response("400 Bad Request", "Expected mode=safe"),
+    }
+}
+
+// Alert forwarding rules as {"rules":[{"event":"critical","channels":["log"],"throttle_ms":0},...]}
+fn alerts_json() -> Response {
+    #[derive(Serialize)]
+    struct RuleJson {
+        event: &'static str,
+        channels: Vec<&'static str, 3>,
+        throttle_ms: u32,
+    }
+    
+    #[derive(Serialize)]
+    struct Body {
+        rules: Vec<RuleJson, { AlertKind::ALL.len() }>,
+    }
+    
+    let alerts = config::get().alerts;
+    let rules = AlertKind::ALL
+        .into_iter()
+        .map(|kind| {
+            let rule = alerts.rule(kind);
+            RuleJson { event: kind.name(), channels: rule.channels.names().collect(), throttle_ms: rule.throttle_ms }
+        })
+        .collect();
+    http::json_response(&Body { rules }, &mut [0; 512])
+}
+
+// POST /api/alerts with "event=low-battery&channels=log,udp,beep&throttle_ms=0",
+// "channels=none" stops forwarding; a left out field keeps its value
+fn update_alert_rule(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    let Some(kind) = http::form_param(body, "event").and_then(AlertKind::parse) else {
+        return http::text_response("400 Bad Request", "Unknown event, see GET /api/alerts");
+    };
+    let channels = match http::form_param(body, "channels").map(AlertChannels::parse) {
+        Some(Some(channels)) => Some(channels),
+        Some(None) => return http::text_response("400 Bad Request", "Channels are log, udp, beep or none"),
+        None => None,
+    };
+    let throttle_ms = match http::form_param(body, "throttle_ms").map(|ms| ms.parse::<u32>()) {
+        Some(Ok(ms)) => Some(ms),
+        Some(Err(_)) => return http::text_response("400 Bad Request", "Throttle must be a number of ms"),
+        None => None,
+    };
+    
+    let result = config::apply(|config| {
+        let rule = &mut config.alerts.rules[kind as usize];
+        rule.channels = channels.unwrap_or(rule.channels);
+        rule.throttle_ms = throttle_ms.unwrap_or(rule.throttle_ms);
+        Ok(())
+    });
+    match result {
+        Ok(_) => {
+            info!("Alert rule for {} updated", kind.name());
+            http::text_response("200 OK", "Alert rule updated")
+        }
+        Err(e) => http::text_response("400 Bad Request", e),
+    }
+}
+
+// POST /api/locate starts the locator tone, with "state=off" it stops it
+fn update_locator(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    match http::form_param(body, "state") {
+        None | Some("on") => {
+            locate::start();
+            http::text_response("200 OK", "Locator tone on, press the button to stop it")
+        }
+        Some("off") => {
+            locate::stop();
+            http::text_response("200 OK", "Locator tone off")
+        }
+        Some(_) => http::text_response("400 Bad Request", "Expected state=on|off"),
+    }
+}
+
+// Log level per scope, like {"system":"info","sensing":"info",...}
+fn log_levels_json() -> Response {
+    struct Body;
+    
+    impl Serialize for Body {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut levels = serializer.serialize_struct("levels", Scope::ALL.len())?;
+            for scope in Scope::ALL {
+                levels.serialize_field(scope.name(), logging::level(scope).name())?;
+            }
+            levels.end()
+        }
+    }
+    
+    http::json_response(&Body, &mut [0; 192])
+}
+
+// POST /api/log with "scope=feedback&level=debug" ("scope=all" for every
+// scope). Only lasts until the next reboot, and can't bring back messages
+// that DEFMT_LOG left out of the build.
+fn update_log_level(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    let Some(level) = http::form_param(body, "level").and_then(Level::parse) else {
+        return http::text_response("400 Bad Request", "Expected level=trace|debug|info|warn|error");
+    };
+    
+    let Some(name) = http::form_param(body, "scope") else {
+        return http::text_response("400 Bad Request", "Missing scope");
+    };
+    if name == "all" {
+        Scope::ALL.into_iter().for_each(|scope| logging::set_level(scope, level));
+    } else if let Some(scope) = Scope::parse(name) {
+        logging::set_level(scope, level);
+    } else {
+        return http::text_response("400 Bad Request", "Unknown scope");
+    }
+    info!("Log level for {} set to {}", name, level.name());
+    
+    http::text_response("200 OK", "Log level updated")
+}
+
+// POST /api/mounting with "body", "cane" or "wheelchair" as body. Height and
+// tilt for the cane are set on the tuning page.
+fn update_mounting(request: &Request<'_>) -> Response {
+    let Some(mode) = request.body_str().and_then(Mounting::parse) else {
+        return http::text_response("400 Bad Request", "Expected \"body\", \"cane\" or \"wheelchair\"");
+    };
+    
+    config::update(|config| config.mounting.mode = mode);
+    info!("Mounting set to {}", mode.name());
+    
+    http::text_response("200 OK", "Mounting updated")
+}
+
+// POST /api/actuators with a form body like "swap=1&invert0=0&invert1=1".
+// Fields that are left out keep their current value.
+fn update_actuators(request: &Request<'_>) -> Response {
+    let Some(body) = request.body_str() else {
+        return http::text_response("400 Bad Request", "Body must be text");
+    };
+    
+    let flag = |name| match http::form_param(body, name) {
+        Some("1") | Some("true") => Ok(Some(true)),
+        Some("0") | Some("false") => Ok(Some(false)),
+        Some(_) => Err(()),
+        None => Ok(None),
+    };
+    
+    let (Ok(swap), Ok(invert0), Ok(invert1)) = (flag("swap"), flag("invert0"), flag("invert1")) else {
+        return http::text_response("400 Bad Request", "Flags must be 0 or 1");
+    };
+    
+    config::update(|config| {
+        let actuators = &mut config.actuators;
+        actuators.swap_sides = swap.unwrap_or(actuators.swap_sides);
+        actuators.invert[0] = invert0.unwrap_or(actuators.invert[0]);
+        actuators.invert[1] = invert1.unwrap_or(actuators.invert[1]);
+    });
+    info!("Actuator mapping updated");
+    
+    http::text_response("200 OK", "Actuator mapping updated")
+}
+
+// Master levels as {"volume":100,"haptic":80}, in percent
+fn levels_json() -> Response {
+    #[derive(Serialize)]
+    struct Body {
+        volume: u8,
+        haptic: u8,
+    }
+    
+    let levels = config::get().levels;
+    http::json_response(&Body { volume: levels.volume_percent, haptic: levels.haptic_percent }, &mut [0; 32])
+}
+
+// POST /api/levels with "volume=70&haptic=80" (20-100 %), a value left out
+// keeps its current setting. Stored a few seconds later.
+fn update_levels(request: &Request<'_>) -> Response {
+    let body = request.body_str().unwrap_or("");
+    let percent = |name| http::form_param(body, name).map(|value| value.parse::<u8>().map_err(|_| "Levels must be whole percentages"));
+    
+    let result = config::apply(|config| {
+        if let Some(volume) = percent("volume") {
+            config.levels.volume_percent = volume?;
+        }
+        if let Some(haptic) = percent("haptic") {
+            config.levels.haptic_percent = haptic?;
+        }
+        Ok(())
+    });
+    
+    match result {
+        Ok(_) => {
+            let levels = config::get().levels;
+            info!("Master volume {}%, haptic strength {}%", levels.volume_percent, levels.haptic_percent);
+            http::text_response("200 OK", "Levels updated")
+        }
+        Err(e) => http::text_response("400 Bad Request", e),
+    }
+}
+
+// Every tuning value in one form, posted to /api/tuning
+fn generate_tuning_page() -> Response {
+    let mut response = String::new();
+    http::write_headers(&mut response, "200 OK", "text/html");
     
-    // HTML content
     let _ = FmtWrite::write_str(&mut response, "<!DOCTYPE html>\n");
     let _ = FmtWrite::write_str(&mut response, "<html>\n");
-    let _ = FmtWrite::write_str(&mut response, "<head>\n");
-    let _ = FmtWrite::write_str(&mut response, "    <title>VisionAssist Status</title>\n");
-    let _ = FmtWrite::write_str(&mut response, "    <meta http-equiv=\"refresh\" content=\"2\">\n");
-    let _ = FmtWrite::write_str(&mut response, "    <style>\n");
-    let _ = FmtWrite::write_str(&mut response, "        body { font-family: Arial, sans-serif; margin: 20px; }\n");
-    let _ = FmtWrite::write_str(&mut response, "        .sensor { margin: 10px 0; padding: 10px; border: 1px solid #ccc; }\n");
-    let _ = FmtWrite::write_str(&mut response, "        .critical { background-color: #ffcccc; }\n");
-    let _ = FmtWrite::write_str(&mut response, "        .warning { background-color: #ffffcc; }\n");
-    let _ = FmtWrite::write_str(&mut response, "        .normal { background-color: #ccffcc; }\n");
-    let _ = FmtWrite::write_str(&mut response, "    </style>\n");
-    let _ = FmtWrite::write_str(&mut response, "</head>\n");
-    let _ = FmtWrite::write_str(&mut response, "<body>\n");
-    let _ = FmtWrite::write_str(&mut response, "    <h1>VisionAssist Status</h1>\n");
-    
-    // Left sensor
-    let _ = FmtWrite::write_str(&mut response, "    <div class=\"sensor ");
-    if left < 30.0 {
-        let _ = FmtWrite::write_str(&mut response, "critical");
-    } else if left < 60.0 {
-        let _ = FmtWrite::write_str(&mut response, "warning");
-    } else {
-        let _ = FmtWrite::write_str(&mut response, "normal");
-    }
-    let _ = FmtWrite::write_str(&mut response, "\">\n");
-    let _ = FmtWrite::write_str(&mut response, "        <h2>Left Sensor</h2>\n");
-    let _ = FmtWrite::write_fmt(&mut response, format_args!("        <p>Distance: {} cm</p>\n", left as u32));
-    let _ = FmtWrite::write_str(&mut response, "    </div>\n");
-    
-    // Right sensor
-    let _ = FmtWrite::write_str(&mut response, "    <div class=\"sensor ");
-    if right < 30.0 {
-        let _ = FmtWrite::write_str(&mut response, "critical");
-    } else if right < 60.0 {
-        let _ = FmtWrite::write_str(&mut response, "warning");
-    } else {
-        let _ = FmtWrite::write_str(&mut response, "normal");
+    let _ = FmtWrite::write_str(&mut response, "<head><title>VisionAssist Tuning</title></head>\n");
+    let _ = FmtWrite::write_str(&mut response, "<body style=\"font-family: Arial, sans-serif; margin: 20px;\">\n");
+    let _ = FmtWrite::write_str(&mut response, "<h1>Tuning</h1>\n");
+    let _ = FmtWrite::write_str(&mut response, "<p>Changes take effect right away, no reboot needed.</p>\n");
+    let _ = FmtWrite::write_str(&mut response, "<form method=\"post\" action=\"/api/tuning\">\n");
+    
+    let config = config::get();
+    for param in tuning::PARAMS {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "<p>{0} <input name=\"{0}\" value=\"{1}\"></p>\n",
+            param.name, (param.get)(&config)
+        ));
     }
-    let _ = FmtWrite::write_str(&mut response, "\">\n");
-    let _ = FmtWrite::write_str(&mut response, "        <h2>Right Sensor</h2>\n");
-    let _ = FmtWrite::write_fmt(&mut response, format_args!("        <p>Distance: {} cm</p>\n", right as u32));
-    let _ = FmtWrite::write_str(&mut response, "    </div>\n");
     
+    if ADMIN_TOKEN.is_some() {
+        let _ = FmtWrite::write_str(&mut response, "<p>Admin token <input type=\"password\" name=\"token\"></p>\n");
+    }
+    let _ = FmtWrite::write_str(&mut response, "<button>Apply</button>\n");
+    let _ = FmtWrite::write_str(&mut response, "</form>\n");
     let _ = FmtWrite::write_str(&mut response, "</body>\n");
     let _ = FmtWrite::write_str(&mut response, "</html>\n");
     
     response
-}
\ No newline at end of file
+}
+
+// POST /api/tuning with a form body like "filter.noisy_tau_ms=200&sensing.cycle_delay_ms=40".
+// All values are applied together or not at all, the reply lists what changed.
+fn update_tuning(request: &Request<'_>) -> Response {
+    let Some(body) = request.body_str() else {
+        return http::text_response("400 Bad Request", "Body must be text");
+    };
+    
+    let mut failed = "";
+    let result = config::apply(|config| {
+        for (name, value) in body.trim().split('&').filter_map(|pair| pair.split_once('=')) {
+            // checked by require_admin
+            if name == "token" {
+                continue;
+            }
+            failed = name;
+            tuning::find(name).ok_or("Unknown tuning value")?.parse_into(config, value)?;
+        }
+        failed = "";
+        Ok(())
+    });
+    
+    let mut text: String<1024> = String::new();
+    match result {
+        Err(e) if !failed.is_empty() => {
+            let _ = FmtWrite::write_fmt(&mut text, format_args!("{}: {}", failed, e));
+            return http::text_response("400 Bad Request", &text);
+        }
+        Err(e) => return http::text_response("400 Bad Request", e),
+        Ok(result) if result.changed.is_empty() => {
+            let _ = FmtWrite::write_str(&mut text, "No changes");
+        }
+        Ok(result) => {
+            let _ = FmtWrite::write_str(&mut text, "Changed:");
+            for name in result.changed.iter() {
+                let _ = FmtWrite::write_fmt(&mut text, format_args!(" {}", name));
+            }
+            info!("{} tuning values changed", result.changed.len());
+        }
+    }
+    
+    http::text_response("200 OK", &text)
+}
+
+// Simple editor for the haptic patterns, separate from the auto-refreshing dashboard
+fn generate_patterns_page(request: &Request<'_>) -> Response {
+    let mut response = String::new();
+    let text = locale::strings();
+    let theme = write_page_start(&mut response, request, text, text.feedback_title, None);
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h1>{}</h1>\n", text.haptic_patterns));
+    write_theme_links(&mut response, text, theme);
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}</p>\n", text.pattern_hint));
+    if ADMIN_TOKEN.is_some() {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{} <input type=\"password\" id=\"token\"></p>\n", text.admin_token));
+    }
+    
+    for (i, pattern) in config::get().haptic.patterns.iter().enumerate() {
+        let level = i + 1;
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "    <p>{1}: <input id=\"p{0}\" value=\"{2}\"> <button onclick=\"preview({0})\">{3}</button> <button onclick=\"save({0})\">{4}</button></p>\n",
+            level, locale::fill(text.level, &[&level, &text.levels[i]]), pattern, text.try_it, text.save
+        ));
+    }
+    
+    // Motor mapping
+    let actuators = config::get().actuators;
+    let checked = |on: bool| if on { " checked" } else { "" };
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h2>{}</h2>\n", text.motors));
+    let _ = FmtWrite::write_fmt(&mut response, format_args!(
+        "    <p><label><input type=\"checkbox\" id=\"swap\"{}> {}</label></p>\n",
+        checked(actuators.swap_sides), text.swap_sides
+    ));
+    for (i, invert) in actuators.invert.iter().enumerate() {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "    <p><label><input type=\"checkbox\" id=\"invert{}\"{}> {}</label></p>\n",
+            i, checked(*invert), locale::fill(text.invert, &[&(19 + i)])
+        ));
+    }
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p><button onclick=\"saveMotors()\">{}</button></p>\n", text.save));
+    
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h2>{}</h2>\n", text.buzzer));
+    let _ = FmtWrite::write_fmt(&mut response, format_args!(
+        "    <p><input id=\"buzzer\" value=\"100,50,100\"> <button onclick=\"send('buzzer ' + document.getElementById('buzzer').value)\">{}</button></p>\n",
+        text.try_it
+    ));
+    
+    let _ = FmtWrite::write_str(&mut response, "    <script>\n");
+    let _ = FmtWrite::write_str(&mut response, "    function auth() { var t = document.getElementById('token'); return t ? { 'X-Admin-Token': t.value } : {}; }\n");
+    let _ = FmtWrite::write_str(&mut response, "    function send(body) { fetch('/api/preview/feedback', { method: 'POST', headers: auth(), body: body }); }\n");
+    let _ = FmtWrite::write_str(&mut response, "    function flag(id) { return id + '=' + (document.getElementById(id).checked ? 1 : 0); }\n");
+    let _ = FmtWrite::write_str(&mut response, "    function saveMotors() {\n");
+    let _ = FmtWrite::write_str(&mut response, "        fetch('/api/actuators', { method: 'POST', headers: auth(), body: [flag('swap'), flag('invert0'), flag('invert1')].join('&') })\n");
+    let _ = FmtWrite::write_str(&mut response, "            .then(r => r.text()).then(alert);\n");
+    let _ = FmtWrite::write_str(&mut response, "    }\n");
+    let _ = FmtWrite::write_str(&mut response, "    function preview(l) { send('both ' + document.getElementById('p' + l).value); }\n");
+    let _ = FmtWrite::write_str(&mut response, "    function save(l) {\n");
+    let _ = FmtWrite::write_str(&mut response, "        fetch('/api/patterns/' + l, { method: 'POST', headers: auth(), body: document.getElementById('p' + l).value })\n");
+    let _ = FmtWrite::write_str(&mut response, "            .then(r => r.text()).then(alert);\n");
+    let _ = FmtWrite::write_str(&mut response, "    }\n");
+    let _ = FmtWrite::write_str(&mut response, "    </script>\n");
+    let _ = FmtWrite::write_str(&mut response, "</body>\n");
+    let _ = FmtWrite::write_str(&mut response, "</html>\n");
+    
+    response
+}
+
+fn generate_http_response(request: &Request<'_>) -> Response {
+    let mut response = String::new();
+    let text = locale::strings();
+    
+    // Get current zones and distances
+    let state = zone_state::latest();
+    
+    let theme = write_page_start(&mut response, request, text, text.status_title, Some(DASHBOARD_REFRESH_S));
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h1>{}</h1>\n", text.status_title));
+    let _ = FmtWrite::write_fmt(&mut response, format_args!(
+        "    <p><a href=\"/patterns\">{}</a> | <a href=\"/tuning\">{}</a></p>\n",
+        text.feedback_settings, text.tuning
+    ));
+    write_theme_links(&mut response, text, theme);
+    
+    // Whose device this is, as far as the owner shares it
+    let shared = owner::shared();
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}", locale::fill(text.device, &[&shared.name])));
+    if let Some(owner) = &shared.owner {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("{}", locale::fill(text.owner, &[owner])));
+    }
+    if let Some(contact) = &shared.contact {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("{}", locale::fill(text.contact, &[contact])));
+    }
+    let _ = FmtWrite::write_str(&mut response, "</p>\n");
+    
+    // Due maintenance, until someone confirms it was done
+    for task in maintenance::due() {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "    <form class=\"sensor warning\" method=\"post\" action=\"/api/maintenance/ack\">{} \
+             <input type=\"hidden\" name=\"task\" value=\"{}\">",
+            locale::fill(text.maintenance_due, &[&task.description()]), task.name()
+        ));
+        if ADMIN_TOKEN.is_some() {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!("<input type=\"password\" name=\"token\" placeholder=\"{}\">", text.admin_token));
+        }
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("<input type=\"submit\" value=\"{}\"></form>\n", text.done));
+    }
+    let (active, names) = profiles::list();
+    if let Some(name) = names.get(active) {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}</p>\n", locale::fill(text.profile, &[name])));
+    }
+    let zone_scale = text.number(state.map_or(1.0, |state| state.zone_scale), 2);
+    let _ = FmtWrite::write_fmt(&mut response, format_args!(
+        "    <p>{}</p>\n", locale::fill(text.motion, &[&crate::imu::motion().name(), &zone_scale])
+    ));
+    let _ = FmtWrite::write_fmt(&mut response, format_args!(
+        "    <p>{}</p>\n", locale::fill(text.mounting, &[&config::get().mounting.mode.name()])
+    ));
+    match pairing::role() {
+        Role::Off => {},
+        role if pairing::linked() => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!(
+                "    <p>{}</p>\n", locale::fill(text.paired_linked, &[&role.name(), &pairing::latency_ms()])
+            ));
+        }
+        role => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!(
+                "    <p class=\"sensor warning\">{}</p>\n", locale::fill(text.paired_unlinked, &[&role.name()])
+            ));
+        }
+    }
+    
+    if usage::safe_mode() {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p class=\"sensor warning\">{}</p>\n", text.safe_mode));
+    }
+    let verdict = health::verdict();
+    if verdict != Status::Ok {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p class=\"sensor warning\">{}", locale::fill(text.health, &[&verdict.name()])));
+        for report in health::reports().iter().filter(|report| report.status != Status::Ok) {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!(", {} {}", report.subsystem.name(), report.status.name()));
+        }
+        let _ = FmtWrite::write_str(&mut response, "</p>\n");
+    }
+    if thermal::hot() {
+        let temperature = text.number(thermal::temperature_c().unwrap_or(0.0), 0);
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "    <p class=\"sensor warning\">{}</p>\n",
+            locale::fill(text.running_hot, &[&temperature, &thermal::duty_percent()])
+        ));
+    }
+    if supply::duty_cap() < u8::MAX {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "    <p class=\"sensor warning\">{}</p>\n",
+            locale::fill(text.supply_sagged, &[&supply::status().sags, &(supply::duty_cap() as u32 * 100 / 255)])
+        ));
+    }
+    match lifecycle::state() {
+        State::Active => {},
+        State::Standby => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}</p>\n", text.standby));
+        }
+        State::Fault => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p class=\"sensor critical\">{}</p>\n", text.fault));
+        }
+        state => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}</p>\n", locale::fill(text.state, &[&state.name()])));
+        }
+    }
+    
+    // Every sensor that is on, slow to update in standby
+    let now_ms = Instant::now().as_millis();
+    match state {
+        Some(state) if state.is_stale(now_ms) && lifecycle::state() != State::Standby => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!(
+                "    <p class=\"sensor critical\">{}</p>\n", locale::fill(text.not_sensing, &[&state.age_ms(now_ms)])
+            ));
+        }
+        Some(state) => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}</p>\n", locale::fill(text.reading_age, &[&state.age_ms(now_ms)])));
+            for (side, sensor) in state.active() {
+                write_sensor(&mut response, text, side, sensor);
+            }
+            write_radar(&mut response, now_ms);
+        }
+        None => {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!("    <p>{}</p>\n", text.waiting));
+        }
+    }
+    
+    // Distances over the last minutes, drawn by history.js in the colors of the key
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h2>{}</h2>\n", text.history));
+    let _ = FmtWrite::write_str(&mut response, "    <svg id=\"history\" class=\"history\" viewBox=\"0 0 300 100\" width=\"450\"></svg>\n");
+    let _ = FmtWrite::write_str(&mut response, "    <p>");
+    for (side, color) in Side::ALL.into_iter().zip(HISTORY_COLORS) {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "<span class=\"history-key-{}\" style=\"color:{}\">&#9632; {}</span> ",
+            side.protocol_name().tag(), color, text.side(side)
+        ));
+    }
+    let _ = FmtWrite::write_str(&mut response, "</p>\n    <script src=\"/history.js\"></script>\n");
+    
+    // Clients seen within the last minute
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h2>{}</h2>\n", text.connected_clients));
+    let _ = FmtWrite::write_str(&mut response, "    <ul>\n");
+    for client in clients::list().iter().filter(|client| client.is_active(now_ms)) {
+        let seen_s = (now_ms - client.last_seen_ms) / 1000;
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "        <li>{}</li>\n",
+            locale::fill(text.client, &[&client.address, &client.port, &client.connections, &seen_s])
+        ));
+    }
+    let _ = FmtWrite::write_str(&mut response, "    </ul>\n");
+    
+    // Recent device events, newest first
+    let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h2>{}</h2>\n", text.recent_events));
+    let _ = FmtWrite::write_str(&mut response, "    <ul>\n");
+    for (timestamp, event) in events::recent().iter().rev() {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("        <li>{}.{:03} s: ", timestamp / 1000, timestamp % 1000));
+        events::describe(&mut response, event);
+        let _ = FmtWrite::write_str(&mut response, "</li>\n");
+    }
+    let _ = FmtWrite::write_str(&mut response, "    </ul>\n");
+    
+    // What the previous session left before it was shut down
+    if let Some(last) = session::last() {
+        let _ = FmtWrite::write_fmt(&mut response, format_args!("    <h2>{}</h2>\n", text.last_session));
+        let stats = &last.stats;
+        let _ = FmtWrite::write_fmt(&mut response, format_args!(
+            "    <p>{}</p>\n",
+            locale::fill(text.session_summary, &[
+                &(last.uptime_s / 60), &last.reason.name(), &stats.critical_alerts, &stats.warnings, &stats.sensor_faults,
+            ])
+        ));
+        let _ = FmtWrite::write_str(&mut response, "    <ul>\n");
+        for event in last.events.iter().rev() {
+            let _ = FmtWrite::write_fmt(&mut response, format_args!(
+                "        <li>{}.{:03} s: {}</li>\n",
+                event.timestamp_ms / 1000, event.timestamp_ms % 1000, event.text
+            ));
+        }
+        let _ = FmtWrite::write_str(&mut response, "    </ul>\n");
+    }
+    
+    let _ = FmtWrite::write_str(&mut response, "</body>\n");
+    let _ = FmtWrite::write_str(&mut response, "</html>\n");
+    
+    response
+}
+
+// Headers, with the theme cookie if the request picked one, and the page's
+// head in the request's theme, up to the opening body tag
+fn write_page_start<const N: usize>(
+    response: &mut String<N>,
+    request: &Request<'_>,
+    text: &Strings,
+    title: &str,
+    refresh_s: Option<u8>,
+) -> Theme {
+    let (theme, pick) = theme::select(request);
+    let max_age = theme::COOKIE_MAX_AGE_S;
+    let content_type = "text/html; charset=utf-8";
+    match pick {
+        Pick::Keep => http::write_headers(response, "200 OK", content_type),
+        Pick::Remember(theme) => http::write_headers_with(response, "200 OK", content_type, format_args!(
+            "Set-Cookie: theme={}; Path=/; Max-Age={}; SameSite=Lax\r\n", theme.name(), max_age
+        )),
+        Pick::Forget => http::write_headers_with(response, "200 OK", content_type, format_args!(
+            "Set-Cookie: theme=; Path=/; Max-Age=0; SameSite=Lax\r\n"
+        )),
+    }
+    
+    let _ = FmtWrite::write_str(response, "<!DOCTYPE html>\n");
+    let _ = FmtWrite::write_fmt(response, format_args!("<html lang=\"{}\">\n", text.code));
+    let _ = FmtWrite::write_str(response, "<head>\n");
+    let _ = FmtWrite::write_fmt(response, format_args!("    <title>{}</title>\n", title));
+    let _ = FmtWrite::write_str(response, "    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    if let Some(refresh_s) = refresh_s {
+        let _ = FmtWrite::write_fmt(response, format_args!("    <meta http-equiv=\"refresh\" content=\"{}\">\n", refresh_s));
+    }
+    let _ = FmtWrite::write_fmt(response, format_args!("    <link rel=\"stylesheet\" href=\"{}\">\n", theme.stylesheet()));
+    let _ = FmtWrite::write_str(response, "</head>\n");
+    let _ = FmtWrite::write_str(response, "<body>\n");
+    theme
+}
+
+// A link per theme, for this browser only, the current one in bold
+fn write_theme_links<const N: usize>(response: &mut String<N>, text: &Strings, current: Theme) {
+    let _ = FmtWrite::write_fmt(response, format_args!("    <p class=\"themes\">{}:", text.theme));
+    for (theme, name) in Theme::ALL.into_iter().zip(text.themes) {
+        let _ = if theme == current {
+            FmtWrite::write_fmt(response, format_args!(" <strong>{}</strong> |", name))
+        } else {
+            FmtWrite::write_fmt(response, format_args!(" <a href=\"?theme={}\">{}</a> |", theme.name(), name))
+        };
+    }
+    let _ = FmtWrite::write_fmt(response, format_args!(" <a href=\"?theme=device\">{}</a></p>\n", text.device_theme));
+}
+
+// A file from the asset store, which browsers may keep for a while
+fn asset_response(asset: &Asset) -> Response {
+    let mut response = String::new();
+    http::write_headers_with(&mut response, "200 OK", asset.content_type, format_args!(
+        "Cache-Control: max-age={}\r\n", assets::MAX_AGE_S
+    ));
+    if response.push_str(asset.body).is_err() {
+        return http::text_response("500 Internal Server Error", "Response too large");
+    }
+    response
+}
+
+// Radar view of the occupancy map: a wedge per sector out to its nearest
+// obstacle, colored like the sensor boxes. Sectors without a fresh reading
+// are only outlined at full range.
+fn write_radar<const N: usize>(response: &mut String<N>, now_ms: u64) {
+    const RANGE_CM: f32 = 300.0;
+    const RADIUS: f32 = 100.0;
+    let _ = FmtWrite::write_str(response, "    <svg class=\"radar\" viewBox=\"-110 -110 220 115\" width=\"330\">\n");
+    for sector in occupancy::map() {
+        // the class lets a theme recolor the wedge
+        let (radius, fill, class) = match sector.fresh(now_ms) {
+            Some(reading) => (RADIUS * reading.distance_cm.min(RANGE_CM) / RANGE_CM, zone_color(reading.zone), zone_class(reading.zone)),
+            None => (RADIUS, "none", "stale"),
+        };
+        // 0 is up, bearings grow clockwise like on a compass
+        let point = |bearing_deg: f32| {
+            let angle = bearing_deg.to_radians();
+            (radius * libm::sinf(angle), -radius * libm::cosf(angle))
+        };
+        let (x1, y1) = point(sector.bearing_deg - occupancy::SECTOR_WIDTH_DEG / 2.0);
+        let (x2, y2) = point(sector.bearing_deg + occupancy::SECTOR_WIDTH_DEG / 2.0);
+        let _ = FmtWrite::write_fmt(response, format_args!(
+            "        <path d=\"M0,0 L{:.1},{:.1} A{:.1},{:.1} 0 0,1 {:.1},{:.1} Z\" fill=\"{}\" class=\"{}\"/>\n",
+            x1, y1, radius, radius, x2, y2, fill, class
+        ));
+    }
+    let _ = FmtWrite::write_str(response, "    </svg>\n");
+}
+
+fn zone_color(zone: Zone) -> &'static str {
+    match zone {
+        Zone::Critical => "#ffcccc",
+        Zone::Warning => "#ffffcc",
+        Zone::Notice | Zone::Clear => "#ccffcc",
+    }
+}
+
+// The stylesheet class for a zone's color
+fn zone_class(zone: Zone) -> &'static str {
+    match zone {
+        Zone::Critical => "critical",
+        Zone::Warning => "warning",
+        Zone::Notice | Zone::Clear => "normal",
+    }
+}
+
+// One sensor box, colored by its zone
+fn write_sensor<const N: usize>(response: &mut String<N>, text: &Strings, channel: Side, side: &SideState) {
+    let _ = FmtWrite::write_fmt(response, format_args!("    <div class=\"sensor {}\">\n", zone_class(side.zone)));
+    let _ = FmtWrite::write_fmt(response, format_args!("        <h2>{}</h2>\n", locale::fill(text.sensor, &[&text.side(channel)])));
+    let units = config::get().units;
+    let (distance, trend) = (Distance::new(side.distance_cm), text.trend(side.trend));
+    let _ = FmtWrite::write_fmt(response, format_args!("        <p>{}</p>\n", locale::fill(text.distance, &[&distance, &trend])));
+    let noise = text.number(units.from_cm(side.noise_cm), 1);
+    let _ = FmtWrite::write_fmt(response, format_args!("        <p>{}</p>\n", locale::fill(text.noise, &[&noise, &units.symbol()])));
+    let _ = FmtWrite::write_str(response, "    </div>\n");
+}
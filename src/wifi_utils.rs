@@ -1,3 +1,4 @@
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use embassy_executor::Spawner;
 use embassy_net::Config;
 use embassy_rp::{
@@ -7,25 +8,101 @@ use embassy_rp::{
     pio::{Pio, InterruptHandler as PioInterruptHandler},
 };
 use static_cell::StaticCell;
+use cyw43::JoinOptions;
 use cyw43_pio::PioSpi;
 use embassy_lab_utils::init_network_stack as lab_init_network_stack;
 use fixed::types::U24F8;
-use defmt::{info, warn};
+use vision_protocol::partition::{self, PartitionError};
+use vision_protocol::zoning::Zone;
+use embassy_futures::select::{Either4, select4};
+use embassy_time::{Duration, Ticker, Timer};
+
+use crate::buffers;
+use crate::config::{self, HapticPattern};
+use crate::defaults;
+use crate::events::{self, Event};
+use crate::health::{self, Status, Subsystem};
+use crate::lifecycle::State;
+use crate::logging::{Scope, info, warn};
+use crate::pairing::{self, Role};
+use crate::partitions;
+use crate::radio;
+use crate::secrets;
+use crate::shutdown;
+use crate::feedback::{self, FeedbackCommand, Pattern, PreviewTarget, Priority};
+use crate::zone_state;
+
+const SCOPE: Scope = Scope::Wifi;
 
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
 });
 
-// CYW43 WiFi firmware
+// CYW43 WiFi firmware, left out of builds that load it from the flash partition
+#[cfg(feature = "cyw43-blobs")]
 pub const FIRMWARE: &[u8] = include_bytes!("../cyw43-firmware/43439A0.bin");
+#[cfg(feature = "cyw43-blobs")]
 pub const CLM: &[u8] = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
 
 // WiFi AP configuration
-pub const AP_SSID: &str = "VisionAssist";
-pub const AP_CHANNEL: u8 = 6; // WiFi channel (1-11)
+pub use crate::defaults::AP_SSID;
+// Used when the scan finds nothing or fails
+const FALLBACK_CHANNEL: u8 = 6;
+
+// Sockets the network stack has room for, by who uses them: the web server
+// listeners, the TCP server and one per server to turn clients away when
+// they are all taken, the broadcasts and the pairing link (guide or
+// follower, never both), the one embassy-net keeps for DNS queries and
+// whatever the board config sets aside
+const TCP_SOCKETS: usize = buffers::POOL_SIZE + cfg!(feature = "web") as usize + cfg!(feature = "tcp") as usize;
+const UDP_SOCKETS: usize = 2;
+const DNS_SOCKETS: usize = 1;
+pub const SOCKET_COUNT: usize = TCP_SOCKETS + UDP_SOCKETS + DNS_SOCKETS + defaults::SPARE_SOCKETS;
+
+// Channel the access point was started on (0 until then) and whether the
+// startup scan picked it
+static AP_CHANNEL: AtomicU8 = AtomicU8::new(0);
+static AP_CHANNEL_SCANNED: AtomicBool = AtomicBool::new(false);
+// A follower is on its guide's network
+static JOINED: AtomicBool = AtomicBool::new(false);
+
+pub fn ap_channel() -> u8 {
+    AP_CHANNEL.load(Ordering::Relaxed)
+}
+
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub fn ap_channel_scanned() -> bool {
+    AP_CHANNEL_SCANNED.load(Ordering::Relaxed)
+}
 
 static STATE: StaticCell<cyw43::State> = StaticCell::new();
 
+#[cfg(feature = "cyw43-blobs")]
+fn embedded_blobs() -> Option<(&'static [u8], &'static [u8])> {
+    Some((FIRMWARE, CLM))
+}
+
+#[cfg(not(feature = "cyw43-blobs"))]
+fn embedded_blobs() -> Option<(&'static [u8], &'static [u8])> {
+    None
+}
+
+// The firmware and CLM written to their flash partition by `visionctl
+// cyw43-image`, or the ones built in when the partition is empty
+fn blobs() -> Result<(&'static [u8], &'static [u8]), &'static str> {
+    // only read through the XIP window, nothing writes there while we run
+    let flash = unsafe { core::slice::from_raw_parts(partitions::CYW43.xip_address() as *const u8, partitions::CYW43.size) };
+    match partition::parse(flash) {
+        Ok(blobs) => {
+            info!("CYW43 firmware loaded from its flash partition");
+            return Ok(blobs);
+        }
+        Err(PartitionError::Empty) => {}
+        Err(e) => warn!("CYW43 firmware partition unusable ({}), trying the built in firmware", e),
+    }
+    embedded_blobs().ok_or("No CYW43 firmware, write it to flash with visionctl cyw43-image")
+}
+
 // Simple init function that returns what we need
 pub async fn init_wifi(
     spawner: &Spawner,
@@ -35,7 +112,8 @@ pub async fn init_wifi(
     pin_29: PIN_29,
     pio0: PIO0,
     dma: DMA_CH2,
-) -> (cyw43::NetDriver<'static>, cyw43::Control<'static>) {
+) -> Result<(cyw43::NetDriver<'static>, cyw43::Control<'static>), &'static str> {
+    let (firmware, clm) = blobs()?;
     let pwr = Output::new(pin_23, Level::Low);
     let cs = Output::new(pin_25, Level::High);
     let mut pio = Pio::new(pio0, Irqs);
@@ -53,25 +131,111 @@ pub async fn init_wifi(
     );
 
     let state = STATE.init(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, FIRMWARE).await;
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, firmware).await;
     spawner.spawn(wifi_task(runner)).unwrap();
 
     // Initialize with CLM firmware
-    control.init(CLM).await;
+    control.init(clm).await;
     control.set_power_management(cyw43::PowerManagementMode::PowerSave).await;
 
-    (net_device, control)
+    Ok((net_device, control))
+}
+
+// Look at the networks around and pick the 2.4 GHz channel (1-11) with the
+// least going on. Every network counts on its own channel and, less, on the
+// neighbours its 20 MHz overlap with; stronger ones count more. Ties go to
+// 1, 6 and 11 since those don't overlap each other.
+async fn least_busy_channel(control: &mut cyw43::Control<'static>) -> u8 {
+    let mut load = [0u32; 11];
+    let mut networks = 0;
+    let mut scanner = control.scan(Default::default()).await;
+    while let Some(bss) = scanner.next().await {
+        let channel = (bss.chanspec & 0xFF) as i32;
+        if !(1..=14).contains(&channel) {
+            continue;
+        }
+        networks += 1;
+        // -100 dBm barely counts, -30 dBm a lot
+        let strength = (bss.rssi as i32 + 100).clamp(1, 70) as u32;
+        for (index, load) in load.iter_mut().enumerate() {
+            let distance = (index as i32 + 1 - channel).unsigned_abs();
+            if distance < 5 {
+                *load += strength * (5 - distance);
+            }
+        }
+    }
+    drop(scanner);
+
+    if networks == 0 {
+        return FALLBACK_CHANNEL;
+    }
+    let channel = [1, 6, 11, 2, 3, 4, 5, 7, 8, 9, 10]
+        .into_iter()
+        .fold(FALLBACK_CHANNEL, |best, channel| {
+            if load[channel as usize - 1] < load[best as usize - 1] { channel } else { best }
+        });
+    info!("Scanned {} networks, channel {} is the least busy", networks, channel);
+    channel
 }
 
 pub async fn start_ap(control: &mut cyw43::Control<'static>) -> Result<(), &'static str> {
+    // Fixed in the config, or whatever is quietest right now. The config is
+    // validated, this only guards against a channel the chip would refuse.
+    let (channel, scanned) = match config::get().wifi.ap_channel {
+        0 => (least_busy_channel(control).await, true),
+        channel @ 1..=11 => (channel, false),
+        channel => {
+            warn!("Channel {} is outside 1-11, picking the least busy one", channel);
+            (least_busy_channel(control).await, true)
+        }
+    };
+    AP_CHANNEL.store(channel, Ordering::Relaxed);
+    AP_CHANNEL_SCANNED.store(scanned, Ordering::Relaxed);
     info!("Starting WiFi Access Point '{}'...", AP_SSID);
     
-    // Start AP mode using the correct API (SSID, channel)
-    control.start_ap_open(AP_SSID, AP_CHANNEL).await;
-    info!("WiFi Access Point '{}' started successfully on channel {}!", AP_SSID, AP_CHANNEL);
+    // WPA2 once a passphrase has been provisioned, open until then
+    match secrets::passphrase() {
+        Some(passphrase) => control.start_ap_wpa2(AP_SSID, &passphrase, channel).await,
+        None => control.start_ap_open(AP_SSID, channel).await,
+    }
+    info!("WiFi Access Point '{}' started successfully on channel {}!", AP_SSID, channel);
     Ok(())
 }
 
+// A follower joins its guide's access point instead of opening its own,
+// with the passphrase both were provisioned with
+async fn join_guide(control: &mut cyw43::Control<'static>) -> Result<(), &'static str> {
+    info!("Joining the guide's WiFi '{}'...", AP_SSID);
+    let passphrase = secrets::passphrase();
+    let options = match &passphrase {
+        Some(passphrase) => JoinOptions::new(passphrase.as_bytes()),
+        None => JoinOptions::new_open(),
+    };
+    control.join(AP_SSID, options).await.map_err(|_| "Guide's WiFi not found or passphrase rejected")?;
+    JOINED.store(true, Ordering::Relaxed);
+    info!("Joined the guide's WiFi '{}'", AP_SSID);
+    Ok(())
+}
+
+// Bring WiFi up the way this unit's pairing role wants it
+async fn start_radio(control: &mut cyw43::Control<'static>) -> Result<(), &'static str> {
+    match pairing::role() {
+        Role::Follower => join_guide(control).await,
+        Role::Off | Role::Guide => start_ap(control).await,
+    }
+}
+
+async fn stop_radio(control: &mut cyw43::Control<'static>) {
+    if pairing::role() == Role::Follower {
+        control.leave().await;
+        JOINED.store(false, Ordering::Relaxed);
+        info!("Left the guide's WiFi");
+    } else {
+        control.close_ap().await;
+        info!("Access Point closed");
+    }
+}
+
 pub async fn init_network_stack(
     spawner: &Spawner,
     pin_23: PIN_23,
@@ -80,42 +244,178 @@ pub async fn init_network_stack(
     pin_29: PIN_29,
     pio0: PIO0,
     dma: DMA_CH2,
-) -> (&'static embassy_net::Stack<'static>, embassy_net::tcp::TcpSocket<'static>) {
+) -> Option<&'static embassy_net::Stack<'static>> {
     // Initialize WiFi
-    let (net_device, mut control) = init_wifi(spawner, pin_23, pin_24, pin_25, pin_29, pio0, dma).await;
+    let (net_device, mut control) = match init_wifi(spawner, pin_23, pin_24, pin_25, pin_29, pio0, dma).await {
+        Ok(wifi) => wifi,
+        Err(e) => {
+            warn!("WiFi unavailable: {}", e);
+            health::error(Subsystem::Wifi);
+            health::set_status(Subsystem::Wifi, Status::Fault);
+            return None;
+        }
+    };
     
-    // Start AP mode
-    match start_ap(&mut control).await {
-        Ok(_) => info!("Access Point started successfully"),
-        Err(e) => warn!("Failed to start Access Point: {}", e),
+    // Start AP mode (or join the guide's as a follower), unless the radio was switched off
+    if radio::enabled() {
+        match start_radio(&mut control).await {
+            Ok(_) => info!("WiFi started successfully"),
+            Err(e) => {
+                warn!("Failed to start WiFi: {}", e);
+                health::error(Subsystem::Wifi);
+                health::set_status(Subsystem::Wifi, Status::Fault);
+            }
+        }
+    } else {
+        control.set_power_management(cyw43::PowerManagementMode::SuperSave).await;
+        info!("WiFi switched off, Access Point not started");
     }
     
-    // Configure network stack with static IP for AP mode
+    // Configure network stack with static IP for AP mode, a follower takes
+    // the next address on its guide's network
+    let (address, shown) = match pairing::role() {
+        Role::Follower => (pairing::FOLLOWER_ADDRESS, "192.168.4.2"),
+        Role::Off | Role::Guide => (embassy_net::Ipv4Address::new(192, 168, 4, 1), "192.168.4.1"),
+    };
     let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: embassy_net::Ipv4Cidr::new(embassy_net::Ipv4Address::new(192, 168, 4, 1), 24),
+        address: embassy_net::Ipv4Cidr::new(address, 24),
         gateway: None,
         dns_servers: heapless::Vec::new(),
     });
 
     // Use the lab utils to initialize the network stack
-    static STACK_RESOURCES: StaticCell<embassy_net::StackResources<4>> = StaticCell::new();
+    static STACK_RESOURCES: StaticCell<embassy_net::StackResources<SOCKET_COUNT>> = StaticCell::new();
     static STACK: StaticCell<embassy_net::Stack<'static>> = StaticCell::new();
     
     let stack_instance = lab_init_network_stack(spawner, net_device, &STACK_RESOURCES, config);
     let stack = STACK.init(stack_instance);
 
-    // Create TCP socket with buffers
-    static RX_BUFFER: StaticCell<[u8; 1024]> = StaticCell::new();
-    static TX_BUFFER: StaticCell<[u8; 1024]> = StaticCell::new();
-    let rx_buffer = RX_BUFFER.init([0; 1024]);
-    let tx_buffer = TX_BUFFER.init([0; 1024]);
-    let socket = embassy_net::tcp::TcpSocket::new(*stack, rx_buffer, tx_buffer);
+    info!("Network stack initialized with IP: {}", shown);
+    info!(
+        "Network sockets: {} TCP, {} UDP, {} DNS, {} spare, {} in all",
+        TCP_SOCKETS, UDP_SOCKETS, DNS_SOCKETS, defaults::SPARE_SOCKETS, SOCKET_COUNT
+    );
+    info!("Connect to WiFi network '{}' and browse to http://{}", AP_SSID, shown);
+    info!("TCP server available on {}:8080", shown);
+
+    // The onboard LED hangs off the WiFi chip, so the control handle goes to the LED task
+    spawner.spawn(status_led_task(control)).unwrap();
+    spawner.spawn(link_monitor_task(stack)).unwrap();
+
+    Some(stack)
+}
+
+// Turns WiFi link changes into events, with an optional beep cue so the
+// user notices the companion app can't reach the device anymore
+#[embassy_executor::task]
+async fn link_monitor_task(stack: &'static embassy_net::Stack<'static>) -> ! {
+    loop {
+        let up = if stack.is_link_up() {
+            stack.wait_link_down().await;
+            false
+        } else {
+            stack.wait_link_up().await;
+            true
+        };
+
+        events::publish(Event::WifiLink { up });
+        // switched off on purpose isn't a problem
+        if up || !radio::enabled() {
+            health::set_status(Subsystem::Wifi, Status::Ok);
+        } else {
+            health::error(Subsystem::Wifi);
+            health::set_status(Subsystem::Wifi, Status::Degraded);
+        }
+        // switching the radio off has its own confirmation
+        if config::get().wifi.link_cues && radio::enabled() {
+            // short-long when the link comes back, long-short when it is lost
+            let steps = if up { [60, 80, 150] } else { [150, 80, 60] };
+            feedback::submit(FeedbackCommand {
+                pattern: Pattern::Preview { steps: HapticPattern::new(&steps), target: PreviewTarget::Buzzer },
+                side: None,
+                priority: Priority::Normal,
+                ttl_ms: 1000,
+            });
+        }
+    }
+}
 
-    info!("Network stack initialized with IP: 192.168.4.1");
-    info!("Connect to WiFi network '{}' and browse to http://192.168.4.1", AP_SSID);
-    info!("TCP server available on 192.168.4.1:8080");
+// Drives the onboard LED: solid while any channel is in the critical zone, a
+// short blink when a client connects, three when the WiFi link drops, a
+// second long one when the device enters its fault state and a brief flash
+// every few seconds while WiFi is switched off. Also switches
+// the radio, since the LED hangs off the same chip, and keeps a follower
+// trying to join its guide's network.
+#[embassy_executor::task]
+async fn status_led_task(mut control: cyw43::Control<'static>) -> ! {
+    let mut zones = zone_state::receiver();
+    let mut subscriber = events::subscribe();
+    let mut radio_off_blink = Ticker::every(Duration::from_secs(3));
+    let mut critical = false;
 
-    (stack, socket)
+    loop {
+        match select4(zones.changed(), events::next(&mut subscriber), radio::switched(), radio_off_blink.next()).await {
+            Either4::First(state) => {
+                let now_critical = state.active().any(|(_, side)| side.zone == Zone::Critical);
+                if now_critical != critical {
+                    critical = now_critical;
+                    control.gpio_set(0, critical).await;
+                }
+            },
+            Either4::Second(Event::ClientConnected { .. }) => {
+                control.gpio_set(0, !critical).await;
+                Timer::after_millis(100).await;
+                control.gpio_set(0, critical).await;
+            },
+            Either4::Second(Event::WifiLink { up: false }) => {
+                // a follower that dropped off its guide's network joins again
+                JOINED.store(false, Ordering::Relaxed);
+                for _ in 0..3 {
+                    control.gpio_set(0, !critical).await;
+                    Timer::after_millis(100).await;
+                    control.gpio_set(0, critical).await;
+                    Timer::after_millis(100).await;
+                }
+            },
+            Either4::Second(Event::Lifecycle { state: State::Fault }) => {
+                control.gpio_set(0, !critical).await;
+                Timer::after_millis(1000).await;
+                control.gpio_set(0, critical).await;
+            },
+            Either4::Second(_) => {},
+            Either4::Third(true) => {
+                control.set_power_management(cyw43::PowerManagementMode::PowerSave).await;
+                match start_radio(&mut control).await {
+                    Ok(_) => health::set_status(Subsystem::Wifi, Status::Ok),
+                    Err(e) => {
+                        warn!("Failed to start WiFi: {}", e);
+                        health::error(Subsystem::Wifi);
+                        health::set_status(Subsystem::Wifi, Status::Fault);
+                    }
+                }
+            },
+            Either4::Third(false) => {
+                stop_radio(&mut control).await;
+                control.set_power_management(cyw43::PowerManagementMode::SuperSave).await;
+                // nothing is sensed anymore after a shutdown, the LED would be stuck
+                if shutdown::requested() {
+                    control.gpio_set(0, false).await;
+                }
+                radio::closed();
+            },
+            Either4::Fourth(()) => {
+                if !radio::enabled() {
+                    control.gpio_set(0, !critical).await;
+                    Timer::after_millis(50).await;
+                    control.gpio_set(0, critical).await;
+                } else if pairing::role() == Role::Follower && !JOINED.load(Ordering::Relaxed) {
+                    if let Err(e) = join_guide(&mut control).await {
+                        warn!("Failed to join the guide's WiFi: {}", e);
+                    }
+                }
+            },
+        }
+    }
 }
 
 #[embassy_executor::task]
@@ -0,0 +1,135 @@
+//! Latest processed zone state of every channel, shared through a `Watch`.
+//!
+//! The sensing loop publishes a [`ZoneState`] every cycle. Feedback, the
+//! status LED, a guide's pairing link and the flash writer wait for changes
+//! with a [`receiver`], the servers just read the [`latest`] one. Everybody sees the
+//! same debounced zones rather than working them out again from raw
+//! distances.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use vision_protocol::zoning::Zone;
+
+use crate::channels;
+use crate::events::Side;
+
+// Whether an obstacle is getting closer
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Trend {
+    Approaching,
+    Steady,
+    Receding,
+}
+
+impl Trend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Trend::Approaching => "approaching",
+            Trend::Steady => "steady",
+            Trend::Receding => "receding",
+        }
+    }
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub struct SideState {
+    pub zone: Zone,
+    pub distance_cm: f32,
+    pub noise_cm: f32,
+    pub trend: Trend,
+    // When this channel's reading was taken
+    pub timestamp_ms: u64,
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub struct ZoneState {
+    // Per channel in Side::ALL order, None for a channel that is off
+    pub channels: [Option<SideState>; channels::COUNT],
+    // Factor the zone distances are scaled by for the user's motion
+    pub zone_scale: f32,
+    pub timestamp_ms: u64,
+}
+
+// A state older than this means the sensing loop has stopped
+pub const STALE_MS: u64 = vision_protocol::STALE_AFTER_MS as u64;
+
+impl ZoneState {
+    pub fn side(&self, side: Side) -> Option<&SideState> {
+        self.channels[side as usize].as_ref()
+    }
+
+    // The channels that are on, with their state
+    pub fn active(&self) -> impl Iterator<Item = (Side, &SideState)> {
+        Side::ALL.into_iter().zip(self.channels.iter()).filter_map(|(side, state)| Some((side, state.as_ref()?)))
+    }
+
+    pub fn age_ms(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.timestamp_ms)
+    }
+
+    pub fn is_stale(&self, now_ms: u64) -> bool {
+        self.age_ms(now_ms) > STALE_MS
+    }
+}
+
+// Feedback, the status LED, the pairing link and the flash writer
+const MAX_RECEIVERS: usize = 4;
+
+pub type ZoneReceiver = Receiver<'static, CriticalSectionRawMutex, ZoneState, MAX_RECEIVERS>;
+
+static ZONE_STATE: Watch<CriticalSectionRawMutex, ZoneState, MAX_RECEIVERS> = Watch::new();
+
+pub fn publish(state: ZoneState) {
+    ZONE_STATE.sender().send(state);
+}
+
+// Panics when more than MAX_RECEIVERS tasks ask for one, raise the limit then
+pub fn receiver() -> ZoneReceiver {
+    ZONE_STATE.receiver().unwrap()
+}
+
+// Most recent state, None until the first sensing cycle is done
+pub fn latest() -> Option<ZoneState> {
+    ZONE_STATE.anon_receiver().try_get()
+}
+
+// Closing or opening faster than this (cm/s) counts as a trend
+const TREND_CM_PER_S: f32 = 10.0;
+// How quickly the speed estimate follows changes
+const SPEED_SMOOTHING: f32 = 0.3;
+
+// Works out the trend of one channel from consecutive distances
+pub struct TrendTracker {
+    last: Option<(f32, u64)>,
+    speed_cm_per_s: f32,
+}
+
+impl TrendTracker {
+    pub const fn new() -> Self {
+        Self { last: None, speed_cm_per_s: 0.0 }
+    }
+
+    // Positive while the obstacle gets farther away
+    pub fn speed_cm_per_s(&self) -> f32 {
+        self.speed_cm_per_s
+    }
+
+    pub fn update(&mut self, distance_cm: f32, now_ms: u64) -> Trend {
+        if let Some((last_cm, last_ms)) = self.last {
+            let elapsed_s = now_ms.saturating_sub(last_ms) as f32 / 1000.0;
+            if elapsed_s > 0.0 {
+                let speed = (distance_cm - last_cm) / elapsed_s;
+                self.speed_cm_per_s += SPEED_SMOOTHING * (speed - self.speed_cm_per_s);
+            }
+        }
+        self.last = Some((distance_cm, now_ms));
+
+        if self.speed_cm_per_s < -TREND_CM_PER_S {
+            Trend::Approaching
+        } else if self.speed_cm_per_s > TREND_CM_PER_S {
+            Trend::Receding
+        } else {
+            Trend::Steady
+        }
+    }
+}
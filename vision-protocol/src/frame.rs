@@ -0,0 +1,250 @@
+//! Binary frames around postcard-encoded messages.
+//!
+//! Every frame starts with a fixed header so a reader on a flaky link can
+//! find the next frame after garbage and tell a damaged one from a good one:
+//!
+//! ```text
+//! magic "VA" | version | payload length (u16 LE) | sequence (u16 LE) | CRC16 (u16 LE) | payload
+//! ```
+//!
+//! The CRC (CRC-16/CCITT-FALSE) covers the header up to the CRC and the
+//! payload. Senders count the sequence up by one per frame, wrapping at
+//! 65535, so receivers can also spot frames that went missing.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::PROTOCOL_VERSION;
+
+pub const MAGIC: [u8; 2] = *b"VA";
+pub const HEADER_LEN: usize = 9;
+// Keeps a garbled length from making a reader wait for megabytes
+pub const MAX_PAYLOAD_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    // Not enough bytes yet for a whole frame, read more and try again
+    Truncated,
+    // No frame starts here, skip to the next magic (see `resync`)
+    BadMagic,
+    // A frame from another protocol version
+    Version(u8),
+    // The length is over MAX_PAYLOAD_LEN, or doesn't fit the output buffer
+    TooLong,
+    // The frame was damaged on the way
+    Crc,
+    // The payload isn't the expected message
+    Payload,
+}
+
+// A decoded frame, with the message still as bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFrame<'a> {
+    pub seq: u16,
+    pub payload: &'a [u8],
+    // Bytes the whole frame took up, where the next one starts
+    pub len: usize,
+}
+
+// Write a frame around an already encoded payload, returns the frame
+pub fn encode_raw<'a>(seq: u16, payload: &[u8], out: &'a mut [u8]) -> Result<&'a [u8], FrameError> {
+    let len = HEADER_LEN + payload.len();
+    if payload.len() > MAX_PAYLOAD_LEN || len > out.len() {
+        return Err(FrameError::TooLong);
+    }
+    out[HEADER_LEN..len].copy_from_slice(payload);
+    finish(seq, payload.len(), out)
+}
+
+// Serialize a message with postcard and frame it
+pub fn encode<'a, T: Serialize>(seq: u16, message: &T, out: &'a mut [u8]) -> Result<&'a [u8], FrameError> {
+    let end = out.len().min(HEADER_LEN + MAX_PAYLOAD_LEN);
+    let payload = out.get_mut(HEADER_LEN..end).ok_or(FrameError::TooLong)?;
+    let payload_len = postcard::to_slice(message, payload).map_err(|_| FrameError::TooLong)?.len();
+    finish(seq, payload_len, out)
+}
+
+fn finish(seq: u16, payload_len: usize, out: &mut [u8]) -> Result<&[u8], FrameError> {
+    out[..2].copy_from_slice(&MAGIC);
+    out[2] = PROTOCOL_VERSION;
+    out[3..5].copy_from_slice(&(payload_len as u16).to_le_bytes());
+    out[5..7].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc16(&out[..7], &out[HEADER_LEN..HEADER_LEN + payload_len]);
+    out[7..9].copy_from_slice(&crc.to_le_bytes());
+    Ok(&out[..HEADER_LEN + payload_len])
+}
+
+// Check the frame at the start of `bytes`. The payload is only looked at
+// once the CRC matched.
+pub fn decode_raw(bytes: &[u8]) -> Result<RawFrame<'_>, FrameError> {
+    if bytes.len() < MAGIC.len() {
+        return Err(FrameError::Truncated);
+    }
+    if bytes[..2] != MAGIC {
+        return Err(FrameError::BadMagic);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(FrameError::Truncated);
+    }
+
+    let payload_len = u16::from_le_bytes([bytes[3], bytes[4]]) as usize;
+    if payload_len > MAX_PAYLOAD_LEN {
+        return Err(FrameError::TooLong);
+    }
+    let len = HEADER_LEN + payload_len;
+    if bytes.len() < len {
+        return Err(FrameError::Truncated);
+    }
+
+    let payload = &bytes[HEADER_LEN..len];
+    if u16::from_le_bytes([bytes[7], bytes[8]]) != crc16(&bytes[..7], payload) {
+        return Err(FrameError::Crc);
+    }
+    // after the CRC, so a flipped bit in the version reads as damage
+    if bytes[2] != PROTOCOL_VERSION {
+        return Err(FrameError::Version(bytes[2]));
+    }
+    Ok(RawFrame { seq: u16::from_le_bytes([bytes[5], bytes[6]]), payload, len })
+}
+
+// Decode the frame at the start of `bytes` into a message, with its
+// sequence number and the frame's length
+pub fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<(u16, T, usize), FrameError> {
+    let frame = decode_raw(bytes)?;
+    let message = postcard::from_bytes(frame.payload).map_err(|_| FrameError::Payload)?;
+    Ok((frame.seq, message, frame.len))
+}
+
+// How many bytes to drop after a bad frame to get to the next possible
+// start. Skips at least one byte so a damaged header can't stall a reader;
+// keeps a trailing 'V' that may be the first half of the next magic.
+pub fn resync(bytes: &[u8]) -> usize {
+    (1..bytes.len())
+        .find(|&start| bytes[start..].starts_with(&MAGIC) || bytes[start..] == MAGIC[..1])
+        .unwrap_or(bytes.len())
+}
+
+// Room a frame of this message type needs at most
+pub const fn max_frame_len<T: MaxSize>() -> usize {
+    HEADER_LEN + T::POSTCARD_MAX_SIZE
+}
+
+// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF) over two slices, bit by bit
+// since frames are small
+fn crc16(header: &[u8], payload: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in header.iter().chain(payload) {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(seq: u16, payload: &[u8]) -> ([u8; HEADER_LEN + 16], usize) {
+        let mut out = [0; HEADER_LEN + 16];
+        let len = encode_raw(seq, payload, &mut out).unwrap().len();
+        (out, len)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (out, len) = frame(0xBEEF, b"payload");
+        assert_eq!(len, HEADER_LEN + 7);
+        assert_eq!(decode_raw(&out[..len]), Ok(RawFrame { seq: 0xBEEF, payload: b"payload", len }));
+
+        // trailing bytes belong to the next frame
+        assert_eq!(decode_raw(&out).unwrap().len, len);
+
+        let (out, len) = frame(0, &[]);
+        assert_eq!(decode_raw(&out[..len]), Ok(RawFrame { seq: 0, payload: &[], len: HEADER_LEN }));
+    }
+
+    #[test]
+    fn typed_round_trip() {
+        let mut out = [0; 32];
+        let len = encode(7, &(1234u16, -5i8, true), &mut out).unwrap().len();
+        assert_eq!(decode::<(u16, i8, bool)>(&out[..len]), Ok((7, (1234, -5, true), len)));
+        assert_eq!(decode::<(u16, i8, bool)>(&out[..len - 1]), Err(FrameError::Truncated));
+    }
+
+    #[test]
+    fn truncated_at_every_length() {
+        let (out, len) = frame(1, b"abc");
+        for cut in 0..len {
+            assert_eq!(decode_raw(&out[..cut]), Err(FrameError::Truncated), "cut at {cut}");
+        }
+    }
+
+    #[test]
+    fn crc_catches_every_single_bit_flip() {
+        let (out, len) = frame(42, b"VA\x00\xff");
+        // the length field is left out, flipping it makes a frame truncated or too long
+        for byte in (0..len).filter(|&byte| !(0..2).contains(&byte) && !(3..5).contains(&byte)) {
+            for bit in 0..8 {
+                let mut damaged = out;
+                damaged[byte] ^= 1 << bit;
+                assert_eq!(decode_raw(&damaged[..len]), Err(FrameError::Crc), "byte {byte} bit {bit}");
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_magic() {
+        let (mut out, len) = frame(1, b"abc");
+        out[1] = b'B';
+        assert_eq!(decode_raw(&out[..len]), Err(FrameError::BadMagic));
+        assert_eq!(decode_raw(b"xy"), Err(FrameError::BadMagic));
+    }
+
+    #[test]
+    fn other_version() {
+        let (mut out, len) = frame(1, b"abc");
+        out[2] = PROTOCOL_VERSION + 1;
+        let crc = crc16(&out[..7], &out[HEADER_LEN..len]);
+        out[7..9].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(decode_raw(&out[..len]), Err(FrameError::Version(PROTOCOL_VERSION + 1)));
+    }
+
+    #[test]
+    fn too_long() {
+        let mut out = [0; HEADER_LEN + MAX_PAYLOAD_LEN + 1];
+        assert_eq!(encode_raw(0, &[0; MAX_PAYLOAD_LEN + 1], &mut out), Err(FrameError::TooLong));
+        assert_eq!(encode_raw(0, b"abc", &mut out[..HEADER_LEN + 2]), Err(FrameError::TooLong));
+
+        let (mut out, len) = frame(1, b"abc");
+        out[3..5].copy_from_slice(&(MAX_PAYLOAD_LEN as u16 + 1).to_le_bytes());
+        assert_eq!(decode_raw(&out[..len]), Err(FrameError::TooLong));
+    }
+
+    #[test]
+    fn resync_finds_the_next_magic() {
+        assert_eq!(resync(b"xxVAyy"), 2);
+        // a frame starting right at the front was bad, so it's skipped
+        assert_eq!(resync(b"VAxxVA"), 4);
+        assert_eq!(resync(b"VVA"), 1);
+        // a trailing 'V' may be the start of the next frame
+        assert_eq!(resync(b"xyzV"), 3);
+        assert_eq!(resync(b"xyz"), 3);
+        assert_eq!(resync(b"V"), 1);
+        assert_eq!(resync(b""), 0);
+
+        // skipping to a good frame after garbage
+        let (out, len) = frame(9, b"abc");
+        let mut stream = [0; 4 + HEADER_LEN + 16];
+        stream[..4].copy_from_slice(b"\x00VV\x01");
+        stream[4..4 + len].copy_from_slice(&out[..len]);
+        let mut start = 0;
+        while decode_raw(&stream[start..]).is_err() {
+            start += resync(&stream[start..]);
+        }
+        assert_eq!(start, 4);
+        assert_eq!(decode_raw(&stream[start..]).unwrap().seq, 9);
+    }
+}
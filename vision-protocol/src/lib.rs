@@ -0,0 +1,1125 @@
+//! Messages exchanged between a VisionAssist device and host tools.
+//!
+//! The firmware and `visionctl` both build on these types, so the two ends
+//! can't disagree about what a command or a telemetry sample looks like. The
+//! types derive serde (and postcard's `MaxSize` where they have a fixed size);
+//! the text forms below are what the TCP line protocol carries today. A
+//! binary transport wraps the postcard encoding in [`frame`]s. The layout of
+//! the WiFi firmware's flash [`partition`] is shared with the tool that
+//! writes it, and the proximity [`zoning`] with the host tests.
+
+#![no_std]
+
+pub mod frame;
+pub mod partition;
+pub mod zoning;
+
+use core::fmt;
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+// Bump whenever a message changes shape
+pub const PROTOCOL_VERSION: u8 = 23;
+// Bump only when old clients can't work with the device at all anymore, a
+// HELLO with another major version is refused
+pub const PROTOCOL_MAJOR: u8 = 1;
+
+// Port the device's TCP server listens on
+pub const TCP_PORT: u16 = 8080;
+
+// UDP port the device broadcasts assist requests and forwarded alerts to, on
+// the access point's subnet
+pub const BROADCAST_UDP_PORT: u16 = 8081;
+
+// Telemetry older than this means the device stopped sensing
+pub const STALE_AFTER_MS: u32 = 500;
+
+// STREAM rate limits, in samples per second
+pub const DEFAULT_STREAM_HZ: u8 = 10;
+pub const MAX_STREAM_HZ: u8 = 20;
+// Most samples a STREAM puts in one write, a second's worth at the top rate
+pub const MAX_STREAM_BATCH: u8 = 20;
+
+// Longest a SIMULATE may override the sensors
+pub const MAX_SIMULATE_MS: u32 = 60_000;
+
+// How often a BENCHTEST reports the supply voltage
+pub const BENCH_SAMPLE_MS: u32 = 100;
+
+// Most echo pin edges an ECHO line carries
+pub const MAX_ECHO_EDGES: usize = 8;
+
+// Distance channels a device can have, one per SideName
+pub const CHANNELS: usize = SideName::ALL.len();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ZoneName {
+    Critical,
+    Warning,
+    Notice,
+}
+
+impl ZoneName {
+    pub fn name(self) -> &'static str {
+        match self {
+            ZoneName::Critical => "critical",
+            ZoneName::Warning => "warning",
+            ZoneName::Notice => "notice",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        [ZoneName::Critical, ZoneName::Warning, ZoneName::Notice]
+            .into_iter()
+            .find(|zone| text.eq_ignore_ascii_case(zone.name()))
+    }
+}
+
+// Requests a client can send, one per connection on TCP (an AUTH keeps the
+// connection open for the command it unlocks)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command<'a> {
+    Status,
+    // Leaving out exit_cm keeps the zone's current hysteresis gap
+    SetThreshold { zone: ZoneName, enter_cm: f32, exit_cm: Option<f32> },
+    // Samples are written `batch` at a time, or whatever has been collected
+    // after `flush_ms` when that comes first
+    Stream { hz: u8, batch: u8, flush_ms: Option<u32> },
+    // Whole device configuration as one line of JSON
+    ExportConfig,
+    ImportConfig { json: &'a str },
+    // Replace the readings of the given channels for a while, for testing.
+    // Needs the device's QA token.
+    // Distances per channel in SideName::ALL order, None keeps the real reading
+    Simulate { distances_cm: [Option<f32>; CHANNELS], duration_ms: u32, token: &'a str },
+    // Switch WiFi on or off, kept across reboots. Once off only the button
+    // brings it back.
+    Radio { on: bool },
+    // Handshake, the device answers with a `Welcome`. Firmware from before
+    // the handshake answers with a status line instead.
+    Hello { version: Version, capabilities: Capabilities },
+    // Raise this session to the admin role with the device's admin token
+    Auth { token: &'a str },
+    // Save the session summary and power down until the next power cycle
+    Shutdown,
+    // Save the session summary and restart. In safe mode the device starts
+    // with the default settings and without its optional subsystems, until
+    // the next restart.
+    Reboot { safe: bool },
+    // Start or stop the locator tone, a button press stops it too
+    Locate { on: bool },
+    // With ON/OFF, start or stop recording the echo pin of every ping;
+    // without, download the pings recorded so far as ECHO lines
+    Echoes { capture: Option<bool> },
+    // Make something fail on purpose, only in builds with fault injection
+    Fault { fault: Fault },
+    // Run the actuator exercise for checking a newly assembled unit, with a
+    // BENCH line of the supply voltage every `BENCH_SAMPLE_MS`
+    BenchTest,
+}
+
+// Failures FAULT can inject, to exercise the recovery paths on hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fault {
+    // The next `count` sensing cycles of a channel, or of every channel, time out
+    SensorTimeout { side: Option<SideName>, count: u16 },
+    // The next `count` reads or writes on TCP connections fail
+    SocketError { count: u16 },
+    // The next `count` flash sector writes fail
+    FlashWrite { count: u16 },
+    // Block the firmware until the watchdog restarts the device
+    Hang,
+    // Forget whatever hasn't failed yet
+    Clear,
+}
+
+// What a session may do. Every session starts as a viewer, which can watch
+// but not change anything; AUTH with the admin token makes it an admin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Role {
+    Viewer,
+    Admin,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    // Not a command we know, the device answers these with a status line
+    UnknownCommand,
+    // A known command with bad arguments
+    Invalid(&'static str),
+}
+
+impl<'a> Command<'a> {
+    // Parse one line of the text protocol, command words are case-insensitive
+    pub fn parse(line: &'a str) -> Result<Self, ParseError> {
+        let mut words = line.split_ascii_whitespace();
+        let command = words.next().unwrap_or("");
+
+        if command.eq_ignore_ascii_case("STATUS") {
+            Ok(Command::Status)
+        } else if command.eq_ignore_ascii_case("SET") {
+            let usage = ParseError::Invalid("expected SET <zone> <enter_cm> [exit_cm]");
+            let zone = ZoneName::parse(words.next().unwrap_or(""))
+                .ok_or(ParseError::Invalid("unknown zone"))?;
+            // "nan" and "inf" parse as floats but are no distance
+            let distance = |value: &str| value.parse::<f32>().ok().filter(|cm| cm.is_finite());
+            let enter_cm = words.next().and_then(distance).ok_or(usage)?;
+            let exit_cm = match words.next().map(distance) {
+                Some(Some(exit_cm)) => Some(exit_cm),
+                Some(None) => return Err(usage),
+                None => None,
+            };
+            Ok(Command::SetThreshold { zone, enter_cm, exit_cm })
+        } else if command.eq_ignore_ascii_case("STREAM") {
+            parse_stream(words)
+        } else if command.eq_ignore_ascii_case("EXPORT") {
+            Ok(Command::ExportConfig)
+        } else if command.eq_ignore_ascii_case("IMPORT") {
+            let json = line.trim_start()[command.len()..].trim();
+            if json.is_empty() {
+                return Err(ParseError::Invalid("expected IMPORT <json>"));
+            }
+            Ok(Command::ImportConfig { json })
+        } else if command.eq_ignore_ascii_case("SIMULATE") {
+            parse_simulate(words)
+        } else if command.eq_ignore_ascii_case("HELLO") {
+            let usage = ParseError::Invalid("expected HELLO <major>.<minor> [CAPS=<name>,...]");
+            let version = words.next().and_then(Version::parse).ok_or(usage)?;
+            let capabilities = match words.next() {
+                Some(caps) => Capabilities::parse(caps).ok_or(usage)?,
+                None => Capabilities::NONE,
+            };
+            Ok(Command::Hello { version, capabilities })
+        } else if command.eq_ignore_ascii_case("RADIO") {
+            match words.next() {
+                Some(state) if state.eq_ignore_ascii_case("ON") => Ok(Command::Radio { on: true }),
+                Some(state) if state.eq_ignore_ascii_case("OFF") => Ok(Command::Radio { on: false }),
+                _ => Err(ParseError::Invalid("expected RADIO ON|OFF")),
+            }
+        } else if command.eq_ignore_ascii_case("SHUTDOWN") {
+            Ok(Command::Shutdown)
+        } else if command.eq_ignore_ascii_case("REBOOT") {
+            match words.next() {
+                None => Ok(Command::Reboot { safe: false }),
+                Some(mode) if mode.eq_ignore_ascii_case("SAFE") => Ok(Command::Reboot { safe: true }),
+                _ => Err(ParseError::Invalid("expected REBOOT [SAFE]")),
+            }
+        } else if command.eq_ignore_ascii_case("LOCATE") {
+            match words.next() {
+                None => Ok(Command::Locate { on: true }),
+                Some(state) if state.eq_ignore_ascii_case("ON") => Ok(Command::Locate { on: true }),
+                Some(state) if state.eq_ignore_ascii_case("OFF") => Ok(Command::Locate { on: false }),
+                _ => Err(ParseError::Invalid("expected LOCATE [ON|OFF]")),
+            }
+        } else if command.eq_ignore_ascii_case("ECHOES") {
+            match words.next() {
+                None => Ok(Command::Echoes { capture: None }),
+                Some(state) if state.eq_ignore_ascii_case("ON") => Ok(Command::Echoes { capture: Some(true) }),
+                Some(state) if state.eq_ignore_ascii_case("OFF") => Ok(Command::Echoes { capture: Some(false) }),
+                _ => Err(ParseError::Invalid("expected ECHOES [ON|OFF]")),
+            }
+        } else if command.eq_ignore_ascii_case("FAULT") {
+            parse_fault(words)
+        } else if command.eq_ignore_ascii_case("BENCHTEST") {
+            Ok(Command::BenchTest)
+        } else if command.eq_ignore_ascii_case("AUTH") {
+            match (words.next(), words.next()) {
+                (Some(token), None) => Ok(Command::Auth { token }),
+                _ => Err(ParseError::Invalid("expected AUTH <token>")),
+            }
+        } else {
+            Err(ParseError::UnknownCommand)
+        }
+    }
+
+    // Role a session needs to run this command
+    pub fn required_role(&self) -> Role {
+        match self {
+            Command::Status
+            | Command::Stream { .. }
+            | Command::ExportConfig
+            | Command::Hello { .. }
+            | Command::Auth { .. }
+            | Command::Echoes { capture: None } => Role::Viewer,
+            Command::SetThreshold { .. }
+            | Command::ImportConfig { .. }
+            | Command::Simulate { .. }
+            | Command::Radio { .. }
+            | Command::Shutdown
+            | Command::Reboot { .. }
+            | Command::Locate { .. }
+            | Command::Echoes { capture: Some(_) }
+            | Command::Fault { .. }
+            | Command::BenchTest => Role::Admin,
+        }
+    }
+}
+
+// The line a client sends, without the trailing newline
+impl fmt::Display for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Status => write!(f, "STATUS"),
+            Command::SetThreshold { zone, enter_cm, exit_cm } => {
+                write!(f, "SET {} {}", zone.name(), enter_cm)?;
+                if let Some(exit_cm) = exit_cm {
+                    write!(f, " {}", exit_cm)?;
+                }
+                Ok(())
+            }
+            Command::Stream { hz, batch, flush_ms } => {
+                write!(f, "STREAM {}", hz)?;
+                if *batch > 1 {
+                    write!(f, " BATCH={}", batch)?;
+                }
+                if let Some(flush_ms) = flush_ms {
+                    write!(f, " FLUSH={}", flush_ms)?;
+                }
+                Ok(())
+            }
+            Command::ExportConfig => write!(f, "EXPORT"),
+            Command::ImportConfig { json } => write!(f, "IMPORT {}", json),
+            Command::Simulate { distances_cm, duration_ms, token } => {
+                write!(f, "SIMULATE")?;
+                for (side, distance_cm) in SideName::ALL.into_iter().zip(distances_cm) {
+                    if let Some(distance_cm) = distance_cm {
+                        write!(f, " {}={}", side.tag(), distance_cm)?;
+                    }
+                }
+                write!(f, " DURATION={}ms TOKEN={}", duration_ms, token)
+            }
+            Command::Radio { on } => write!(f, "RADIO {}", if *on { "ON" } else { "OFF" }),
+            Command::Hello { version, capabilities } => write!(f, "HELLO {} {}", version, capabilities),
+            Command::Auth { token } => write!(f, "AUTH {}", token),
+            Command::Shutdown => write!(f, "SHUTDOWN"),
+            Command::Reboot { safe: false } => write!(f, "REBOOT"),
+            Command::Reboot { safe: true } => write!(f, "REBOOT SAFE"),
+            Command::Locate { on } => write!(f, "LOCATE {}", if *on { "ON" } else { "OFF" }),
+            Command::Echoes { capture: None } => write!(f, "ECHOES"),
+            Command::Echoes { capture: Some(on) } => write!(f, "ECHOES {}", if *on { "ON" } else { "OFF" }),
+            Command::Fault { fault: Fault::SensorTimeout { side: Some(side), count } } => {
+                write!(f, "FAULT SENSOR {} {}", side.name(), count)
+            }
+            Command::Fault { fault: Fault::SensorTimeout { side: None, count } } => write!(f, "FAULT SENSOR {}", count),
+            Command::Fault { fault: Fault::SocketError { count } } => write!(f, "FAULT SOCKET {}", count),
+            Command::Fault { fault: Fault::FlashWrite { count } } => write!(f, "FAULT FLASH {}", count),
+            Command::Fault { fault: Fault::Hang } => write!(f, "FAULT HANG"),
+            Command::Fault { fault: Fault::Clear } => write!(f, "FAULT CLEAR"),
+            Command::BenchTest => write!(f, "BENCHTEST"),
+        }
+    }
+}
+
+// Protocol version as exchanged in the handshake, "<major>.<minor>"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl Version {
+    // What this build of the crate speaks
+    pub const CURRENT: Version = Version { major: PROTOCOL_MAJOR, minor: PROTOCOL_VERSION };
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let (major, minor) = text.split_once('.')?;
+        Some(Version { major: major.parse().ok()?, minor: minor.parse().ok()? })
+    }
+
+    // Minor versions only add things, so any two with the same major can
+    // talk; each side just skips what the other doesn't list as a capability
+    pub fn compatible(self, other: Version) -> bool {
+        self.major == other.major
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+// Optional features, exchanged by name so either side can list ones the
+// other has never heard of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    // STREAM with BATCH/FLUSH and numbered samples
+    pub const STREAM_BATCH: Capabilities = Capabilities(1 << 0);
+    // ZONE lines pushed on a STREAM
+    pub const ZONE_EVENTS: Capabilities = Capabilities(1 << 1);
+    // SIMULATE, only in builds with a QA token
+    pub const SIMULATE: Capabilities = Capabilities(1 << 2);
+    // RADIO ON|OFF
+    pub const RADIO: Capabilities = Capabilities(1 << 3);
+    // EXPORT/IMPORT of the whole configuration
+    pub const CONFIG_TRANSFER: Capabilities = Capabilities(1 << 4);
+    // Binary frames, see `frame`
+    pub const FRAMES: Capabilities = Capabilities(1 << 5);
+    // AUTH, only in builds with an admin token; without one every session is an admin
+    pub const AUTH: Capabilities = Capabilities(1 << 6);
+    // FAULT, only in builds with fault injection
+    pub const FAULTS: Capabilities = Capabilities(1 << 7);
+
+    const NAMES: [(Capabilities, &'static str); 8] = [
+        (Capabilities::STREAM_BATCH, "batch"),
+        (Capabilities::ZONE_EVENTS, "zone-events"),
+        (Capabilities::SIMULATE, "simulate"),
+        (Capabilities::RADIO, "radio"),
+        (Capabilities::CONFIG_TRANSFER, "config"),
+        (Capabilities::FRAMES, "frames"),
+        (Capabilities::AUTH, "auth"),
+        (Capabilities::FAULTS, "faults"),
+    ];
+
+    pub const fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    // What both sides can do
+    pub const fn intersection(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // "CAPS=batch,radio", names this version doesn't know are skipped
+    pub fn parse(text: &str) -> Option<Self> {
+        let (key, names) = text.split_once('=')?;
+        if !key.eq_ignore_ascii_case("CAPS") {
+            return None;
+        }
+        Some(names.split(',').fold(Capabilities::NONE, |caps, name| {
+            match Self::NAMES.iter().find(|(_, known)| name.eq_ignore_ascii_case(known)) {
+                Some((flag, _)) => caps.union(*flag),
+                None => caps,
+            }
+        }))
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CAPS=")?;
+        let mut names = Self::NAMES.iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, name)| name);
+        if let Some(first) = names.next() {
+            write!(f, "{}", first)?;
+        }
+        names.try_for_each(|name| write!(f, ",{}", name))
+    }
+}
+
+// The device's answer to a HELLO, "WELCOME <major>.<minor> CAPS=<name>,...".
+// A HELLO with an incompatible version gets "ERR unsupported protocol ..."
+// instead, still a single line old clients can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Welcome {
+    pub version: Version,
+    pub capabilities: Capabilities,
+}
+
+impl Welcome {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("WELCOME") {
+            return None;
+        }
+        let version = Version::parse(words.next()?)?;
+        let capabilities = words.next().map_or(Some(Capabilities::NONE), Capabilities::parse)?;
+        Some(Welcome { version, capabilities })
+    }
+}
+
+// The welcome line, without the trailing newline
+impl fmt::Display for Welcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WELCOME {} {}", self.version, self.capabilities)
+    }
+}
+
+// What a client gets instead of a reply when the device is serving as many
+// connections as it can, "BUSY <seconds>": the device closes the connection
+// and the client may try again after that long
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Busy {
+    pub retry_after_s: u8,
+}
+
+impl Busy {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("BUSY") {
+            return None;
+        }
+        let retry_after_s = words.next()?.parse().ok()?;
+        Some(Busy { retry_after_s })
+    }
+}
+
+// The busy line, without the trailing newline
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BUSY {}", self.retry_after_s)
+    }
+}
+
+// FAULT SENSOR [<side>] <count> | SOCKET <count> | FLASH <count> | HANG | CLEAR
+fn parse_fault<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Command<'a>, ParseError> {
+    let usage = ParseError::Invalid("expected FAULT SENSOR [<side>] <count> | SOCKET <count> | FLASH <count> | HANG | CLEAR");
+    let kind = words.next().ok_or(usage)?;
+    let count = |word: Option<&str>| word.and_then(|count| count.parse::<u16>().ok()).filter(|count| *count > 0).ok_or(usage);
+
+    let fault = if kind.eq_ignore_ascii_case("SENSOR") {
+        let first = words.next();
+        match first.and_then(SideName::parse) {
+            Some(side) => Fault::SensorTimeout { side: Some(side), count: count(words.next())? },
+            None => Fault::SensorTimeout { side: None, count: count(first)? },
+        }
+    } else if kind.eq_ignore_ascii_case("SOCKET") {
+        Fault::SocketError { count: count(words.next())? }
+    } else if kind.eq_ignore_ascii_case("FLASH") {
+        Fault::FlashWrite { count: count(words.next())? }
+    } else if kind.eq_ignore_ascii_case("HANG") {
+        Fault::Hang
+    } else if kind.eq_ignore_ascii_case("CLEAR") {
+        Fault::Clear
+    } else {
+        return Err(usage);
+    };
+    if words.next().is_some() {
+        return Err(usage);
+    }
+    Ok(Command::Fault { fault })
+}
+
+// STREAM [<hz>] [BATCH=<n>] [FLUSH=<ms>], a rate that isn't a number falls
+// back to the default like it always did
+fn parse_stream<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Command<'a>, ParseError> {
+    let usage = ParseError::Invalid("expected STREAM [<hz>] [BATCH=<n>] [FLUSH=<ms>]");
+    let mut hz = DEFAULT_STREAM_HZ;
+    let (mut batch, mut flush_ms) = (1, None);
+
+    let mut next = words.next();
+    if let Some(rate) = next.filter(|word| !word.contains('=')) {
+        hz = rate.parse::<u8>().unwrap_or(DEFAULT_STREAM_HZ);
+        next = words.next();
+    }
+    while let Some(word) = next {
+        let (key, value) = word.split_once('=').ok_or(usage)?;
+        if key.eq_ignore_ascii_case("BATCH") {
+            batch = value
+                .parse::<u8>()
+                .ok()
+                .filter(|batch| (1..=MAX_STREAM_BATCH).contains(batch))
+                .ok_or(ParseError::Invalid("batch must be 1 to 20 samples"))?;
+        } else if key.eq_ignore_ascii_case("FLUSH") {
+            flush_ms = Some(value.parse::<u32>().ok().filter(|ms| *ms > 0).ok_or(ParseError::Invalid("flush must be ms"))?);
+        } else {
+            return Err(usage);
+        }
+        next = words.next();
+    }
+    Ok(Command::Stream { hz, batch, flush_ms })
+}
+
+// SIMULATE L=<cm> R=<cm> OH=<cm> DURATION=<n>[s|ms] TOKEN=<token>, with a
+// channel's tag as the key. Any of the channels can be left out but not all
+// of them. A bare duration is in seconds.
+fn parse_simulate<'a>(words: impl Iterator<Item = &'a str>) -> Result<Command<'a>, ParseError> {
+    let usage = ParseError::Invalid("expected SIMULATE [L=<cm>] [R=<cm>] [OH=<cm>] DURATION=<time> TOKEN=<token>");
+    let (mut distances_cm, mut duration_ms, mut token) = ([None; CHANNELS], None, None);
+
+    for word in words {
+        let (key, value) = word.split_once('=').ok_or(usage)?;
+        let distance = || value.parse::<f32>().ok().filter(|cm| *cm >= 0.0).ok_or(ParseError::Invalid("distances must be cm"));
+        if let Some(side) = SideName::from_tag(key) {
+            distances_cm[side as usize] = Some(distance()?);
+        } else if key.eq_ignore_ascii_case("DURATION") {
+            duration_ms = Some(parse_duration_ms(value).ok_or(ParseError::Invalid("duration must look like 5s or 500ms"))?);
+        } else if key.eq_ignore_ascii_case("TOKEN") {
+            token = Some(value);
+        } else {
+            return Err(usage);
+        }
+    }
+
+    let (Some(duration_ms), Some(token)) = (duration_ms, token) else {
+        return Err(usage);
+    };
+    if distances_cm.iter().all(Option::is_none) {
+        return Err(usage);
+    }
+    if duration_ms == 0 || duration_ms > MAX_SIMULATE_MS {
+        return Err(ParseError::Invalid("duration must be between 1 ms and 60 s"));
+    }
+    Ok(Command::Simulate { distances_cm, duration_ms, token })
+}
+
+// "5s", "500ms" or a bare number of seconds
+pub fn parse_duration_ms(text: &str) -> Option<u32> {
+    if let Some(ms) = text.strip_suffix("ms") {
+        ms.parse().ok()
+    } else {
+        text.strip_suffix('s').unwrap_or(text).parse::<u32>().ok()?.checked_mul(1000)
+    }
+}
+
+// One sample of the device's sensor state
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Telemetry {
+    // Per channel in SideName::ALL order, None for a channel the device has
+    // off (the overhead one unless enabled)
+    pub distances_cm: [Option<u32>; CHANNELS],
+    // Noise estimate (standard deviation in cm) of each channel
+    pub noise_cm: [Option<f32>; CHANNELS],
+    // Factor the zone distances are scaled by for the user's motion
+    pub zone_scale: f32,
+    // How old the readings are, None before the first one (and from older firmware)
+    pub age_ms: Option<u32>,
+    // Time since the device booted, going backwards means it restarted.
+    // None from firmware before protocol 22.
+    pub uptime_ms: Option<u64>,
+    // Wall clock in seconds since 1970, None until a client set the device's clock
+    pub clock_s: Option<u64>,
+    // Sample slot on a STREAM, counting from 0. A jump means samples were
+    // dropped, `dropped` is how many so far. None on a plain STATUS.
+    pub seq: Option<u32>,
+    pub dropped: Option<u32>,
+}
+
+impl Telemetry {
+    // Parse a status line, "<tag>:<cm> ... N<tag>:<noise> ... ZS:<scale>
+    // [AGE:<ms>] [UP:<ms>] [CLK:<s>] [SEQ:<n> DROP:<n>]" with a distance and a noise estimate for
+    // every channel the device has on, e.g. "L:120 R:95 NL:1.2 NR:0.8 ZS:1.00".
+    // Older firmware leaves out the zone scale and the overhead noise.
+    pub fn parse(line: &str) -> Option<Self> {
+        let field = |name: &str| line.split_ascii_whitespace().find_map(|word| word.strip_prefix(name)?.strip_prefix(':'));
+
+        let mut telemetry = Telemetry {
+            distances_cm: [None; CHANNELS],
+            noise_cm: [None; CHANNELS],
+            zone_scale: field("ZS").map_or(Some(1.0), |scale| scale.parse().ok())?,
+            age_ms: field("AGE").map(|ms| ms.parse()).transpose().ok()?,
+            uptime_ms: field("UP").map(|ms| ms.parse()).transpose().ok()?,
+            clock_s: field("CLK").map(|s| s.parse()).transpose().ok()?,
+            seq: field("SEQ").map(|seq| seq.parse()).transpose().ok()?,
+            dropped: field("DROP").map(|dropped| dropped.parse()).transpose().ok()?,
+        };
+        for side in SideName::ALL {
+            telemetry.distances_cm[side as usize] = field(side.tag()).map(|cm| cm.parse()).transpose().ok()?;
+            telemetry.noise_cm[side as usize] = field(noise_tag(side)).map(|noise| noise.parse()).transpose().ok()?;
+        }
+        // the left and right channels are always there
+        if telemetry.distance_cm(SideName::Left).is_none() || telemetry.distance_cm(SideName::Right).is_none() {
+            return None;
+        }
+        Some(telemetry)
+    }
+
+    pub fn distance_cm(&self, side: SideName) -> Option<u32> {
+        self.distances_cm[side as usize]
+    }
+
+    pub fn noise_cm(&self, side: SideName) -> Option<f32> {
+        self.noise_cm[side as usize]
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.age_ms.is_some_and(|age_ms| age_ms > STALE_AFTER_MS)
+    }
+}
+
+// "N" and a channel's tag, the key of its noise estimate on a status line
+fn noise_tag(side: SideName) -> &'static str {
+    match side {
+        SideName::Left => "NL",
+        SideName::Right => "NR",
+        SideName::Overhead => "NOH",
+        SideName::Rear => "NRR",
+    }
+}
+
+// The status line, without the trailing newline
+impl fmt::Display for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        for side in SideName::ALL {
+            if let Some(distance_cm) = self.distance_cm(side) {
+                write!(f, "{}{}:{}", separator, side.tag(), distance_cm)?;
+                separator = " ";
+            }
+        }
+        for side in SideName::ALL {
+            if let Some(noise_cm) = self.noise_cm(side) {
+                write!(f, " {}:{:.1}", noise_tag(side), noise_cm)?;
+            }
+        }
+        write!(f, " ZS:{:.2}", self.zone_scale)?;
+        let tail = TelemetryTail {
+            age_ms: self.age_ms,
+            uptime_ms: self.uptime_ms,
+            clock_s: self.clock_s,
+            seq: self.seq,
+            dropped: self.dropped,
+        };
+        write!(f, "{}", tail)
+    }
+}
+
+// The end of a status line, what changes from one send to the next. For a
+// device that formats the readings once and sends them many times.
+pub struct TelemetryTail {
+    pub age_ms: Option<u32>,
+    pub uptime_ms: Option<u64>,
+    pub clock_s: Option<u64>,
+    pub seq: Option<u32>,
+    pub dropped: Option<u32>,
+}
+
+impl fmt::Display for TelemetryTail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(age_ms) = self.age_ms {
+            write!(f, " AGE:{}", age_ms)?;
+        }
+        if let Some(uptime_ms) = self.uptime_ms {
+            write!(f, " UP:{}", uptime_ms)?;
+        }
+        if let Some(clock_s) = self.clock_s {
+            write!(f, " CLK:{}", clock_s)?;
+        }
+        if let Some(seq) = self.seq {
+            write!(f, " SEQ:{}", seq)?;
+        }
+        if let Some(dropped) = self.dropped {
+            write!(f, " DROP:{}", dropped)?;
+        }
+        Ok(())
+    }
+}
+
+// Sensor channel a zone event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SideName {
+    Left,
+    Right,
+    Overhead,
+    Rear,
+}
+
+impl SideName {
+    // In the order every per channel list uses
+    pub const ALL: [SideName; 4] = [SideName::Left, SideName::Right, SideName::Overhead, SideName::Rear];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SideName::Left => "left",
+            SideName::Right => "right",
+            SideName::Overhead => "overhead",
+            SideName::Rear => "rear",
+        }
+    }
+
+    // Short form on status lines and in SIMULATE
+    pub fn tag(self) -> &'static str {
+        match self {
+            SideName::Left => "L",
+            SideName::Right => "R",
+            SideName::Overhead => "OH",
+            SideName::Rear => "RR",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        SideName::ALL.into_iter().find(|side| text.eq_ignore_ascii_case(side.name()))
+    }
+
+    pub fn from_tag(text: &str) -> Option<Self> {
+        SideName::ALL.into_iter().find(|side| text.eq_ignore_ascii_case(side.tag()))
+    }
+}
+
+// Whether the obstacle is getting closer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TrendName {
+    Approaching,
+    Steady,
+    Receding,
+}
+
+impl TrendName {
+    pub fn name(self) -> &'static str {
+        match self {
+            TrendName::Approaching => "approaching",
+            TrendName::Steady => "steady",
+            TrendName::Receding => "receding",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        [TrendName::Approaching, TrendName::Steady, TrendName::Receding]
+            .into_iter()
+            .find(|trend| text.eq_ignore_ascii_case(trend.name()))
+    }
+}
+
+// A channel moved into another zone, pushed on a STREAM the moment it happens
+// so apps can announce it ("obstacle ahead left, one meter")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ZoneEvent {
+    pub side: SideName,
+    // None once the channel is clear again
+    pub zone: Option<ZoneName>,
+    pub distance_cm: u32,
+    pub trend: TrendName,
+}
+
+impl ZoneEvent {
+    // Parse an event line, "ZONE <side> <zone|clear> <cm> <trend>"
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("ZONE") {
+            return None;
+        }
+
+        let side = SideName::parse(words.next()?)?;
+        let zone = match words.next()? {
+            zone if zone.eq_ignore_ascii_case("clear") => None,
+            zone => Some(ZoneName::parse(zone)?),
+        };
+        Some(ZoneEvent {
+            side,
+            zone,
+            distance_cm: words.next()?.parse().ok()?,
+            trend: TrendName::parse(words.next()?)?,
+        })
+    }
+}
+
+// The event line, without the trailing newline
+impl fmt::Display for ZoneEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let zone = self.zone.map_or("clear", ZoneName::name);
+        write!(f, "ZONE {} {} {} {}", self.side.name(), zone, self.distance_cm, self.trend.name())
+    }
+}
+
+// Where the device is, from a GPS receiver
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Position {
+    pub lat_deg: f32,
+    pub lon_deg: f32,
+}
+
+// The user asked for help with the button. Pushed on a STREAM like zone
+// events and broadcast over UDP (see `BROADCAST_UDP_PORT`), so a caregiver's
+// app hears it either way.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AssistEvent<'a> {
+    // The device's name, its access point SSID unless one was provisioned
+    pub device: &'a str,
+    // None without a GPS fix (or receiver)
+    pub position: Option<Position>,
+    // Who the device belongs to and who to call, as much of them as the
+    // owner chose to share (possibly masked, see the firmware's `owner`)
+    pub owner: Option<&'a str>,
+    pub contact: Option<&'a str>,
+}
+
+impl<'a> AssistEvent<'a> {
+    // Parse an event line,
+    // "ASSIST [LAT=<deg> LON=<deg>] [OWNER="<text>"] [CONTACT="<text>"] <device>"
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let line = line.trim();
+        if !line.get(..6)?.eq_ignore_ascii_case("ASSIST") || !line[6..].starts_with(' ') {
+            return None;
+        }
+        let mut rest = line[6..].trim_start();
+
+        let mut position = None;
+        if let Some(lat) = rest.strip_prefix("LAT=") {
+            let (lat, after) = lat.split_once(' ')?;
+            let (lon, after) = after.trim_start().strip_prefix("LON=")?.split_once(' ')?;
+            position = Some(Position { lat_deg: lat.parse().ok()?, lon_deg: lon.parse().ok()? });
+            rest = after.trim_start();
+        }
+
+        let mut owner = None;
+        if let Some(quoted) = rest.strip_prefix("OWNER=\"") {
+            let (text, after) = quoted.split_once('"')?;
+            owner = Some(text);
+            rest = after.trim_start();
+        }
+        let mut contact = None;
+        if let Some(quoted) = rest.strip_prefix("CONTACT=\"") {
+            let (text, after) = quoted.split_once('"')?;
+            contact = Some(text);
+            rest = after.trim_start();
+        }
+
+        (!rest.is_empty()).then_some(AssistEvent { device: rest, position, owner, contact })
+    }
+}
+
+// The event line, without the trailing newline. Owner and contact can't
+// contain a double quote.
+impl fmt::Display for AssistEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ASSIST")?;
+        if let Some(position) = self.position {
+            write!(f, " LAT={:.6} LON={:.6}", position.lat_deg, position.lon_deg)?;
+        }
+        if let Some(owner) = self.owner {
+            write!(f, " OWNER=\"{}\"", owner)?;
+        }
+        if let Some(contact) = self.contact {
+            write!(f, " CONTACT=\"{}\"", contact)?;
+        }
+        write!(f, " {}", self.device)
+    }
+}
+
+// An event the device's alert rules forward (see the firmware's `alerts`),
+// broadcast over UDP like assist requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlertEvent<'a> {
+    // Which rule forwarded it, e.g. "low-battery"
+    pub kind: &'a str,
+    // What happened, as the dashboard's event list puts it
+    pub text: &'a str,
+}
+
+impl<'a> AlertEvent<'a> {
+    // Parse an event line, "ALERT <kind> <text>"
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let mut parts = line.trim().splitn(3, ' ');
+        if !parts.next()?.eq_ignore_ascii_case("ALERT") {
+            return None;
+        }
+        let kind = parts.next()?;
+        let text = parts.next()?.trim();
+        (!kind.is_empty() && !text.is_empty()).then_some(AlertEvent { kind, text })
+    }
+}
+
+// The event line, without the trailing newline
+impl fmt::Display for AlertEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ALERT {} {}", self.kind, self.text)
+    }
+}
+
+// One ping as the echo pin saw it, a line of the ECHOES download. Edges are
+// in µs after the trigger pulse, a rising one first: a clean ping has two,
+// more show ringing or a second echo, none a ping that timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EchoTrace {
+    // Counts every recorded ping, so gaps between downloads show
+    pub seq: u32,
+    pub side: SideName,
+    // When the trigger went out, ms since boot
+    pub at_ms: u64,
+    pub edge_count: u8,
+    pub edges_us: [u32; MAX_ECHO_EDGES],
+}
+
+impl EchoTrace {
+    pub fn edges_us(&self) -> &[u32] {
+        &self.edges_us[..self.edge_count as usize]
+    }
+
+    // Parse a trace line, "ECHO <seq> <side> <at_ms> <us>,<us>,..." with "-" for no edges
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("ECHO") {
+            return None;
+        }
+
+        let mut trace = EchoTrace {
+            seq: words.next()?.parse().ok()?,
+            side: SideName::parse(words.next()?)?,
+            at_ms: words.next()?.parse().ok()?,
+            edge_count: 0,
+            edges_us: [0; MAX_ECHO_EDGES],
+        };
+        let edges = words.next()?;
+        if edges != "-" {
+            for edge in edges.split(',') {
+                *trace.edges_us.get_mut(trace.edge_count as usize)? = edge.parse().ok()?;
+                trace.edge_count += 1;
+            }
+        }
+        Some(trace)
+    }
+}
+
+// The trace line, without the trailing newline
+impl fmt::Display for EchoTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ECHO {} {} {} ", self.seq, self.side.name(), self.at_ms)?;
+        if self.edge_count == 0 {
+            return write!(f, "-");
+        }
+        for (index, edge_us) in self.edges_us().iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", edge_us)?;
+        }
+        Ok(())
+    }
+}
+
+// What a BENCHTEST is exercising while a sample is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BenchStep {
+    // Everything off, the resting supply voltage
+    Idle,
+    // The buzzer toggled at this frequency
+    Buzzer { hz: u32 },
+    // A motor by its output (0 = GPIO19, 1 = GPIO20) at this duty, 0-255
+    Motor { output: u8, duty: u8 },
+}
+
+// One line of a BENCHTEST, "BENCH <idle | buzzer <hz> | motor<output> <duty>> <mV|->",
+// "-" on devices that can't measure their supply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BenchSample {
+    pub step: BenchStep,
+    pub millivolts: Option<u32>,
+}
+
+impl BenchSample {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("BENCH") {
+            return None;
+        }
+
+        let step = match words.next()? {
+            "idle" => BenchStep::Idle,
+            "buzzer" => BenchStep::Buzzer { hz: words.next()?.parse().ok()? },
+            motor => BenchStep::Motor { output: motor.strip_prefix("motor")?.parse().ok()?, duty: words.next()?.parse().ok()? },
+        };
+        let millivolts = match words.next()? {
+            "-" => None,
+            millivolts => Some(millivolts.parse().ok()?),
+        };
+        Some(BenchSample { step, millivolts })
+    }
+}
+
+// The sample line, without the trailing newline
+impl fmt::Display for BenchSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.step {
+            BenchStep::Idle => write!(f, "BENCH idle ")?,
+            BenchStep::Buzzer { hz } => write!(f, "BENCH buzzer {} ", hz)?,
+            BenchStep::Motor { output, duty } => write!(f, "BENCH motor{} {} ", output, duty)?,
+        }
+        match self.millivolts {
+            Some(millivolts) => write!(f, "{}", millivolts),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn hello_round_trip() {
+        let hello = Command::Hello { version: Version { major: 1, minor: 4 }, capabilities: Capabilities::FRAMES };
+        assert_eq!(hello.to_string(), "HELLO 1.4 CAPS=frames");
+        assert_eq!(Command::parse("hello 1.4 caps=FRAMES"), Ok(hello));
+
+        // clients from before capabilities send none
+        assert_eq!(
+            Command::parse("HELLO 1.4"),
+            Ok(Command::Hello { version: Version { major: 1, minor: 4 }, capabilities: Capabilities::NONE })
+        );
+        assert!(matches!(Command::parse("HELLO"), Err(ParseError::Invalid(_))));
+        assert!(matches!(Command::parse("HELLO 1"), Err(ParseError::Invalid(_))));
+        assert!(matches!(Command::parse("HELLO 1.x"), Err(ParseError::Invalid(_))));
+        assert!(matches!(Command::parse("HELLO 1.4 batch"), Err(ParseError::Invalid(_))));
+    }
+
+    #[test]
+    fn welcome_round_trip() {
+        let welcome = Welcome { version: Version::CURRENT, capabilities: Capabilities::STREAM_BATCH.union(Capabilities::AUTH) };
+        let line = welcome.to_string();
+        assert_eq!(line, std::format!("WELCOME 1.{} CAPS=batch,auth", PROTOCOL_VERSION));
+        assert_eq!(Welcome::parse(&line), Some(welcome));
+
+        assert_eq!(
+            Welcome::parse("WELCOME 1.2"),
+            Some(Welcome { version: Version { major: 1, minor: 2 }, capabilities: Capabilities::NONE })
+        );
+        assert_eq!(Welcome::parse("WELCOME 1.2 CAPS=").map(|welcome| welcome.capabilities), Some(Capabilities::NONE));
+        assert_eq!(Welcome::parse("ERR unsupported protocol 2.0"), None);
+        assert_eq!(Welcome::parse("WELCOME"), None);
+    }
+
+    #[test]
+    fn other_major_version_refused() {
+        assert!(!Version::CURRENT.compatible(Version { major: PROTOCOL_MAJOR + 1, minor: 0 }));
+        assert!(!Version::CURRENT.compatible(Version { major: PROTOCOL_MAJOR - 1, minor: PROTOCOL_VERSION }));
+    }
+
+    #[test]
+    fn newer_minor_version_accepted() {
+        assert!(Version::CURRENT.compatible(Version { major: PROTOCOL_MAJOR, minor: PROTOCOL_VERSION + 1 }));
+        assert!(Version::CURRENT.compatible(Version { major: PROTOCOL_MAJOR, minor: 0 }));
+        let newer = Welcome::parse("WELCOME 1.200 CAPS=batch,teleport").unwrap();
+        assert!(Version::CURRENT.compatible(newer.version));
+    }
+
+    #[test]
+    fn unknown_capabilities_skipped() {
+        assert_eq!(Capabilities::parse("CAPS=teleport,radio,,x-y"), Some(Capabilities::RADIO));
+        assert_eq!(Capabilities::parse("CAPS=teleport"), Some(Capabilities::NONE));
+        assert_eq!(Capabilities::parse("FLAGS=radio"), None);
+        assert_eq!(Capabilities::parse("radio"), None);
+
+        let welcome = Welcome::parse("WELCOME 1.99 CAPS=frames,teleport,zone-events").unwrap();
+        assert_eq!(welcome.capabilities, Capabilities::FRAMES.union(Capabilities::ZONE_EVENTS));
+        // and aren't echoed back
+        assert_eq!(welcome.capabilities.to_string(), "CAPS=zone-events,frames");
+    }
+}
@@ -0,0 +1,94 @@
+//! Layout of the flash partition the CYW43 WiFi firmware can be loaded from.
+//!
+//! Embedding the 43439A0 firmware and CLM blobs adds about 230 KB to every
+//! image. Instead they can be written once to a fixed partition just below
+//! the settings sectors, with `visionctl cyw43-image`, and firmware built
+//! without the blobs reads them from there:
+//!
+//! ```text
+//! magic "CYWF" | firmware length (u32 LE) | CLM length (u32 LE) | CRC-32 (u32 LE) | padding to FIRMWARE_START
+//! firmware | padding to 4 bytes | CLM
+//! ```
+//!
+//! The CRC (CRC-32/ISO-HDLC, as zip uses) covers the firmware and the CLM
+//! with the padding between them. An erased or half written partition fails
+//! the check and the device falls back to the blobs it was built with.
+
+// Offset from the start of flash, the settings sectors follow right after it
+pub const PARTITION_OFFSET: u32 = 0x1B_3000;
+pub const PARTITION_SIZE: usize = 256 * 1024;
+// Where the partition shows up in the RP2350's execute-in-place window
+pub const PARTITION_ADDRESS: u32 = 0x1000_0000 + PARTITION_OFFSET;
+
+pub const MAGIC: [u8; 4] = *b"CYWF";
+pub const HEADER_LEN: usize = 16;
+// The firmware is kept word aligned, well past the header
+pub const FIRMWARE_START: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PartitionError {
+    // Erased, or never written
+    Empty,
+    // The lengths don't fit in the partition
+    TooLong,
+    // Written partly, or damaged
+    Crc,
+}
+
+fn clm_start(firmware_len: usize) -> usize {
+    FIRMWARE_START + firmware_len.next_multiple_of(4)
+}
+
+// Lay out a partition image for the blobs into `out`, which must be at least
+// PARTITION_SIZE long. Returns the bytes to write, from the partition start.
+pub fn build<'a>(firmware: &[u8], clm: &[u8], out: &'a mut [u8]) -> Result<&'a [u8], PartitionError> {
+    let len = clm_start(firmware.len()) + clm.len();
+    if len > PARTITION_SIZE || len > out.len() {
+        return Err(PartitionError::TooLong);
+    }
+    out[..len].fill(0);
+    out[FIRMWARE_START..FIRMWARE_START + firmware.len()].copy_from_slice(firmware);
+    out[clm_start(firmware.len())..len].copy_from_slice(clm);
+
+    out[..4].copy_from_slice(&MAGIC);
+    out[4..8].copy_from_slice(&(firmware.len() as u32).to_le_bytes());
+    out[8..12].copy_from_slice(&(clm.len() as u32).to_le_bytes());
+    let crc = crc32(&out[FIRMWARE_START..len]);
+    out[12..16].copy_from_slice(&crc.to_le_bytes());
+    Ok(&out[..len])
+}
+
+// Check a partition and find the firmware and the CLM in it
+pub fn parse(partition: &[u8]) -> Result<(&[u8], &[u8]), PartitionError> {
+    if partition.len() < HEADER_LEN || partition[..4] != MAGIC {
+        return Err(PartitionError::Empty);
+    }
+    let word = |at: usize| u32::from_le_bytes([partition[at], partition[at + 1], partition[at + 2], partition[at + 3]]) as usize;
+    let (firmware_len, clm_len) = (word(4), word(8));
+    // also keeps the sums below from overflowing on garbage
+    if firmware_len > PARTITION_SIZE || clm_len > PARTITION_SIZE {
+        return Err(PartitionError::TooLong);
+    }
+    let len = clm_start(firmware_len) + clm_len;
+    if len > partition.len().min(PARTITION_SIZE) {
+        return Err(PartitionError::TooLong);
+    }
+    if crc32(&partition[FIRMWARE_START..len]) != word(12) as u32 {
+        return Err(PartitionError::Crc);
+    }
+    Ok((&partition[FIRMWARE_START..FIRMWARE_START + firmware_len], &partition[clm_start(firmware_len)..len]))
+}
+
+// CRC-32/ISO-HDLC (reflected poly 0xEDB88320), bit by bit to stay small, it
+// runs once per boot
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
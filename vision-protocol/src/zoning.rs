@@ -0,0 +1,357 @@
+//! Maps smoothed distances onto proximity zones.
+//!
+//! Zone changes use hysteresis: getting more severe happens as soon as the
+//! `enter_cm` threshold is crossed, while stepping back out needs the reading
+//! to pass `exit_cm` *and* the zone's minimum dwell time to have elapsed. That
+//! keeps readings hovering around a boundary from making the motors stutter.
+//!
+//! Everything here is plain math on the zone settings, no hardware or
+//! globals, so it lives here where the zone and intensity logic can be tested
+//! on the host. The firmware keeps these settings in its configuration.
+
+use serde::{Deserialize, Serialize};
+
+// Thresholds for a single zone. A reading has to drop below `enter_cm` to get
+// into the zone but has to climb above `exit_cm` to leave it again, and once
+// entered we stay at least `min_dwell_ms` before stepping down.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ZoneThreshold {
+    pub enter_cm: f32,
+    pub exit_cm: f32,
+    pub min_dwell_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ZoneConfig {
+    pub critical: ZoneThreshold,  // very close obstacles
+    pub warning: ZoneThreshold,   // getting closer
+    pub notice: ZoneThreshold,    // far enough but worth noting
+}
+
+// Ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Zone {
+    Clear,
+    Notice,
+    Warning,
+    Critical,
+}
+
+impl Zone {
+    pub fn name(self) -> &'static str {
+        match self {
+            Zone::Clear => "clear",
+            Zone::Notice => "notice",
+            Zone::Warning => "warning",
+            Zone::Critical => "critical",
+        }
+    }
+}
+
+impl ZoneConfig {
+    // Same zones with every distance multiplied by `factor`
+    pub fn scaled(&self, factor: f32) -> ZoneConfig {
+        let mut scaled = *self;
+        for threshold in [&mut scaled.critical, &mut scaled.warning, &mut scaled.notice] {
+            threshold.enter_cm *= factor;
+            threshold.exit_cm *= factor;
+        }
+        scaled
+    }
+
+    // Positive thresholds with exit >= enter, ordered critical < warning < notice.
+    // Written so that NaN from corrupted settings fails every check.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        for threshold in [&self.critical, &self.warning, &self.notice] {
+            if !(threshold.enter_cm > 0.0 && threshold.exit_cm >= threshold.enter_cm && threshold.exit_cm.is_finite()) {
+                return Err("Zone thresholds must be positive with exit >= enter");
+            }
+        }
+        if !(self.critical.enter_cm < self.warning.enter_cm && self.warning.enter_cm < self.notice.enter_cm) {
+            return Err("Zones must be ordered critical < warning < notice");
+        }
+        Ok(())
+    }
+
+    fn threshold(&self, zone: Zone) -> Option<&ZoneThreshold> {
+        match zone {
+            Zone::Critical => Some(&self.critical),
+            Zone::Warning => Some(&self.warning),
+            Zone::Notice => Some(&self.notice),
+            Zone::Clear => None,
+        }
+    }
+}
+
+// Zone a distance belongs to, given the zone we're currently in. Zones we're
+// already in (or past) are left at their exit threshold, the others are
+// entered at their enter threshold.
+fn classify(distance: f32, current: Zone, zones: &ZoneConfig) -> Zone {
+    for zone in [Zone::Critical, Zone::Warning, Zone::Notice] {
+        if let Some(threshold) = zones.threshold(zone) {
+            let limit = if zone <= current { threshold.exit_cm } else { threshold.enter_cm };
+            if distance < limit {
+                return zone;
+            }
+        }
+    }
+    Zone::Clear
+}
+
+// Vibration intensity (0-255) for a distance in `zone`. Each zone covers a
+// third of the range and the intensity grows linearly through it, so it keeps
+// rising smoothly across zone boundaries. Thanks to hysteresis the distance can sit a bit past
+// the zone edge, hence the clamping.
+pub fn intensity(distance: f32, zone: Zone, zones: &ZoneConfig) -> u8 {
+    let critical = zones.critical.enter_cm;
+    let warning = zones.warning.enter_cm;
+    let notice = zones.notice.enter_cm;
+
+    let (low, high, normalized) = match zone {
+        // Critical zone (170-255)
+        Zone::Critical => (170.0, 255.0, (critical - distance) / critical),
+        // Warning zone (85-170)
+        Zone::Warning => (85.0, 170.0, (warning - distance) / (warning - critical)),
+        // Notice zone (1-85)
+        Zone::Notice => (1.0, 85.0, (notice - distance) / (notice - warning)),
+        // Beyond notice zone
+        Zone::Clear => return 0,
+    };
+
+    (low + normalized.clamp(0.0, 1.0) * (high - low)) as u8
+}
+
+// Per-channel zone state
+pub struct ZoneTracker {
+    zone: Zone,
+    entered_at_ms: u64,
+    // readings in a row that asked for a more severe zone
+    escalations: u8,
+}
+
+impl ZoneTracker {
+    pub const fn new() -> Self {
+        Self { zone: Zone::Clear, entered_at_ms: 0, escalations: 0 }
+    }
+
+    // Feed a new reading taken at `now_ms` and get the (possibly unchanged) zone
+    // back. A more severe zone is entered once `confirmations` readings in a
+    // row ask for it; normally that's 1, so warnings are never delayed.
+    pub fn update(&mut self, distance: f32, now_ms: u64, zones: &ZoneConfig, confirmations: u8) -> Zone {
+        let target = classify(distance, self.zone, zones);
+
+        if target > self.zone {
+            self.escalations = self.escalations.saturating_add(1);
+            if self.escalations >= confirmations {
+                self.enter(target, now_ms);
+            }
+            return self.zone;
+        }
+        self.escalations = 0;
+
+        if target < self.zone {
+            let min_dwell_ms = zones.threshold(self.zone).map_or(0, |t| t.min_dwell_ms);
+            if now_ms.saturating_sub(self.entered_at_ms) >= min_dwell_ms as u64 {
+                self.enter(target, now_ms);
+            }
+        }
+
+        self.zone
+    }
+
+    fn enter(&mut self, zone: Zone, now_ms: u64) {
+        self.zone = zone;
+        self.entered_at_ms = now_ms;
+        self.escalations = 0;
+    }
+}
+
+impl Default for ZoneTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONES: ZoneConfig = ZoneConfig {
+        critical: ZoneThreshold { enter_cm: 50.0, exit_cm: 55.0, min_dwell_ms: 300 },
+        warning: ZoneThreshold { enter_cm: 100.0, exit_cm: 110.0, min_dwell_ms: 300 },
+        notice: ZoneThreshold { enter_cm: 150.0, exit_cm: 165.0, min_dwell_ms: 500 },
+    };
+
+    #[test]
+    fn entered_below_the_enter_threshold() {
+        for (distance, zone) in [
+            (0.0, Zone::Critical),
+            (49.9, Zone::Critical),
+            (50.0, Zone::Warning),
+            (99.9, Zone::Warning),
+            (100.0, Zone::Notice),
+            (149.9, Zone::Notice),
+            (150.0, Zone::Clear),
+            (1000.0, Zone::Clear),
+        ] {
+            assert_eq!(classify(distance, Zone::Clear, &ZONES), zone, "{distance} cm");
+        }
+    }
+
+    #[test]
+    fn left_above_the_exit_threshold() {
+        assert_eq!(classify(54.9, Zone::Critical, &ZONES), Zone::Critical);
+        assert_eq!(classify(55.0, Zone::Critical, &ZONES), Zone::Warning);
+        assert_eq!(classify(109.9, Zone::Warning, &ZONES), Zone::Warning);
+        assert_eq!(classify(110.0, Zone::Warning, &ZONES), Zone::Notice);
+        assert_eq!(classify(164.9, Zone::Notice, &ZONES), Zone::Notice);
+        assert_eq!(classify(165.0, Zone::Notice, &ZONES), Zone::Clear);
+        // a more severe zone is still only entered below its enter threshold
+        assert_eq!(classify(52.0, Zone::Warning, &ZONES), Zone::Warning);
+    }
+
+    // Feeds (time, distance) readings to a new tracker and checks the zone after each
+    fn trace(confirmations: u8, readings: &[(u64, f32, Zone)]) {
+        let mut tracker = ZoneTracker::new();
+        for &(now_ms, distance, zone) in readings {
+            assert_eq!(tracker.update(distance, now_ms, &ZONES, confirmations), zone, "{distance} cm at {now_ms} ms");
+        }
+    }
+
+    #[test]
+    fn alternating_around_a_boundary_holds_the_zone() {
+        // between warning's enter (100) and exit (110) nothing changes, however long it takes
+        trace(1, &[
+            (0, 120.0, Zone::Notice),
+            (50, 99.0, Zone::Warning),
+            (100, 105.0, Zone::Warning),
+            (150, 99.0, Zone::Warning),
+            (200, 108.0, Zone::Warning),
+            (1000, 101.0, Zone::Warning),
+            (1050, 109.9, Zone::Warning),
+            // past the exit, and the dwell time is long over
+            (1100, 111.0, Zone::Notice),
+            // back under the exit but not the enter threshold
+            (1150, 105.0, Zone::Notice),
+            (1200, 99.0, Zone::Warning),
+        ]);
+    }
+
+    #[test]
+    fn stepping_down_waits_for_the_dwell_time() {
+        trace(1, &[
+            (1000, 40.0, Zone::Critical),
+            (1100, 60.0, Zone::Critical),
+            (1299, 60.0, Zone::Critical),
+            (1300, 60.0, Zone::Warning),
+            // notice has a longer dwell than warning, counted from entering warning
+            (1400, 120.0, Zone::Warning),
+            (1600, 120.0, Zone::Notice),
+            (1700, 200.0, Zone::Notice),
+            (2099, 200.0, Zone::Notice),
+            (2100, 200.0, Zone::Clear),
+        ]);
+    }
+
+    #[test]
+    fn stepping_up_never_waits() {
+        // even right after stepping down
+        trace(1, &[
+            (0, 120.0, Zone::Notice),
+            (600, 200.0, Zone::Clear),
+            (601, 40.0, Zone::Critical),
+        ]);
+    }
+
+    #[test]
+    fn jumps_across_two_zones() {
+        trace(1, &[
+            (0, 140.0, Zone::Notice),
+            (50, 30.0, Zone::Critical),
+            // straight down to notice once the critical dwell is over
+            (400, 130.0, Zone::Notice),
+            (450, 200.0, Zone::Notice),
+            (900, 200.0, Zone::Clear),
+            (950, 30.0, Zone::Critical),
+            (1250, 300.0, Zone::Clear),
+        ]);
+    }
+
+    #[test]
+    fn confirmations_delay_escalation() {
+        trace(2, &[
+            (0, 80.0, Zone::Clear),
+            (50, 80.0, Zone::Warning),
+            // a single closer reading doesn't count
+            (100, 40.0, Zone::Warning),
+            (150, 80.0, Zone::Warning),
+            (200, 40.0, Zone::Warning),
+            (250, 40.0, Zone::Critical),
+        ]);
+    }
+
+    #[test]
+    fn intensity_at_the_zone_edges() {
+        assert_eq!(intensity(150.0, Zone::Notice, &ZONES), 1);
+        assert_eq!(intensity(100.0, Zone::Warning, &ZONES), 85);
+        assert_eq!(intensity(50.0, Zone::Critical, &ZONES), 170);
+        assert_eq!(intensity(0.0, Zone::Critical, &ZONES), 255);
+        assert_eq!(intensity(10.0, Zone::Clear, &ZONES), 0);
+    }
+
+    #[test]
+    fn intensity_clamped_past_the_edges() {
+        // held in a zone by hysteresis
+        assert_eq!(intensity(160.0, Zone::Notice, &ZONES), 1);
+        assert_eq!(intensity(105.0, Zone::Warning, &ZONES), 85);
+        assert_eq!(intensity(53.0, Zone::Critical, &ZONES), 170);
+        // or not yet stepped up
+        assert_eq!(intensity(90.0, Zone::Notice, &ZONES), 85);
+        assert_eq!(intensity(30.0, Zone::Warning, &ZONES), 170);
+        assert_eq!(intensity(-5.0, Zone::Critical, &ZONES), 255);
+    }
+
+    #[test]
+    fn intensity_rises_across_zones() {
+        let mut previous = 0;
+        let mut zone = Zone::Clear;
+        for step in (0..=400).rev() {
+            let distance = step as f32 * 0.5;
+            zone = classify(distance, zone, &ZONES);
+            let level = intensity(distance, zone, &ZONES);
+            assert!(level >= previous, "{level} after {previous} at {distance} cm");
+            previous = level;
+        }
+        assert_eq!(previous, 255);
+    }
+
+    #[test]
+    fn validate_rejects_bad_thresholds() {
+        assert_eq!(ZONES.validate(), Ok(()));
+
+        let mut zones = ZONES;
+        zones.warning.enter_cm = f32::NAN;
+        assert!(zones.validate().is_err());
+
+        let mut zones = ZONES;
+        zones.notice.exit_cm = f32::INFINITY;
+        assert!(zones.validate().is_err());
+
+        let mut zones = ZONES;
+        zones.critical.exit_cm = 45.0;
+        assert!(zones.validate().is_err());
+
+        let mut zones = ZONES;
+        zones.critical.enter_cm = 120.0;
+        zones.critical.exit_cm = 125.0;
+        assert!(zones.validate().is_err());
+    }
+
+    #[test]
+    fn scaled_keeps_the_dwell_times() {
+        let scaled = ZONES.scaled(2.0);
+        assert_eq!(scaled.critical, ZoneThreshold { enter_cm: 100.0, exit_cm: 110.0, min_dwell_ms: 300 });
+        assert_eq!(scaled.notice, ZoneThreshold { enter_cm: 300.0, exit_cm: 330.0, min_dwell_ms: 500 });
+    }
+}
@@ -0,0 +1,634 @@
+//! `visionctl`: headless companion tool for VisionAssist devices.
+//!
+//! Talks the line protocol of the device's TCP server (port 8080), one command
+//! per connection:
+//!
+//! ```text
+//! visionctl [--host <addr[:port]>] status
+//! visionctl [--host <addr[:port]>] hello
+//! visionctl [--host <addr[:port]>] set-threshold <critical|warning|notice> <enter_cm> [exit_cm]
+//! visionctl [--host <addr[:port]>] stream [--rate <hz>] [--batch <n>] [--flush <ms>] [--plot]
+//! visionctl [--host <addr[:port]>] export > tuned.json
+//! visionctl [--host <addr[:port]>] import tuned.json
+//! visionctl [--host <addr[:port]>] simulate [--left <cm>] [--right <cm>] [--overhead <cm>] [--duration <time>] --token <token>
+//! visionctl [--host <addr[:port]>] radio off
+//! visionctl [--host <addr[:port]>] shutdown
+//! visionctl [--host <addr[:port]>] reboot [safe]
+//! visionctl [--host <addr[:port]>] locate [off]
+//! visionctl [--host <addr[:port]>] echoes [on|off]
+//! visionctl [--host <addr[:port]>] fault <sensor [<side>] <count> | socket <count> | flash <count> | hang | clear>
+//! visionctl [--host <addr[:port]>] benchtest
+//! visionctl listen
+//! visionctl cyw43-image <firmware.bin> <clm.bin> <out.uf2|out.bin>
+//! ```
+//!
+//! `listen` waits for the assist requests devices broadcast when their user
+//! triple presses the button, and for the alerts their rules forward.
+//! `cyw43-image` doesn't talk to a device: it packs the WiFi chip's firmware
+//! into an image of its flash partition, a UF2 to copy onto the board in
+//! bootloader mode or a plain binary for a debug probe.
+//!
+//! There is no `update-firmware`. The device can't take new firmware over
+//! the network, it has no bootloader with a second slot to write it to, so
+//! firmware is flashed over USB or a debug probe.
+//!
+//! Commands that change the device need the admin role on devices built with
+//! an admin token; set `VISIONCTL_ADMIN_TOKEN` and those are preceded by an
+//! AUTH on the same connection.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use vision_protocol::partition::{self, PARTITION_ADDRESS, PARTITION_SIZE};
+use vision_protocol::{
+    parse_duration_ms, AlertEvent, AssistEvent, BenchSample, BenchStep, Busy, Capabilities, Command, EchoTrace, ParseError, SideName, Telemetry, Version, Welcome, ZoneEvent, ZoneName, CHANNELS, DEFAULT_STREAM_HZ,
+    MAX_SIMULATE_MS, MAX_STREAM_BATCH, Role, BROADCAST_UDP_PORT, TCP_PORT,
+};
+
+const DEFAULT_HOST: &str = "192.168.4.1";
+// Widest bar drawn by `stream --plot`, covers 0-400 cm
+const PLOT_WIDTH: usize = 40;
+const PLOT_MAX_CM: u32 = 400;
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let host = match take_option(&mut args, "--host") {
+        Some(host) if host.contains(':') => host,
+        Some(host) => format!("{}:{}", host, TCP_PORT),
+        None => format!("{}:{}", DEFAULT_HOST, TCP_PORT),
+    };
+
+    let result = match args.first().map(String::as_str) {
+        Some("status") => status(&host),
+        Some("hello") => hello(&host),
+        Some("set-threshold") => set_threshold(&host, &args[1..]),
+        Some("stream") => stream(&host, &mut args),
+        Some("export") => export(&host),
+        Some("import") if args.len() == 2 => import(&host, &args[1]),
+        Some("simulate") => simulate(&host, &mut args),
+        Some("radio") if args.len() == 2 => radio(&host, &args[1]),
+        Some("shutdown") => shutdown(&host),
+        Some("reboot") if args.len() <= 2 => reboot(&host, args.get(1).map(String::as_str)),
+        Some("listen") => listen(),
+        Some("locate") if args.len() <= 2 => locate(&host, args.get(1).map(String::as_str)),
+        Some("echoes") if args.len() <= 2 => echoes(&host, args.get(1).map(String::as_str)),
+        Some("fault") => fault(&host, &args[1..]),
+        Some("benchtest") => bench_test(&host),
+        Some("cyw43-image") if args.len() == 4 => cyw43_image(&args[1], &args[2], &args[3]),
+        _ => {
+            eprintln!("usage: visionctl [--host <addr[:port]>] <status | hello | set-threshold <zone> <enter_cm> [exit_cm] | stream [--rate <hz>] [--batch <n>] [--flush <ms>] [--plot] | export | import <file> | simulate [--left <cm>] [--right <cm>] [--overhead <cm>] [--duration <time>] --token <token> | radio <on|off> | shutdown | reboot [safe] | locate [off] | echoes [on|off] | fault <kind> [args] | benchtest | listen | cyw43-image <firmware> <clm> <out>>");
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("visionctl: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Remove `--name <value>` from the arguments and return the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.remove(index);
+    (index < args.len()).then(|| args.remove(index))
+}
+
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+// Send one command and hand back a reader for the reply
+fn send(host: &str, command: Command) -> Result<BufReader<TcpStream>, String> {
+    let stream = TcpStream::connect(host).map_err(|e| format!("can't connect to {}: {}", host, e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+
+    // the device answers the AUTH before it reads the next command
+    if command.required_role() == Role::Admin {
+        if let Ok(token) = env::var("VISIONCTL_ADMIN_TOKEN") {
+            let line = format!("{}\n", Command::Auth { token: &token });
+            reader.get_mut().write_all(line.as_bytes()).map_err(|e| format!("send failed: {}", e))?;
+            let reply = read_reply(&mut reader)?;
+            if let Some(error) = reply.strip_prefix("ERR ") {
+                return Err(format!("authentication failed: {}", error));
+            }
+        }
+    }
+
+    // one write, the device only reads a single segment per request
+    let line = format!("{}\n", command);
+    reader.get_mut().write_all(line.as_bytes()).map_err(|e| format!("send failed: {}", e))?;
+    Ok(reader)
+}
+
+fn read_reply(reader: &mut BufReader<TcpStream>) -> Result<String, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| format!("no reply: {}", e))?;
+    // the device had no connection left for us
+    if let Some(busy) = Busy::parse(&line) {
+        return Err(format!("device busy, try again in {} s", busy.retry_after_s));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn status(host: &str) -> Result<(), String> {
+    let reply = read_reply(&mut send(host, Command::Status)?)?;
+    let telemetry = Telemetry::parse(&reply).ok_or_else(|| format!("unexpected reply '{}'", reply))?;
+    for side in SideName::ALL {
+        let Some(distance_cm) = telemetry.distance_cm(side) else {
+            continue;
+        };
+        match telemetry.noise_cm(side) {
+            Some(noise_cm) => println!("{:<9} {} cm (noise {:.1} cm)", format!("{}:", side.name()), distance_cm, noise_cm),
+            None => println!("{:<9} {} cm", format!("{}:", side.name()), distance_cm),
+        }
+    }
+    println!("zones scaled x{:.2}", telemetry.zone_scale);
+    if let Some(age_ms) = telemetry.age_ms {
+        println!("readings {} ms old{}", age_ms, if telemetry.is_stale() { ", device is NOT SENSING" } else { "" });
+    }
+    if let Some(uptime_ms) = telemetry.uptime_ms {
+        let uptime_s = uptime_ms / 1000;
+        println!("up {}:{:02}:{:02}", uptime_s / 3600, uptime_s / 60 % 60, uptime_s % 60);
+    }
+    match telemetry.clock_s {
+        Some(clock_s) => {
+            let here_s = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()) as i64;
+            println!("device clock {} s off from this machine's", clock_s as i64 - here_s);
+        }
+        None if telemetry.uptime_ms.is_some() => println!("device clock not set"),
+        None => {}
+    }
+    Ok(())
+}
+
+// Everything this tool knows how to use
+const CAPABILITIES: Capabilities = Capabilities::STREAM_BATCH
+    .union(Capabilities::ZONE_EVENTS)
+    .union(Capabilities::SIMULATE)
+    .union(Capabilities::RADIO)
+    .union(Capabilities::CONFIG_TRANSFER)
+    .union(Capabilities::AUTH);
+
+// Handshake: which protocol the device speaks and what both sides can do
+fn hello(host: &str) -> Result<(), String> {
+    let command = Command::Hello { version: Version::CURRENT, capabilities: CAPABILITIES };
+    let reply = read_reply(&mut send(host, command)?)?;
+    if let Some(error) = reply.strip_prefix("ERR ") {
+        return Err(error.to_string());
+    }
+    // firmware from before the handshake treats HELLO as STATUS
+    if Telemetry::parse(&reply).is_some() {
+        println!("device firmware predates the handshake (protocol 8 or older)");
+        return Ok(());
+    }
+
+    let welcome = Welcome::parse(&reply).ok_or_else(|| format!("unexpected reply '{}'", reply))?;
+    if !Version::CURRENT.compatible(welcome.version) {
+        return Err(format!("device speaks protocol {}, this tool {}", welcome.version, Version::CURRENT));
+    }
+    println!("protocol {} (this tool {})", welcome.version, Version::CURRENT);
+    println!("device {}", welcome.capabilities);
+    println!("shared {}", welcome.capabilities.intersection(CAPABILITIES));
+    Ok(())
+}
+
+fn set_threshold(host: &str, args: &[String]) -> Result<(), String> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err("set-threshold needs <zone> <enter_cm> [exit_cm]".to_string());
+    }
+    let zone = ZoneName::parse(&args[0]).ok_or_else(|| format!("unknown zone '{}'", args[0]))?;
+    let distance = |value: &String| value.parse::<f32>().map_err(|_| format!("'{}' is not a distance in cm", value));
+    let enter_cm = distance(&args[1])?;
+    let exit_cm = args.get(2).map(distance).transpose()?;
+
+    let reply = read_reply(&mut send(host, Command::SetThreshold { zone, enter_cm, exit_cm })?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("{}", reply);
+            Ok(())
+        }
+    }
+}
+
+// Print the device configuration as JSON, ready for `import` on another device
+fn export(host: &str) -> Result<(), String> {
+    let reply = read_reply(&mut send(host, Command::ExportConfig)?)?;
+    if let Some(error) = reply.strip_prefix("ERR ") {
+        return Err(error.to_string());
+    }
+    println!("{}", reply);
+    Ok(())
+}
+
+fn import(host: &str, path: &str) -> Result<(), String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    // the protocol is line based, so the JSON has to travel on a single line
+    let json = json.trim().replace(['\r', '\n'], " ");
+
+    let reply = read_reply(&mut send(host, Command::ImportConfig { json: &json })?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("{}", reply);
+            Ok(())
+        }
+    }
+}
+
+// Fake readings on the device for a while, the duration defaults to 5 s
+fn simulate(host: &str, args: &mut Vec<String>) -> Result<(), String> {
+    // --left, --right and --overhead
+    let mut distances_cm = [None; CHANNELS];
+    for side in SideName::ALL {
+        distances_cm[side as usize] = take_option(args, &format!("--{}", side.name()))
+            .map(|value| value.parse::<f32>().map_err(|_| format!("'{}' is not a distance in cm", value)))
+            .transpose()?;
+    }
+    let duration_ms = match take_option(args, "--duration") {
+        Some(duration) => parse_duration_ms(&duration).ok_or_else(|| format!("'{}' is not a duration like 5s or 500ms", duration))?,
+        None => 5000,
+    };
+    let token = take_option(args, "--token").ok_or("simulate needs --token")?;
+    if distances_cm.iter().all(Option::is_none) {
+        return Err("simulate needs at least one of --left, --right and --overhead".to_string());
+    }
+    if duration_ms == 0 || duration_ms > MAX_SIMULATE_MS {
+        return Err("duration must be between 1 ms and 60 s".to_string());
+    }
+
+    let command = Command::Simulate { distances_cm, duration_ms, token: &token };
+    let reply = read_reply(&mut send(host, command)?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("{}", reply);
+            Ok(())
+        }
+    }
+}
+
+// Switching off drops the connection to the device, hold its button to get it back
+fn radio(host: &str, state: &str) -> Result<(), String> {
+    let on = match state {
+        "on" => true,
+        "off" => false,
+        _ => return Err(format!("'{}' is not on or off", state)),
+    };
+    let reply = read_reply(&mut send(host, Command::Radio { on })?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("{}", reply);
+            Ok(())
+        }
+    }
+}
+
+fn shutdown(host: &str) -> Result<(), String> {
+    let reply = read_reply(&mut send(host, Command::Shutdown)?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("device is shutting down");
+            Ok(())
+        }
+    }
+}
+
+// Restarts the device, `safe` with the default settings so a bad
+// configuration can be replaced
+fn reboot(host: &str, mode: Option<&str>) -> Result<(), String> {
+    let safe = match mode {
+        None => false,
+        Some("safe") => true,
+        Some(mode) => return Err(format!("'{}' is not safe", mode)),
+    };
+    let reply = read_reply(&mut send(host, Command::Reboot { safe })?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("device is restarting{}", if safe { " in safe mode" } else { "" });
+            Ok(())
+        }
+    }
+}
+
+// Beeps until the device's button is pressed or `locate off`
+fn locate(host: &str, state: Option<&str>) -> Result<(), String> {
+    let on = match state {
+        None | Some("on") => true,
+        Some("off") => false,
+        Some(state) => return Err(format!("'{}' is not on or off", state)),
+    };
+    let reply = read_reply(&mut send(host, Command::Locate { on })?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("{}", reply);
+            Ok(())
+        }
+    }
+}
+
+// Turn echo capture on or off, or print the pings captured so far: every
+// edge of the echo pin in µs after the trigger, with the distance the first
+// pulse means and a note when there is more than one
+fn echoes(host: &str, state: Option<&str>) -> Result<(), String> {
+    let capture = match state {
+        None => None,
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(state) => return Err(format!("'{}' is not on or off", state)),
+    };
+    let mut reader = send(host, Command::Echoes { capture })?;
+    loop {
+        let reply = read_reply(&mut reader)?;
+        if let Some(error) = reply.strip_prefix("ERR ") {
+            return Err(error.to_string());
+        }
+        let Some(trace) = EchoTrace::parse(&reply) else {
+            if capture.is_none() && reply == "OK 0" {
+                println!("no pings captured, start with 'echoes on'");
+            } else if capture.is_some() {
+                println!("{}", reply);
+            }
+            return Ok(());
+        };
+
+        let edges: Vec<String> = trace.edges_us().iter().map(u32::to_string).collect();
+        print!("#{} {} at {} ms: ", trace.seq, trace.side.name(), trace.at_ms);
+        match trace.edges_us() {
+            [] => println!("no echo"),
+            [rise] => println!("{} (no end)", rise),
+            [rise, fall, rest @ ..] => {
+                print!("{} us, {:.1} cm", edges.join(" "), (fall - rise) as f32 * 0.034 / 2.0);
+                println!("{}", if rest.is_empty() { "" } else { ", extra edges" });
+            }
+        }
+    }
+}
+
+// Run the actuator script of a newly assembled unit and print the lowest
+// supply voltage of every step as it ends, with how far it is below the
+// resting voltage, so a weak battery or a bad motor lead stands out
+fn bench_test(host: &str) -> Result<(), String> {
+    let mut reader = send(host, Command::BenchTest)?;
+    println!("listen for the buzzer sweep, then feel each motor ramp up");
+    let mut current: Option<(BenchStep, Option<u32>)> = None;
+    let mut resting_mv = None;
+    loop {
+        let reply = read_reply(&mut reader)?;
+        if let Some(error) = reply.strip_prefix("ERR ") {
+            return Err(error.to_string());
+        }
+        let Some(sample) = BenchSample::parse(&reply) else {
+            if let Some((step, lowest_mv)) = current {
+                print_bench_step(step, lowest_mv, resting_mv);
+            }
+            if reply != "OK" {
+                return Err(format!("unexpected reply '{}'", reply));
+            }
+            println!("bench test done");
+            return Ok(());
+        };
+
+        match current.as_mut() {
+            Some((step, lowest_mv)) if *step == sample.step => {
+                *lowest_mv = match (*lowest_mv, sample.millivolts) {
+                    (Some(lowest), Some(millivolts)) => Some(lowest.min(millivolts)),
+                    (lowest, millivolts) => lowest.or(millivolts),
+                };
+            }
+            _ => {
+                if let Some((step, lowest_mv)) = current {
+                    print_bench_step(step, lowest_mv, resting_mv);
+                    if step == BenchStep::Idle {
+                        resting_mv = lowest_mv;
+                    }
+                }
+                current = Some((sample.step, sample.millivolts));
+            }
+        }
+    }
+}
+
+fn print_bench_step(step: BenchStep, lowest_mv: Option<u32>, resting_mv: Option<u32>) {
+    let name = match step {
+        BenchStep::Idle => "idle".to_string(),
+        BenchStep::Buzzer { hz } => format!("buzzer {} Hz", hz),
+        BenchStep::Motor { output, duty } => format!("motor{} duty {}", output, duty),
+    };
+    match (lowest_mv, resting_mv) {
+        (None, _) => println!("{:<18} (supply not measured)", name),
+        (Some(lowest), Some(resting)) => {
+            println!("{:<18} {:.2} V ({:+.2} V)", name, lowest as f32 / 1000.0, (lowest as f32 - resting as f32) / 1000.0)
+        }
+        (Some(lowest), None) => println!("{:<18} {:.2} V", name, lowest as f32 / 1000.0),
+    }
+}
+
+// Make the device fail on purpose, only firmware built with fault injection
+// takes it. The words are FAULT's own, so the protocol crate checks them.
+fn fault(host: &str, args: &[String]) -> Result<(), String> {
+    let line = format!("FAULT {}", args.join(" "));
+    let command = match Command::parse(&line) {
+        Ok(command) => command,
+        Err(ParseError::Invalid(e)) => return Err(e.to_string()),
+        Err(ParseError::UnknownCommand) => unreachable!("FAULT is a known command"),
+    };
+    let reply = read_reply(&mut send(host, command)?)?;
+    match reply.strip_prefix("ERR ") {
+        Some(error) => Err(error.to_string()),
+        None => {
+            println!("{}", reply);
+            Ok(())
+        }
+    }
+}
+
+// UF2 block framing, see https://github.com/microsoft/uf2
+const UF2_MAGIC_START: [u32; 2] = [0x0A32_4655, 0x9E5D_5157];
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+const UF2_FLAG_FAMILY_ID: u32 = 0x2000;
+// RP2350 "absolute": written where the block says, whatever the partition table
+const UF2_FAMILY_ABSOLUTE: u32 = 0xE48B_FF57;
+const UF2_PAYLOAD: usize = 256;
+
+// Pack the CYW43 firmware and CLM into an image of their flash partition
+fn cyw43_image(firmware: &str, clm: &str, out: &str) -> Result<(), String> {
+    let read = |path: &str| fs::read(path).map_err(|e| format!("can't read {}: {}", path, e));
+    let (firmware, clm) = (read(firmware)?, read(clm)?);
+    let mut buffer = vec![0; PARTITION_SIZE];
+    let image = partition::build(&firmware, &clm, &mut buffer).map_err(|_| format!("firmware and CLM don't fit in {} KB", PARTITION_SIZE / 1024))?;
+
+    let bytes = if out.ends_with(".uf2") { uf2(image, PARTITION_ADDRESS) } else { image.to_vec() };
+    fs::write(out, bytes).map_err(|e| format!("can't write {}: {}", out, e))?;
+    if out.ends_with(".uf2") {
+        println!("wrote {} ({} bytes for 0x{:08x}), copy it to the board in bootloader mode", out, image.len(), PARTITION_ADDRESS);
+    } else {
+        println!("wrote {}, flash it with: probe-rs download --chip RP235x --binary-format bin --base-address 0x{:08x} {}", out, PARTITION_ADDRESS, out);
+    }
+    Ok(())
+}
+
+// Wrap bytes for flash at `address` in UF2 blocks of 256 bytes each
+fn uf2(data: &[u8], address: u32) -> Vec<u8> {
+    let blocks = data.len().div_ceil(UF2_PAYLOAD);
+    let mut out = Vec::with_capacity(blocks * 512);
+    for (index, chunk) in data.chunks(UF2_PAYLOAD).enumerate() {
+        let header = [
+            UF2_MAGIC_START[0],
+            UF2_MAGIC_START[1],
+            UF2_FLAG_FAMILY_ID,
+            address + (index * UF2_PAYLOAD) as u32,
+            UF2_PAYLOAD as u32,
+            index as u32,
+            blocks as u32,
+            UF2_FAMILY_ABSOLUTE,
+        ];
+        for word in header {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut payload = [0; 476];
+        payload[..chunk.len()].copy_from_slice(chunk);
+        out.extend_from_slice(&payload);
+        out.extend_from_slice(&UF2_MAGIC_END.to_le_bytes());
+    }
+    out
+}
+
+// Print assist requests and alerts as devices on the network broadcast them
+fn listen() -> Result<(), String> {
+    let socket = UdpSocket::bind(("0.0.0.0", BROADCAST_UDP_PORT)).map_err(|e| e.to_string())?;
+    eprintln!("listening for assist requests and alerts on UDP port {}", BROADCAST_UDP_PORT);
+    let mut buffer = [0; 256];
+    let mut last: Option<(std::net::SocketAddr, Instant)> = None;
+    loop {
+        let (len, from) = socket.recv_from(&mut buffer).map_err(|e| e.to_string())?;
+        let Ok(line) = std::str::from_utf8(&buffer[..len]) else {
+            continue;
+        };
+        if let Some(alert) = AlertEvent::parse(line) {
+            println!("{}: {} alert: {}", from.ip(), alert.kind, alert.text);
+            continue;
+        }
+        let Some(event) = AssistEvent::parse(line) else {
+            continue;
+        };
+        // each request is broadcast a few times, a second apart
+        let now = Instant::now();
+        if last.is_some_and(|(sender, at)| sender == from && now - at < Duration::from_secs(5)) {
+            continue;
+        }
+        last = Some((from, now));
+
+        match event.position {
+            Some(position) => println!(
+                "{}: assist requested by {} at {:.6}, {:.6}",
+                from.ip(),
+                event.device,
+                position.lat_deg,
+                position.lon_deg
+            ),
+            None => println!("{}: assist requested by {}", from.ip(), event.device),
+        }
+        if let Some(owner) = event.owner {
+            println!("  owner: {}", owner);
+        }
+        if let Some(contact) = event.contact {
+            println!("  emergency contact: {}", contact);
+        }
+    }
+}
+
+fn stream(host: &str, args: &mut Vec<String>) -> Result<(), String> {
+    let hz = match take_option(args, "--rate") {
+        Some(rate) => rate.parse::<u8>().map_err(|_| format!("'{}' is not a rate in Hz", rate))?,
+        None => DEFAULT_STREAM_HZ,
+    };
+    let batch = match take_option(args, "--batch") {
+        Some(batch) => batch
+            .parse::<u8>()
+            .ok()
+            .filter(|batch| (1..=MAX_STREAM_BATCH).contains(batch))
+            .ok_or_else(|| format!("'{}' is not a batch size from 1 to {}", batch, MAX_STREAM_BATCH))?,
+        None => 1,
+    };
+    let flush_ms = match take_option(args, "--flush") {
+        Some(flush) => Some(flush.parse::<u32>().ok().filter(|ms| *ms > 0).ok_or_else(|| format!("'{}' is not a time in ms", flush))?),
+        None => None,
+    };
+    let plot = take_flag(args, "--plot");
+
+    let mut reader = send(host, Command::Stream { hz, batch, flush_ms })?;
+    // the stream only ends when we hang up, don't time out between samples
+    reader.get_ref().set_read_timeout(None).map_err(|e| e.to_string())?;
+
+    let mut last_seq = None;
+    loop {
+        let line = read_reply(&mut reader)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        // the device numbers its samples, tell about the ones that never came
+        if let Some(seq) = Telemetry::parse(&line).and_then(|t| t.seq) {
+            if let Some(last) = last_seq.filter(|last| seq > last + 1) {
+                eprintln!("visionctl: {} samples dropped", seq - last - 1);
+            }
+            last_seq = Some(seq);
+        }
+        // events go through as they are, they're meant to be read out
+        if plot && ZoneEvent::parse(&line).is_none() && AssistEvent::parse(&line).is_none() {
+            println!("{}", plot_line(&line));
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+// Status line as a bar per channel, shorter bar means closer obstacle
+fn plot_line(line: &str) -> String {
+    let telemetry = Telemetry::parse(line);
+
+    let bar = |cm: Option<u32>| match cm {
+        Some(cm) => {
+            let len = (cm.min(PLOT_MAX_CM) as usize * PLOT_WIDTH) / PLOT_MAX_CM as usize;
+            format!("{:<width$} {:>3} cm", "#".repeat(len), cm, width = PLOT_WIDTH)
+        }
+        None => format!("{:<width$}   ? cm", "", width = PLOT_WIDTH),
+    };
+
+    // left and right always, the others when the device has them on
+    let mut plot = String::new();
+    for side in SideName::ALL {
+        let distance_cm = telemetry.and_then(|t| t.distance_cm(side));
+        if distance_cm.is_none() && !matches!(side, SideName::Left | SideName::Right) {
+            continue;
+        }
+        if !plot.is_empty() {
+            plot.push_str("  ");
+        }
+        plot.push_str(&format!("{} |{}", side.tag(), bar(distance_cm)));
+    }
+    if telemetry.is_some_and(|t| t.is_stale()) {
+        plot.push_str("  STALE");
+    }
+    plot
+}